@@ -0,0 +1,53 @@
+use super::routine::Routine;
+use crate::assembler::{Assembler, Image, VTable};
+use std::collections::HashMap;
+
+pub struct Asm {
+    image: Image<Routine>,
+}
+
+impl Asm {
+    pub fn const_32(&mut self, value: u32) -> usize {
+        let constants = self.image.constants_mut();
+        let index = constants.len() / 4;
+        for byte in value.to_le_bytes() {
+            constants.push(byte);
+        }
+        index
+    }
+
+    /// Finalizes the routine and adds it to the assembler
+    pub fn push_routine(&mut self, mut routine: Routine) {
+        routine.finalize();
+        self.image.push_routine(routine);
+    }
+}
+
+impl Default for Asm {
+    fn default() -> Self {
+        Self {
+            // Keeps literal loads and `blx` targets aligned
+            image: Image::new(4),
+        }
+    }
+}
+
+impl Assembler for Asm {
+    type AsmRoutine = Routine;
+
+    fn global_const_address(&self) -> usize {
+        self.image.global_const_address()
+    }
+
+    fn get_label_address(&self, name: &str) -> Option<usize> {
+        self.image.get_label_address(name)
+    }
+
+    fn jit(self) -> Option<VTable> {
+        self.image.jit()
+    }
+
+    fn virtual_jit(self) -> Option<(Vec<u8>, HashMap<String, usize>)> {
+        self.image.virtual_jit()
+    }
+}
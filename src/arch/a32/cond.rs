@@ -0,0 +1,35 @@
+/// Condition codes as encoded in the top nibble of A32 instructions and in conditional branches
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    /// Equal
+    EQ = 0x0,
+    /// Not equal
+    NE = 0x1,
+    /// Carry set, unsigned higher or same
+    HS = 0x2,
+    /// Carry clear, unsigned lower
+    LO = 0x3,
+    /// Negative
+    MI = 0x4,
+    /// Positive or zero
+    PL = 0x5,
+    /// Overflow
+    VS = 0x6,
+    /// No overflow
+    VC = 0x7,
+    /// Unsigned higher
+    HI = 0x8,
+    /// Unsigned lower or same
+    LS = 0x9,
+    /// Signed greater than or equal
+    GE = 0xA,
+    /// Signed less than
+    LT = 0xB,
+    /// Signed greater than
+    GT = 0xC,
+    /// Signed less than or equal
+    LE = 0xD,
+    /// Always
+    AL = 0xE,
+}
@@ -0,0 +1,129 @@
+use super::{reg::Reg, routine::Routine};
+use crate::{
+    arch::qemu::{Layout, Machine},
+    assembler::Subroutine,
+};
+use std::{collections::HashMap, io::Result};
+
+/// Runs 32-bit ARM Linux executables through `qemu-arm`, whose path can be overridden through
+/// `QEMU_ARM`
+pub const MACHINE: Machine = Machine {
+    qemu: "qemu-arm",
+    env: "QEMU_ARM",
+    machine: 40,        // EM_ARM
+    flags: 0x0500_0000, // EF_ARM_EABI_VER5
+    word_size: 4,
+    max_args: 4,
+};
+
+/// Runs the routine with the given label of an image produced by `virtual_jit` through
+/// `qemu-arm` passing `args` in `R0` to `R3`
+///
+/// Returns the value of `R0` after the routine returned
+pub fn run(code: &[u8], vtable: &HashMap<String, usize>, label: &str, args: &[u32]) -> Result<u32> {
+    Ok(MACHINE.run(&elf(code, vtable, label, args)?)? as u32)
+}
+
+/// Wraps an image produced by `virtual_jit` into a static 32-bit ARM Linux executable
+///
+/// The entry stub calls the routine with the given label passing `args` in `R0` to `R3`, writes
+/// the 4 bytes of `R0` to stdout and exits with the lowest byte of `R0` as status code. Thumb
+/// routines are entered through `blx`.
+pub fn elf(
+    code: &[u8],
+    vtable: &HashMap<String, usize>,
+    label: &str,
+    args: &[u32],
+) -> Result<Vec<u8>> {
+    let words: Vec<u64> = args.iter().map(|&arg| arg as u64).collect();
+    MACHINE.elf(code, vtable, label, &words, |layout| {
+        stub(layout, args.len())
+    })
+}
+
+fn stub(layout: &Layout, args: usize) -> Routine {
+    let mut stub = Routine::new("_start".to_string());
+    for index in 0..args {
+        let rel = (layout.args + index * 4) as i32 - (layout.entry + stub.code().len()) as i32;
+        stub.ldr_rel(arg_reg(index), rel);
+    }
+    let rel = layout.target as i32 - (layout.entry + stub.code().len()) as i32;
+    stub.br_rel_link(rel);
+    stub.push(&[Reg::R0]);
+    // write(1, sp, 4)
+    stub.mov_reg(Reg::R4, Reg::R0);
+    stub.mov_imm(Reg::R0, 1);
+    stub.mov_reg(Reg::R1, Reg::SP);
+    stub.mov_imm(Reg::R2, 4);
+    stub.mov_imm(Reg::R7, 4);
+    stub.svc(0);
+    // exit_group(r0)
+    stub.mov_reg(Reg::R0, Reg::R4);
+    stub.mov_imm(Reg::R7, 248);
+    stub.svc(0);
+    stub
+}
+
+fn arg_reg(index: usize) -> Reg {
+    [Reg::R0, Reg::R1, Reg::R2, Reg::R3][index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::{a32::asm::Asm, qemu::LOAD_ADDRESS},
+        assembler::Assembler,
+        mem,
+    };
+
+    /// Arm routine calling a Thumb routine
+    ///
+    /// `arm(a, b) = thumb(a) - b` with `thumb(x) = x * x + 0x1000`
+    fn image() -> (Vec<u8>, HashMap<String, usize>) {
+        let mut asm = Asm::default();
+        let mut arm = Routine::new("arm".to_string());
+        arm.push(&[Reg::R4, Reg::LR]);
+        arm.mov_reg(Reg::R4, Reg::R1);
+        arm.br_link("thumb".to_string());
+        arm.sub_reg(Reg::R0, Reg::R0, Reg::R4);
+        arm.pop(&[Reg::R4, Reg::PC]);
+        asm.push_routine(arm);
+        let mut thumb = Routine::thumb("thumb".to_string());
+        let offset = thumb.const_32(0x1000);
+        thumb.ldr_const(Reg::R1, offset);
+        thumb.mul(Reg::R0, Reg::R0, Reg::R0);
+        thumb.add_reg(Reg::R0, Reg::R0, Reg::R1);
+        thumb.ret();
+        asm.push_routine(thumb);
+        asm.virtual_jit().unwrap()
+    }
+
+    #[test]
+    fn elf_layout() {
+        let (code, vtable) = image();
+        let file = elf(&code, &vtable, "arm", &[7, 9]).unwrap();
+        assert_eq!(&file[..4], b"\x7FELF");
+        assert_eq!(file[4], 1);
+        assert_eq!(u16::from_le_bytes([file[18], file[19]]), 40);
+        // The image directly follows the headers, the arguments follow the image
+        assert_eq!(file[96..96 + code.len()], code);
+        let args = mem::align(96 + code.len(), 8);
+        assert_eq!(file[args..args + 4], 7u32.to_le_bytes());
+        assert_eq!(file[args + 4..args + 8], 9u32.to_le_bytes());
+        // The stub is Arm code, so the entry point has no Thumb bit
+        let entry = u32::from_le_bytes(file[24..28].try_into().unwrap());
+        assert_eq!(entry as u64, LOAD_ADDRESS + args as u64 + 8);
+        assert!(elf(&code, &vtable, "missing", &[]).is_err());
+        assert!(elf(&code, &vtable, "arm", &[0; 5]).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs qemu-arm"]
+    fn runs_routines() {
+        let (code, vtable) = image();
+        assert_eq!(run(&code, &vtable, "arm", &[7, 9]).unwrap(), 0x1000 + 40);
+        // The stub enters Thumb routines through `blx`
+        assert_eq!(run(&code, &vtable, "thumb", &[3]).unwrap(), 0x1009);
+    }
+}
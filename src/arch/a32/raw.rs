@@ -0,0 +1,112 @@
+/// Returns the rotation and 8-bit value of an A32 modified immediate if `value` can be encoded
+pub fn a32_imm(value: u32) -> Option<u32> {
+    (0..16).find_map(|rot| {
+        let imm8 = value.rotate_left(rot * 2);
+        (imm8 <= 0xFF).then_some((rot << 8) | imm8)
+    })
+}
+
+/// Returns the `i:imm3:imm8` bits of a T32 modified immediate placed as in a 32-bit Thumb
+/// instruction if `value` can be encoded
+pub fn t32_imm(value: u32) -> Option<u32> {
+    let low = value & 0xFF;
+    let high = (value >> 8) & 0xFF;
+    let imm12 = if value <= 0xFF {
+        value
+    } else if value == low * 0x0001_0001 {
+        0x100 | low
+    } else if value == high * 0x0100_0100 {
+        0x200 | high
+    } else if value == low * 0x0101_0101 {
+        0x300 | low
+    } else {
+        // `1bcdefgh` rotated right by 8 to 31 bits
+        let rot = (8..32).find(|rot| {
+            let imm8 = value.rotate_left(*rot);
+            imm8 <= 0xFF && imm8 & 0x80 != 0
+        })?;
+        (rot << 7) | (value.rotate_left(rot) & 0x7F)
+    };
+    Some(((imm12 >> 11) << 26) | (((imm12 >> 8) & 7) << 12) | (imm12 & 0xFF))
+}
+
+/// Returns the `imm4` and `i:imm3:imm8` bits of a T32 `movw`/`movt`
+pub fn t32_imm16(imm16: u32) -> u32 {
+    ((imm16 >> 12) << 16)
+        | (((imm16 >> 11) & 1) << 26)
+        | (((imm16 >> 8) & 7) << 12)
+        | (imm16 & 0xFF)
+}
+
+/// Returns the immediate bits of an A32 branch to the byte offset `rel` from `PC`
+pub fn a32_branch(rel: isize) -> u32 {
+    assert!(
+        (-0x200_0000..=0x1FF_FFFC).contains(&rel) && rel % 4 == 0,
+        "Tried to branch to label not in range"
+    );
+    (rel >> 2) as u32 & 0xFF_FFFF
+}
+
+/// Returns the immediate bits of an unconditional T32 branch to the byte offset `rel` from `PC`
+pub fn t32_branch(rel: isize) -> u32 {
+    assert!(
+        (-0x100_0000..=0xFF_FFFE).contains(&rel) && rel % 2 == 0,
+        "Tried to branch to label not in range"
+    );
+    let imm = rel as u32;
+    let s = (imm >> 24) & 1;
+    let j1 = ((imm >> 23) & 1) ^ 1 ^ s;
+    let j2 = ((imm >> 22) & 1) ^ 1 ^ s;
+    (s << 26) | (((imm >> 12) & 0x3FF) << 16) | (j1 << 13) | (j2 << 11) | ((imm >> 1) & 0x7FF)
+}
+
+/// Returns the immediate bits of a conditional T32 branch to the byte offset `rel` from `PC`
+pub fn t32_cond_branch(rel: isize) -> u32 {
+    assert!(
+        (-0x10_0000..=0xF_FFFE).contains(&rel) && rel % 2 == 0,
+        "Tried to branch to label not in range"
+    );
+    let imm = rel as u32;
+    (((imm >> 20) & 1) << 26)
+        | (((imm >> 12) & 0x3F) << 16)
+        | (((imm >> 18) & 1) << 13)
+        | (((imm >> 19) & 1) << 11)
+        | ((imm >> 1) & 0x7FF)
+}
+
+/// Returns the `U` and `imm12` bits of a literal load to the byte offset `rel` from `PC`
+///
+/// Both the A32 and the T32 encoding keep them at the same position.
+pub fn literal(rel: isize) -> u32 {
+    assert!(
+        (-0xFFF..=0xFFF).contains(&rel),
+        "Tried to load constant not in range of the literal pool"
+    );
+    (((rel >= 0) as u32) << 23) | rel.unsigned_abs() as u32
+}
+
+pub fn read_le_32(slice: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes([
+        slice[index],
+        slice[index + 1],
+        slice[index + 2],
+        slice[index + 3],
+    ])
+}
+
+pub fn write_le_32(slice: &mut [u8], index: usize, value: u32) {
+    slice[index..index + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Reads a 32-bit Thumb instruction that is stored as two little-endian halfwords
+pub fn read_t32(slice: &[u8], index: usize) -> u32 {
+    let hw1 = u16::from_le_bytes([slice[index], slice[index + 1]]) as u32;
+    let hw2 = u16::from_le_bytes([slice[index + 2], slice[index + 3]]) as u32;
+    (hw1 << 16) | hw2
+}
+
+/// Writes a 32-bit Thumb instruction as two little-endian halfwords
+pub fn write_t32(slice: &mut [u8], index: usize, value: u32) {
+    slice[index..index + 2].copy_from_slice(&((value >> 16) as u16).to_le_bytes());
+    slice[index + 2..index + 4].copy_from_slice(&(value as u16).to_le_bytes());
+}
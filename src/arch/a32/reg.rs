@@ -0,0 +1,26 @@
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    R0 = 0,
+    R1 = 1,
+    R2 = 2,
+    R3 = 3,
+    R4 = 4,
+    R5 = 5,
+    R6 = 6,
+    R7 = 7,
+    R8 = 8,
+    R9 = 9,
+    R10 = 10,
+    R11 = 11,
+    R12 = 12,
+    R13 = 13,
+    R14 = 14,
+    R15 = 15,
+}
+
+impl Reg {
+    pub const SP: Reg = Reg::R13;
+    pub const LR: Reg = Reg::R14;
+    pub const PC: Reg = Reg::R15;
+}
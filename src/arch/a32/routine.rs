@@ -0,0 +1,969 @@
+use super::{cond::Cond, raw, reg::Reg};
+use crate::assembler::{Assembler, Label, PostOp, Subroutine};
+
+/// Instruction set a routine is encoded in
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Fixed 32-bit A32 encodings
+    Arm,
+    /// Mixed 16/32-bit T32 encodings
+    Thumb,
+}
+
+pub struct Routine {
+    pub(super) name: String,
+    pub(super) constants: Vec<u8>,
+    pub(super) code: Vec<u8>,
+    pub(super) post_ops: Vec<Op>,
+    pub(super) mode: Mode,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<Fixup>,
+}
+
+/// Branch to a local label whose offset is patched when the routine is finalized
+struct Fixup {
+    insn_offset: usize,
+    label: Label,
+    cond: Cond,
+}
+
+/// Data-processing operation, the discriminant is the A32 opcode
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Dp {
+    And = 0,
+    Eor = 1,
+    Sub = 2,
+    Rsb = 3,
+    Add = 4,
+    Adc = 5,
+    Sbc = 6,
+    Tst = 8,
+    Cmp = 10,
+    Cmn = 11,
+    Orr = 12,
+    Mov = 13,
+    Bic = 14,
+    Mvn = 15,
+}
+
+impl Dp {
+    fn t32(self) -> u32 {
+        match self {
+            Self::And | Self::Tst => 0,
+            Self::Bic => 1,
+            Self::Orr | Self::Mov => 2,
+            Self::Mvn => 3,
+            Self::Eor => 4,
+            Self::Add | Self::Cmn => 8,
+            Self::Adc => 10,
+            Self::Sbc => 11,
+            Self::Sub | Self::Cmp => 13,
+            Self::Rsb => 14,
+        }
+    }
+
+    fn is_compare(self) -> bool {
+        matches!(self, Self::Tst | Self::Cmp | Self::Cmn)
+    }
+
+    fn is_move(self) -> bool {
+        matches!(self, Self::Mov | Self::Mvn)
+    }
+}
+
+impl Routine {
+    /// Creates a routine encoded in A32
+    pub fn new(name: String) -> Self {
+        Self::with_mode(name, Mode::Arm)
+    }
+
+    /// Creates a routine encoded in T32
+    pub fn thumb(name: String) -> Self {
+        Self::with_mode(name, Mode::Thumb)
+    }
+
+    pub fn with_mode(name: String, mode: Mode) -> Self {
+        Self {
+            name,
+            constants: Vec::with_capacity(0),
+            code: Vec::with_capacity(0),
+            post_ops: Vec::with_capacity(0),
+            mode,
+            labels: Vec::with_capacity(0),
+            fixups: Vec::with_capacity(0),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns from a routine, switching to the instruction set of the caller
+    pub fn ret(&mut self) {
+        self.bx(Reg::LR);
+    }
+
+    /// Placeholder
+    pub fn nop(&mut self) {
+        self.insn(0xE320_F000, 0xF3AF_8000);
+    }
+
+    /// Supervisor call
+    pub fn svc(&mut self, imm: u32) {
+        match self.mode {
+            Mode::Arm => {
+                assert!(imm <= 0xFF_FFFF, "Immediate must fit into 24 bits");
+                self.a32(0xEF00_0000 | imm);
+            }
+            Mode::Thumb => {
+                assert!(imm <= 0xFF, "Immediate must fit into 8 bits");
+                self.t16(0xDF00 | imm as u16);
+            }
+        }
+    }
+
+    /// Branches to the address in `rm`, switching to Thumb if bit 0 is set
+    pub fn bx(&mut self, rm: Reg) {
+        match self.mode {
+            Mode::Arm => self.a32(0xE12F_FF10 | rm as u32),
+            Mode::Thumb => self.t16(0x4700 | ((rm as u16) << 3)),
+        }
+    }
+
+    /// Calls the address in `rm`, switching to Thumb if bit 0 is set
+    pub fn blx_reg(&mut self, rm: Reg) {
+        match self.mode {
+            Mode::Arm => self.a32(0xE12F_FF30 | rm as u32),
+            Mode::Thumb => self.t16(0x4780 | ((rm as u16) << 3)),
+        }
+    }
+
+    /// Branches to label that must be present in the V-Table and use the same instruction set
+    pub fn br(&mut self, label: String) {
+        self.post_ops.push(Op::Routine {
+            insn_offset: self.code.len(),
+            label,
+            link: false,
+            mode: self.mode,
+        });
+        self.insn(0, 0);
+    }
+
+    /// Calls label that must be present in the V-Table, using `blx` if it uses the other
+    /// instruction set
+    pub fn br_link(&mut self, label: String) {
+        self.post_ops.push(Op::Routine {
+            insn_offset: self.code.len(),
+            label,
+            link: true,
+            mode: self.mode,
+        });
+        self.insn(0, 0);
+    }
+
+    /// Calls the address `rel` bytes away from this instruction, using `blx` if bit 0 of `rel`
+    /// does not match the instruction set of the routine
+    ///
+    /// The routine has to be placed at an address aligned to 4 bytes.
+    pub fn br_rel_link(&mut self, rel: i32) {
+        let insn_addr = self.code.len();
+        let insn = call(
+            self.mode,
+            insn_addr,
+            (insn_addr as isize + rel as isize) as usize,
+        );
+        self.insn(insn, insn);
+    }
+
+    /// Creates a new label that has to be bound before the routine is finalized
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds the label to the position of the next instruction
+    pub fn bind(&mut self, label: Label) {
+        let slot = &mut self.labels[label.0];
+        assert!(slot.is_none(), "Tried to bind label twice");
+        *slot = Some(self.code.len());
+    }
+
+    /// Branches to a local label
+    pub fn b(&mut self, label: Label) {
+        self.b_cond(Cond::AL, label);
+    }
+
+    /// Branches to a local label if the condition holds
+    ///
+    /// Conditional branches in Thumb reach ±1 MiB, all others ±16 MiB or more
+    pub fn b_cond(&mut self, cond: Cond, label: Label) {
+        self.fixups.push(Fixup {
+            insn_offset: self.code.len(),
+            label,
+            cond,
+        });
+        let t32 = if cond == Cond::AL {
+            0xF000_9000
+        } else {
+            0xF000_8000 | ((cond as u32) << 22)
+        };
+        self.insn(((cond as u32) << 28) | 0x0A00_0000, t32);
+    }
+
+    /// Moves the value stored in the source register into the destination register
+    pub fn mov_reg(&mut self, rd: Reg, rm: Reg) {
+        self.dp_reg(Dp::Mov, rd, Reg::R0, rm);
+    }
+
+    /// Moves the 32-bit immediate into `rd` using a single `mov`/`mvn` if possible and
+    /// `movw`/`movt` otherwise
+    pub fn mov_imm(&mut self, rd: Reg, imm: u32) {
+        if self.encode_imm(imm).is_some() {
+            self.dp_imm(Dp::Mov, rd, Reg::R0, imm);
+        } else if self.encode_imm(!imm).is_some() {
+            self.dp_imm(Dp::Mvn, rd, Reg::R0, !imm);
+        } else {
+            self.movw(rd, imm & 0xFFFF);
+            if imm > 0xFFFF {
+                self.movt(rd, imm >> 16);
+            }
+        }
+    }
+
+    /// Moves the 16-bit immediate into `rd` clearing the upper bits
+    pub fn movw(&mut self, rd: Reg, imm16: u32) {
+        assert!(imm16 <= 0xFFFF, "Immediate must fit into 16 bits");
+        self.insn(
+            0xE300_0000 | ((imm16 >> 12) << 16) | ((rd as u32) << 12) | (imm16 & 0xFFF),
+            0xF240_0000 | raw::t32_imm16(imm16) | ((rd as u32) << 8),
+        );
+    }
+
+    /// Moves the 16-bit immediate into the upper bits of `rd` keeping the lower bits
+    pub fn movt(&mut self, rd: Reg, imm16: u32) {
+        assert!(imm16 <= 0xFFFF, "Immediate must fit into 16 bits");
+        self.insn(
+            0xE340_0000 | ((imm16 >> 12) << 16) | ((rd as u32) << 12) | (imm16 & 0xFFF),
+            0xF2C0_0000 | raw::t32_imm16(imm16) | ((rd as u32) << 8),
+        );
+    }
+
+    /// Inverts all bits of `rm`
+    pub fn mvn_reg(&mut self, rd: Reg, rm: Reg) {
+        self.dp_reg(Dp::Mvn, rd, Reg::R0, rm);
+    }
+
+    /// Adds `rn` and `rm`
+    pub fn add_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Add, rd, rn, rm);
+    }
+
+    /// Adds `rn`, `rm` and the carry flag, setting the flags
+    pub fn adcs_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg_flags(Dp::Adc, rd, rn, rm);
+    }
+
+    /// Adds `rn` and `rm`, setting the flags
+    pub fn adds_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg_flags(Dp::Add, rd, rn, rm);
+    }
+
+    /// Subtracts `rm` from `rn`
+    pub fn sub_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Sub, rd, rn, rm);
+    }
+
+    /// Subtracts `rm` and the inverted carry flag from `rn`, setting the flags
+    pub fn sbcs_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg_flags(Dp::Sbc, rd, rn, rm);
+    }
+
+    /// Subtracts `rm` from `rn`, setting the flags
+    pub fn subs_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg_flags(Dp::Sub, rd, rn, rm);
+    }
+
+    /// Subtracts `rn` from `rm`
+    pub fn rsb_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Rsb, rd, rn, rm);
+    }
+
+    /// Computes the bitwise and of `rn` and `rm`
+    pub fn and_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::And, rd, rn, rm);
+    }
+
+    /// Computes the bitwise or of `rn` and `rm`
+    pub fn orr_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Orr, rd, rn, rm);
+    }
+
+    /// Computes the bitwise exclusive or of `rn` and `rm`
+    pub fn eor_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Eor, rd, rn, rm);
+    }
+
+    /// Computes the bitwise and of `rn` and the inverted `rm`
+    pub fn bic_reg(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Bic, rd, rn, rm);
+    }
+
+    /// Sets the flags according to `rn - rm`
+    pub fn cmp_reg(&mut self, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Cmp, Reg::R0, rn, rm);
+    }
+
+    /// Sets the flags according to `rn + rm`
+    pub fn cmn_reg(&mut self, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Cmn, Reg::R0, rn, rm);
+    }
+
+    /// Sets the flags according to `rn & rm`
+    pub fn tst_reg(&mut self, rn: Reg, rm: Reg) {
+        self.dp_reg(Dp::Tst, Reg::R0, rn, rm);
+    }
+
+    /// Adds the modified immediate to `rn`
+    pub fn add_imm(&mut self, rd: Reg, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Add, rd, rn, imm);
+    }
+
+    /// Subtracts the modified immediate from `rn`
+    pub fn sub_imm(&mut self, rd: Reg, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Sub, rd, rn, imm);
+    }
+
+    /// Subtracts `rn` from the modified immediate
+    pub fn rsb_imm(&mut self, rd: Reg, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Rsb, rd, rn, imm);
+    }
+
+    /// Computes the bitwise and of `rn` and the modified immediate
+    pub fn and_imm(&mut self, rd: Reg, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::And, rd, rn, imm);
+    }
+
+    /// Computes the bitwise or of `rn` and the modified immediate
+    pub fn orr_imm(&mut self, rd: Reg, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Orr, rd, rn, imm);
+    }
+
+    /// Computes the bitwise exclusive or of `rn` and the modified immediate
+    pub fn eor_imm(&mut self, rd: Reg, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Eor, rd, rn, imm);
+    }
+
+    /// Computes the bitwise and of `rn` and the inverted modified immediate
+    pub fn bic_imm(&mut self, rd: Reg, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Bic, rd, rn, imm);
+    }
+
+    /// Sets the flags according to `rn - imm`
+    pub fn cmp_imm(&mut self, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Cmp, Reg::R0, rn, imm);
+    }
+
+    /// Sets the flags according to `rn + imm`
+    pub fn cmn_imm(&mut self, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Cmn, Reg::R0, rn, imm);
+    }
+
+    /// Sets the flags according to `rn & imm`
+    pub fn tst_imm(&mut self, rn: Reg, imm: u32) {
+        self.dp_imm(Dp::Tst, Reg::R0, rn, imm);
+    }
+
+    /// Multiplies `rn` and `rm` keeping the lower 32 bits
+    pub fn mul(&mut self, rd: Reg, rn: Reg, rm: Reg) {
+        self.insn(
+            0xE000_0090 | ((rd as u32) << 16) | ((rm as u32) << 8) | rn as u32,
+            0xFB00_F000 | ((rn as u32) << 16) | ((rd as u32) << 8) | rm as u32,
+        );
+    }
+
+    /// Loads the 32-bit value at the address `rn + offset`
+    ///
+    /// The offset must be within ±4095 in Arm and within -255 to 4095 in Thumb
+    pub fn ldr_imm(&mut self, rt: Reg, rn: Reg, offset: i32) {
+        self.load_store(true, rt, rn, offset);
+    }
+
+    /// Stores `rt` at the address `rn + offset`
+    ///
+    /// The offset must be within ±4095 in Arm and within -255 to 4095 in Thumb
+    pub fn str_imm(&mut self, rt: Reg, rn: Reg, offset: i32) {
+        self.load_store(false, rt, rn, offset);
+    }
+
+    /// Loads the word `rel` bytes away from this instruction
+    ///
+    /// The routine has to be placed at an address aligned to 4 bytes.
+    pub fn ldr_rel(&mut self, rt: Reg, rel: i32) {
+        let insn_addr = self.code.len();
+        let target = insn_addr as isize + rel as isize;
+        let rel = target - literal_pc(self.mode, insn_addr) as isize;
+        let insn = raw::literal(rel) | ((rt as u32) << 12);
+        self.insn(0xE51F_0000 | insn, 0xF85F_0000 | insn);
+    }
+
+    /// Pushes the registers onto the stack, the lowest register ending up at the lowest address
+    pub fn push(&mut self, regs: &[Reg]) {
+        let list = reg_list(regs);
+        if let [reg] = regs {
+            // str reg, [sp, #-4]!
+            self.insn(
+                0xE52D_0004 | ((*reg as u32) << 12),
+                0xF84D_0D04 | ((*reg as u32) << 12),
+            );
+        } else {
+            assert!(
+                self.mode == Mode::Arm || list & 0xA000 == 0,
+                "Tried to push SP or PC in Thumb"
+            );
+            self.insn(0xE92D_0000 | list, 0xE92D_0000 | list);
+        }
+    }
+
+    /// Pops the registers from the stack, popping `PC` returns
+    pub fn pop(&mut self, regs: &[Reg]) {
+        let list = reg_list(regs);
+        if let [reg] = regs {
+            // ldr reg, [sp], #4
+            self.insn(
+                0xE49D_0004 | ((*reg as u32) << 12),
+                0xF85D_0B04 | ((*reg as u32) << 12),
+            );
+        } else {
+            assert!(
+                self.mode == Mode::Arm || (list & 0x2000 == 0 && list & 0xC000 != 0xC000),
+                "Tried to pop SP or both LR and PC in Thumb"
+            );
+            self.insn(0xE8BD_0000 | list, 0xE8BD_0000 | list);
+        }
+    }
+
+    /// Loads the value of a 32-bit constant from the literal pool in front of the code
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps. The constant must
+    /// be within 4 KiB of the instruction.
+    pub fn ldr_const(&mut self, rt: Reg, offset: usize) {
+        self.post_ops.push(Op::Const {
+            insn_offset: self.code.len(),
+            const_offset: offset,
+            mode: self.mode,
+        });
+        self.insn(
+            0xE51F_0000 | ((rt as u32) << 12),
+            0xF85F_0000 | ((rt as u32) << 12),
+        );
+    }
+
+    /// Loads the value of a 32-bit global constant
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps. The constant must
+    /// be within 4 KiB of the instruction.
+    pub fn ldr_global_const(&mut self, rt: Reg, offset: usize) {
+        self.post_ops.push(Op::GlobalConst {
+            insn_offset: self.code.len(),
+            const_offset: offset,
+            mode: self.mode,
+        });
+        self.insn(
+            0xE51F_0000 | ((rt as u32) << 12),
+            0xF85F_0000 | ((rt as u32) << 12),
+        );
+    }
+
+    /// Stores a 32-bit constant in front of the code
+    ///
+    /// Return the index of the constant
+    pub fn const_32(&mut self, value: u32) -> usize {
+        let index = self.constants.len() / 4;
+        for byte in value.to_le_bytes() {
+            self.constants.push(byte);
+        }
+        index
+    }
+
+    /// Patches the branches to local labels
+    ///
+    /// This is done by the assembler when the routine is pushed
+    pub fn finalize(&mut self) {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(target) = self.labels[fixup.label.0] else {
+                panic!("Tried to branch to unbound label");
+            };
+            let pc = fixup.insn_offset
+                + match self.mode {
+                    Mode::Arm => 8,
+                    Mode::Thumb => 4,
+                };
+            let rel = target as isize - pc as isize;
+            match self.mode {
+                Mode::Arm => {
+                    let insn = raw::read_le_32(&self.code, fixup.insn_offset);
+                    raw::write_le_32(
+                        &mut self.code,
+                        fixup.insn_offset,
+                        insn | raw::a32_branch(rel),
+                    );
+                }
+                Mode::Thumb => {
+                    let imm = if fixup.cond == Cond::AL {
+                        raw::t32_branch(rel)
+                    } else {
+                        raw::t32_cond_branch(rel)
+                    };
+                    let insn = raw::read_t32(&self.code, fixup.insn_offset);
+                    raw::write_t32(&mut self.code, fixup.insn_offset, insn | imm);
+                }
+            }
+        }
+    }
+
+    fn encode_imm(&self, imm: u32) -> Option<u32> {
+        match self.mode {
+            Mode::Arm => raw::a32_imm(imm),
+            Mode::Thumb => raw::t32_imm(imm),
+        }
+    }
+
+    fn dp_reg(&mut self, op: Dp, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp(op, op.is_compare(), rd, rn, rm as u32, false);
+    }
+
+    fn dp_reg_flags(&mut self, op: Dp, rd: Reg, rn: Reg, rm: Reg) {
+        self.dp(op, true, rd, rn, rm as u32, false);
+    }
+
+    fn dp_imm(&mut self, op: Dp, rd: Reg, rn: Reg, imm: u32) {
+        let Some(imm) = self.encode_imm(imm) else {
+            panic!("Immediate cannot be encoded as modified immediate");
+        };
+        self.dp(op, op.is_compare(), rd, rn, imm, true);
+    }
+
+    fn dp(&mut self, op: Dp, s: bool, rd: Reg, rn: Reg, operand: u32, imm: bool) {
+        let s = (s as u32) << 20;
+        match self.mode {
+            Mode::Arm => {
+                let rd = if op.is_compare() { 0 } else { rd as u32 };
+                let rn = if op.is_move() { 0 } else { rn as u32 };
+                let base = if imm { 0xE200_0000 } else { 0xE000_0000 };
+                self.a32(base | ((op as u32) << 21) | s | (rn << 16) | (rd << 12) | operand);
+            }
+            Mode::Thumb => {
+                let rd = if op.is_compare() { 0xF } else { rd as u32 };
+                let rn = if op.is_move() { 0xF } else { rn as u32 };
+                let base = if imm { 0xF000_0000 } else { 0xEA00_0000 };
+                self.t32(base | (op.t32() << 21) | s | (rn << 16) | (rd << 8) | operand);
+            }
+        }
+    }
+
+    fn load_store(&mut self, load: bool, rt: Reg, rn: Reg, offset: i32) {
+        let l = (load as u32) << 20;
+        let regs = ((rn as u32) << 16) | ((rt as u32) << 12);
+        match self.mode {
+            Mode::Arm => {
+                assert!(
+                    (-0xFFF..=0xFFF).contains(&offset),
+                    "Offset must fit into 12 bits"
+                );
+                let u = ((offset >= 0) as u32) << 23;
+                self.a32(0xE500_0000 | u | l | regs | offset.unsigned_abs());
+            }
+            Mode::Thumb => {
+                if offset >= 0 {
+                    assert!(offset <= 0xFFF, "Offset must fit into 12 bits");
+                    self.t32(0xF8C0_0000 | l | regs | offset as u32);
+                } else {
+                    assert!(offset >= -0xFF, "Negative offset must fit into 8 bits");
+                    self.t32(0xF840_0C00 | l | regs | offset.unsigned_abs());
+                }
+            }
+        }
+    }
+
+    /// Emits the A32 or the T32 encoding depending on the mode of the routine
+    fn insn(&mut self, a32: u32, t32: u32) {
+        match self.mode {
+            Mode::Arm => self.a32(a32),
+            Mode::Thumb => self.t32(t32),
+        }
+    }
+
+    fn a32(&mut self, value: u32) {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn t32(&mut self, value: u32) {
+        self.code
+            .extend_from_slice(&((value >> 16) as u16).to_le_bytes());
+        self.code.extend_from_slice(&(value as u16).to_le_bytes());
+    }
+
+    fn t16(&mut self, value: u16) {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn reg_list(regs: &[Reg]) -> u32 {
+    assert!(!regs.is_empty(), "Register list must not be empty");
+    regs.iter().fold(0, |list, reg| list | (1 << *reg as u32))
+}
+
+/// Returns the value of `PC` used by literal loads at `insn_addr`
+fn literal_pc(mode: Mode, insn_addr: usize) -> usize {
+    match mode {
+        Mode::Arm => insn_addr + 8,
+        Mode::Thumb => (insn_addr + 4) & !3,
+    }
+}
+
+/// Returns a `bl` or `blx` at `insn_addr` in `mode` to `target`
+///
+/// Bit 0 of `target` selects Thumb as the instruction set of the target.
+fn call(mode: Mode, insn_addr: usize, target: usize) -> u32 {
+    let thumb_target = target & 1 != 0;
+    let target = target & !1;
+    match (mode, thumb_target) {
+        (Mode::Arm, false) => {
+            0xEB00_0000 | raw::a32_branch(target as isize - (insn_addr + 8) as isize)
+        }
+        (Mode::Arm, true) => {
+            let rel = target as isize - (insn_addr + 8) as isize;
+            0xFA00_0000 | (((rel as u32 >> 1) & 1) << 24) | raw::a32_branch(rel & !2)
+        }
+        (Mode::Thumb, true) => {
+            0xF000_D000 | raw::t32_branch(target as isize - (insn_addr + 4) as isize)
+        }
+        // `blx` to Arm is relative to `PC` aligned down to 4 bytes
+        (Mode::Thumb, false) => {
+            0xF000_C000 | raw::t32_branch(target as isize - ((insn_addr + 4) & !3) as isize)
+        }
+    }
+}
+
+impl Subroutine for Routine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn entry_bits(&self) -> usize {
+        (self.mode == Mode::Thumb) as usize
+    }
+
+    fn constants(&self) -> &[u8] {
+        &self.constants
+    }
+
+    fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    fn process(
+        &self,
+        assembler: &impl Assembler,
+        abs_addr: usize,
+        code_offset: usize,
+        bytes: &mut [u8],
+    ) {
+        for op in &self.post_ops {
+            op.process(assembler, abs_addr, code_offset, bytes);
+        }
+    }
+}
+
+pub enum Op {
+    Routine {
+        insn_offset: usize,
+        label: String,
+        link: bool,
+        mode: Mode,
+    },
+    Const {
+        insn_offset: usize,
+        const_offset: usize,
+        mode: Mode,
+    },
+    GlobalConst {
+        insn_offset: usize,
+        const_offset: usize,
+        mode: Mode,
+    },
+}
+
+impl PostOp for Op {
+    fn process(
+        &self,
+        assembler: &impl Assembler,
+        abs_addr: usize,
+        code_offset: usize,
+        bytes: &mut [u8],
+    ) {
+        let (insn_offset, mode) = match self {
+            Self::Routine {
+                insn_offset, mode, ..
+            }
+            | Self::Const {
+                insn_offset, mode, ..
+            }
+            | Self::GlobalConst {
+                insn_offset, mode, ..
+            } => (code_offset + *insn_offset, *mode),
+        };
+        let insn_addr = abs_addr + insn_offset;
+        let insn = match self {
+            Self::Routine { label, link, .. } => {
+                let Some(target) = assembler.get_label_address(label) else {
+                    panic!("Tried to branch to non-existent label");
+                };
+                if *link {
+                    call(mode, insn_addr, target)
+                } else {
+                    assert!(
+                        (target & 1 != 0) == (mode == Mode::Thumb),
+                        "Tried to branch to routine using the other instruction set"
+                    );
+                    let rel = (target & !1) as isize - insn_addr as isize;
+                    match mode {
+                        Mode::Arm => 0xEA00_0000 | raw::a32_branch(rel - 8),
+                        Mode::Thumb => 0xF000_9000 | raw::t32_branch(rel - 4),
+                    }
+                }
+            }
+            Self::Const { const_offset, .. } | Self::GlobalConst { const_offset, .. } => {
+                let base = if let Self::Const { .. } = self {
+                    abs_addr
+                } else {
+                    assembler.global_const_address()
+                };
+                let rel = (base + const_offset * 4) as isize - literal_pc(mode, insn_addr) as isize;
+                let insn = match mode {
+                    Mode::Arm => raw::read_le_32(bytes, insn_offset),
+                    Mode::Thumb => raw::read_t32(bytes, insn_offset),
+                };
+                insn | raw::literal(rel)
+            }
+        };
+        match mode {
+            Mode::Arm => raw::write_le_32(bytes, insn_offset, insn),
+            Mode::Thumb => raw::write_t32(bytes, insn_offset, insn),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a32::asm::Asm;
+
+    fn assemble(mode: Mode, build: impl FnOnce(&mut Routine)) -> Vec<u8> {
+        let mut routine = Routine::with_mode("f".to_string(), mode);
+        build(&mut routine);
+        routine.finalize();
+        routine.code
+    }
+
+    fn words(code: &[u8]) -> Vec<u32> {
+        code.chunks(4).map(|it| raw::read_le_32(it, 0)).collect()
+    }
+
+    /// Returns the halfwords of T32 code, where a 32-bit instruction is its first halfword
+    /// followed by its second one
+    fn halves(code: &[u8]) -> Vec<u16> {
+        code.chunks(2)
+            .map(|it| u16::from_le_bytes([it[0], it[1]]))
+            .collect()
+    }
+
+    fn data_processing(r: &mut Routine) {
+        r.mov_reg(Reg::R0, Reg::R1);
+        r.mov_imm(Reg::R0, 255);
+        r.mov_imm(Reg::R0, u32::MAX);
+        r.mov_imm(Reg::R0, 0x5678_1234);
+        r.add_reg(Reg::R0, Reg::R1, Reg::R2);
+        r.adds_reg(Reg::R0, Reg::R1, Reg::R2);
+        r.sub_reg(Reg::R0, Reg::R1, Reg::R2);
+        r.rsb_imm(Reg::R0, Reg::R1, 0);
+        r.and_imm(Reg::R0, Reg::R1, 0xFF00);
+        r.orr_reg(Reg::R0, Reg::R1, Reg::R2);
+        r.bic_reg(Reg::R0, Reg::R1, Reg::R2);
+        r.cmp_reg(Reg::R0, Reg::R1);
+        r.cmp_imm(Reg::R0, 1);
+        r.tst_imm(Reg::R0, 0x8000_0000);
+        r.mul(Reg::R0, Reg::R1, Reg::R2);
+    }
+
+    fn memory_and_control(r: &mut Routine) {
+        r.ldr_imm(Reg::R0, Reg::R1, 4);
+        r.ldr_imm(Reg::R0, Reg::R1, -4);
+        r.str_imm(Reg::R0, Reg::SP, 8);
+        r.push(&[Reg::R4, Reg::LR]);
+        r.pop(&[Reg::R4, Reg::PC]);
+        r.push(&[Reg::R0]);
+        r.pop(&[Reg::R0]);
+        r.ret();
+        r.blx_reg(Reg::R3);
+        r.svc(0);
+        r.nop();
+    }
+
+    #[test]
+    fn a32_encodings() {
+        let code = assemble(Mode::Arm, data_processing);
+        assert_eq!(
+            words(&code),
+            [
+                0xE1A00001, 0xE3A000FF, 0xE3E00000, 0xE3010234, 0xE3450678, 0xE0810002, 0xE0910002,
+                0xE0410002, 0xE2610000, 0xE2010CFF, 0xE1810002, 0xE1C10002, 0xE1500001, 0xE3500001,
+                0xE3100102, 0xE0000291,
+            ]
+        );
+        let code = assemble(Mode::Arm, memory_and_control);
+        assert_eq!(
+            words(&code),
+            [
+                0xE5910004, 0xE5110004, 0xE58D0008, 0xE92D4010, 0xE8BD8010, 0xE52D0004, 0xE49D0004,
+                0xE12FFF1E, 0xE12FFF33, 0xEF000000, 0xE320F000,
+            ]
+        );
+    }
+
+    #[test]
+    fn t32_encodings() {
+        // All ones is a T32 modified immediate, so it takes a `mov` instead of the A32 `mvn`
+        let code = assemble(Mode::Thumb, data_processing);
+        assert_eq!(
+            halves(&code),
+            [
+                0xEA4F, 0x0001, 0xF04F, 0x00FF, 0xF04F, 0x30FF, 0xF241, 0x2034, 0xF2C5, 0x6078,
+                0xEB01, 0x0002, 0xEB11, 0x0002, 0xEBA1, 0x0002, 0xF1C1, 0x0000, 0xF401, 0x407F,
+                0xEA41, 0x0002, 0xEA21, 0x0002, 0xEBB0, 0x0F01, 0xF1B0, 0x0F01, 0xF010, 0x4F00,
+                0xFB01, 0xF002,
+            ]
+        );
+        let code = assemble(Mode::Thumb, memory_and_control);
+        assert_eq!(
+            halves(&code),
+            [
+                0xF8D1, 0x0004, 0xF851, 0x0C04, 0xF8CD, 0x0008, 0xE92D, 0x4010, 0xE8BD, 0x8010,
+                0xF84D, 0x0D04, 0xF85D, 0x0B04, 0x4770, 0x4798, 0xDF00, 0xF3AF, 0x8000,
+            ]
+        );
+    }
+
+    fn branches(r: &mut Routine) {
+        let forward = r.new_label();
+        let backward = r.new_label();
+        r.bind(backward);
+        r.nop();
+        r.b_cond(Cond::EQ, forward);
+        r.nop();
+        r.b_cond(Cond::NE, backward);
+        r.b(backward);
+        r.bind(forward);
+        r.b(forward);
+    }
+
+    #[test]
+    fn branch_fixups() {
+        let code = assemble(Mode::Arm, branches);
+        assert_eq!(
+            words(&code),
+            [0xE320F000, 0x0A000002, 0xE320F000, 0x1AFFFFFB, 0xEAFFFFFA, 0xEAFFFFFE]
+        );
+        let code = assemble(Mode::Thumb, branches);
+        assert_eq!(
+            halves(&code),
+            [
+                0xF3AF, 0x8000, 0xF000, 0x8006, 0xF3AF, 0x8000, 0xF47F, 0xAFF8, 0xF7FF, 0xBFF6,
+                0xF7FF, 0xBFFE,
+            ]
+        );
+    }
+
+    /// Arm routine calling a Thumb routine that calls an Arm routine, each loading a constant
+    ///
+    /// `arm_main(x) = (x + 5)² + 0x11223344`
+    fn interworking() -> Asm {
+        let mut asm = Asm::default();
+        let global = asm.const_32(0x1122_3344);
+        let mut main = Routine::new("arm_main".to_string());
+        main.push(&[Reg::R4, Reg::LR]);
+        main.ldr_global_const(Reg::R4, global);
+        main.br_link("thumb_helper".to_string());
+        main.add_reg(Reg::R0, Reg::R0, Reg::R4);
+        main.pop(&[Reg::R4, Reg::PC]);
+        asm.push_routine(main);
+        let mut helper = Routine::thumb("thumb_helper".to_string());
+        let five = helper.const_32(5);
+        helper.push(&[Reg::LR]);
+        helper.ldr_const(Reg::R1, five);
+        helper.add_reg(Reg::R0, Reg::R0, Reg::R1);
+        helper.br_link("arm_leaf".to_string());
+        helper.pop(&[Reg::PC]);
+        asm.push_routine(helper);
+        let mut leaf = Routine::new("arm_leaf".to_string());
+        leaf.mul(Reg::R0, Reg::R0, Reg::R0);
+        leaf.ret();
+        asm.push_routine(leaf);
+        asm
+    }
+
+    #[test]
+    fn interworking_fixups() {
+        let (code, vtable) = interworking().virtual_jit().unwrap();
+        // Thumb entries have bit 0 set, Arm entries are word aligned
+        assert_eq!(vtable["arm_leaf"], 4);
+        assert_eq!(vtable["thumb_helper"], 16 | 1);
+        assert_eq!(vtable["arm_main"], 36);
+        assert_eq!(words(&code[..16]), [0x11223344, 0xE0000090, 0xE12FFF1E, 5]);
+        // ldr.w r1, [pc, #-12] and blx to `arm_leaf`
+        assert_eq!(
+            halves(&code[16..36]),
+            [0xF84D, 0xED04, 0xF85F, 0x100C, 0xEB00, 0x0001, 0xF7FF, 0xEFF2, 0xF85D, 0xFB04]
+        );
+        // ldr r4, [pc, #-48] and blx with H set to `thumb_helper`
+        assert_eq!(
+            words(&code[36..]),
+            [0xE92D4010, 0xE51F4030, 0xFAFFFFF7, 0xE0800004, 0xE8BD8010]
+        );
+    }
+
+    #[test]
+    fn thumb_entry_in_vtable() {
+        let mut asm = Asm::default();
+        let mut thumb = Routine::thumb("thumb".to_string());
+        thumb.ret();
+        asm.push_routine(thumb);
+        let mut arm = Routine::new("arm".to_string());
+        arm.ret();
+        asm.push_routine(arm);
+        // The code is never run, so this works on any host
+        let vtable = asm.jit().unwrap();
+        let arm = vtable.lookup("arm").unwrap() as usize;
+        let thumb = vtable.lookup("thumb").unwrap() as usize;
+        assert_eq!(arm & 3, 0);
+        assert_eq!(thumb & 1, 1);
+        assert_eq!(
+            thumb - 1,
+            arm + 4,
+            "Thumb routine is not placed behind the Arm routine"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Tried to branch to routine using the other instruction set")]
+    fn branch_across_instruction_sets() {
+        let mut asm = Asm::default();
+        let mut arm = Routine::new("arm".to_string());
+        arm.br("thumb".to_string());
+        asm.push_routine(arm);
+        let mut thumb = Routine::thumb("thumb".to_string());
+        thumb.ret();
+        asm.push_routine(thumb);
+        asm.virtual_jit();
+    }
+}
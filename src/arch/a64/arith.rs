@@ -0,0 +1,385 @@
+use super::{
+    operand::{Extend, Shift},
+    reg::{is_64_bit, zero_reg, Reg},
+    routine::Routine,
+};
+
+/// Opcodes of the add and subtract instructions in bits 29 and 30
+const ADD: u32 = 0b00 << 29;
+const ADDS: u32 = 0b01 << 29;
+const SUB: u32 = 0b10 << 29;
+const SUBS: u32 = 0b11 << 29;
+
+impl Routine {
+    /// Adds `lhs` and the shifted `rhs`
+    pub fn add_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.add_sub_shifted(ADD, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Adds `lhs` and the shifted `rhs` setting the flags according to the result
+    pub fn adds_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.add_sub_shifted(ADDS, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Subtracts the shifted `rhs` from `lhs`
+    pub fn sub_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.add_sub_shifted(SUB, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Subtracts the shifted `rhs` from `lhs` setting the flags according to the result
+    pub fn subs_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.add_sub_shifted(SUBS, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Adds `lhs` and `rhs` setting the flags according to the result
+    pub fn adds_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.adds_shifted(dst_reg, lhs, rhs, Shift::LSL, 0);
+    }
+
+    /// Subtracts `rhs` from `lhs` setting the flags according to the result
+    pub fn subs_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.subs_shifted(dst_reg, lhs, rhs, Shift::LSL, 0);
+    }
+
+    /// Adds `lhs` and the extended `rhs` shifted left by up to 4
+    ///
+    /// `X31` is SP for `dst_reg` and `lhs`. `rhs` is 64-bit for `UXTX` and `SXTX` on 64-bit
+    /// registers and 32-bit otherwise.
+    pub fn add_extended(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, extend: Extend, amount: u8) {
+        self.add_sub_extended(ADD, dst_reg, lhs, rhs, extend, amount);
+    }
+
+    /// Adds `lhs` and the extended `rhs` setting the flags, where `X31` is SP only for `lhs`
+    pub fn adds_extended(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, extend: Extend, amount: u8) {
+        self.add_sub_extended(ADDS, dst_reg, lhs, rhs, extend, amount);
+    }
+
+    /// Subtracts the extended `rhs` shifted left by up to 4 from `lhs`, where `X31` is SP for
+    /// `dst_reg` and `lhs`
+    pub fn sub_extended(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, extend: Extend, amount: u8) {
+        self.add_sub_extended(SUB, dst_reg, lhs, rhs, extend, amount);
+    }
+
+    /// Subtracts the extended `rhs` from `lhs` setting the flags, where `X31` is SP only for `lhs`
+    pub fn subs_extended(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, extend: Extend, amount: u8) {
+        self.add_sub_extended(SUBS, dst_reg, lhs, rhs, extend, amount);
+    }
+
+    /// Adds `lhs`, `rhs` and the carry flag
+    pub fn adc(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x1A000000 | ADD, dst_reg, lhs, rhs);
+    }
+
+    /// Adds `lhs`, `rhs` and the carry flag setting the flags according to the result
+    pub fn adcs(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x1A000000 | ADDS, dst_reg, lhs, rhs);
+    }
+
+    /// Subtracts `rhs` and the inverted carry flag from `lhs`
+    pub fn sbc(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x1A000000 | SUB, dst_reg, lhs, rhs);
+    }
+
+    /// Subtracts `rhs` and the inverted carry flag from `lhs` setting the flags according to the
+    /// result
+    pub fn sbcs(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x1A000000 | SUBS, dst_reg, lhs, rhs);
+    }
+
+    /// Negates the value of the source register
+    pub fn neg(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.sub_shifted(dst_reg, zero_reg(dst_reg), src_reg, Shift::LSL, 0);
+    }
+
+    /// Negates the value of the source register setting the flags according to the result
+    pub fn negs(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.subs_shifted(dst_reg, zero_reg(dst_reg), src_reg, Shift::LSL, 0);
+    }
+
+    /// Computes `acc + lhs * rhs`
+    pub fn madd(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, acc: Reg) {
+        self.mul_op(0x1B000000, dst_reg, lhs, rhs, acc);
+    }
+
+    /// Computes `acc - lhs * rhs`
+    pub fn msub(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, acc: Reg) {
+        self.mul_op(0x1B008000, dst_reg, lhs, rhs, acc);
+    }
+
+    /// Computes the negated product of `lhs` and `rhs`
+    pub fn mneg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.msub(dst_reg, lhs, rhs, zero_reg(dst_reg));
+    }
+
+    /// Multiplies the signed 32-bit registers into the 64-bit destination register
+    pub fn smull(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.long_mul(0x9B200000, dst_reg, lhs, rhs, Reg::X31);
+    }
+
+    /// Multiplies the unsigned 32-bit registers into the 64-bit destination register
+    pub fn umull(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.long_mul(0x9BA00000, dst_reg, lhs, rhs, Reg::X31);
+    }
+
+    /// Computes `acc + lhs * rhs` of the signed 32-bit registers with a 64-bit result
+    pub fn smaddl(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, acc: Reg) {
+        self.long_mul(0x9B200000, dst_reg, lhs, rhs, acc);
+    }
+
+    /// Computes `acc + lhs * rhs` of the unsigned 32-bit registers with a 64-bit result
+    pub fn umaddl(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, acc: Reg) {
+        self.long_mul(0x9BA00000, dst_reg, lhs, rhs, acc);
+    }
+
+    /// Computes the upper 64 bits of the signed 128-bit product of the 64-bit registers
+    pub fn smulh(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        assert!(is_64_bit(dst_reg), "Registers must be 64-bit");
+        self.mul_op(0x1B407C00, dst_reg, lhs, rhs, Reg::X31);
+    }
+
+    /// Computes the upper 64 bits of the unsigned 128-bit product of the 64-bit registers
+    pub fn umulh(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        assert!(is_64_bit(dst_reg), "Registers must be 64-bit");
+        self.mul_op(0x1BC07C00, dst_reg, lhs, rhs, Reg::X31);
+    }
+
+    /// Divides `lhs` by `rhs` as signed integers rounding towards zero
+    ///
+    /// Dividing by zero results in zero and dividing the minimum value by -1 in the minimum value.
+    pub fn sdiv(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x1AC00C00, dst_reg, lhs, rhs);
+    }
+
+    /// Divides `lhs` by `rhs` as unsigned integers, where dividing by zero results in zero
+    pub fn udiv(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x1AC00800, dst_reg, lhs, rhs);
+    }
+
+    fn add_sub_shifted(
+        &mut self,
+        opcode: u32,
+        dst_reg: Reg,
+        lhs: Reg,
+        rhs: Reg,
+        shift: Shift,
+        amount: u8,
+    ) {
+        assert!(shift != Shift::ROR, "Add and subtract cannot rotate");
+        let bits = if is_64_bit(dst_reg) { 64 } else { 32 };
+        assert!(amount < bits, "Shift amount exceeds the register size");
+        self.reg_op(
+            0x0B000000 | opcode | ((shift as u32) << 22) | ((amount as u32) << 10),
+            dst_reg,
+            lhs,
+            rhs,
+        );
+    }
+
+    fn add_sub_extended(
+        &mut self,
+        opcode: u32,
+        dst_reg: Reg,
+        lhs: Reg,
+        rhs: Reg,
+        extend: Extend,
+        amount: u8,
+    ) {
+        let bits_64 = is_64_bit(dst_reg);
+        assert!(
+            amount <= 4,
+            "Extended operands can only be shifted by up to 4"
+        );
+        assert!(
+            bits_64 == is_64_bit(lhs),
+            "Destination and first source register must be of equal size"
+        );
+        let rhs_64 = bits_64 && matches!(extend, Extend::UXTX | Extend::SXTX);
+        assert!(
+            is_64_bit(rhs) == rhs_64,
+            "Second source register must be 64-bit exactly for UXTX and SXTX on 64-bit registers"
+        );
+        self.int_insn(
+            0x0B200000
+                | opcode
+                | ((bits_64 as u32) << 31)
+                | ((rhs as u32 & 0x1F) << 16)
+                | ((extend as u32) << 13)
+                | ((amount as u32) << 10)
+                | ((lhs as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    /// Emits a data-processing instruction with three sources of the same size
+    fn mul_op(&mut self, opcode: u32, dst_reg: Reg, lhs: Reg, rhs: Reg, acc: Reg) {
+        assert!(
+            is_64_bit(acc) == is_64_bit(dst_reg),
+            "All registers must be of equal size"
+        );
+        self.reg_op(opcode | ((acc as u32 & 0x1F) << 10), dst_reg, lhs, rhs);
+    }
+
+    /// Emits a widening multiplication of 32-bit sources into 64-bit registers
+    fn long_mul(&mut self, opcode: u32, dst_reg: Reg, lhs: Reg, rhs: Reg, acc: Reg) {
+        assert!(
+            is_64_bit(dst_reg) && is_64_bit(acc),
+            "Destination and accumulator must be 64-bit"
+        );
+        assert!(
+            !is_64_bit(lhs) && !is_64_bit(rhs),
+            "Source registers must be 32-bit"
+        );
+        self.int_insn(
+            opcode
+                | ((rhs as u32 & 0x1F) << 16)
+                | ((acc as u32 & 0x1F) << 10)
+                | ((lhs as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::routine::tests::assemble;
+
+    #[test]
+    fn shifted_register() {
+        let words = assemble(|r| {
+            r.add_shifted(Reg::X0, Reg::X1, Reg::X2, Shift::LSL, 12);
+            r.adds_shifted(Reg::W3, Reg::W4, Reg::W5, Shift::LSR, 31);
+            r.sub_shifted(Reg::X6, Reg::X7, Reg::X8, Shift::ASR, 63);
+            r.subs_shifted(Reg::W9, Reg::W10, Reg::W11, Shift::LSL, 0);
+            r.adds_reg(Reg::X12, Reg::X13, Reg::X14);
+            r.subs_reg(Reg::W15, Reg::W16, Reg::W17);
+            r.neg(Reg::X12, Reg::X13);
+            r.negs(Reg::W14, Reg::W15);
+            r.add_reg(Reg::X0, Reg::X1, Reg::X2);
+            r.sub_reg(Reg::W3, Reg::W4, Reg::W5);
+            r.cmp_reg(Reg::X1, Reg::X2);
+            r.add_imm12(Reg::X9, Reg::X31, 4095);
+            r.sub_imm12(Reg::X31, Reg::X31, 16);
+        });
+        assert_eq!(
+            words,
+            [
+                0x8B023020, // add x0, x1, x2, lsl #12
+                0x2B457C83, // adds w3, w4, w5, lsr #31
+                0xCB88FCE6, // sub x6, x7, x8, asr #63
+                0x6B0B0149, // subs w9, w10, w11
+                0xAB0E01AC, // adds x12, x13, x14
+                0x6B11020F, // subs w15, w16, w17
+                0xCB0D03EC, // neg x12, x13
+                0x6B0F03EE, // negs w14, w15
+                0x8B020020, // add x0, x1, x2
+                0x4B050083, // sub w3, w4, w5
+                0xEB02003F, // cmp x1, x2
+                0x913FFFE9, // add x9, sp, #4095
+                0xD10043FF, // sub sp, sp, #16
+            ]
+        );
+    }
+
+    #[test]
+    fn extended_register() {
+        let words = assemble(|r| {
+            r.add_extended(Reg::X31, Reg::X31, Reg::W1, Extend::UXTW, 4);
+            r.adds_extended(Reg::X2, Reg::X31, Reg::X3, Extend::SXTX, 0);
+            r.sub_extended(Reg::W4, Reg::W31, Reg::W5, Extend::UXTB, 0);
+            r.subs_extended(Reg::X6, Reg::X7, Reg::W8, Extend::SXTH, 2);
+            r.add_extended(Reg::X9, Reg::X10, Reg::W11, Extend::SXTW, 1);
+        });
+        assert_eq!(
+            words,
+            [
+                0x8B2153FF, // add sp, sp, w1, uxtw #4
+                0xAB23E3E2, // adds x2, sp, x3, sxtx
+                0x4B2503E4, // sub w4, wsp, w5, uxtb
+                0xEB28A8E6, // subs x6, x7, w8, sxth #2
+                0x8B2BC549, // add x9, x10, w11, sxtw #1
+            ]
+        );
+    }
+
+    #[test]
+    fn carry_multiply_and_divide() {
+        let words = assemble(|r| {
+            r.adc(Reg::X0, Reg::X1, Reg::X2);
+            r.adcs(Reg::W3, Reg::W4, Reg::W5);
+            r.sbc(Reg::X6, Reg::X7, Reg::X8);
+            r.sbcs(Reg::W9, Reg::W10, Reg::W11);
+            r.madd(Reg::X0, Reg::X1, Reg::X2, Reg::X3);
+            r.msub(Reg::W4, Reg::W5, Reg::W6, Reg::W7);
+            r.mneg(Reg::X8, Reg::X9, Reg::X10);
+            r.mul(Reg::X6, Reg::X7, Reg::X8);
+            r.smull(Reg::X11, Reg::W12, Reg::W13);
+            r.umull(Reg::X14, Reg::W15, Reg::W16);
+            r.smaddl(Reg::X17, Reg::W18, Reg::W19, Reg::X20);
+            r.umaddl(Reg::X21, Reg::W22, Reg::W23, Reg::X24);
+            r.smulh(Reg::X25, Reg::X26, Reg::X27);
+            r.umulh(Reg::X28, Reg::X29, Reg::X30);
+            r.sdiv(Reg::W0, Reg::W1, Reg::W2);
+            r.udiv(Reg::X3, Reg::X4, Reg::X5);
+        });
+        assert_eq!(
+            words,
+            [
+                0x9A020020, // adc x0, x1, x2
+                0x3A050083, // adcs w3, w4, w5
+                0xDA0800E6, // sbc x6, x7, x8
+                0x7A0B0149, // sbcs w9, w10, w11
+                0x9B020C20, // madd x0, x1, x2, x3
+                0x1B069CA4, // msub w4, w5, w6, w7
+                0x9B0AFD28, // mneg x8, x9, x10
+                0x9B087CE6, // mul x6, x7, x8
+                0x9B2D7D8B, // smull x11, w12, w13
+                0x9BB07DEE, // umull x14, w15, w16
+                0x9B335251, // smaddl x17, w18, w19, x20
+                0x9BB762D5, // umaddl x21, w22, w23, x24
+                0x9B5B7F59, // smulh x25, x26, x27
+                0x9BDE7FBC, // umulh x28, x29, x30
+                0x1AC20C20, // sdiv w0, w1, w2
+                0x9AC50883, // udiv x3, x4, x5
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Add and subtract cannot rotate")]
+    fn rotated_operand() {
+        assemble(|r| r.add_shifted(Reg::X0, Reg::X1, Reg::X2, Shift::ROR, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Shift amount exceeds the register size")]
+    fn shift_out_of_range() {
+        assemble(|r| r.sub_shifted(Reg::W0, Reg::W1, Reg::W2, Shift::LSL, 32));
+    }
+
+    #[test]
+    #[should_panic(expected = "Extended operands can only be shifted by up to 4")]
+    fn extended_shift_out_of_range() {
+        assemble(|r| r.add_extended(Reg::X0, Reg::X1, Reg::W2, Extend::UXTW, 5));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Second source register must be 64-bit exactly for UXTX and SXTX on 64-bit registers"
+    )]
+    fn extended_operand_size() {
+        assemble(|r| r.add_extended(Reg::X0, Reg::X1, Reg::X2, Extend::UXTW, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Source registers must be 32-bit")]
+    fn long_multiply_of_64_bit_registers() {
+        assemble(|r| r.smull(Reg::X0, Reg::X1, Reg::X2));
+    }
+
+    #[test]
+    #[should_panic(expected = "All registers must be of equal size")]
+    fn mixed_register_sizes() {
+        assemble(|r| r.madd(Reg::X0, Reg::X1, Reg::X2, Reg::W3));
+    }
+}
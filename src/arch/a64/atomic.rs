@@ -0,0 +1,608 @@
+use super::{
+    cond::Cond,
+    reg::{is_64_bit, low_32, Reg},
+    routine::Routine,
+};
+
+/// Opcodes of the exclusive and ordered accesses with `Rt2` set to 31
+const LDXR: u32 = 0x08407C00;
+const LDAXR: u32 = 0x0840FC00;
+const STXR: u32 = 0x08007C00;
+const STLXR: u32 = 0x0800FC00;
+const LDAR: u32 = 0x08C0FC00;
+const STLR: u32 = 0x0880FC00;
+const CAS: u32 = 0x08A07C00;
+const CASP: u32 = 0x08207C00;
+
+/// Bits of the compare and swap instructions selecting acquire and release semantics
+const CAS_ACQUIRE: u32 = 1 << 22;
+const CAS_RELEASE: u32 = 1 << 15;
+
+/// Temporary register of the helpers holding the loaded value
+const TEMP: Reg = Reg::X16;
+
+/// Register receiving the status of store-exclusives in the helpers
+const STATUS: Reg = Reg::W17;
+
+/// Memory ordering of an atomic access
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Relaxed,
+    /// Later accesses are not observed before this one
+    Acquire,
+    /// Earlier accesses are observed before this one
+    Release,
+    /// Both `Acquire` and `Release`, which is sequentially consistent for read-modify-write
+    /// instructions
+    AcqRel,
+}
+
+impl Order {
+    fn acquire(self) -> bool {
+        matches!(self, Self::Acquire | Self::AcqRel)
+    }
+
+    fn release(self) -> bool {
+        matches!(self, Self::Release | Self::AcqRel)
+    }
+}
+
+/// Shareability domain and access types a barrier orders
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Barrier {
+    /// Outer shareable, loads before loads and stores
+    OSHLD = 0b0001,
+    /// Outer shareable, stores before stores
+    OSHST = 0b0010,
+    /// Outer shareable, all accesses
+    OSH = 0b0011,
+    /// Non-shareable, loads before loads and stores
+    NSHLD = 0b0101,
+    /// Non-shareable, stores before stores
+    NSHST = 0b0110,
+    /// Non-shareable, all accesses
+    NSH = 0b0111,
+    /// Inner shareable, loads before loads and stores
+    ISHLD = 0b1001,
+    /// Inner shareable, stores before stores
+    ISHST = 0b1010,
+    /// Inner shareable, all accesses, which covers all threads of a process
+    ISH = 0b1011,
+    /// Full system, loads before loads and stores
+    LD = 0b1101,
+    /// Full system, stores before stores
+    ST = 0b1110,
+    /// Full system, all accesses
+    SY = 0b1111,
+}
+
+/// Read-modify-write operation of the LSE atomics, combining the value in memory with a register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtomicOp {
+    Add,
+    /// Clears the bits set in the register
+    Clr,
+    Eor,
+    /// Sets the bits set in the register
+    Set,
+    /// Replaces the value in memory by the register
+    Swp,
+}
+
+impl AtomicOp {
+    /// Returns the `o3:opc` bits of the LSE instruction
+    fn encoding(self) -> u32 {
+        match self {
+            Self::Add => 0b0000 << 12,
+            Self::Clr => 0b0001 << 12,
+            Self::Eor => 0b0010 << 12,
+            Self::Set => 0b0011 << 12,
+            Self::Swp => 0b1000 << 12,
+        }
+    }
+}
+
+impl Routine {
+    /// Loads from the address in `base` marking it for exclusive access
+    pub fn ldxr(&mut self, dst_reg: Reg, base: Reg) {
+        self.exclusive(LDXR, Reg::X31, dst_reg, base);
+    }
+
+    /// Loads from the address in `base` with acquire semantics marking it for exclusive access
+    pub fn ldaxr(&mut self, dst_reg: Reg, base: Reg) {
+        self.exclusive(LDAXR, Reg::X31, dst_reg, base);
+    }
+
+    /// Stores to the address in `base` if it is still marked for exclusive access, setting the
+    /// 32-bit `status` to 0 on success and 1 otherwise
+    pub fn stxr(&mut self, status: Reg, src_reg: Reg, base: Reg) {
+        self.store_exclusive(STXR, status, src_reg, base);
+    }
+
+    /// Same as `stxr` with release semantics
+    pub fn stlxr(&mut self, status: Reg, src_reg: Reg, base: Reg) {
+        self.store_exclusive(STLXR, status, src_reg, base);
+    }
+
+    /// Loads from the address in `base` with acquire semantics
+    pub fn ldar(&mut self, dst_reg: Reg, base: Reg) {
+        self.exclusive(LDAR, Reg::X31, dst_reg, base);
+    }
+
+    /// Stores to the address in `base` with release semantics
+    pub fn stlr(&mut self, src_reg: Reg, base: Reg) {
+        self.exclusive(STLR, Reg::X31, src_reg, base);
+    }
+
+    /// Clears the exclusive access mark of the last `ldxr`
+    pub fn clrex(&mut self) {
+        self.int_insn(0xD5033F5F);
+    }
+
+    /// Atomically adds `src_reg` to the value at the address in `base` loading the old value into
+    /// the destination register
+    ///
+    /// Requires LSE like the other single instruction atomics.
+    pub fn ldadd(&mut self, src_reg: Reg, dst_reg: Reg, base: Reg, order: Order) {
+        self.atomic_op(AtomicOp::Add, src_reg, dst_reg, base, order);
+    }
+
+    /// Atomically clears the bits of `src_reg` in the value at the address in `base` loading the
+    /// old value into the destination register
+    pub fn ldclr(&mut self, src_reg: Reg, dst_reg: Reg, base: Reg, order: Order) {
+        self.atomic_op(AtomicOp::Clr, src_reg, dst_reg, base, order);
+    }
+
+    /// Atomically computes the exclusive or of `src_reg` and the value at the address in `base`
+    /// loading the old value into the destination register
+    pub fn ldeor(&mut self, src_reg: Reg, dst_reg: Reg, base: Reg, order: Order) {
+        self.atomic_op(AtomicOp::Eor, src_reg, dst_reg, base, order);
+    }
+
+    /// Atomically sets the bits of `src_reg` in the value at the address in `base` loading the old
+    /// value into the destination register
+    pub fn ldset(&mut self, src_reg: Reg, dst_reg: Reg, base: Reg, order: Order) {
+        self.atomic_op(AtomicOp::Set, src_reg, dst_reg, base, order);
+    }
+
+    /// Atomically replaces the value at the address in `base` by `src_reg` loading the old value
+    /// into the destination register
+    pub fn swp(&mut self, src_reg: Reg, dst_reg: Reg, base: Reg, order: Order) {
+        self.atomic_op(AtomicOp::Swp, src_reg, dst_reg, base, order);
+    }
+
+    /// Atomically stores `new` at the address in `base` if the value there equals `expected`,
+    /// loading the old value into `expected` in either case
+    pub fn cas(&mut self, expected: Reg, new: Reg, base: Reg, order: Order) {
+        assert!(self.features.lse, "Compare and swap requires LSE");
+        assert!(
+            is_64_bit(expected) == is_64_bit(new),
+            "Both registers must be of equal size"
+        );
+        self.exclusive(CAS | ordering_bits(order), expected, new, base);
+    }
+
+    /// Same as `cas` for the pairs of consecutive registers starting at the even `expected` and
+    /// `new`, comparing and storing both values at once
+    pub fn casp(&mut self, expected: Reg, new: Reg, base: Reg, order: Order) {
+        assert!(self.features.lse, "Compare and swap requires LSE");
+        assert!(
+            is_64_bit(expected) == is_64_bit(new),
+            "Both registers must be of equal size"
+        );
+        assert!(
+            (expected as u32 | new as u32) & 1 == 0,
+            "Register pairs must start at an even register"
+        );
+        assert!(
+            (expected as u32 & 0x1F) < 30 && (new as u32 & 0x1F) < 30,
+            "Register pairs cannot include the zero register"
+        );
+        assert!(is_64_bit(base), "Base register must be 64-bit");
+        self.int_insn(
+            CASP | ordering_bits(order)
+                | ((is_64_bit(new) as u32) << 30)
+                | ((expected as u32 & 0x1F) << 16)
+                | ((base as u32 & 0x1F) << 5)
+                | (new as u32 & 0x1F),
+        );
+    }
+
+    /// Data memory barrier ordering the accesses before it with those after it
+    pub fn dmb(&mut self, barrier: Barrier) {
+        self.int_insn(0xD50330BF | ((barrier as u32) << 8));
+    }
+
+    /// Data synchronization barrier completing the accesses before it before any instruction
+    /// after it executes
+    pub fn dsb(&mut self, barrier: Barrier) {
+        self.int_insn(0xD503309F | ((barrier as u32) << 8));
+    }
+
+    /// Instruction synchronization barrier refetching the instructions after it
+    pub fn isb(&mut self) {
+        self.int_insn(0xD5033FDF);
+    }
+
+    /// Atomically applies the operation to the value at the address in `base` loading the old
+    /// value into the destination register
+    ///
+    /// Uses the LSE instruction if enabled and a loop of exclusive accesses otherwise, which
+    /// clobbers `X16` and `X17`. The destination register must differ from the other registers.
+    pub fn atomic_fetch(
+        &mut self,
+        op: AtomicOp,
+        src_reg: Reg,
+        dst_reg: Reg,
+        base: Reg,
+        order: Order,
+    ) {
+        if self.features.lse {
+            self.atomic_op(op, src_reg, dst_reg, base, order);
+            return;
+        }
+        assert!(
+            dst_reg as u32 & 0x1F != src_reg as u32 & 0x1F
+                && dst_reg as u32 & 0x1F != base as u32 & 0x1F,
+            "Destination register must differ from the other registers"
+        );
+        check_scratch(&[src_reg, dst_reg, base]);
+        let temp = sized(TEMP, dst_reg);
+        let retry = self.new_label();
+        self.bind(retry);
+        self.load_exclusive(order, dst_reg, base);
+        let value = match op {
+            AtomicOp::Add => {
+                self.add_reg(temp, dst_reg, src_reg);
+                temp
+            }
+            AtomicOp::Clr => {
+                self.bic_reg(temp, dst_reg, src_reg);
+                temp
+            }
+            AtomicOp::Eor => {
+                self.eor_reg(temp, dst_reg, src_reg);
+                temp
+            }
+            AtomicOp::Set => {
+                self.orr_reg(temp, dst_reg, src_reg);
+                temp
+            }
+            AtomicOp::Swp => src_reg,
+        };
+        self.store_exclusive_ordered(order, value, base);
+        self.cbnz(STATUS, retry);
+    }
+
+    /// Atomically stores `new` at the address in `base` if the value there equals `expected`,
+    /// loading the old value into `expected` and leaving the flags as `EQ` exactly on success
+    ///
+    /// Uses `cas` if LSE is enabled and a loop of exclusive accesses otherwise. Both clobber
+    /// `X16` and the loop also `X17`.
+    pub fn compare_exchange(&mut self, expected: Reg, new: Reg, base: Reg, order: Order) {
+        check_scratch(&[expected, new, base]);
+        let temp = sized(TEMP, expected);
+        if self.features.lse {
+            self.mov_reg(temp, expected);
+            self.cas(temp, new, base, order);
+            self.cmp_reg(temp, expected);
+            self.mov_reg(expected, temp);
+            return;
+        }
+        let retry = self.new_label();
+        let fail = self.new_label();
+        let done = self.new_label();
+        self.bind(retry);
+        self.load_exclusive(order, temp, base);
+        self.cmp_reg(temp, expected);
+        self.b_cond(Cond::NE, fail);
+        self.store_exclusive_ordered(order, new, base);
+        self.cbnz(STATUS, retry);
+        self.b(done);
+        self.bind(fail);
+        self.clrex();
+        self.bind(done);
+        self.mov_reg(expected, temp);
+    }
+
+    fn atomic_op(&mut self, op: AtomicOp, src_reg: Reg, dst_reg: Reg, base: Reg, order: Order) {
+        assert!(self.features.lse, "Atomic memory operations require LSE");
+        assert!(
+            is_64_bit(src_reg) == is_64_bit(dst_reg),
+            "Both registers must be of equal size"
+        );
+        assert!(is_64_bit(base), "Base register must be 64-bit");
+        self.int_insn(
+            0xB8200000
+                | op.encoding()
+                | ((is_64_bit(dst_reg) as u32) << 30)
+                | ((order.acquire() as u32) << 23)
+                | ((order.release() as u32) << 22)
+                | ((src_reg as u32 & 0x1F) << 16)
+                | ((base as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    /// Emits an instruction of the exclusive and ordered access group with the size of `rt`
+    fn exclusive(&mut self, opcode: u32, rs: Reg, rt: Reg, base: Reg) {
+        assert!(is_64_bit(base), "Base register must be 64-bit");
+        self.int_insn(
+            opcode
+                | ((2 | is_64_bit(rt) as u32) << 30)
+                | ((rs as u32 & 0x1F) << 16)
+                | ((base as u32 & 0x1F) << 5)
+                | (rt as u32 & 0x1F),
+        );
+    }
+
+    fn store_exclusive(&mut self, opcode: u32, status: Reg, src_reg: Reg, base: Reg) {
+        assert!(!is_64_bit(status), "Status register must be 32-bit");
+        let status_index = status as u32 & 0x1F;
+        assert!(
+            status_index != src_reg as u32 & 0x1F && status_index != base as u32 & 0x1F,
+            "Status register must differ from the other registers"
+        );
+        self.exclusive(opcode, status, src_reg, base);
+    }
+
+    fn load_exclusive(&mut self, order: Order, dst_reg: Reg, base: Reg) {
+        if order.acquire() {
+            self.ldaxr(dst_reg, base);
+        } else {
+            self.ldxr(dst_reg, base);
+        }
+    }
+
+    /// Stores exclusively with the status in `STATUS`
+    fn store_exclusive_ordered(&mut self, order: Order, src_reg: Reg, base: Reg) {
+        if order.release() {
+            self.stlxr(STATUS, src_reg, base);
+        } else {
+            self.stxr(STATUS, src_reg, base);
+        }
+    }
+}
+
+fn ordering_bits(order: Order) -> u32 {
+    ((order.acquire() as u32) * CAS_ACQUIRE) | ((order.release() as u32) * CAS_RELEASE)
+}
+
+/// Returns the scratch register with the size of `reg`
+fn sized(scratch: Reg, reg: Reg) -> Reg {
+    if is_64_bit(reg) {
+        scratch
+    } else {
+        low_32(scratch)
+    }
+}
+
+fn check_scratch(regs: &[Reg]) {
+    for reg in regs {
+        assert!(
+            !matches!(*reg as u32 & 0x1F, 16 | 17),
+            "Helpers use X16 and X17 as scratch registers"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::{
+        features::Features,
+        routine::tests::{assemble, assemble_with},
+    };
+
+    #[test]
+    fn exclusive_and_ordered() {
+        let words = assemble(|r| {
+            r.ldxr(Reg::X0, Reg::X1);
+            r.ldxr(Reg::W2, Reg::X31);
+            r.ldaxr(Reg::W3, Reg::X4);
+            r.stxr(Reg::W5, Reg::X6, Reg::X7);
+            r.stlxr(Reg::W8, Reg::W9, Reg::X10);
+            r.ldar(Reg::X11, Reg::X12);
+            r.stlr(Reg::W13, Reg::X14);
+            r.clrex();
+        });
+        assert_eq!(
+            words,
+            [
+                0xC85F7C20, // ldxr x0, [x1]
+                0x885F7FE2, // ldxr w2, [sp]
+                0x885FFC83, // ldaxr w3, [x4]
+                0xC8057CE6, // stxr w5, x6, [x7]
+                0x8808FD49, // stlxr w8, w9, [x10]
+                0xC8DFFD8B, // ldar x11, [x12]
+                0x889FFDCD, // stlr w13, [x14]
+                0xD5033F5F, // clrex
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_operations() {
+        let words = assemble(|r| {
+            r.ldadd(Reg::X0, Reg::X1, Reg::X2, Order::Relaxed);
+            r.ldadd(Reg::W3, Reg::W4, Reg::X5, Order::Acquire);
+            r.ldclr(Reg::X6, Reg::X7, Reg::X8, Order::Release);
+            r.ldeor(Reg::W9, Reg::W10, Reg::X31, Order::AcqRel);
+            r.ldset(Reg::X11, Reg::X12, Reg::X13, Order::Relaxed);
+            r.swp(Reg::W14, Reg::W15, Reg::X16, Order::AcqRel);
+        });
+        assert_eq!(
+            words,
+            [
+                0xF8200041, // ldadd x0, x1, [x2]
+                0xB8A300A4, // ldadda w3, w4, [x5]
+                0xF8661107, // ldclrl x6, x7, [x8]
+                0xB8E923EA, // ldeoral w9, w10, [sp]
+                0xF82B31AC, // ldset x11, x12, [x13]
+                0xB8EE820F, // swpal w14, w15, [x16]
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let words = assemble(|r| {
+            r.cas(Reg::X0, Reg::X1, Reg::X2, Order::Relaxed);
+            r.cas(Reg::W3, Reg::W4, Reg::X5, Order::Acquire);
+            r.cas(Reg::X6, Reg::X7, Reg::X31, Order::Release);
+            r.cas(Reg::W8, Reg::W9, Reg::X10, Order::AcqRel);
+            r.casp(Reg::X0, Reg::X2, Reg::X4, Order::Relaxed);
+            r.casp(Reg::W6, Reg::W8, Reg::X10, Order::AcqRel);
+        });
+        assert_eq!(
+            words,
+            [
+                0xC8A07C41, // cas x0, x1, [x2]
+                0x88E37CA4, // casa w3, w4, [x5]
+                0xC8A6FFE7, // casl x6, x7, [sp]
+                0x88E8FD49, // casal w8, w9, [x10]
+                0x48207C82, // casp x0, x1, x2, x3, [x4]
+                0x0866FD48, // caspal w6, w7, w8, w9, [x10]
+            ]
+        );
+    }
+
+    #[test]
+    fn barriers() {
+        let words = assemble(|r| {
+            r.dmb(Barrier::ISH);
+            r.dmb(Barrier::ISHLD);
+            r.dsb(Barrier::SY);
+            r.dsb(Barrier::NSHST);
+            r.isb();
+        });
+        assert_eq!(
+            words,
+            [
+                0xD5033BBF, // dmb ish
+                0xD50339BF, // dmb ishld
+                0xD5033F9F, // dsb sy
+                0xD503369F, // dsb nshst
+                0xD5033FDF, // isb
+            ]
+        );
+    }
+
+    #[test]
+    fn helpers_with_lse() {
+        let words = assemble(|r| {
+            r.atomic_fetch(AtomicOp::Add, Reg::X0, Reg::X1, Reg::X2, Order::Relaxed);
+            r.compare_exchange(Reg::X0, Reg::X1, Reg::X2, Order::Acquire);
+        });
+        assert_eq!(
+            words,
+            [
+                0xF8200041, // ldadd x0, x1, [x2]
+                0xAA0003F0, // mov x16, x0
+                0xC8F07C41, // casa x16, x1, [x2]
+                0xEB00021F, // cmp x16, x0
+                0xAA1003E0, // mov x0, x16
+            ]
+        );
+    }
+
+    #[test]
+    fn helpers_without_lse() {
+        let words = assemble_with(Features::default(), |r| {
+            r.atomic_fetch(AtomicOp::Add, Reg::X1, Reg::X0, Reg::X2, Order::AcqRel);
+            r.compare_exchange(Reg::W0, Reg::W1, Reg::X2, Order::Relaxed);
+        });
+        assert_eq!(
+            words,
+            [
+                0xC85FFC40, // ldaxr x0, [x2]
+                0x8B010010, // add x16, x0, x1
+                0xC811FC50, // stlxr w17, x16, [x2]
+                0x35FFFFB1, // cbnz w17, #-12
+                0x885F7C50, // ldxr w16, [x2]
+                0x6B00021F, // cmp w16, w0
+                0x54000081, // b.ne #16
+                0x88117C41, // stxr w17, w1, [x2]
+                0x35FFFF91, // cbnz w17, #-16
+                0x14000002, // b #8
+                0xD5033F5F, // clrex
+                0x2A1003E0, // mov w0, w16
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Atomic memory operations require LSE")]
+    fn memory_operation_without_lse() {
+        assemble_with(Features::default(), |r| {
+            r.ldadd(Reg::X0, Reg::X1, Reg::X2, Order::Relaxed)
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Compare and swap requires LSE")]
+    fn compare_and_swap_without_lse() {
+        assemble_with(Features::default(), |r| {
+            r.cas(Reg::X0, Reg::X1, Reg::X2, Order::Relaxed)
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Compare and swap requires LSE")]
+    fn pair_compare_and_swap_without_lse() {
+        assemble_with(Features::default(), |r| {
+            r.casp(Reg::X0, Reg::X2, Reg::X4, Order::Relaxed)
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Register pairs must start at an even register")]
+    fn odd_register_pair() {
+        assemble(|r| r.casp(Reg::X1, Reg::X2, Reg::X4, Order::Relaxed));
+    }
+
+    #[test]
+    #[should_panic(expected = "Register pairs cannot include the zero register")]
+    fn register_pair_with_zero_register() {
+        assemble(|r| r.casp(Reg::X0, Reg::X30, Reg::X4, Order::Relaxed));
+    }
+
+    #[test]
+    #[should_panic(expected = "Both registers must be of equal size")]
+    fn memory_operation_of_mixed_sizes() {
+        assemble(|r| r.swp(Reg::X0, Reg::W1, Reg::X2, Order::Relaxed));
+    }
+
+    #[test]
+    #[should_panic(expected = "Base register must be 64-bit")]
+    fn base_in_32_bit_register() {
+        assemble(|r| r.ldar(Reg::X0, Reg::W1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Status register must differ from the other registers")]
+    fn status_in_source_register() {
+        assemble(|r| r.stxr(Reg::W0, Reg::X0, Reg::X1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Status register must be 32-bit")]
+    fn status_in_64_bit_register() {
+        assemble(|r| r.stxr(Reg::X0, Reg::X1, Reg::X2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Destination register must differ from the other registers")]
+    fn fetch_into_base_register() {
+        assemble_with(Features::default(), |r| {
+            r.atomic_fetch(AtomicOp::Eor, Reg::X0, Reg::X1, Reg::X1, Order::Relaxed)
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Helpers use X16 and X17 as scratch registers")]
+    fn helper_with_scratch_register() {
+        assemble(|r| r.compare_exchange(Reg::X0, Reg::X17, Reg::X2, Order::Relaxed));
+    }
+}
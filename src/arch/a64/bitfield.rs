@@ -0,0 +1,416 @@
+use super::{
+    reg::{is_64_bit, Reg},
+    routine::Routine,
+};
+
+/// Opcodes of the bitfield move instructions without the `sf` and `N` bits
+const SBFM: u32 = 0x13000000;
+const BFM: u32 = 0x33000000;
+const UBFM: u32 = 0x53000000;
+
+/// Opcodes of the shifts by a register
+const LSLV: u32 = 0x1AC02000;
+const LSRV: u32 = 0x1AC02400;
+const ASRV: u32 = 0x1AC02800;
+const RORV: u32 = 0x1AC02C00;
+
+/// Opcodes of the data-processing instructions with one source in bits 10 to 15
+const RBIT: u32 = 0b000000 << 10;
+const REV16: u32 = 0b000001 << 10;
+const REV32: u32 = 0b000010 << 10;
+const REV64: u32 = 0b000011 << 10;
+const CLZ: u32 = 0b000100 << 10;
+const CLS: u32 = 0b000101 << 10;
+
+impl Routine {
+    /// Shifts the source register left by a constant amount
+    pub fn lsl_imm(&mut self, dst_reg: Reg, src_reg: Reg, amount: u8) {
+        let bits = register_bits(dst_reg);
+        assert!(amount < bits, "Shift amount exceeds the register size");
+        self.ubfm(dst_reg, src_reg, (bits - amount) % bits, bits - 1 - amount);
+    }
+
+    /// Shifts the source register right by a constant amount shifting in zeros
+    pub fn lsr_imm(&mut self, dst_reg: Reg, src_reg: Reg, amount: u8) {
+        let bits = register_bits(dst_reg);
+        assert!(amount < bits, "Shift amount exceeds the register size");
+        self.ubfm(dst_reg, src_reg, amount, bits - 1);
+    }
+
+    /// Shifts the source register right by a constant amount shifting in copies of the sign bit
+    pub fn asr_imm(&mut self, dst_reg: Reg, src_reg: Reg, amount: u8) {
+        let bits = register_bits(dst_reg);
+        assert!(amount < bits, "Shift amount exceeds the register size");
+        self.sbfm(dst_reg, src_reg, amount, bits - 1);
+    }
+
+    /// Rotates the source register right by a constant amount
+    pub fn ror_imm(&mut self, dst_reg: Reg, src_reg: Reg, amount: u8) {
+        let bits_64 = is_64_bit(dst_reg);
+        assert!(
+            amount < register_bits(dst_reg),
+            "Shift amount exceeds the register size"
+        );
+        // Alias of `extr` extracting from the source register concatenated with itself
+        self.reg_op(
+            0x13800000 | ((bits_64 as u32) << 22) | ((amount as u32) << 10),
+            dst_reg,
+            src_reg,
+            src_reg,
+        );
+    }
+
+    /// Shifts `lhs` left by `rhs` modulo the register size
+    pub fn lsl_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(LSLV, dst_reg, lhs, rhs);
+    }
+
+    /// Shifts `lhs` right by `rhs` modulo the register size shifting in zeros
+    pub fn lsr_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(LSRV, dst_reg, lhs, rhs);
+    }
+
+    /// Shifts `lhs` right by `rhs` modulo the register size shifting in copies of the sign bit
+    pub fn asr_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(ASRV, dst_reg, lhs, rhs);
+    }
+
+    /// Rotates `lhs` right by `rhs` modulo the register size
+    pub fn ror_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(RORV, dst_reg, lhs, rhs);
+    }
+
+    /// Moves a bitfield of the source register into the otherwise zeroed destination register
+    ///
+    /// If `imms >= immr` bits `immr` to `imms` are moved to the bottom, otherwise bits 0 to `imms`
+    /// are moved to bit `size - immr`.
+    pub fn ubfm(&mut self, dst_reg: Reg, src_reg: Reg, immr: u8, imms: u8) {
+        self.bitfield(UBFM, dst_reg, src_reg, immr, imms);
+    }
+
+    /// Moves a bitfield like `ubfm` sign-extending it from its highest bit
+    pub fn sbfm(&mut self, dst_reg: Reg, src_reg: Reg, immr: u8, imms: u8) {
+        self.bitfield(SBFM, dst_reg, src_reg, immr, imms);
+    }
+
+    /// Moves a bitfield like `ubfm` leaving the other bits of the destination register unchanged
+    pub fn bfm(&mut self, dst_reg: Reg, src_reg: Reg, immr: u8, imms: u8) {
+        self.bitfield(BFM, dst_reg, src_reg, immr, imms);
+    }
+
+    /// Extracts `width` bits starting at `lsb` of the source register zero-extending them
+    pub fn ubfx(&mut self, dst_reg: Reg, src_reg: Reg, lsb: u8, width: u8) {
+        check_field(dst_reg, lsb, width);
+        self.ubfm(dst_reg, src_reg, lsb, lsb + width - 1);
+    }
+
+    /// Extracts `width` bits starting at `lsb` of the source register sign-extending them
+    pub fn sbfx(&mut self, dst_reg: Reg, src_reg: Reg, lsb: u8, width: u8) {
+        check_field(dst_reg, lsb, width);
+        self.sbfm(dst_reg, src_reg, lsb, lsb + width - 1);
+    }
+
+    /// Inserts the lowest `width` bits of the source register at `lsb` of the destination register
+    pub fn bfi(&mut self, dst_reg: Reg, src_reg: Reg, lsb: u8, width: u8) {
+        check_field(dst_reg, lsb, width);
+        let bits = register_bits(dst_reg);
+        self.bfm(dst_reg, src_reg, (bits - lsb) % bits, width - 1);
+    }
+
+    /// Replaces the lowest `width` bits of the destination register by the `width` bits starting
+    /// at `lsb` of the source register
+    pub fn bfxil(&mut self, dst_reg: Reg, src_reg: Reg, lsb: u8, width: u8) {
+        check_field(dst_reg, lsb, width);
+        self.bfm(dst_reg, src_reg, lsb, lsb + width - 1);
+    }
+
+    /// Moves the lowest `width` bits of the source register to `lsb` clearing the other bits
+    pub fn ubfiz(&mut self, dst_reg: Reg, src_reg: Reg, lsb: u8, width: u8) {
+        check_field(dst_reg, lsb, width);
+        let bits = register_bits(dst_reg);
+        self.ubfm(dst_reg, src_reg, (bits - lsb) % bits, width - 1);
+    }
+
+    /// Moves the lowest `width` bits of the source register to `lsb` sign-extending them
+    pub fn sbfiz(&mut self, dst_reg: Reg, src_reg: Reg, lsb: u8, width: u8) {
+        check_field(dst_reg, lsb, width);
+        let bits = register_bits(dst_reg);
+        self.sbfm(dst_reg, src_reg, (bits - lsb) % bits, width - 1);
+    }
+
+    /// Zero-extends the lowest byte of the 32-bit source register
+    pub fn uxtb(&mut self, dst_reg: Reg, src_reg: Reg) {
+        assert!(!is_64_bit(dst_reg), "Destination register must be 32-bit");
+        self.ubfm(dst_reg, src_reg, 0, 7);
+    }
+
+    /// Zero-extends the lowest halfword of the 32-bit source register
+    pub fn uxth(&mut self, dst_reg: Reg, src_reg: Reg) {
+        assert!(!is_64_bit(dst_reg), "Destination register must be 32-bit");
+        self.ubfm(dst_reg, src_reg, 0, 15);
+    }
+
+    /// Sign-extends the lowest byte of the 32-bit source register
+    pub fn sxtb(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.sign_extend(dst_reg, src_reg, 7);
+    }
+
+    /// Sign-extends the lowest halfword of the 32-bit source register
+    pub fn sxth(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.sign_extend(dst_reg, src_reg, 15);
+    }
+
+    /// Sign-extends the 32-bit source register into the 64-bit destination register
+    pub fn sxtw(&mut self, dst_reg: Reg, src_reg: Reg) {
+        assert!(is_64_bit(dst_reg), "Destination register must be 64-bit");
+        self.sign_extend(dst_reg, src_reg, 31);
+    }
+
+    /// Counts the leading zero bits of the source register
+    pub fn clz(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.one_source(CLZ, dst_reg, src_reg);
+    }
+
+    /// Counts the leading bits of the source register equal to the sign bit, excluding it
+    pub fn cls(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.one_source(CLS, dst_reg, src_reg);
+    }
+
+    /// Reverses the order of the bits of the source register
+    pub fn rbit(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.one_source(RBIT, dst_reg, src_reg);
+    }
+
+    /// Reverses the order of the bytes of the source register
+    pub fn rev(&mut self, dst_reg: Reg, src_reg: Reg) {
+        let opcode = if is_64_bit(dst_reg) { REV64 } else { REV32 };
+        self.one_source(opcode, dst_reg, src_reg);
+    }
+
+    /// Reverses the order of the bytes in every halfword of the source register
+    pub fn rev16(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.one_source(REV16, dst_reg, src_reg);
+    }
+
+    /// Reverses the order of the bytes in both words of the 64-bit source register
+    pub fn rev32(&mut self, dst_reg: Reg, src_reg: Reg) {
+        assert!(is_64_bit(dst_reg), "Registers must be 64-bit");
+        self.one_source(REV32, dst_reg, src_reg);
+    }
+
+    fn bitfield(&mut self, opcode: u32, dst_reg: Reg, src_reg: Reg, immr: u8, imms: u8) {
+        let bits_64 = is_64_bit(dst_reg);
+        assert!(
+            bits_64 == is_64_bit(src_reg),
+            "Both registers must be of equal size"
+        );
+        let bits = register_bits(dst_reg);
+        assert!(
+            immr < bits && imms < bits,
+            "Bitfield positions exceed the register size"
+        );
+        self.int_insn(
+            opcode
+                | ((bits_64 as u32) << 31)
+                | ((bits_64 as u32) << 22)
+                | ((immr as u32) << 16)
+                | ((imms as u32) << 10)
+                | ((src_reg as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    /// Emits `sbfm` with a source register that is 32-bit regardless of the destination register
+    fn sign_extend(&mut self, dst_reg: Reg, src_reg: Reg, imms: u8) {
+        assert!(!is_64_bit(src_reg), "Source register must be 32-bit");
+        let bits_64 = is_64_bit(dst_reg);
+        self.int_insn(
+            SBFM | ((bits_64 as u32) << 31)
+                | ((bits_64 as u32) << 22)
+                | ((imms as u32) << 10)
+                | ((src_reg as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    fn one_source(&mut self, opcode: u32, dst_reg: Reg, src_reg: Reg) {
+        let bits_64 = is_64_bit(dst_reg);
+        assert!(
+            bits_64 == is_64_bit(src_reg),
+            "Both registers must be of equal size"
+        );
+        self.int_insn(
+            0x5AC00000
+                | opcode
+                | ((bits_64 as u32) << 31)
+                | ((src_reg as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+}
+
+fn register_bits(reg: Reg) -> u8 {
+    if is_64_bit(reg) {
+        64
+    } else {
+        32
+    }
+}
+
+/// Checks that the field of `width` bits starting at `lsb` lies within the register
+fn check_field(reg: Reg, lsb: u8, width: u8) {
+    assert!(width > 0, "Bitfield must not be empty");
+    assert!(
+        lsb as u32 + width as u32 <= register_bits(reg) as u32,
+        "Bitfield exceeds the register size"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::routine::tests::assemble;
+
+    #[test]
+    fn shifts() {
+        let words = assemble(|r| {
+            r.lsl_imm(Reg::X0, Reg::X1, 3);
+            r.lsl_imm(Reg::W2, Reg::W3, 0);
+            r.lsr_imm(Reg::X4, Reg::X5, 63);
+            r.asr_imm(Reg::W6, Reg::W7, 31);
+            r.ror_imm(Reg::X8, Reg::X9, 17);
+            r.ror_imm(Reg::W10, Reg::W11, 1);
+            r.lsl_reg(Reg::X12, Reg::X13, Reg::X14);
+            r.lsr_reg(Reg::W15, Reg::W16, Reg::W17);
+            r.asr_reg(Reg::X18, Reg::X19, Reg::X20);
+            r.ror_reg(Reg::W21, Reg::W22, Reg::W23);
+        });
+        assert_eq!(
+            words,
+            [
+                0xD37DF020, // lsl x0, x1, #3
+                0x53007C62, // lsl w2, w3, #0
+                0xD37FFCA4, // lsr x4, x5, #63
+                0x131F7CE6, // asr w6, w7, #31
+                0x93C94528, // ror x8, x9, #17
+                0x138B056A, // ror w10, w11, #1
+                0x9ACE21AC, // lsl x12, x13, x14
+                0x1AD1260F, // lsr w15, w16, w17
+                0x9AD42A72, // asr x18, x19, x20
+                0x1AD72ED5, // ror w21, w22, w23
+            ]
+        );
+    }
+
+    #[test]
+    fn bitfield_moves() {
+        let words = assemble(|r| {
+            r.ubfm(Reg::X0, Reg::X1, 4, 11);
+            r.sbfm(Reg::W2, Reg::W3, 31, 0);
+            r.bfm(Reg::X4, Reg::X5, 60, 3);
+            r.ubfx(Reg::X6, Reg::X7, 8, 16);
+            r.sbfx(Reg::W8, Reg::W9, 0, 32);
+            r.bfi(Reg::X10, Reg::X11, 32, 32);
+            r.bfxil(Reg::W12, Reg::W13, 3, 5);
+            r.ubfiz(Reg::X14, Reg::X15, 1, 63);
+            r.sbfiz(Reg::W16, Reg::W17, 31, 1);
+            r.uxtb(Reg::W18, Reg::W19);
+            r.uxth(Reg::W20, Reg::W21);
+            r.sxtb(Reg::X22, Reg::W23);
+            r.sxth(Reg::W24, Reg::W25);
+            r.sxtw(Reg::X26, Reg::W27);
+        });
+        assert_eq!(
+            words,
+            [
+                0xD3442C20, // ubfm x0, x1, #4, #11
+                0x131F0062, // sbfm w2, w3, #31, #0
+                0xB37C0CA4, // bfm x4, x5, #60, #3
+                0xD3485CE6, // ubfx x6, x7, #8, #16
+                0x13007D28, // sbfx w8, w9, #0, #32
+                0xB3607D6A, // bfi x10, x11, #32, #32
+                0x33031DAC, // bfxil w12, w13, #3, #5
+                0xD37FF9EE, // ubfiz x14, x15, #1, #63
+                0x13010230, // sbfiz w16, w17, #31, #1
+                0x53001E72, // uxtb w18, w19
+                0x53003EB4, // uxth w20, w21
+                0x93401EF6, // sxtb x22, w23
+                0x13003F38, // sxth w24, w25
+                0x93407F7A, // sxtw x26, w27
+            ]
+        );
+    }
+
+    #[test]
+    fn bit_manipulation() {
+        let words = assemble(|r| {
+            r.clz(Reg::X0, Reg::X1);
+            r.cls(Reg::W2, Reg::W3);
+            r.rbit(Reg::X4, Reg::X5);
+            r.rev(Reg::W6, Reg::W7);
+            r.rev(Reg::X8, Reg::X9);
+            r.rev16(Reg::W10, Reg::W11);
+            r.rev32(Reg::X12, Reg::X13);
+        });
+        assert_eq!(
+            words,
+            [
+                0xDAC01020, // clz x0, x1
+                0x5AC01462, // cls w2, w3
+                0xDAC000A4, // rbit x4, x5
+                0x5AC008E6, // rev w6, w7
+                0xDAC00D28, // rev x8, x9
+                0x5AC0056A, // rev16 w10, w11
+                0xDAC009AC, // rev32 x12, x13
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Bitfield must not be empty")]
+    fn empty_bitfield() {
+        assemble(|r| r.ubfx(Reg::X0, Reg::X1, 0, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Bitfield exceeds the register size")]
+    fn bitfield_beyond_register() {
+        assemble(|r| r.bfi(Reg::W0, Reg::W1, 24, 9));
+    }
+
+    #[test]
+    #[should_panic(expected = "Bitfield positions exceed the register size")]
+    fn bitfield_positions_out_of_range() {
+        assemble(|r| r.ubfm(Reg::W0, Reg::W1, 32, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Shift amount exceeds the register size")]
+    fn shift_out_of_range() {
+        assemble(|r| r.lsl_imm(Reg::X0, Reg::X1, 64));
+    }
+
+    #[test]
+    #[should_panic(expected = "Both registers must be of equal size")]
+    fn mixed_register_sizes() {
+        assemble(|r| r.sbfm(Reg::X0, Reg::W1, 0, 7));
+    }
+
+    #[test]
+    #[should_panic(expected = "Destination register must be 32-bit")]
+    fn zero_extend_into_64_bit_register() {
+        assemble(|r| r.uxtb(Reg::X0, Reg::W1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Source register must be 32-bit")]
+    fn sign_extend_64_bit_register() {
+        assemble(|r| r.sxth(Reg::X0, Reg::X1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Registers must be 64-bit")]
+    fn rev32_of_32_bit_register() {
+        assemble(|r| r.rev32(Reg::W0, Reg::W1));
+    }
+}
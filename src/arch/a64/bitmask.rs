@@ -0,0 +1,156 @@
+/// Returns the `N:immr:imms` field encoding the immediate or `None` if it is not a bitmask
+/// immediate
+///
+/// Bitmask immediates are a run of ones rotated within an element of 2 to 64 bits that is repeated
+/// across the register. For 32-bit registers the immediate must fit into 32 bits.
+pub fn encode(imm: u64, bits_64: bool) -> Option<u32> {
+    let imm = if bits_64 {
+        imm
+    } else {
+        assert!(imm <= u32::MAX as u64, "Immediate must fit into 32 bits");
+        imm | (imm << 32)
+    };
+    if imm == 0 || imm == u64::MAX {
+        return None;
+    }
+    let mut size = 64;
+    while size > 2 {
+        let half = size / 2;
+        let mask = (1u64 << half) - 1;
+        if imm & mask != (imm >> half) & mask {
+            break;
+        }
+        size = half;
+    }
+    let element = imm & element_mask(size);
+    let ones = element.count_ones();
+    let run = (1u64 << ones) - 1;
+    let immr = (0..size).find(|by| rotate_right(run, *by, size) == element)?;
+    let imms = ((!(size - 1) << 1) & 0x3F) | (ones - 1);
+    Some((((size == 64) as u32) << 12) | (immr << 6) | imms)
+}
+
+/// Returns the immediate of the `N:immr:imms` field or `None` if the encoding is reserved
+pub fn decode(field: u32, bits_64: bool) -> Option<u64> {
+    let n = (field >> 12) & 1;
+    let immr = (field >> 6) & 0x3F;
+    let imms = field & 0x3F;
+    if n == 1 && !bits_64 {
+        return None;
+    }
+    let levels = (n << 6) | (!imms & 0x3F);
+    if levels < 2 {
+        return None;
+    }
+    let size = 1 << (31 - levels.leading_zeros());
+    let ones = (imms & (size - 1)) + 1;
+    if ones == size {
+        return None;
+    }
+    let element = rotate_right((1u64 << ones) - 1, immr & (size - 1), size);
+    let imm = (0..64 / size).fold(0, |imm, index| imm | (element << (index * size)));
+    Some(if bits_64 { imm } else { imm & u32::MAX as u64 })
+}
+
+fn element_mask(size: u32) -> u64 {
+    if size == 64 {
+        u64::MAX
+    } else {
+        (1 << size) - 1
+    }
+}
+
+fn rotate_right(value: u64, by: u32, size: u32) -> u64 {
+    ((value >> by) | (value << ((size - by) % size))) & element_mask(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Builds every bitmask immediate from the definition, a rotated run of ones repeated across
+    /// the register
+    fn immediates(bits_64: bool) -> HashSet<u64> {
+        let mut immediates = HashSet::new();
+        let sizes: &[u32] = if bits_64 {
+            &[2, 4, 8, 16, 32, 64]
+        } else {
+            &[2, 4, 8, 16, 32]
+        };
+        for &size in sizes {
+            for ones in 1..size {
+                for by in 0..size {
+                    let element = rotate_right((1 << ones) - 1, by, size);
+                    let imm =
+                        (0..64 / size).fold(0, |imm, index| imm | (element << (index * size)));
+                    immediates.insert(if bits_64 { imm } else { imm as u32 as u64 });
+                }
+            }
+        }
+        immediates
+    }
+
+    #[test]
+    fn round_trip() {
+        for (bits_64, count) in [(true, 5334), (false, 1302)] {
+            let immediates = immediates(bits_64);
+            assert_eq!(immediates.len(), count);
+
+            let mut decoded = HashSet::new();
+            for field in 0..1 << 13 {
+                let Some(imm) = decode(field, bits_64) else {
+                    continue;
+                };
+                assert!(immediates.contains(&imm), "{field:#x} decodes to {imm:#x}");
+                decoded.insert(imm);
+            }
+            assert_eq!(decoded, immediates);
+
+            for imm in &immediates {
+                let field = encode(*imm, bits_64).expect("Bitmask immediate does not encode");
+                assert_eq!(
+                    decode(field, bits_64),
+                    Some(*imm),
+                    "{imm:#x} encodes to {field:#x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn other_values_do_not_encode() {
+        for bits_64 in [true, false] {
+            let immediates = immediates(bits_64);
+            let width = if bits_64 { 64 } else { 32 };
+            let check = |imm: u64| {
+                assert_eq!(
+                    encode(imm, bits_64).is_some(),
+                    immediates.contains(&imm),
+                    "{imm:#x}"
+                );
+            };
+            check(0);
+            check(u64::MAX >> (64 - width));
+            // Values one bit away from an immediate are the closest misses
+            for imm in &immediates {
+                for bit in 0..width {
+                    check(imm ^ (1 << bit));
+                }
+            }
+            let mut state = 0x2545_F491_4F6C_DD1D_u64;
+            for _ in 0..100_000 {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                check(state >> (64 - width));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate must fit into 32 bits")]
+    fn wide_32_bit_immediate() {
+        encode(1 << 32, false);
+    }
+}
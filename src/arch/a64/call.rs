@@ -0,0 +1,911 @@
+use super::{
+    frame::StackSlot,
+    reg::{d_reg, is_64_bit, low_32, s_reg, FReg, Reg},
+    routine::Routine,
+};
+use crate::assembler::{MacroAssembler, Width};
+
+/// Register holding the target while the arguments are set up
+const TARGET: Reg = Reg::X17;
+
+/// Register used to copy memory and break cycles of register moves
+const TEMP: Reg = Reg::X16;
+
+/// Type of a parameter or the return value of a foreign function
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ArgType {
+    I8,
+    I16,
+    I32,
+    I64,
+    Ptr,
+    F32,
+    F64,
+    /// Struct with the C layout of its fields
+    Struct(Vec<ArgType>),
+}
+
+impl ArgType {
+    pub fn size(&self) -> usize {
+        match self {
+            Self::I8 => 1,
+            Self::I16 => 2,
+            Self::I32 | Self::F32 => 4,
+            Self::I64 | Self::Ptr | Self::F64 => 8,
+            Self::Struct(fields) => {
+                let end = fields.iter().fold(0usize, |end, field| {
+                    end.next_multiple_of(field.align()) + field.size()
+                });
+                end.next_multiple_of(self.align())
+            }
+        }
+    }
+
+    pub fn align(&self) -> usize {
+        match self {
+            Self::Struct(fields) => fields.iter().map(Self::align).max().unwrap_or(1),
+            _ => self.size(),
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Self::F32 | Self::F64)
+    }
+
+    /// Returns the member type and count of a homogeneous floating-point aggregate, which is a
+    /// struct of one to four floats of the same type once nested structs are flattened
+    pub fn hfa(&self) -> Option<(ArgType, usize)> {
+        let Self::Struct(_) = self else {
+            return None;
+        };
+        let mut members = Vec::new();
+        self.flatten(&mut members);
+        let first = members.first()?;
+        (first.is_float() && members.len() <= 4 && members.iter().all(|member| member == first))
+            .then(|| (first.clone(), members.len()))
+    }
+
+    fn flatten(&self, members: &mut Vec<ArgType>) {
+        match self {
+            Self::Struct(fields) => fields.iter().for_each(|field| field.flatten(members)),
+            _ => members.push(self.clone()),
+        }
+    }
+
+    fn width(&self) -> Width {
+        match self.size() {
+            1 => Width::Byte,
+            2 => Width::Half,
+            4 => Width::Word,
+            _ => Width::Double,
+        }
+    }
+
+    fn validate(&self) {
+        if let Self::Struct(fields) = self {
+            assert!(!fields.is_empty(), "Structs must have fields");
+            fields.iter().for_each(Self::validate);
+        }
+    }
+}
+
+/// Parameters and return type of a foreign function called through `Routine::call_with`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Signature {
+    /// Types of all arguments of a call including the variadic ones
+    pub params: Vec<ArgType>,
+    pub ret: Option<ArgType>,
+    /// Number of named parameters if the function is variadic
+    ///
+    /// Variadic arguments are passed like named ones on Linux, but have to be promoted by the
+    /// default argument promotions of C, so they cannot be `I8`, `I16` or `F32`.
+    pub named: Option<usize>,
+}
+
+/// Value passed to a foreign function
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arg {
+    /// 64-bit register holding an integer, a pointer or the bit pattern of a float
+    Reg(Reg),
+    /// Stack slot holding the argument, which has to be padded to a multiple of 8 bytes for
+    /// structs
+    Slot(StackSlot),
+}
+
+/// Function called by `Routine::call_with`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallTarget {
+    /// Absolute address stored in the constant pool of the routine
+    Address(usize),
+    /// Offset of a global constant holding the address, represented in 32-bit steps
+    Global(usize),
+    /// 64-bit register holding the address
+    Reg(Reg),
+}
+
+/// Where an argument is placed according to AAPCS64
+#[derive(Clone, Copy, Debug)]
+enum Place {
+    /// General-purpose registers starting at the index, one per 8 bytes
+    Gpr(usize),
+    /// SIMD&FP registers starting at the index, one per float
+    Fpr(usize),
+    /// Offset of the argument in the outgoing stack area
+    Stack(usize),
+    /// Struct copied to the offset of the outgoing stack area whose address is passed in place
+    /// of it
+    Indirect { copy: usize, ptr: Word },
+}
+
+/// Where a pointer to a struct passed indirectly is placed
+#[derive(Clone, Copy, Debug)]
+enum Word {
+    Gpr(usize),
+    Stack(usize),
+}
+
+struct Layout {
+    places: Vec<Place>,
+    /// Bytes SP is lowered by for the call, a multiple of 16
+    area: usize,
+}
+
+impl Signature {
+    /// Assigns every parameter its registers or its stack offset
+    fn layout(&self) -> Layout {
+        let mut ngrn = 0;
+        let mut nsrn = 0;
+        let mut nsaa = 0;
+        let mut indirect = Vec::new();
+        let mut places = Vec::with_capacity(self.params.len());
+        for ty in &self.params {
+            let on_stack = |nsaa: &mut usize| {
+                *nsaa = nsaa.next_multiple_of(ty.align().max(8));
+                let offset = *nsaa;
+                *nsaa += ty.size().next_multiple_of(8);
+                Place::Stack(offset)
+            };
+            let place = if let Some((_, count)) = ty.hfa() {
+                if nsrn + count <= 8 {
+                    nsrn += count;
+                    Place::Fpr(nsrn - count)
+                } else {
+                    nsrn = 8;
+                    on_stack(&mut nsaa)
+                }
+            } else if ty.is_float() {
+                if nsrn < 8 {
+                    nsrn += 1;
+                    Place::Fpr(nsrn - 1)
+                } else {
+                    on_stack(&mut nsaa)
+                }
+            } else if ty.size() > 16 {
+                let ptr = if ngrn < 8 {
+                    ngrn += 1;
+                    Word::Gpr(ngrn - 1)
+                } else {
+                    nsaa += 8;
+                    Word::Stack(nsaa - 8)
+                };
+                indirect.push(places.len());
+                Place::Indirect { copy: 0, ptr }
+            } else {
+                let words = ty.size().div_ceil(8);
+                if ty.align() == 16 {
+                    ngrn = ngrn.next_multiple_of(2);
+                }
+                if ngrn + words <= 8 {
+                    ngrn += words;
+                    Place::Gpr(ngrn - words)
+                } else {
+                    ngrn = 8;
+                    on_stack(&mut nsaa)
+                }
+            };
+            places.push(place);
+        }
+        let mut end = nsaa;
+        for index in indirect {
+            let Place::Indirect { copy, .. } = &mut places[index] else {
+                unreachable!();
+            };
+            *copy = end.next_multiple_of(16);
+            end = *copy + self.params[index].size().next_multiple_of(8);
+        }
+        Layout {
+            places,
+            area: end.next_multiple_of(16),
+        }
+    }
+
+    fn validate(&self) {
+        for ty in self.params.iter().chain(&self.ret) {
+            ty.validate();
+        }
+        if let Some(named) = self.named {
+            assert!(
+                named <= self.params.len(),
+                "Signature has more named parameters than parameters"
+            );
+            for ty in &self.params[named..] {
+                assert!(
+                    !matches!(ty, ArgType::I8 | ArgType::I16 | ArgType::F32),
+                    "Variadic arguments must be promoted to int or double"
+                );
+            }
+        }
+    }
+}
+
+impl Routine {
+    /// Calls a function following AAPCS64, placing the arguments in `X0` to `X7`, `V0` to `V7` or
+    /// on the stack below SP
+    ///
+    /// Integer and pointer results end up in `X0` and float results are moved into `X0` as their
+    /// bit patterns. Struct results are stored into `ret`, which must be padded to a multiple of 8
+    /// bytes. `X16` and `X17` are used as scratch registers, so they cannot hold arguments or the
+    /// target.
+    pub fn call_with(
+        &mut self,
+        sig: &Signature,
+        target: CallTarget,
+        args: &[Arg],
+        ret: Option<StackSlot>,
+    ) {
+        sig.validate();
+        assert_eq!(
+            args.len(),
+            sig.params.len(),
+            "Argument count does not match the signature"
+        );
+        assert_eq!(
+            ret.is_some(),
+            matches!(sig.ret, Some(ArgType::Struct(_))),
+            "Return slot must be given exactly for struct results"
+        );
+        let Layout { places, area } = sig.layout();
+        if area != 0 {
+            self.sp_imm(true, Reg::X31, Reg::X31, area);
+        }
+
+        // Stack arguments and arguments in SIMD&FP registers, which leave the argument registers
+        // untouched
+        let mut moves = Vec::new();
+        for ((ty, arg), place) in sig.params.iter().zip(args).zip(&places) {
+            if let Arg::Reg(reg) = arg {
+                assert!(
+                    is_64_bit(*reg) && !matches!(*reg, TEMP | TARGET),
+                    "Arguments must be in 64-bit registers other than X16 and X17"
+                );
+                assert!(
+                    !matches!(ty, ArgType::Struct(_)),
+                    "Struct arguments must be passed in stack slots"
+                );
+            }
+            match (place, arg) {
+                (Place::Gpr(index), Arg::Reg(reg)) => moves.push((Routine::gpr(*index), *reg)),
+                (Place::Gpr(_), Arg::Slot(_)) => {}
+                (Place::Fpr(index), Arg::Reg(reg)) => {
+                    self.fmov_to_fp(Self::fpr(ty, *index), float_bits(ty, *reg))
+                }
+                (Place::Fpr(index), Arg::Slot(slot)) => {
+                    let (member, count) = ty.hfa().unwrap_or((ty.clone(), 1));
+                    for i in 0..count {
+                        let offset = Self::arg_offset(*slot, area, &member, i * member.size());
+                        let reg = Self::fpr(&member, index + i);
+                        self.load_store_fp(true, reg, Reg::X31, offset);
+                    }
+                }
+                (Place::Stack(offset), Arg::Reg(reg)) => {
+                    self.load_store(false, Width::Double, *reg, Reg::X31, *offset as i32);
+                }
+                (Place::Stack(offset), Arg::Slot(slot)) => {
+                    self.copy_arg(ty, *slot, area, *offset);
+                }
+                (Place::Indirect { copy, ptr }, Arg::Slot(slot)) => {
+                    self.copy_arg(ty, *slot, area, *copy);
+                    if let Word::Stack(offset) = ptr {
+                        self.sp_imm(false, TARGET, Reg::X31, *copy);
+                        self.load_store(false, Width::Double, TARGET, Reg::X31, *offset as i32);
+                    }
+                }
+                (Place::Indirect { .. }, Arg::Reg(_)) => unreachable!(),
+            }
+        }
+
+        match target {
+            CallTarget::Address(address) => {
+                let offset = self.const_64(address as u64);
+                self.ldr_const(TARGET, offset);
+            }
+            CallTarget::Global(offset) => self.ldr_global_const(TARGET, offset),
+            CallTarget::Reg(reg) => {
+                // Copying arguments in memory goes through `TARGET` before the target is moved
+                assert!(
+                    is_64_bit(reg) && !matches!(reg, TEMP | TARGET),
+                    "Target must be in a 64-bit register other than X16 and X17"
+                );
+                MacroAssembler::mov(self, TARGET, reg);
+            }
+        }
+        self.parallel_move(moves);
+
+        // Argument registers loaded from memory, whose sources cannot be overwritten anymore
+        for ((ty, arg), place) in sig.params.iter().zip(args).zip(&places) {
+            match (place, arg) {
+                (Place::Gpr(index), Arg::Slot(slot)) => {
+                    let width = match ty {
+                        ArgType::Struct(_) => Width::Double,
+                        _ => ty.width(),
+                    };
+                    for word in 0..ty.size().div_ceil(8) {
+                        let offset = Self::arg_offset(*slot, area, ty, word * 8);
+                        self.load_store(true, width, Routine::gpr(index + word), Reg::X31, offset);
+                    }
+                }
+                (
+                    Place::Indirect {
+                        copy,
+                        ptr: Word::Gpr(index),
+                    },
+                    _,
+                ) => self.sp_imm(false, Routine::gpr(*index), Reg::X31, *copy),
+                _ => {}
+            }
+        }
+        if let (Some(ty), Some(slot)) = (&sig.ret, ret) {
+            if ty.size() > 16 && ty.hfa().is_none() {
+                assert!(
+                    slot.size() >= ty.size(),
+                    "Stack slot is too small for the result"
+                );
+                self.sp_imm(false, Reg::X8, Reg::X31, slot.offset() + area);
+            }
+        }
+
+        self.br_reg_link(TARGET);
+
+        match (&sig.ret, ret) {
+            (Some(ty), None) if ty.is_float() => {
+                self.fmov_from_fp(float_bits(ty, Reg::X0), Self::fpr(ty, 0))
+            }
+            (Some(ty), Some(slot)) => {
+                if let Some((member, count)) = ty.hfa() {
+                    for i in 0..count {
+                        let offset = Self::arg_offset(slot, area, &member, i * member.size());
+                        self.load_store_fp(false, Self::fpr(&member, i), Reg::X31, offset);
+                    }
+                } else if ty.size() <= 16 {
+                    for word in 0..ty.size().div_ceil(8) {
+                        let offset = Self::arg_offset(slot, area, ty, word * 8);
+                        let reg = Routine::gpr(word);
+                        self.load_store(false, Width::Double, reg, Reg::X31, offset);
+                    }
+                }
+            }
+            _ => {}
+        }
+        if area != 0 {
+            self.sp_imm(false, Reg::X31, Reg::X31, area);
+        }
+    }
+
+    /// Returns `X<index>` of an argument register
+    fn gpr(index: usize) -> Reg {
+        Routine::arg_reg(index).expect("Argument register out of range")
+    }
+
+    /// Returns the `S` or `D` view of `V<index>` holding a float of the type
+    fn fpr(ty: &ArgType, index: usize) -> FReg {
+        assert!(index < 8, "Argument register out of range");
+        match ty {
+            ArgType::F32 => s_reg(index as u32),
+            _ => d_reg(index as u32),
+        }
+    }
+
+    /// Returns the offset from the lowered SP of 8 bytes at `offset` within the slot or of the
+    /// value if the type is a scalar
+    fn arg_offset(slot: StackSlot, area: usize, ty: &ArgType, offset: usize) -> i32 {
+        let size = match ty {
+            ArgType::Struct(_) => ty.size().next_multiple_of(8),
+            _ => ty.size(),
+        };
+        assert!(
+            slot.size() >= size,
+            "Stack slot is too small for the argument"
+        );
+        i32::try_from(slot.offset() + area + offset).expect("Stack slot is too far from SP")
+    }
+
+    /// Copies an argument held in a stack slot to `dst` of the outgoing stack area
+    fn copy_arg(&mut self, ty: &ArgType, slot: StackSlot, area: usize, dst: usize) {
+        let width = match ty {
+            ArgType::Struct(_) => Width::Double,
+            _ => ty.width(),
+        };
+        let size = match ty {
+            ArgType::Struct(_) => ty.size().next_multiple_of(8),
+            _ => ty.size(),
+        };
+        for offset in (0..size).step_by(8) {
+            let src = Self::arg_offset(slot, area, ty, offset);
+            self.load_store(true, width, TARGET, Reg::X31, src);
+            self.load_store(false, width, TARGET, Reg::X31, (dst + offset) as i32);
+        }
+    }
+
+    /// Performs the register moves `(dst, src)` as if they happened at once, breaking cycles
+    /// through `TEMP`
+    fn parallel_move(&mut self, mut moves: Vec<(Reg, Reg)>) {
+        moves.retain(|(dst, src)| dst != src);
+        while !moves.is_empty() {
+            let ready = moves
+                .iter()
+                .position(|(dst, _)| moves.iter().all(|(_, src)| src != dst));
+            match ready {
+                Some(index) => {
+                    let (dst, src) = moves.remove(index);
+                    self.mov_reg(dst, src);
+                }
+                None => {
+                    let (_, src) = moves[0];
+                    self.mov_reg(TEMP, src);
+                    for (_, other) in &mut moves {
+                        if *other == src {
+                            *other = TEMP;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the view of the 64-bit register holding the bit pattern of a float of the type
+fn float_bits(ty: &ArgType, reg: Reg) -> Reg {
+    match ty {
+        ArgType::F32 => low_32(reg),
+        _ => reg,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::a64::{asm::Asm, emu::Emulator, frame::Frame, routine::tests::assemble},
+        assembler::Assembler,
+    };
+
+    const HOST: u64 = 0x7FFF_0000_1000;
+
+    /// Emulates `f`, which calls the host function `host` at `HOST` as set up by `build`
+    fn emulate(
+        build: impl FnOnce(&mut Routine),
+        host: impl FnMut(&mut Emulator) + 'static,
+    ) -> Emulator {
+        let mut routine = Routine::new("f".to_string());
+        routine.set_frame(Frame {
+            calls: true,
+            ..Frame::default()
+        });
+        build(&mut routine);
+        routine.ret();
+        let mut asm = Asm::default();
+        asm.push_routine(routine);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        emu.hook(HOST, host);
+        emu
+    }
+
+    /// Returns a new stack slot holding the words
+    fn slot(routine: &mut Routine, words: &[u64]) -> StackSlot {
+        let slot = routine.alloc_slot(words.len() * 8, 8);
+        for (index, word) in words.iter().enumerate() {
+            routine.mov_imm64(Reg::X10, *word);
+            routine.store_slot(Width::Double, Reg::X10, slot, index * 8);
+        }
+        slot
+    }
+
+    fn double(value: f64) -> u64 {
+        value.to_bits()
+    }
+
+    fn single(value: f32) -> u64 {
+        value.to_bits() as u64
+    }
+
+    fn signature(params: Vec<ArgType>, ret: Option<ArgType>) -> Signature {
+        Signature {
+            params,
+            ret,
+            named: None,
+        }
+    }
+
+    /// Passes a 24-byte struct, which is copied to the stack and passed by reference, to the
+    /// function whose address is in `target`
+    fn call_indirect(target: Reg) -> Emulator {
+        let mut routine = Routine::new("f".to_string());
+        routine.set_frame(Frame {
+            calls: true,
+            ..Frame::default()
+        });
+        let slot = routine.alloc_slot(24, 8);
+        for (index, value) in [10, 20, 12].into_iter().enumerate() {
+            routine.mov_imm16(Reg::X10, value);
+            routine.store_slot(Width::Double, Reg::X10, slot, index * 8);
+        }
+        routine.mov_reg(target, Reg::X0);
+        let sig = Signature {
+            params: vec![ArgType::Struct(vec![ArgType::I64; 3])],
+            ret: Some(ArgType::I64),
+            named: None,
+        };
+        routine.call_with(&sig, CallTarget::Reg(target), &[Arg::Slot(slot)], None);
+        routine.ret();
+
+        let mut asm = Asm::default();
+        asm.push_routine(routine);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        emu.hook(HOST, |emu| {
+            let fields = (0..3).map(|index| emu.read(emu.reg(0) + index * 8, 8).unwrap());
+            let sum = fields.sum();
+            emu.set_reg(0, sum);
+        });
+        emu
+    }
+
+    #[test]
+    fn register_target_with_copied_arguments() {
+        let mut emu = call_indirect(Reg::X9);
+        assert_eq!(emu.call("f", &[HOST]), Ok(42));
+    }
+
+    #[test]
+    fn arguments_spill_to_the_stack() {
+        let mut params = vec![ArgType::I64; 9];
+        params.extend(vec![ArgType::F64; 9]);
+        params.extend([ArgType::F32, ArgType::I32]);
+        let sig = signature(params, Some(ArgType::I64));
+        let mut emu = emulate(
+            |r| {
+                // The first double is passed as bit pattern in a register and the others in
+                // stack slots
+                let mut floats = vec![Arg::Reg(Reg::X12)];
+                floats
+                    .extend((1..9).map(|index| Arg::Slot(slot(r, &[double(index as f64 + 0.5)]))));
+                let float = slot(r, &[single(1.5)]);
+                r.mov_imm64(Reg::X12, double(0.5));
+                // Argument `i` is in `X<i + 1>`, so every register is moved down by one
+                let regs = [
+                    Reg::X1,
+                    Reg::X2,
+                    Reg::X3,
+                    Reg::X4,
+                    Reg::X5,
+                    Reg::X6,
+                    Reg::X7,
+                    Reg::X8,
+                    Reg::X9,
+                ];
+                for (index, reg) in regs.into_iter().enumerate() {
+                    r.mov_imm16(reg, 100 + index as u16);
+                }
+                r.mov_imm64(Reg::X11, -5i64 as u64);
+                let mut args: Vec<_> = regs.map(Arg::Reg).into();
+                args.extend(floats);
+                args.extend([Arg::Slot(float), Arg::Reg(Reg::X11)]);
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &args, None);
+            },
+            |emu| {
+                for index in 0..8 {
+                    assert_eq!(emu.reg(index), 100 + index as u64);
+                    assert_eq!(emu.vreg(index), double(index as f64 + 0.5) as u128);
+                }
+                let sp = emu.sp();
+                assert_eq!(sp % 16, 0);
+                assert_eq!(emu.read(sp, 8), Ok(108));
+                assert_eq!(emu.read(sp + 8, 8), Ok(double(8.5)));
+                assert_eq!(emu.read(sp + 16, 4), Ok(single(1.5)));
+                assert_eq!(emu.read(sp + 24, 4), Ok(0xFFFF_FFFB));
+                emu.set_reg(0, 42);
+            },
+        );
+        assert_eq!(emu.call("f", &[]), Ok(42));
+        assert_eq!(emu.sp(), emu.memory().len() as u64);
+    }
+
+    #[test]
+    fn hfa_after_exhausted_fp_registers() {
+        // The HFA needs three registers but only two are left, so it and every following float
+        // go on the stack
+        let mut params = vec![ArgType::F64; 6];
+        params.extend([ArgType::Struct(vec![ArgType::F32; 3]), ArgType::F64]);
+        let sig = signature(params, Some(ArgType::I64));
+        let mut emu = emulate(
+            |r| {
+                let mut args: Vec<_> = (0..6)
+                    .map(|index| Arg::Slot(slot(r, &[double(index as f64)])))
+                    .collect();
+                let hfa = slot(r, &[single(1.0) | single(2.0) << 32, single(3.0)]);
+                let last = slot(r, &[double(9.0)]);
+                args.extend([Arg::Slot(hfa), Arg::Slot(last)]);
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &args, None);
+            },
+            |emu| {
+                for index in 0..6 {
+                    assert_eq!(emu.vreg(index), double(index as f64) as u128);
+                }
+                assert_eq!([emu.vreg(6), emu.vreg(7)], [0, 0]);
+                let sp = emu.sp();
+                assert_eq!(emu.read(sp, 4), Ok(single(1.0)));
+                assert_eq!(emu.read(sp + 4, 4), Ok(single(2.0)));
+                assert_eq!(emu.read(sp + 8, 4), Ok(single(3.0)));
+                assert_eq!(emu.read(sp + 16, 8), Ok(double(9.0)));
+                emu.set_reg(0, 42);
+            },
+        );
+        assert_eq!(emu.call("f", &[]), Ok(42));
+    }
+
+    #[test]
+    fn struct_after_exhausted_gprs() {
+        // The struct needs two registers but only X7 is left, so it and every following integer
+        // go on the stack
+        let mut params = vec![ArgType::I64; 7];
+        params.extend([ArgType::Struct(vec![ArgType::I64; 2]), ArgType::I64]);
+        let sig = signature(params, Some(ArgType::I64));
+        let mut emu = emulate(
+            |r| {
+                let pair = slot(r, &[0x11, 0x22]);
+                let mut args = Vec::new();
+                for index in 0..7 {
+                    let reg = Routine::gpr(index);
+                    r.mov_imm16(reg, index as u16);
+                    args.push(Arg::Reg(reg));
+                }
+                r.mov_imm16(Reg::X7, 0x77);
+                r.mov_imm16(Reg::X9, 0x33);
+                args.extend([Arg::Slot(pair), Arg::Reg(Reg::X9)]);
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &args, None);
+            },
+            |emu| {
+                for index in 0..7 {
+                    assert_eq!(emu.reg(index), index as u64);
+                }
+                assert_eq!(emu.reg(7), 0x77);
+                let sp = emu.sp();
+                assert_eq!(emu.read(sp, 8), Ok(0x11));
+                assert_eq!(emu.read(sp + 8, 8), Ok(0x22));
+                assert_eq!(emu.read(sp + 16, 8), Ok(0x33));
+                emu.set_reg(0, 42);
+            },
+        );
+        assert_eq!(emu.call("f", &[]), Ok(42));
+    }
+
+    #[test]
+    fn variadic_arguments_like_named_ones() {
+        let sig = Signature {
+            params: vec![ArgType::Ptr, ArgType::F64, ArgType::I64],
+            ret: Some(ArgType::I32),
+            named: Some(1),
+        };
+        let mut emu = emulate(
+            |r| {
+                let float = slot(r, &[double(2.5)]);
+                r.mov_imm16(Reg::X9, 0x99);
+                let args = [Arg::Reg(Reg::X0), Arg::Slot(float), Arg::Reg(Reg::X9)];
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &args, None);
+            },
+            |emu| {
+                assert_eq!(emu.reg(0), 0x1000);
+                assert_eq!(emu.vreg(0), double(2.5) as u128);
+                assert_eq!(emu.reg(1), 0x99);
+                emu.set_reg(0, 42);
+            },
+        );
+        assert_eq!(emu.call("f", &[0x1000]), Ok(42));
+    }
+
+    #[test]
+    #[should_panic(expected = "Variadic arguments must be promoted to int or double")]
+    fn unpromoted_variadic_float() {
+        let sig = Signature {
+            params: vec![ArgType::Ptr, ArgType::F32],
+            ret: None,
+            named: Some(1),
+        };
+        let mut routine = Routine::new("f".to_string());
+        let args = [Arg::Reg(Reg::X0), Arg::Reg(Reg::X1)];
+        routine.call_with(&sig, CallTarget::Reg(Reg::X9), &args, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Variadic arguments must be promoted to int or double")]
+    fn unpromoted_variadic_byte() {
+        let sig = Signature {
+            params: vec![ArgType::Ptr, ArgType::I64, ArgType::I8],
+            ret: None,
+            named: Some(1),
+        };
+        let mut routine = Routine::new("f".to_string());
+        let args = [Arg::Reg(Reg::X0), Arg::Reg(Reg::X1), Arg::Reg(Reg::X2)];
+        routine.call_with(&sig, CallTarget::Reg(Reg::X9), &args, None);
+    }
+
+    #[test]
+    fn float_results_move_to_x0() {
+        for (ty, bits) in [(ArgType::F64, double(2.5)), (ArgType::F32, single(2.5))] {
+            let sig = signature(Vec::new(), Some(ty));
+            let mut emu = emulate(
+                |r| r.call_with(&sig, CallTarget::Address(HOST as usize), &[], None),
+                // The upper bits of the vector register are not part of the result
+                move |emu| emu.set_vreg(0, bits as u128 | 0xFFFF_FFFF << 64),
+            );
+            assert_eq!(emu.call("f", &[]), Ok(bits));
+        }
+    }
+
+    #[test]
+    fn hfa_results() {
+        let sig = signature(Vec::new(), Some(ArgType::Struct(vec![ArgType::F32; 3])));
+        let mut emu = emulate(
+            |r| {
+                let ret = r.alloc_slot(16, 8);
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &[], Some(ret));
+                for index in 0..3 {
+                    r.load_slot(Width::Word, Routine::gpr(index), ret, index * 4);
+                }
+            },
+            |emu| {
+                for index in 0..3 {
+                    emu.set_vreg(index, single(index as f32 + 1.0) as u128);
+                }
+            },
+        );
+        assert_eq!(emu.call("f", &[]), Ok(single(1.0)));
+        assert_eq!([emu.reg(1), emu.reg(2)], [single(2.0), single(3.0)]);
+
+        // Four doubles are larger than 16 bytes but still returned in registers
+        let pair = ArgType::Struct(vec![ArgType::F64; 2]);
+        let sig = signature(
+            Vec::new(),
+            Some(ArgType::Struct(vec![pair, ArgType::F64, ArgType::F64])),
+        );
+        let mut emu = emulate(
+            |r| {
+                let ret = r.alloc_slot(32, 8);
+                r.mov_imm16(Reg::X8, 0x88);
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &[], Some(ret));
+                for index in 0..4 {
+                    r.load_slot(Width::Double, Routine::gpr(index), ret, index * 8);
+                }
+            },
+            |emu| {
+                assert_eq!(emu.reg(8), 0x88);
+                for index in 0..4 {
+                    emu.set_vreg(index, double(index as f64) as u128);
+                }
+            },
+        );
+        assert_eq!(emu.call("f", &[]), Ok(double(0.0)));
+        assert_eq!(
+            [emu.reg(1), emu.reg(2), emu.reg(3)],
+            [double(1.0), double(2.0), double(3.0)]
+        );
+    }
+
+    #[test]
+    fn struct_results() {
+        // Up to 16 bytes are returned in X0 and X1
+        let sig = signature(
+            Vec::new(),
+            Some(ArgType::Struct(vec![
+                ArgType::I32,
+                ArgType::I32,
+                ArgType::I64,
+            ])),
+        );
+        let mut emu = emulate(
+            |r| {
+                let ret = r.alloc_slot(16, 8);
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &[], Some(ret));
+                r.load_slot(Width::Word, Reg::X0, ret, 4);
+                r.load_slot(Width::Double, Reg::X1, ret, 8);
+            },
+            |emu| {
+                emu.set_reg(0, 0x2222_2222_1111_1111);
+                emu.set_reg(1, 0x3333);
+            },
+        );
+        assert_eq!(emu.call("f", &[]), Ok(0x2222_2222));
+        assert_eq!(emu.reg(1), 0x3333);
+
+        // Larger ones are stored to the address passed in X8
+        let sig = signature(Vec::new(), Some(ArgType::Struct(vec![ArgType::I64; 3])));
+        let mut emu = emulate(
+            |r| {
+                let ret = r.alloc_slot(24, 8);
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &[], Some(ret));
+                for index in 0..3 {
+                    r.load_slot(Width::Double, Routine::gpr(index), ret, index * 8);
+                }
+            },
+            |emu| {
+                let address = emu.reg(8);
+                assert_eq!(address, emu.sp());
+                for index in 0..3 {
+                    emu.write(address + index * 8, 8, 0x10 + index).unwrap();
+                }
+            },
+        );
+        assert_eq!(emu.call("f", &[]), Ok(0x10));
+        assert_eq!([emu.reg(1), emu.reg(2)], [0x11, 0x12]);
+    }
+
+    #[test]
+    fn global_target() {
+        let mut asm = Asm::default();
+        let global = asm.const_64(HOST);
+        let mut routine = Routine::new("f".to_string());
+        routine.set_frame(Frame {
+            calls: true,
+            ..Frame::default()
+        });
+        let sig = signature(vec![ArgType::I64], Some(ArgType::I64));
+        routine.call_with(&sig, CallTarget::Global(global), &[Arg::Reg(Reg::X0)], None);
+        routine.ret();
+        asm.push_routine(routine);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        emu.hook(HOST, |emu| emu.set_reg(0, emu.reg(0) * 2));
+        assert_eq!(emu.call("f", &[21]), Ok(42));
+    }
+
+    #[test]
+    fn register_cycles() {
+        let sig = signature(vec![ArgType::I64; 3], None);
+        let words = assemble(|r| {
+            let args = [Reg::X1, Reg::X2, Reg::X0].map(Arg::Reg);
+            r.call_with(&sig, CallTarget::Reg(Reg::X9), &args, None);
+        });
+        assert_eq!(
+            words,
+            [
+                0xAA0903F1, // mov x17, x9
+                0xAA0103F0, // mov x16, x1
+                0xAA0203E1, // mov x1, x2
+                0xAA0003E2, // mov x2, x0
+                0xAA1003E0, // mov x0, x16
+                0xD63F0220, // blr x17
+            ]
+        );
+
+        // A rotation of three registers and a swap
+        let sig = signature(vec![ArgType::I64; 5], Some(ArgType::I64));
+        let mut emu = emulate(
+            |r| {
+                let args = [Reg::X1, Reg::X2, Reg::X0, Reg::X4, Reg::X3].map(Arg::Reg);
+                r.call_with(&sig, CallTarget::Address(HOST as usize), &args, None);
+            },
+            |emu| {
+                let args: Vec<_> = (0..5).map(|index| emu.reg(index)).collect();
+                assert_eq!(args, [2, 3, 1, 5, 4]);
+            },
+        );
+        assert_eq!(emu.call("f", &[1, 2, 3, 4, 5]), Ok(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Target must be in a 64-bit register other than X16 and X17")]
+    fn target_in_scratch_register() {
+        call_indirect(TARGET);
+    }
+}
@@ -0,0 +1,246 @@
+use super::{
+    cond::Cond,
+    reg::{is_64_bit, zero_reg, Reg},
+    routine::Routine,
+};
+
+/// Opcodes of the conditional select instructions without the condition
+const CSEL: u32 = 0x1A800000;
+const CSINC: u32 = 0x1A800400;
+const CSINV: u32 = 0x5A800000;
+const CSNEG: u32 = 0x5A800400;
+
+/// Opcodes of the conditional compare instructions with a register operand
+const CCMN: u32 = 0x3A400000;
+const CCMP: u32 = 0x7A400000;
+
+/// Bit of the conditional compare instructions replacing `rhs` with a 5-bit immediate
+const CCMP_IMM: u32 = 1 << 11;
+
+impl Routine {
+    /// Compares `lhs` with the unsigned 12-bit immediate setting the flags according to
+    /// `lhs - imm12`, where `X31` is SP
+    pub fn cmp_imm12(&mut self, lhs: Reg, imm12: u16) {
+        self.compare_imm(0x71000000, lhs, imm12);
+    }
+
+    /// Compares `lhs` with the negated unsigned 12-bit immediate setting the flags according to
+    /// `lhs + imm12`, where `X31` is SP
+    pub fn cmn_imm12(&mut self, lhs: Reg, imm12: u16) {
+        self.compare_imm(0x31000000, lhs, imm12);
+    }
+
+    /// Compares `lhs` with the negated `rhs` setting the flags according to `lhs + rhs`
+    pub fn cmn_reg(&mut self, lhs: Reg, rhs: Reg) {
+        self.adds_reg(zero_reg(lhs), lhs, rhs);
+    }
+
+    /// Selects `lhs` if the condition holds and `rhs` otherwise
+    pub fn csel(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, cond: Cond) {
+        self.reg_op(CSEL | ((cond as u32) << 12), dst_reg, lhs, rhs);
+    }
+
+    /// Selects `lhs` if the condition holds and `rhs + 1` otherwise
+    pub fn csinc(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, cond: Cond) {
+        self.reg_op(CSINC | ((cond as u32) << 12), dst_reg, lhs, rhs);
+    }
+
+    /// Selects `lhs` if the condition holds and the inverted `rhs` otherwise
+    pub fn csinv(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, cond: Cond) {
+        self.reg_op(CSINV | ((cond as u32) << 12), dst_reg, lhs, rhs);
+    }
+
+    /// Selects `lhs` if the condition holds and the negated `rhs` otherwise
+    pub fn csneg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, cond: Cond) {
+        self.reg_op(CSNEG | ((cond as u32) << 12), dst_reg, lhs, rhs);
+    }
+
+    /// Sets the destination register to 1 if the condition holds and to 0 otherwise
+    ///
+    /// Panics for `AL` and `NV` like the other aliases inverting the condition.
+    pub fn cset(&mut self, dst_reg: Reg, cond: Cond) {
+        let zero = zero_reg(dst_reg);
+        self.csinc(dst_reg, zero, zero, cond.invert());
+    }
+
+    /// Sets all bits of the destination register if the condition holds and clears them otherwise
+    pub fn csetm(&mut self, dst_reg: Reg, cond: Cond) {
+        let zero = zero_reg(dst_reg);
+        self.csinv(dst_reg, zero, zero, cond.invert());
+    }
+
+    /// Selects `src_reg + 1` if the condition holds and `src_reg` otherwise
+    pub fn cinc(&mut self, dst_reg: Reg, src_reg: Reg, cond: Cond) {
+        self.csinc(dst_reg, src_reg, src_reg, cond.invert());
+    }
+
+    /// Selects the inverted `src_reg` if the condition holds and `src_reg` otherwise
+    pub fn cinv(&mut self, dst_reg: Reg, src_reg: Reg, cond: Cond) {
+        self.csinv(dst_reg, src_reg, src_reg, cond.invert());
+    }
+
+    /// Selects the negated `src_reg` if the condition holds and `src_reg` otherwise
+    pub fn cneg(&mut self, dst_reg: Reg, src_reg: Reg, cond: Cond) {
+        self.csneg(dst_reg, src_reg, src_reg, cond.invert());
+    }
+
+    /// Compares `lhs` with `rhs` if the condition holds and sets the flags to the 4-bit `nzcv`
+    /// otherwise
+    pub fn ccmp(&mut self, lhs: Reg, rhs: Reg, nzcv: u8, cond: Cond) {
+        assert!(
+            is_64_bit(lhs) == is_64_bit(rhs),
+            "Both registers must be of equal size"
+        );
+        self.cond_compare(CCMP, lhs, rhs as u32 & 0x1F, nzcv, cond);
+    }
+
+    /// Compares `lhs` with the negated `rhs` if the condition holds and sets the flags to the
+    /// 4-bit `nzcv` otherwise
+    pub fn ccmn(&mut self, lhs: Reg, rhs: Reg, nzcv: u8, cond: Cond) {
+        assert!(
+            is_64_bit(lhs) == is_64_bit(rhs),
+            "Both registers must be of equal size"
+        );
+        self.cond_compare(CCMN, lhs, rhs as u32 & 0x1F, nzcv, cond);
+    }
+
+    /// Compares `lhs` with the unsigned 5-bit immediate if the condition holds and sets the flags
+    /// to the 4-bit `nzcv` otherwise
+    pub fn ccmp_imm5(&mut self, lhs: Reg, imm5: u8, nzcv: u8, cond: Cond) {
+        assert!(imm5 < 32, "Immediate exceeds 5 bits");
+        self.cond_compare(CCMP | CCMP_IMM, lhs, imm5 as u32, nzcv, cond);
+    }
+
+    /// Compares `lhs` with the negated unsigned 5-bit immediate if the condition holds and sets
+    /// the flags to the 4-bit `nzcv` otherwise
+    pub fn ccmn_imm5(&mut self, lhs: Reg, imm5: u8, nzcv: u8, cond: Cond) {
+        assert!(imm5 < 32, "Immediate exceeds 5 bits");
+        self.cond_compare(CCMN | CCMP_IMM, lhs, imm5 as u32, nzcv, cond);
+    }
+
+    fn compare_imm(&mut self, opcode: u32, lhs: Reg, imm12: u16) {
+        assert!(imm12 < 0x1000, "Immediate exceeds 12 bits");
+        self.int_insn(
+            opcode
+                | ((is_64_bit(lhs) as u32) << 31)
+                | ((imm12 as u32) << 10)
+                | ((lhs as u32 & 0x1F) << 5)
+                | 0x1F,
+        );
+    }
+
+    /// Emits a conditional compare where `rhs` is either a register index or an immediate
+    fn cond_compare(&mut self, opcode: u32, lhs: Reg, rhs: u32, nzcv: u8, cond: Cond) {
+        assert!(nzcv < 16, "Flags exceed 4 bits");
+        self.int_insn(
+            opcode
+                | ((is_64_bit(lhs) as u32) << 31)
+                | (rhs << 16)
+                | ((cond as u32) << 12)
+                | ((lhs as u32 & 0x1F) << 5)
+                | nzcv as u32,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::routine::tests::assemble;
+
+    #[test]
+    fn compare() {
+        let words = assemble(|r| {
+            r.cmp_imm12(Reg::X1, 4095);
+            r.cmp_imm12(Reg::W31, 0);
+            r.cmn_imm12(Reg::X2, 1);
+            r.cmn_reg(Reg::W3, Reg::W4);
+            r.ccmp(Reg::X0, Reg::X1, 0, Cond::LS);
+            r.ccmn(Reg::W2, Reg::W3, 15, Cond::GE);
+            r.ccmp_imm5(Reg::X4, 31, 4, Cond::LT);
+            r.ccmn_imm5(Reg::W5, 0, 8, Cond::GT);
+        });
+        assert_eq!(
+            words,
+            [
+                0xF13FFC3F, // cmp x1, #4095
+                0x710003FF, // cmp wsp, #0
+                0xB100045F, // cmn x2, #1
+                0x2B04007F, // cmn w3, w4
+                0xFA419000, // ccmp x0, x1, #0, ls
+                0x3A43A04F, // ccmn w2, w3, #15, ge
+                0xFA5FB884, // ccmp x4, #31, #4, lt
+                0x3A40C8A8, // ccmn w5, #0, #8, gt
+            ]
+        );
+    }
+
+    #[test]
+    fn conditional_select() {
+        let words = assemble(|r| {
+            r.csel(Reg::X0, Reg::X1, Reg::X2, Cond::EQ);
+            r.csinc(Reg::W3, Reg::W4, Reg::W5, Cond::NE);
+            r.csinv(Reg::X6, Reg::X7, Reg::X8, Cond::HS);
+            r.csneg(Reg::W9, Reg::W10, Reg::W11, Cond::LO);
+            r.cset(Reg::X12, Cond::MI);
+            r.csetm(Reg::W13, Cond::PL);
+            r.cinc(Reg::X14, Reg::X15, Cond::VS);
+            r.cinv(Reg::W16, Reg::W17, Cond::VC);
+            r.cneg(Reg::X18, Reg::X19, Cond::HI);
+            r.csel(Reg::W20, Reg::W21, Reg::W22, Cond::LE);
+            r.csel(Reg::X23, Reg::X24, Reg::X25, Cond::AL);
+        });
+        assert_eq!(
+            words,
+            [
+                0x9A820020, // csel x0, x1, x2, eq
+                0x1A851483, // csinc w3, w4, w5, ne
+                0xDA8820E6, // csinv x6, x7, x8, hs
+                0x5A8B3549, // csneg w9, w10, w11, lo
+                0x9A9F57EC, // cset x12, mi
+                0x5A9F43ED, // csetm w13, pl
+                0x9A8F75EE, // cinc x14, x15, vs
+                0x5A916230, // cinv w16, w17, vc
+                0xDA939672, // cneg x18, x19, hi
+                0x1A96D2B4, // csel w20, w21, w22, le
+                0x9A99E317, // csel x23, x24, x25, al
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot invert a condition that always holds")]
+    fn cset_always() {
+        assemble(|r| r.cset(Reg::X0, Cond::AL));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot invert a condition that always holds")]
+    fn cneg_never() {
+        assemble(|r| r.cneg(Reg::X0, Reg::X1, Cond::NV));
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate exceeds 12 bits")]
+    fn compare_immediate_out_of_range() {
+        assemble(|r| r.cmp_imm12(Reg::X0, 0x1000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate exceeds 5 bits")]
+    fn conditional_compare_immediate_out_of_range() {
+        assemble(|r| r.ccmp_imm5(Reg::X0, 32, 0, Cond::EQ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Flags exceed 4 bits")]
+    fn flags_out_of_range() {
+        assemble(|r| r.ccmn(Reg::X0, Reg::X1, 16, Cond::EQ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Both registers must be of equal size")]
+    fn mixed_register_sizes() {
+        assemble(|r| r.ccmp(Reg::X0, Reg::W1, 0, Cond::EQ));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::assembler::Condition;
+
+/// Condition codes as encoded in `b.cond` and the conditional select and compare instructions
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    /// Equal
+    EQ = 0x0,
+    /// Not equal
+    NE = 0x1,
+    /// Carry set, unsigned higher or same
+    HS = 0x2,
+    /// Carry clear, unsigned lower
+    LO = 0x3,
+    /// Negative
+    MI = 0x4,
+    /// Positive or zero
+    PL = 0x5,
+    /// Overflow
+    VS = 0x6,
+    /// No overflow
+    VC = 0x7,
+    /// Unsigned higher
+    HI = 0x8,
+    /// Unsigned lower or same
+    LS = 0x9,
+    /// Signed greater than or equal
+    GE = 0xA,
+    /// Signed less than
+    LT = 0xB,
+    /// Signed greater than
+    GT = 0xC,
+    /// Signed less than or equal
+    LE = 0xD,
+    /// Always
+    AL = 0xE,
+    /// Always, behaves like `AL`
+    NV = 0xF,
+}
+
+impl Cond {
+    /// Returns the condition holding exactly when this one does not
+    ///
+    /// Panics for `AL` and `NV` which hold in either case.
+    pub fn invert(self) -> Self {
+        match self {
+            Self::EQ => Self::NE,
+            Self::NE => Self::EQ,
+            Self::HS => Self::LO,
+            Self::LO => Self::HS,
+            Self::MI => Self::PL,
+            Self::PL => Self::MI,
+            Self::VS => Self::VC,
+            Self::VC => Self::VS,
+            Self::HI => Self::LS,
+            Self::LS => Self::HI,
+            Self::GE => Self::LT,
+            Self::LT => Self::GE,
+            Self::GT => Self::LE,
+            Self::LE => Self::GT,
+            Self::AL | Self::NV => panic!("Cannot invert a condition that always holds"),
+        }
+    }
+}
+
+impl From<Condition> for Cond {
+    fn from(cond: Condition) -> Self {
+        match cond {
+            Condition::Eq => Self::EQ,
+            Condition::Ne => Self::NE,
+            Condition::Lt => Self::LT,
+            Condition::Le => Self::LE,
+            Condition::Gt => Self::GT,
+            Condition::Ge => Self::GE,
+            Condition::Below => Self::LO,
+            Condition::BelowEq => Self::LS,
+            Condition::Above => Self::HI,
+            Condition::AboveEq => Self::HS,
+        }
+    }
+}
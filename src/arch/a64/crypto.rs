@@ -0,0 +1,444 @@
+use super::{
+    reg::{f_bytes, is_64_bit, Arrangement, FReg, Reg, VReg},
+    routine::Routine,
+};
+
+/// Bit of the CRC instructions selecting the Castagnoli polynomial
+const CRC32C: u32 = 1 << 12;
+
+impl Routine {
+    /// Updates the CRC32 checksum in `acc` with the low byte of `data`
+    pub fn crc32b(&mut self, dst_reg: Reg, acc: Reg, data: Reg) {
+        self.crc32(0, 0, dst_reg, acc, data);
+    }
+
+    /// Updates the CRC32 checksum in `acc` with the low 16 bits of `data`
+    pub fn crc32h(&mut self, dst_reg: Reg, acc: Reg, data: Reg) {
+        self.crc32(0, 1, dst_reg, acc, data);
+    }
+
+    /// Updates the CRC32 checksum in `acc` with the 32-bit `data`
+    pub fn crc32w(&mut self, dst_reg: Reg, acc: Reg, data: Reg) {
+        self.crc32(0, 2, dst_reg, acc, data);
+    }
+
+    /// Updates the CRC32 checksum in `acc` with the 64-bit `data`
+    pub fn crc32x(&mut self, dst_reg: Reg, acc: Reg, data: Reg) {
+        self.crc32(0, 3, dst_reg, acc, data);
+    }
+
+    /// Updates the CRC32C checksum in `acc` with the low byte of `data`
+    pub fn crc32cb(&mut self, dst_reg: Reg, acc: Reg, data: Reg) {
+        self.crc32(CRC32C, 0, dst_reg, acc, data);
+    }
+
+    /// Updates the CRC32C checksum in `acc` with the low 16 bits of `data`
+    pub fn crc32ch(&mut self, dst_reg: Reg, acc: Reg, data: Reg) {
+        self.crc32(CRC32C, 1, dst_reg, acc, data);
+    }
+
+    /// Updates the CRC32C checksum in `acc` with the 32-bit `data`
+    pub fn crc32cw(&mut self, dst_reg: Reg, acc: Reg, data: Reg) {
+        self.crc32(CRC32C, 2, dst_reg, acc, data);
+    }
+
+    /// Updates the CRC32C checksum in `acc` with the 64-bit `data`
+    pub fn crc32cx(&mut self, dst_reg: Reg, acc: Reg, data: Reg) {
+        self.crc32(CRC32C, 3, dst_reg, acc, data);
+    }
+
+    /// Performs an AES encryption round on the state in `dst` with the round key, which is
+    /// AddRoundKey, SubBytes and ShiftRows
+    pub fn aese(&mut self, dst: VReg, key: VReg) {
+        self.aes(0x4E284800, dst, key);
+    }
+
+    /// Performs an AES decryption round on the state in `dst` with the round key, which is
+    /// AddRoundKey, InvSubBytes and InvShiftRows
+    pub fn aesd(&mut self, dst: VReg, key: VReg) {
+        self.aes(0x4E285800, dst, key);
+    }
+
+    /// Performs the AES MixColumns transformation
+    pub fn aesmc(&mut self, dst: VReg, src: VReg) {
+        self.aes(0x4E286800, dst, src);
+    }
+
+    /// Performs the AES InvMixColumns transformation
+    pub fn aesimc(&mut self, dst: VReg, src: VReg) {
+        self.aes(0x4E287800, dst, src);
+    }
+
+    /// Performs four SHA1 rounds with the choose function on the hash `abcd` and `e`
+    pub fn sha1c(&mut self, abcd: VReg, e: FReg, wk: VReg) {
+        self.sha1_hash(0x5E000000, abcd, e, wk);
+    }
+
+    /// Performs four SHA1 rounds with the parity function on the hash `abcd` and `e`
+    pub fn sha1p(&mut self, abcd: VReg, e: FReg, wk: VReg) {
+        self.sha1_hash(0x5E001000, abcd, e, wk);
+    }
+
+    /// Performs four SHA1 rounds with the majority function on the hash `abcd` and `e`
+    pub fn sha1m(&mut self, abcd: VReg, e: FReg, wk: VReg) {
+        self.sha1_hash(0x5E002000, abcd, e, wk);
+    }
+
+    /// Rotates the `S` register left by 30 bits, which is the fixed rotate of SHA1
+    pub fn sha1h(&mut self, dst_reg: FReg, src_reg: FReg) {
+        self.check_sha1();
+        assert!(
+            f_bytes(dst_reg) == 4 && f_bytes(src_reg) == 4,
+            "Registers must be single-precision views"
+        );
+        self.int_insn(0x5E280800 | ((src_reg as u32 & 0x1F) << 5) | (dst_reg as u32 & 0x1F));
+    }
+
+    /// Performs the first part of the SHA1 schedule update
+    pub fn sha1su0(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.check_sha1();
+        self.sha_three_reg(0x5E003000, dst, lhs, rhs);
+    }
+
+    /// Performs the second part of the SHA1 schedule update
+    pub fn sha1su1(&mut self, dst: VReg, src: VReg) {
+        self.check_sha1();
+        self.sha_two_reg(0x5E281800, dst, src);
+    }
+
+    /// Performs four SHA256 rounds updating the `abcd` half of the hash
+    pub fn sha256h(&mut self, abcd: VReg, efgh: VReg, wk: VReg) {
+        self.check_sha2();
+        self.sha_three_reg(0x5E004000, abcd, efgh, wk);
+    }
+
+    /// Performs four SHA256 rounds updating the `efgh` half of the hash
+    pub fn sha256h2(&mut self, efgh: VReg, abcd: VReg, wk: VReg) {
+        self.check_sha2();
+        self.sha_three_reg(0x5E005000, efgh, abcd, wk);
+    }
+
+    /// Performs the first part of the SHA256 schedule update
+    pub fn sha256su0(&mut self, dst: VReg, src: VReg) {
+        self.check_sha2();
+        self.sha_two_reg(0x5E282800, dst, src);
+    }
+
+    /// Performs the second part of the SHA256 schedule update
+    pub fn sha256su1(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.check_sha2();
+        self.sha_three_reg(0x5E006000, dst, lhs, rhs);
+    }
+
+    /// Multiplies the lower halves of `lhs` and `rhs` as polynomials over GF(2)
+    ///
+    /// `16B` sources yield eight 16-bit products in an `8H` destination and `2D` sources one
+    /// 128-bit product in a `2D` destination, which requires the PMULL extension.
+    pub fn pmull(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.polynomial_mul(false, dst, lhs, rhs);
+    }
+
+    /// Multiplies the upper halves of `lhs` and `rhs` like `pmull`
+    pub fn pmull2(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.polynomial_mul(true, dst, lhs, rhs);
+    }
+
+    fn check_sha1(&self) {
+        assert!(
+            self.features.sha1,
+            "SHA1 instructions require the SHA1 extension"
+        );
+    }
+
+    fn check_sha2(&self) {
+        assert!(
+            self.features.sha2,
+            "SHA256 instructions require the SHA2 extension"
+        );
+    }
+
+    fn crc32(&mut self, opcode: u32, size: u32, dst_reg: Reg, acc: Reg, data: Reg) {
+        assert!(
+            self.features.crc,
+            "CRC32 instructions require the CRC extension"
+        );
+        assert!(
+            !is_64_bit(dst_reg) && !is_64_bit(acc),
+            "Checksum registers must be 32-bit"
+        );
+        assert!(
+            is_64_bit(data) == (size == 3),
+            "Data register must be 64-bit for 64-bit data and 32-bit otherwise"
+        );
+        self.int_insn(
+            0x1AC04000
+                | ((is_64_bit(data) as u32) << 31)
+                | ((data as u32 & 0x1F) << 16)
+                | opcode
+                | (size << 10)
+                | ((acc as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    fn aes(&mut self, opcode: u32, dst: VReg, src: VReg) {
+        assert!(
+            self.features.aes,
+            "AES instructions require the AES extension"
+        );
+        assert!(
+            dst.arrangement() == Arrangement::T16B && src.arrangement() == Arrangement::T16B,
+            "AES instructions operate on 16B registers"
+        );
+        self.int_insn(opcode | (src.index() << 5) | dst.index());
+    }
+
+    fn sha1_hash(&mut self, opcode: u32, abcd: VReg, e: FReg, wk: VReg) {
+        self.check_sha1();
+        assert!(
+            f_bytes(e) == 4,
+            "Hash element e must be a single-precision view"
+        );
+        check_4s(&[abcd, wk]);
+        self.int_insn(opcode | (wk.index() << 16) | ((e as u32 & 0x1F) << 5) | abcd.index());
+    }
+
+    fn sha_three_reg(&mut self, opcode: u32, dst: VReg, lhs: VReg, rhs: VReg) {
+        check_4s(&[dst, lhs, rhs]);
+        self.int_insn(opcode | (rhs.index() << 16) | (lhs.index() << 5) | dst.index());
+    }
+
+    fn sha_two_reg(&mut self, opcode: u32, dst: VReg, src: VReg) {
+        check_4s(&[dst, src]);
+        self.int_insn(opcode | (src.index() << 5) | dst.index());
+    }
+
+    fn polynomial_mul(&mut self, upper: bool, dst: VReg, lhs: VReg, rhs: VReg) {
+        assert!(
+            lhs.arrangement() == rhs.arrangement(),
+            "Both sources must have the same arrangement"
+        );
+        let size = match (lhs.arrangement(), dst.arrangement()) {
+            (Arrangement::T16B, Arrangement::T8H) => 0b00,
+            (Arrangement::T2D, Arrangement::T2D) => {
+                assert!(
+                    self.features.pmull,
+                    "64-bit polynomial multiplication requires the PMULL extension"
+                );
+                0b11
+            }
+            _ => panic!("Sources must be 16B with an 8H destination or 2D with a 2D destination"),
+        };
+        self.int_insn(
+            0x0E20E000
+                | ((upper as u32) << 30)
+                | (size << 22)
+                | (rhs.index() << 16)
+                | (lhs.index() << 5)
+                | dst.index(),
+        );
+    }
+}
+
+fn check_4s(regs: &[VReg]) {
+    assert!(
+        regs.iter().all(|reg| reg.arrangement() == Arrangement::T4S),
+        "SHA instructions operate on 4S registers"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::{
+        features::Features,
+        reg::v_reg,
+        routine::tests::{assemble, assemble_with},
+    };
+    use Arrangement::*;
+
+    #[test]
+    fn checksums() {
+        let words = assemble(|r| {
+            r.crc32b(Reg::W0, Reg::W1, Reg::W2);
+            r.crc32h(Reg::W3, Reg::W4, Reg::W5);
+            r.crc32w(Reg::W6, Reg::W7, Reg::W8);
+            r.crc32x(Reg::W9, Reg::W10, Reg::X11);
+            r.crc32cb(Reg::W12, Reg::W13, Reg::W14);
+            r.crc32ch(Reg::W15, Reg::W16, Reg::W17);
+            r.crc32cw(Reg::W18, Reg::W19, Reg::W20);
+            r.crc32cx(Reg::W21, Reg::W22, Reg::X23);
+        });
+        assert_eq!(
+            words,
+            [
+                0x1AC24020, // crc32b w0, w1, w2
+                0x1AC54483, // crc32h w3, w4, w5
+                0x1AC848E6, // crc32w w6, w7, w8
+                0x9ACB4D49, // crc32x w9, w10, x11
+                0x1ACE51AC, // crc32cb w12, w13, w14
+                0x1AD1560F, // crc32ch w15, w16, w17
+                0x1AD45A72, // crc32cw w18, w19, w20
+                0x9AD75ED5, // crc32cx w21, w22, x23
+            ]
+        );
+    }
+
+    #[test]
+    fn aes() {
+        let words = assemble(|r| {
+            r.aese(v_reg(0, T16B), v_reg(1, T16B));
+            r.aesd(v_reg(2, T16B), v_reg(3, T16B));
+            r.aesmc(v_reg(4, T16B), v_reg(5, T16B));
+            r.aesimc(v_reg(6, T16B), v_reg(7, T16B));
+        });
+        assert_eq!(
+            words,
+            [
+                0x4E284820, // aese v0.16b, v1.16b
+                0x4E285862, // aesd v2.16b, v3.16b
+                0x4E2868A4, // aesmc v4.16b, v5.16b
+                0x4E2878E6, // aesimc v6.16b, v7.16b
+            ]
+        );
+    }
+
+    #[test]
+    fn sha() {
+        let words = assemble(|r| {
+            r.sha1c(v_reg(0, T4S), FReg::S1, v_reg(2, T4S));
+            r.sha1p(v_reg(3, T4S), FReg::S4, v_reg(5, T4S));
+            r.sha1m(v_reg(6, T4S), FReg::S7, v_reg(8, T4S));
+            r.sha1h(FReg::S9, FReg::S10);
+            r.sha1su0(v_reg(11, T4S), v_reg(12, T4S), v_reg(13, T4S));
+            r.sha1su1(v_reg(14, T4S), v_reg(15, T4S));
+            r.sha256h(v_reg(16, T4S), v_reg(17, T4S), v_reg(18, T4S));
+            r.sha256h2(v_reg(19, T4S), v_reg(20, T4S), v_reg(21, T4S));
+            r.sha256su0(v_reg(22, T4S), v_reg(23, T4S));
+            r.sha256su1(v_reg(24, T4S), v_reg(25, T4S), v_reg(26, T4S));
+        });
+        assert_eq!(
+            words,
+            [
+                0x5E020020, // sha1c q0, s1, v2.4s
+                0x5E051083, // sha1p q3, s4, v5.4s
+                0x5E0820E6, // sha1m q6, s7, v8.4s
+                0x5E280949, // sha1h s9, s10
+                0x5E0D318B, // sha1su0 v11.4s, v12.4s, v13.4s
+                0x5E2819EE, // sha1su1 v14.4s, v15.4s
+                0x5E124230, // sha256h q16, q17, v18.4s
+                0x5E155293, // sha256h2 q19, q20, v21.4s
+                0x5E282AF6, // sha256su0 v22.4s, v23.4s
+                0x5E1A6338, // sha256su1 v24.4s, v25.4s, v26.4s
+            ]
+        );
+    }
+
+    #[test]
+    fn polynomial_multiplication() {
+        let words = assemble(|r| {
+            r.pmull(v_reg(0, T8H), v_reg(1, T16B), v_reg(2, T16B));
+            r.pmull2(v_reg(3, T8H), v_reg(4, T16B), v_reg(5, T16B));
+            r.pmull(v_reg(6, T2D), v_reg(7, T2D), v_reg(8, T2D));
+            r.pmull2(v_reg(9, T2D), v_reg(10, T2D), v_reg(11, T2D));
+        });
+        assert_eq!(
+            words,
+            [
+                0x0E22E020, // pmull v0.8h, v1.8b, v2.8b
+                0x4E25E083, // pmull2 v3.8h, v4.16b, v5.16b
+                0x0EE8E0E6, // pmull v6.1q, v7.1d, v8.1d
+                0x4EEBE149, // pmull2 v9.1q, v10.2d, v11.2d
+            ]
+        );
+    }
+
+    #[test]
+    fn byte_polynomial_multiplication_without_extensions() {
+        let words = assemble_with(Features::default(), |r| {
+            r.pmull(v_reg(0, T8H), v_reg(1, T16B), v_reg(2, T16B));
+        });
+        assert_eq!(
+            words,
+            [
+                0x0E22E020, // pmull v0.8h, v1.8b, v2.8b
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "CRC32 instructions require the CRC extension")]
+    fn checksum_without_crc() {
+        assemble_with(Features::default(), |r| {
+            r.crc32cw(Reg::W0, Reg::W1, Reg::W2)
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "AES instructions require the AES extension")]
+    fn aes_without_aes() {
+        assemble_with(Features::default(), |r| {
+            r.aesmc(v_reg(0, T16B), v_reg(1, T16B))
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "SHA1 instructions require the SHA1 extension")]
+    fn sha1_without_sha1() {
+        assemble_with(Features::default(), |r| r.sha1h(FReg::S0, FReg::S1));
+    }
+
+    #[test]
+    #[should_panic(expected = "SHA256 instructions require the SHA2 extension")]
+    fn sha256_without_sha2() {
+        assemble_with(Features::default(), |r| {
+            r.sha256su0(v_reg(0, T4S), v_reg(1, T4S))
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "64-bit polynomial multiplication requires the PMULL extension")]
+    fn wide_polynomial_multiplication_without_pmull() {
+        assemble_with(Features::default(), |r| {
+            r.pmull2(v_reg(0, T2D), v_reg(1, T2D), v_reg(2, T2D))
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Checksum registers must be 32-bit")]
+    fn checksum_in_64_bit_register() {
+        assemble(|r| r.crc32x(Reg::X0, Reg::W1, Reg::X2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Data register must be 64-bit for 64-bit data and 32-bit otherwise")]
+    fn byte_data_in_64_bit_register() {
+        assemble(|r| r.crc32b(Reg::W0, Reg::W1, Reg::X2));
+    }
+
+    #[test]
+    #[should_panic(expected = "AES instructions operate on 16B registers")]
+    fn aes_on_words() {
+        assemble(|r| r.aese(v_reg(0, T4S), v_reg(1, T4S)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Hash element e must be a single-precision view")]
+    fn sha1_element_in_double_precision_view() {
+        assemble(|r| r.sha1c(v_reg(0, T4S), FReg::D1, v_reg(2, T4S)));
+    }
+
+    #[test]
+    #[should_panic(expected = "SHA instructions operate on 4S registers")]
+    fn sha256_on_bytes() {
+        assemble(|r| r.sha256h(v_reg(0, T4S), v_reg(1, T16B), v_reg(2, T4S)));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Sources must be 16B with an 8H destination or 2D with a 2D destination"
+    )]
+    fn polynomial_multiplication_of_halfwords() {
+        assemble(|r| r.pmull(v_reg(0, T4S), v_reg(1, T8H), v_reg(2, T8H)));
+    }
+}
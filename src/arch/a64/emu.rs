@@ -0,0 +1,1379 @@
+use super::{bitmask, float};
+use std::collections::HashMap;
+
+/// Default size of the flat memory of an [`Emulator`] including the image
+pub const DEFAULT_MEMORY_SIZE: usize = 0x10_0000;
+
+/// Address `X30` is set to before a call, returning to it stops the emulator
+pub const RETURN_ADDRESS: u64 = 0xFFFF_FFFF_FFFF_FFFC;
+
+/// Default amount of instructions executed before a call is aborted
+pub const DEFAULT_STEP_LIMIT: usize = 1_000_000;
+
+pub type Hook = Box<dyn FnMut(&mut Emulator)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// The instruction at `pc` is not supported by the emulator
+    Undefined { pc: u64, insn: u32 },
+    /// An access of `size` bytes at `address` lies outside of the flat memory
+    Unmapped { address: u64, size: usize },
+    /// The called symbol is not present in the V-Table
+    UnknownSymbol(String),
+    /// More than the configured amount of instructions were executed
+    StepLimit,
+}
+
+/// Interpreter for the integer and scalar floating-point subset of AArch64 emitted by this crate
+///
+/// The image produced by `virtual_jit` is placed at address 0 of a flat, zero-initialized memory
+/// and the stack grows down from the end of that memory.
+pub struct Emulator {
+    regs: [u64; 31],
+    vregs: [u128; 32],
+    sp: u64,
+    pc: u64,
+    nzcv: u8,
+    memory: Vec<u8>,
+    symbols: HashMap<String, usize>,
+    hooks: HashMap<u64, Hook>,
+    step_limit: usize,
+    /// Address marked for exclusive access by the last load-exclusive
+    exclusive: Option<u64>,
+}
+
+impl Emulator {
+    pub fn new(image: Vec<u8>, symbols: HashMap<String, usize>) -> Self {
+        Self::with_memory_size(image, symbols, DEFAULT_MEMORY_SIZE)
+    }
+
+    pub fn with_memory_size(
+        mut image: Vec<u8>,
+        symbols: HashMap<String, usize>,
+        size: usize,
+    ) -> Self {
+        assert!(image.len() <= size, "Image does not fit into memory");
+        image.resize(size, 0);
+        Self {
+            regs: [0; 31],
+            vregs: [0; 32],
+            sp: 0,
+            pc: 0,
+            nzcv: 0,
+            memory: image,
+            symbols,
+            hooks: HashMap::with_capacity(0),
+            step_limit: DEFAULT_STEP_LIMIT,
+            exclusive: None,
+        }
+    }
+
+    /// Calls `hook` instead of executing code whenever `address` is branched to
+    ///
+    /// After the hook returns, execution continues at the address stored in `X30`, so a hook
+    /// behaves like a routine following the calling convention. Addresses outside of the memory
+    /// can be used for host functions that are loaded from the constant pool.
+    pub fn hook(&mut self, address: u64, hook: impl FnMut(&mut Emulator) + 'static) {
+        self.hooks.insert(address, Box::new(hook));
+    }
+
+    /// Replaces the routine with the given label by `hook`
+    pub fn hook_symbol(&mut self, label: &str, hook: impl FnMut(&mut Emulator) + 'static) {
+        let Some(&address) = self.symbols.get(label) else {
+            panic!("Tried to hook non-existent label");
+        };
+        self.hook(address as u64, hook);
+    }
+
+    pub fn set_step_limit(&mut self, step_limit: usize) {
+        self.step_limit = step_limit;
+    }
+
+    /// Calls the routine with the given label passing `args` in `X0` to `X7`
+    ///
+    /// Returns the value of `X0` after the routine returned
+    pub fn call(&mut self, label: &str, args: &[u64]) -> Result<u64, Fault> {
+        assert!(args.len() <= 8, "At most 8 arguments can be passed");
+        let Some(&address) = self.symbols.get(label) else {
+            return Err(Fault::UnknownSymbol(label.to_string()));
+        };
+        for (index, arg) in args.iter().enumerate() {
+            self.regs[index] = *arg;
+        }
+        self.sp = self.memory.len() as u64 & !0xF;
+        self.regs[30] = RETURN_ADDRESS;
+        self.pc = address as u64;
+        self.run()?;
+        Ok(self.regs[0])
+    }
+
+    /// Executes instructions until the return address is reached
+    pub fn run(&mut self) -> Result<(), Fault> {
+        for _ in 0..self.step_limit {
+            if self.pc == RETURN_ADDRESS {
+                return Ok(());
+            }
+            if let Some(mut hook) = self.hooks.remove(&self.pc) {
+                let pc = self.pc;
+                hook(self);
+                self.hooks.insert(pc, hook);
+                self.pc = self.regs[30];
+                continue;
+            }
+            self.step()?;
+        }
+        Err(Fault::StepLimit)
+    }
+
+    /// Executes a single instruction
+    pub fn step(&mut self) -> Result<(), Fault> {
+        let pc = self.pc;
+        let insn = self.read(pc, 4)? as u32;
+        self.pc = pc.wrapping_add(4);
+        let handled = match (insn >> 25) & 0xF {
+            0b1000 | 0b1001 => self.exec_data_imm(insn),
+            0b1010 | 0b1011 => self.exec_branch(pc, insn)?,
+            0b0100 | 0b0110 | 0b1100 | 0b1110 => self.exec_load_store(pc, insn)?,
+            0b0101 | 0b1101 => self.exec_data_reg(insn),
+            0b0111 | 0b1111 => self.exec_fp(insn),
+            _ => false,
+        };
+        if handled {
+            Ok(())
+        } else {
+            Err(Fault::Undefined { pc, insn })
+        }
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u64 {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, value: u64) {
+        self.sp = value;
+    }
+
+    /// Returns the value of `X<index>`, 31 being the zero register
+    pub fn reg(&self, index: usize) -> u64 {
+        self.get(index as u32, true)
+    }
+
+    /// Sets the value of `X<index>`, writes to register 31 are ignored
+    pub fn set_reg(&mut self, index: usize, value: u64) {
+        self.set(index as u32, true, value);
+    }
+
+    /// Returns the 128 bits of `V<index>`
+    pub fn vreg(&self, index: usize) -> u128 {
+        self.vregs[index]
+    }
+
+    pub fn set_vreg(&mut self, index: usize, value: u128) {
+        self.vregs[index] = value;
+    }
+
+    /// Returns the flags as `0bNZCV`
+    pub fn nzcv(&self) -> u8 {
+        self.nzcv
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// Reads a little-endian value of `size` bytes
+    pub fn read(&self, address: u64, size: usize) -> Result<u64, Fault> {
+        let range = self.range(address, size)?;
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(&self.memory[range]);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Writes the lowest `size` bytes of `value` in little-endian order
+    pub fn write(&mut self, address: u64, size: usize, value: u64) -> Result<(), Fault> {
+        let range = self.range(address, size)?;
+        self.memory[range].copy_from_slice(&value.to_le_bytes()[..size]);
+        Ok(())
+    }
+
+    fn range(&self, address: u64, size: usize) -> Result<std::ops::Range<usize>, Fault> {
+        let start = address as usize;
+        match start.checked_add(size) {
+            Some(end) if end <= self.memory.len() => Ok(start..end),
+            _ => Err(Fault::Unmapped { address, size }),
+        }
+    }
+
+    /// Reads a register where 31 is the zero register
+    fn get(&self, index: u32, sf: bool) -> u64 {
+        let value = match index & 0x1F {
+            31 => 0,
+            index => self.regs[index as usize],
+        };
+        truncate(value, sf)
+    }
+
+    /// Reads a register where 31 is the stack pointer
+    fn get_sp(&self, index: u32, sf: bool) -> u64 {
+        match index & 0x1F {
+            31 => truncate(self.sp, sf),
+            index => self.get(index, sf),
+        }
+    }
+
+    /// Writes a register where 31 is the zero register
+    fn set(&mut self, index: u32, sf: bool, value: u64) {
+        if index & 0x1F != 31 {
+            self.regs[(index & 0x1F) as usize] = truncate(value, sf);
+        }
+    }
+
+    /// Writes a register where 31 is the stack pointer
+    fn set_sp_reg(&mut self, index: u32, sf: bool, value: u64) {
+        match index & 0x1F {
+            31 => self.sp = truncate(value, sf),
+            index => self.set(index, sf, value),
+        }
+    }
+
+    fn condition(&self, cond: u32) -> bool {
+        let n = self.nzcv & 0b1000 != 0;
+        let z = self.nzcv & 0b0100 != 0;
+        let c = self.nzcv & 0b0010 != 0;
+        let v = self.nzcv & 0b0001 != 0;
+        let result = match (cond >> 1) & 0x7 {
+            0 => z,
+            1 => c,
+            2 => n,
+            3 => v,
+            4 => c && !z,
+            5 => n == v,
+            6 => n == v && !z,
+            _ => true,
+        };
+        if cond & 1 != 0 && cond != 0xF {
+            !result
+        } else {
+            result
+        }
+    }
+
+    /// Adds with carry and returns the result and the resulting flags
+    /// Reads the scalar view of a SIMD&FP register, `ftype` 0 being single and 1 double
+    /// precision
+    fn get_fp(&self, index: u32, ftype: u32) -> f64 {
+        let bits = self.vregs[(index & 0x1F) as usize] as u64;
+        match ftype {
+            0 => f32::from_bits(bits as u32) as f64,
+            _ => f64::from_bits(bits),
+        }
+    }
+
+    /// Writes the scalar view of a SIMD&FP register rounding to its precision
+    fn set_fp(&mut self, index: u32, ftype: u32, value: f64) {
+        let bits = match ftype {
+            0 => (value as f32).to_bits() as u64,
+            _ => value.to_bits(),
+        };
+        self.set_fp_bits(index, bits);
+    }
+
+    /// Writes the bits to a SIMD&FP register clearing the bits above them
+    fn set_fp_bits(&mut self, index: u32, bits: u64) {
+        self.vregs[(index & 0x1F) as usize] = bits as u128;
+    }
+
+    fn add_with_carry(&self, lhs: u64, rhs: u64, carry: bool, sf: bool) -> (u64, u8) {
+        let (lhs, rhs) = (truncate(lhs, sf), truncate(rhs, sf));
+        let unsigned = lhs as u128 + rhs as u128 + carry as u128;
+        let result = truncate(unsigned as u64, sf);
+        let (sign, carried) = if sf {
+            (result >> 63, unsigned >> 64 != 0)
+        } else {
+            (result >> 31, unsigned >> 32 != 0)
+        };
+        let sign_bit = if sf { 63 } else { 31 };
+        let overflow = ((lhs ^ result) & (rhs ^ result)) >> sign_bit & 1 != 0;
+        let flags = ((sign as u8) << 3)
+            | (((result == 0) as u8) << 2)
+            | ((carried as u8) << 1)
+            | overflow as u8;
+        (result, flags)
+    }
+
+    fn logical_flags(result: u64, sf: bool) -> u8 {
+        let sign = if sf { result >> 63 } else { (result >> 31) & 1 };
+        ((sign as u8) << 3) | (((result == 0) as u8) << 2)
+    }
+
+    fn exec_data_imm(&mut self, insn: u32) -> bool {
+        let sf = insn >> 31 != 0;
+        let rd = insn & 0x1F;
+        let rn = (insn >> 5) & 0x1F;
+        match (insn >> 23) & 0x7 {
+            // PC-relative addressing
+            0b000 | 0b001 => {
+                let immlo = ((insn >> 29) & 0x3) as i64;
+                let immhi = sign_extend(((insn >> 5) & 0x7FFFF) as u64, 19);
+                let imm = (immhi << 2) | immlo;
+                let pc = self.pc.wrapping_sub(4);
+                let value = if insn >> 31 == 0 {
+                    pc.wrapping_add(imm as u64)
+                } else {
+                    (pc & !0xFFF).wrapping_add((imm << 12) as u64)
+                };
+                self.set(rd, true, value);
+                true
+            }
+            // Add/subtract (immediate)
+            0b010 => {
+                let sub = insn & (1 << 30) != 0;
+                let set_flags = insn & (1 << 29) != 0;
+                let mut imm = ((insn >> 10) & 0xFFF) as u64;
+                if insn & (1 << 22) != 0 {
+                    imm <<= 12;
+                }
+                let lhs = self.get_sp(rn, sf);
+                let (result, flags) = if sub {
+                    self.add_with_carry(lhs, !imm, true, sf)
+                } else {
+                    self.add_with_carry(lhs, imm, false, sf)
+                };
+                if set_flags {
+                    self.nzcv = flags;
+                    self.set(rd, sf, result);
+                } else {
+                    self.set_sp_reg(rd, sf, result);
+                }
+                true
+            }
+            // Logical (immediate)
+            0b100 => {
+                let Some(imm) = bitmask::decode((insn >> 10) & 0x1FFF, sf) else {
+                    return false;
+                };
+                let lhs = self.get(rn, sf);
+                let result = match (insn >> 29) & 0x3 {
+                    0b00 | 0b11 => lhs & imm,
+                    0b01 => lhs | imm,
+                    _ => lhs ^ imm,
+                };
+                if (insn >> 29) & 0x3 == 0b11 {
+                    self.nzcv = Self::logical_flags(result, sf);
+                    self.set(rd, sf, result);
+                } else {
+                    self.set_sp_reg(rd, sf, result);
+                }
+                true
+            }
+            // Move wide (immediate)
+            0b101 => {
+                let hw = (insn >> 21) & 0x3;
+                if !sf && hw > 1 {
+                    return false;
+                }
+                let shift = hw * 16;
+                let imm = ((insn >> 5) & 0xFFFF) as u64;
+                let value = match (insn >> 29) & 0x3 {
+                    0b00 => !(imm << shift),
+                    0b10 => imm << shift,
+                    0b11 => (self.get(rd, true) & !(0xFFFF << shift)) | (imm << shift),
+                    _ => return false,
+                };
+                self.set(rd, sf, value);
+                true
+            }
+            // Bitfield
+            0b110 => {
+                let bits = if sf { 64 } else { 32 };
+                let immr = (insn >> 16) & 0x3F;
+                let imms = (insn >> 10) & 0x3F;
+                let opc = (insn >> 29) & 0x3;
+                if opc == 0b11 || (insn >> 22) & 1 != sf as u32 || immr >= bits || imms >= bits {
+                    return false;
+                }
+                let src = self.get(rn, sf);
+                // Width of the field and the position it is moved to
+                let (field, width, lsb) = if imms >= immr {
+                    (src >> immr, imms - immr + 1, 0)
+                } else {
+                    (src, imms + 1, bits - immr)
+                };
+                let mask = low_mask(width) << lsb;
+                let field = (field & low_mask(width)) << lsb;
+                let result = match opc {
+                    0b00 => sign_extend(field, lsb + width) as u64,
+                    0b01 => (self.get(rd, sf) & !mask) | field,
+                    _ => field,
+                };
+                self.set(rd, sf, result);
+                true
+            }
+            // Extract
+            0b111 => {
+                let bits = if sf { 64 } else { 32 };
+                let lsb = (insn >> 10) & 0x3F;
+                if (insn >> 29) & 0x3 != 0 || (insn >> 22) & 1 != sf as u32 || lsb >= bits {
+                    return false;
+                }
+                let (high, low) = (self.get((insn >> 16) & 0x1F, sf), self.get(rn, sf));
+                let result = if lsb == 0 {
+                    low
+                } else {
+                    (low >> lsb) | (high << (bits - lsb))
+                };
+                self.set(rd, sf, result);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn exec_branch(&mut self, pc: u64, insn: u32) -> Result<bool, Fault> {
+        // Unconditional branch (immediate)
+        if insn & 0x7C000000 == 0x14000000 {
+            let offset = sign_extend((insn & 0x3FFFFFF) as u64, 26) << 2;
+            if insn >> 31 != 0 {
+                self.regs[30] = pc.wrapping_add(4);
+            }
+            self.pc = pc.wrapping_add(offset as u64);
+            return Ok(true);
+        }
+        // Compare and branch (immediate)
+        if insn & 0x7E000000 == 0x34000000 {
+            let sf = insn >> 31 != 0;
+            let value = self.get(insn & 0x1F, sf);
+            let offset = sign_extend(((insn >> 5) & 0x7FFFF) as u64, 19) << 2;
+            if (value == 0) != (insn & (1 << 24) != 0) {
+                self.pc = pc.wrapping_add(offset as u64);
+            }
+            return Ok(true);
+        }
+        // Test and branch (immediate)
+        if insn & 0x7E000000 == 0x36000000 {
+            let bit = ((insn >> 31) << 5) | ((insn >> 19) & 0x1F);
+            let value = self.get(insn & 0x1F, true) >> bit & 1;
+            let offset = sign_extend(((insn >> 5) & 0x3FFF) as u64, 14) << 2;
+            if (value == 0) != (insn & (1 << 24) != 0) {
+                self.pc = pc.wrapping_add(offset as u64);
+            }
+            return Ok(true);
+        }
+        // Conditional branch (immediate)
+        if insn & 0xFF000010 == 0x54000000 {
+            let offset = sign_extend(((insn >> 5) & 0x7FFFF) as u64, 19) << 2;
+            if self.condition(insn & 0xF) {
+                self.pc = pc.wrapping_add(offset as u64);
+            }
+            return Ok(true);
+        }
+        // Barriers and clearing the exclusive mark, which have no effect on a single core
+        if insn & 0xFFFFF01F == 0xD503301F {
+            match (insn >> 5) & 0x7 {
+                0b010 => self.exclusive = None,
+                0b100..=0b110 => {}
+                _ => return Ok(false),
+            }
+            return Ok(true);
+        }
+        // Hints
+        if insn & 0xFFFFF01F == 0xD503201F {
+            return Ok(true);
+        }
+        // Unconditional branch (register)
+        if insn & 0xFF9FFC1F == 0xD61F0000 {
+            let target = self.get((insn >> 5) & 0x1F, true);
+            match (insn >> 21) & 0x3 {
+                0b00 | 0b10 => {}
+                _ => self.regs[30] = pc.wrapping_add(4),
+            }
+            self.pc = target;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn exec_load_store(&mut self, pc: u64, insn: u32) -> Result<bool, Fault> {
+        // Load SIMD&FP register (literal)
+        if insn & 0x3F000000 == 0x1C000000 {
+            let offset = sign_extend(((insn >> 5) & 0x7FFFF) as u64, 19) << 2;
+            let address = pc.wrapping_add(offset as u64);
+            let size = match insn >> 30 {
+                0b00 => 4,
+                0b01 => 8,
+                _ => return Ok(false),
+            };
+            let value = self.read(address, size)?;
+            self.set_fp_bits(insn & 0x1F, value);
+            return Ok(true);
+        }
+        // Other SIMD&FP loads and stores are not supported except for scalars up to 64 bits
+        let simd = insn & (1 << 26) != 0;
+        if simd && (insn & 0x3A000000 != 0x38000000 || insn & (1 << 23) != 0) {
+            return Ok(false);
+        }
+        // Load register (literal)
+        if insn & 0x3B000000 == 0x18000000 {
+            let offset = sign_extend(((insn >> 5) & 0x7FFFF) as u64, 19) << 2;
+            let address = pc.wrapping_add(offset as u64);
+            let rt = insn & 0x1F;
+            match insn >> 30 {
+                0b00 => {
+                    let value = self.read(address, 4)?;
+                    self.set(rt, false, value);
+                }
+                0b01 => {
+                    let value = self.read(address, 8)?;
+                    self.set(rt, true, value);
+                }
+                0b10 => {
+                    let value = sign_extend(self.read(address, 4)?, 32);
+                    self.set(rt, true, value as u64);
+                }
+                _ => {}
+            }
+            return Ok(true);
+        }
+        // Load/store exclusive and ordered, compare and swap
+        if insn & 0x3F000000 == 0x08000000 {
+            return self.exec_exclusive(insn);
+        }
+        // Atomic memory operations
+        if insn & 0x3F200C00 == 0x38200000 {
+            return self.exec_atomic(insn);
+        }
+        // Load/store register pair
+        if insn & 0x3A000000 == 0x28000000 {
+            return self.exec_load_store_pair(insn);
+        }
+        // Load/store register
+        if insn & 0x3A000000 == 0x38000000 {
+            let size = insn >> 30;
+            let opc = (insn >> 22) & 0x3;
+            let rn = (insn >> 5) & 0x1F;
+            let base = self.get_sp(rn, true);
+            let (address, write_back) = if insn & (1 << 24) != 0 {
+                // Unsigned immediate
+                let imm = ((insn >> 10) & 0xFFF) as u64;
+                (base.wrapping_add(imm << size), None)
+            } else if insn & (1 << 21) == 0 {
+                // Signed 9-bit immediate
+                let imm = sign_extend(((insn >> 12) & 0x1FF) as u64, 9) as u64;
+                match (insn >> 10) & 0x3 {
+                    0b00 | 0b10 => (base.wrapping_add(imm), None),
+                    0b01 => (base, Some(base.wrapping_add(imm))),
+                    _ => (base.wrapping_add(imm), Some(base.wrapping_add(imm))),
+                }
+            } else if (insn >> 10) & 0x3 == 0b10 {
+                // Register offset
+                let option = (insn >> 13) & 0x7;
+                let offset = self.extend_reg((insn >> 16) & 0x1F, option, 0);
+                let shift = if insn & (1 << 12) != 0 { size } else { 0 };
+                (base.wrapping_add(offset << shift), None)
+            } else {
+                return Ok(false);
+            };
+            let bytes = 1 << size;
+            let rt = insn & 0x1F;
+            if simd {
+                if opc == 0b00 {
+                    self.write(address, bytes, self.vregs[rt as usize] as u64)?;
+                } else {
+                    let value = self.read(address, bytes)?;
+                    self.set_fp_bits(rt, value);
+                }
+                if let Some(address) = write_back {
+                    self.set_sp_reg(rn, true, address);
+                }
+                return Ok(true);
+            }
+            match opc {
+                0b00 => {
+                    let value = self.get(rt, true);
+                    self.write(address, bytes, value)?;
+                }
+                0b01 => {
+                    let value = self.read(address, bytes)?;
+                    self.set(rt, true, value);
+                }
+                0b10 if size < 3 => {
+                    let value = sign_extend(self.read(address, bytes)?, 8 << size);
+                    self.set(rt, true, value as u64);
+                }
+                0b11 if size < 2 => {
+                    let value = sign_extend(self.read(address, bytes)?, 8 << size);
+                    self.set(rt, false, value as u64);
+                }
+                // Prefetches have no architectural effect
+                0b10 if write_back.is_none() => return Ok(true),
+                _ => return Ok(false),
+            }
+            if let Some(address) = write_back {
+                self.set_sp_reg(rn, true, address);
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn exec_exclusive(&mut self, insn: u32) -> Result<bool, Fault> {
+        let size = insn >> 30;
+        let bytes = 1 << size;
+        let rs = (insn >> 16) & 0x1F;
+        let rn = (insn >> 5) & 0x1F;
+        let rt = insn & 0x1F;
+        let address = self.get_sp(rn, true);
+        let load = insn & (1 << 22) != 0;
+        match ((insn >> 23) & 1, (insn >> 21) & 1) {
+            // Exclusive accesses
+            (0, 0) => {
+                if load {
+                    let value = self.read(address, bytes)?;
+                    self.exclusive = Some(address);
+                    self.set(rt, true, value);
+                } else {
+                    let marked = self.exclusive.take() == Some(address);
+                    if marked {
+                        let value = self.get(rt, true);
+                        self.write(address, bytes, value)?;
+                    }
+                    self.set(rs, false, !marked as u64);
+                }
+            }
+            // Load-acquire and store-release
+            (1, 0) => {
+                if load {
+                    let value = self.read(address, bytes)?;
+                    self.set(rt, true, value);
+                } else {
+                    let value = self.get(rt, true);
+                    self.write(address, bytes, value)?;
+                }
+            }
+            // Compare and swap
+            (1, 1) => {
+                let old = self.read(address, bytes)?;
+                if old == self.get(rs, true) & low_mask(bytes as u32 * 8) {
+                    let value = self.get(rt, true);
+                    self.write(address, bytes, value)?;
+                }
+                self.set(rs, true, old);
+            }
+            // Compare and swap pair
+            (0, 1) if size < 2 => {
+                let bytes = 4 << size;
+                let mask = low_mask(bytes as u32 * 8);
+                let old = [
+                    self.read(address, bytes)?,
+                    self.read(address + bytes as u64, bytes)?,
+                ];
+                if old[0] == self.get(rs, true) & mask && old[1] == self.get(rs + 1, true) & mask {
+                    let values = [self.get(rt, true), self.get(rt + 1, true)];
+                    self.write(address, bytes, values[0])?;
+                    self.write(address + bytes as u64, bytes, values[1])?;
+                }
+                self.set(rs, true, old[0]);
+                self.set(rs + 1, true, old[1]);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn exec_atomic(&mut self, insn: u32) -> Result<bool, Fault> {
+        let bytes = 1 << (insn >> 30);
+        let rs = (insn >> 16) & 0x1F;
+        let rt = insn & 0x1F;
+        let address = self.get_sp((insn >> 5) & 0x1F, true);
+        let old = self.read(address, bytes)?;
+        let value = self.get(rs, true);
+        let new = match (insn >> 12) & 0xF {
+            0b0000 => old.wrapping_add(value),
+            0b0001 => old & !value,
+            0b0010 => old ^ value,
+            0b0011 => old | value,
+            0b1000 => value,
+            _ => return Ok(false),
+        };
+        self.write(address, bytes, new)?;
+        self.set(rt, true, old);
+        Ok(true)
+    }
+
+    fn exec_load_store_pair(&mut self, insn: u32) -> Result<bool, Fault> {
+        let (bytes, signed) = match insn >> 30 {
+            0b00 => (4, false),
+            0b01 => (4, true),
+            0b10 => (8, false),
+            _ => return Ok(false),
+        };
+        let load = insn & (1 << 22) != 0;
+        if signed && !load {
+            return Ok(false);
+        }
+        let rt = insn & 0x1F;
+        let rn = (insn >> 5) & 0x1F;
+        let rt2 = (insn >> 10) & 0x1F;
+        let imm = (sign_extend(((insn >> 15) & 0x7F) as u64, 7) * bytes as i64) as u64;
+        let base = self.get_sp(rn, true);
+        let (address, write_back) = match (insn >> 23) & 0x3 {
+            0b01 => (base, Some(base.wrapping_add(imm))),
+            0b11 => (base.wrapping_add(imm), Some(base.wrapping_add(imm))),
+            _ => (base.wrapping_add(imm), None),
+        };
+        let sf = bytes == 8 || signed;
+        for (index, reg) in [rt, rt2].into_iter().enumerate() {
+            let address = address.wrapping_add((index * bytes) as u64);
+            if load {
+                let mut value = self.read(address, bytes)?;
+                if signed {
+                    value = sign_extend(value, 32) as u64;
+                }
+                self.set(reg, sf, value);
+            } else {
+                let value = self.get(reg, true);
+                self.write(address, bytes, value)?;
+            }
+        }
+        if let Some(address) = write_back {
+            self.set_sp_reg(rn, true, address);
+        }
+        Ok(true)
+    }
+
+    fn exec_data_reg(&mut self, insn: u32) -> bool {
+        let sf = insn >> 31 != 0;
+        let rd = insn & 0x1F;
+        let rn = (insn >> 5) & 0x1F;
+        let rm = (insn >> 16) & 0x1F;
+        // Logical (shifted register)
+        if insn & 0x1F000000 == 0x0A000000 {
+            let amount = (insn >> 10) & 0x3F;
+            if !sf && amount > 31 {
+                return false;
+            }
+            let mut rhs = shift(self.get(rm, sf), (insn >> 22) & 0x3, amount, sf);
+            if insn & (1 << 21) != 0 {
+                rhs = truncate(!rhs, sf);
+            }
+            let lhs = self.get(rn, sf);
+            let result = match (insn >> 29) & 0x3 {
+                0b00 | 0b11 => lhs & rhs,
+                0b01 => lhs | rhs,
+                _ => lhs ^ rhs,
+            };
+            if (insn >> 29) & 0x3 == 0b11 {
+                self.nzcv = Self::logical_flags(result, sf);
+            }
+            self.set(rd, sf, result);
+            return true;
+        }
+        // Add/subtract (shifted register)
+        if insn & 0x1F200000 == 0x0B000000 {
+            let amount = (insn >> 10) & 0x3F;
+            let kind = (insn >> 22) & 0x3;
+            if kind == 0b11 || (!sf && amount > 31) {
+                return false;
+            }
+            let rhs = shift(self.get(rm, sf), kind, amount, sf);
+            let lhs = self.get(rn, sf);
+            self.add_sub(insn, rd, lhs, rhs, sf, false);
+            return true;
+        }
+        // Add/subtract (extended register)
+        if insn & 0x1FE00000 == 0x0B200000 {
+            let amount = (insn >> 10) & 0x7;
+            if amount > 4 {
+                return false;
+            }
+            let rhs = self.extend_reg(rm, (insn >> 13) & 0x7, amount);
+            let lhs = self.get_sp(rn, sf);
+            self.add_sub(insn, rd, lhs, rhs, sf, true);
+            return true;
+        }
+        // Add/subtract (with carry)
+        if insn & 0x1FE0FC00 == 0x1A000000 {
+            let carry = self.nzcv & 0b0010 != 0;
+            let lhs = self.get(rn, sf);
+            let rhs = self.get(rm, sf);
+            let rhs = if insn & (1 << 30) != 0 { !rhs } else { rhs };
+            let (result, flags) = self.add_with_carry(lhs, rhs, carry, sf);
+            if insn & (1 << 29) != 0 {
+                self.nzcv = flags;
+            }
+            self.set(rd, sf, result);
+            return true;
+        }
+        // Conditional compare (register and immediate)
+        if insn & 0x3FE00410 == 0x3A400000 {
+            if self.condition((insn >> 12) & 0xF) {
+                let lhs = self.get(rn, sf);
+                let rhs = if insn & (1 << 11) != 0 {
+                    rm as u64
+                } else {
+                    self.get(rm, sf)
+                };
+                self.nzcv = if insn & (1 << 30) != 0 {
+                    self.add_with_carry(lhs, !rhs, true, sf).1
+                } else {
+                    self.add_with_carry(lhs, rhs, false, sf).1
+                };
+            } else {
+                self.nzcv = (insn & 0xF) as u8;
+            }
+            return true;
+        }
+        // Conditional select
+        if insn & 0x3FE00800 == 0x1A800000 {
+            let result = if self.condition((insn >> 12) & 0xF) {
+                self.get(rn, sf)
+            } else {
+                let rhs = self.get(rm, sf);
+                match ((insn >> 30) & 1, (insn >> 10) & 1) {
+                    (0, 0) => rhs,
+                    (0, _) => rhs.wrapping_add(1),
+                    (_, 0) => !rhs,
+                    _ => rhs.wrapping_neg(),
+                }
+            };
+            self.set(rd, sf, result);
+            return true;
+        }
+        // Data-processing (1 source)
+        if insn & 0x7FFF0000 == 0x5AC00000 {
+            let src = self.get(rn, sf);
+            let result = match ((insn >> 10) & 0x3F, sf) {
+                (0b000000, true) => src.reverse_bits(),
+                (0b000000, false) => (src as u32).reverse_bits() as u64,
+                (0b000001, _) => {
+                    let swapped =
+                        ((src & 0x00FF_00FF_00FF_00FF) << 8) | ((src >> 8) & 0x00FF_00FF_00FF_00FF);
+                    truncate(swapped, sf)
+                }
+                (0b000010, false) => (src as u32).swap_bytes() as u64,
+                (0b000010, true) => {
+                    let low = (src as u32).swap_bytes() as u64;
+                    let high = ((src >> 32) as u32).swap_bytes() as u64;
+                    (high << 32) | low
+                }
+                (0b000011, true) => src.swap_bytes(),
+                (0b000100, true) => src.leading_zeros() as u64,
+                (0b000100, false) => (src as u32).leading_zeros() as u64,
+                (0b000101, true) => ((src ^ (src << 1)) | 1).leading_zeros() as u64,
+                (0b000101, false) => {
+                    let src = src as u32;
+                    ((src ^ (src << 1)) | 1).leading_zeros() as u64
+                }
+                _ => return false,
+            };
+            self.set(rd, sf, result);
+            return true;
+        }
+        // Data-processing (2 source), shifts by a register
+        if insn & 0x7FE0F000 == 0x1AC02000 {
+            let bits = if sf { 64 } else { 32 };
+            let amount = (self.get(rm, true) % bits) as u32;
+            let result = shift(self.get(rn, sf), (insn >> 10) & 0x3, amount, sf);
+            self.set(rd, sf, result);
+            return true;
+        }
+        // Data-processing (2 source), the divisions
+        if insn & 0x7FE0F800 == 0x1AC00800 {
+            let (lhs, rhs) = (self.get(rn, sf), self.get(rm, sf));
+            let result = if rhs == 0 {
+                0
+            } else if insn & (1 << 10) != 0 {
+                let bits = if sf { 64 } else { 32 };
+                sign_extend(lhs, bits).wrapping_div(sign_extend(rhs, bits)) as u64
+            } else {
+                lhs / rhs
+            };
+            self.set(rd, sf, truncate(result, sf));
+            return true;
+        }
+        // Data-processing (3 source)
+        if insn & 0x7F000000 == 0x1B000000 {
+            let ra = (insn >> 10) & 0x1F;
+            let sub = insn & (1 << 15) != 0;
+            let (lhs, rhs) = (self.get(rn, true), self.get(rm, true));
+            let result = match (sf, (insn >> 21) & 0x7) {
+                (_, 0b000) => {
+                    let product = truncate(lhs.wrapping_mul(rhs), sf);
+                    let acc = self.get(ra, sf);
+                    if sub {
+                        acc.wrapping_sub(product)
+                    } else {
+                        acc.wrapping_add(product)
+                    }
+                }
+                (true, 0b001) | (true, 0b101) => {
+                    let product = if insn & (1 << 23) != 0 {
+                        (lhs as u32 as u64).wrapping_mul(rhs as u32 as u64)
+                    } else {
+                        (lhs as i32 as i64).wrapping_mul(rhs as i32 as i64) as u64
+                    };
+                    let acc = self.get(ra, true);
+                    if sub {
+                        acc.wrapping_sub(product)
+                    } else {
+                        acc.wrapping_add(product)
+                    }
+                }
+                (true, 0b010) => ((lhs as i64 as i128 * rhs as i64 as i128) >> 64) as u64,
+                (true, 0b110) => ((lhs as u128 * rhs as u128) >> 64) as u64,
+                _ => return false,
+            };
+            self.set(rd, sf, result);
+            return true;
+        }
+        false
+    }
+
+    /// Performs the add or subtract selected by bits 29 and 30 of `insn`
+    fn exec_fp(&mut self, insn: u32) -> bool {
+        let ftype = (insn >> 22) & 0x3;
+        // Half precision is not supported
+        if ftype > 1 {
+            return false;
+        }
+        let rd = insn & 0x1F;
+        let rn = (insn >> 5) & 0x1F;
+        let rm = (insn >> 16) & 0x1F;
+        let bits = if ftype == 0 { 32 } else { 64 };
+        // Conversion between floating-point and integer
+        if insn & 0x7F20FC00 == 0x1E200000 {
+            let sf = insn >> 31 != 0;
+            match (insn >> 16) & 0x1F {
+                // scvtf
+                0b00010 => {
+                    let value = sign_extend(self.get(rn, sf), if sf { 64 } else { 32 });
+                    // Rounds the integer once to the precision of the destination
+                    match ftype {
+                        0 => self.set_fp_bits(rd, (value as f32).to_bits() as u64),
+                        _ => self.set_fp_bits(rd, (value as f64).to_bits()),
+                    }
+                }
+                // ucvtf
+                0b00011 => {
+                    let value = self.get(rn, sf);
+                    match ftype {
+                        0 => self.set_fp_bits(rd, (value as f32).to_bits() as u64),
+                        _ => self.set_fp_bits(rd, (value as f64).to_bits()),
+                    }
+                }
+                // fcvtzs, where the casts saturate and turn NaN into zero
+                0b11000 => {
+                    let value = self.get_fp(rn, ftype);
+                    let result = if sf {
+                        value as i64 as u64
+                    } else {
+                        value as i32 as u32 as u64
+                    };
+                    self.set(rd, sf, result);
+                }
+                // fcvtzu
+                0b11001 => {
+                    let value = self.get_fp(rn, ftype);
+                    let result = if sf {
+                        value as u64
+                    } else {
+                        value as u32 as u64
+                    };
+                    self.set(rd, sf, result);
+                }
+                // fmov to a general-purpose register
+                0b00110 if sf == (ftype == 1) => {
+                    let value = self.vregs[rn as usize] as u64;
+                    self.set(rd, sf, value);
+                }
+                // fmov from a general-purpose register
+                0b00111 if sf == (ftype == 1) => {
+                    let value = self.get(rn, sf);
+                    self.set_fp_bits(rd, value);
+                }
+                _ => return false,
+            }
+            return true;
+        }
+        // Move of an 8-bit immediate
+        if insn & 0xFF201FE0 == 0x1E201000 {
+            let value = float::decode_fp_imm((insn >> 13) as u8);
+            self.set_fp(rd, ftype, value);
+            return true;
+        }
+        // Data-processing with two sources
+        if insn & 0xFF200C00 == 0x1E200800 {
+            let lhs = self.get_fp(rn, ftype);
+            let rhs = self.get_fp(rm, ftype);
+            // Single-precision results are exact in double precision before rounding once
+            let result = match (insn >> 12) & 0xF {
+                0b0000 => lhs * rhs,
+                0b0001 => lhs / rhs,
+                0b0010 => lhs + rhs,
+                0b0011 => lhs - rhs,
+                0b1000 => -(lhs * rhs),
+                _ => return false,
+            };
+            self.set_fp(rd, ftype, result);
+            return true;
+        }
+        // Compare, against zero if bit 3 is set
+        if insn & 0xFF20FC07 == 0x1E202000 {
+            let lhs = self.get_fp(rn, ftype);
+            let rhs = if insn & (1 << 3) != 0 {
+                0.0
+            } else {
+                self.get_fp(rm, ftype)
+            };
+            self.nzcv = match lhs.partial_cmp(&rhs) {
+                Some(std::cmp::Ordering::Equal) => 0b0110,
+                Some(std::cmp::Ordering::Less) => 0b1000,
+                Some(std::cmp::Ordering::Greater) => 0b0010,
+                None => 0b0011,
+            };
+            return true;
+        }
+        // Data-processing with one source
+        if insn & 0xFF207C00 == 0x1E204000 {
+            let value = self.vregs[rn as usize] as u64 & low_mask(bits);
+            let sign = 1 << (bits - 1);
+            match (insn >> 15) & 0x3F {
+                0b000000 => self.set_fp_bits(rd, value),
+                0b000001 => self.set_fp_bits(rd, value & !sign),
+                0b000010 => self.set_fp_bits(rd, value ^ sign),
+                0b000011 => {
+                    let value = self.get_fp(rn, ftype);
+                    self.set_fp(rd, ftype, value.sqrt());
+                }
+                // fcvt between single and double precision
+                opcode @ (0b000100 | 0b000101) => {
+                    let value = self.get_fp(rn, ftype);
+                    self.set_fp(rd, opcode & 1, value);
+                }
+                _ => return false,
+            }
+            return true;
+        }
+        false
+    }
+
+    fn add_sub(&mut self, insn: u32, rd: u32, lhs: u64, rhs: u64, sf: bool, rd_sp: bool) {
+        let set_flags = insn & (1 << 29) != 0;
+        let (result, flags) = if insn & (1 << 30) != 0 {
+            self.add_with_carry(lhs, !rhs, true, sf)
+        } else {
+            self.add_with_carry(lhs, rhs, false, sf)
+        };
+        if set_flags {
+            self.nzcv = flags;
+            self.set(rd, sf, result);
+        } else if rd_sp {
+            self.set_sp_reg(rd, sf, result);
+        } else {
+            self.set(rd, sf, result);
+        }
+    }
+
+    /// Extends the value of a register as selected by the 3-bit `option` field
+    fn extend_reg(&self, index: u32, option: u32, amount: u32) -> u64 {
+        let value = self.get(index, true);
+        let value = match option {
+            0b000 => value as u8 as u64,
+            0b001 => value as u16 as u64,
+            0b010 => value as u32 as u64,
+            0b011 => value,
+            0b100 => value as i8 as u64,
+            0b101 => value as i16 as u64,
+            0b110 => value as i32 as u64,
+            _ => value,
+        };
+        value << amount
+    }
+}
+
+fn truncate(value: u64, sf: bool) -> u64 {
+    if sf {
+        value
+    } else {
+        value & 0xFFFF_FFFF
+    }
+}
+
+/// Returns a mask of the lowest `bits` bits, where `bits` is at most 64
+fn low_mask(bits: u32) -> u64 {
+    if bits == 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+fn shift(value: u64, kind: u32, amount: u32, sf: bool) -> u64 {
+    let bits = if sf { 64 } else { 32 };
+    let amount = amount % bits;
+    let result = match kind {
+        0b00 => value << amount,
+        0b01 => value >> amount,
+        0b10 => (sign_extend(value, bits) >> amount) as u64,
+        _ if amount == 0 => value,
+        _ => (value >> amount) | (value << (bits - amount)),
+    };
+    truncate(result, sf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::a64::{
+            asm::Asm,
+            cond::Cond,
+            frame::Frame,
+            reg::{d_reg, s_reg, Reg},
+            routine::Routine,
+        },
+        assembler::Assembler,
+    };
+    use std::{cell::Cell, rc::Rc};
+
+    fn emulate(asm: Asm) -> Emulator {
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        Emulator::new(code, vtable)
+    }
+
+    fn single(build: impl FnOnce(&mut Routine)) -> Emulator {
+        let mut routine = Routine::new("f".to_string());
+        build(&mut routine);
+        let mut asm = Asm::default();
+        asm.push_routine(routine);
+        emulate(asm)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut emu = single(|r| {
+            // (x0 + x1) * x2 - 3, x1 / x2 in x3
+            r.add_reg(Reg::X0, Reg::X0, Reg::X1);
+            r.mul(Reg::X0, Reg::X0, Reg::X2);
+            r.sub_imm12(Reg::X0, Reg::X0, 3);
+            r.udiv(Reg::X3, Reg::X1, Reg::X2);
+            r.ret();
+        });
+        assert_eq!(emu.call("f", &[2, 5, 4]), Ok(25));
+        assert_eq!(emu.reg(3), 1);
+        assert_eq!(emu.call("f", &[0, 0, 7]), Ok(-3i64 as u64));
+    }
+
+    #[test]
+    fn narrow_arithmetic_truncates() {
+        let mut emu = single(|r| {
+            r.add_reg(Reg::W0, Reg::W0, Reg::W1);
+            r.ret();
+        });
+        assert_eq!(emu.call("f", &[u32::MAX as u64, 2]), Ok(1));
+    }
+
+    #[test]
+    fn immediates() {
+        for imm in [
+            0,
+            0xFFFF,
+            0x1234_0000,
+            !0,
+            0x5555_5555_5555_5555,
+            0x1234_5678_9ABC_DEF0,
+        ] {
+            let mut emu = single(|r| {
+                r.mov_imm64(Reg::X0, imm);
+                r.ret();
+            });
+            assert_eq!(emu.call("f", &[]), Ok(imm), "{imm:#x}");
+        }
+    }
+
+    #[test]
+    fn branches() {
+        // Sums 1 to x0 in a loop
+        let mut emu = single(|r| {
+            let head = r.new_label();
+            let done = r.new_label();
+            r.mov_imm16(Reg::X1, 0);
+            r.bind(head);
+            r.cbz(Reg::X0, done);
+            r.add_reg(Reg::X1, Reg::X1, Reg::X0);
+            r.sub_imm12(Reg::X0, Reg::X0, 1);
+            r.b(head);
+            r.bind(done);
+            r.mov_reg(Reg::X0, Reg::X1);
+            r.ret();
+        });
+        assert_eq!(emu.call("f", &[0]), Ok(0));
+        assert_eq!(emu.call("f", &[100]), Ok(5050));
+    }
+
+    #[test]
+    fn conditional_branches() {
+        // Returns the signed maximum of x0 and x1
+        let mut emu = single(|r| {
+            let keep = r.new_label();
+            r.cmp_reg(Reg::X0, Reg::X1);
+            r.b_cond(Cond::GE, keep);
+            r.mov_reg(Reg::X0, Reg::X1);
+            r.bind(keep);
+            r.ret();
+        });
+        assert_eq!(emu.call("f", &[3, 7]), Ok(7));
+        assert_eq!(emu.call("f", &[7, 3]), Ok(7));
+        assert_eq!(emu.call("f", &[-5i64 as u64, 2]), Ok(2));
+    }
+
+    #[test]
+    fn literal_loads() {
+        let mut asm = Asm::default();
+        let global = asm.const_64(0x1122_3344_5566_7788);
+        let mut routine = Routine::new("f".to_string());
+        let local = routine.const_32(0xCAFE);
+        routine.ldr_global_const(Reg::X0, global);
+        routine.ldr_const(Reg::W1, local);
+        routine.add_reg(Reg::X0, Reg::X0, Reg::X1);
+        routine.ret();
+        asm.push_routine(routine);
+        let mut emu = emulate(asm);
+        assert_eq!(emu.call("f", &[]), Ok(0x1122_3344_5566_7788 + 0xCAFE));
+    }
+
+    #[test]
+    fn scalar_fp_loads_and_stores() {
+        let mut emu = single(|r| {
+            r.load_store_fp(true, d_reg(1), Reg::X0, 8);
+            r.load_store_fp(false, d_reg(1), Reg::X0, 0x2001);
+            r.load_store_fp(true, s_reg(2), Reg::X0, -4);
+            r.load_store_fp(false, s_reg(2), Reg::X0, 3);
+            r.ret();
+        });
+        emu.write(0x8008, 8, 0x1122_3344_5566_7788).unwrap();
+        emu.write(0x7FFC, 4, 0xCAFE_F00D).unwrap();
+        emu.call("f", &[0x8000]).unwrap();
+        assert_eq!(emu.vreg(1), 0x1122_3344_5566_7788);
+        assert_eq!(emu.vreg(2), 0xCAFE_F00D);
+        assert_eq!(emu.read(0xA001, 8), Ok(0x1122_3344_5566_7788));
+        assert_eq!(emu.read(0x8003, 4), Ok(0xCAFE_F00D));
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        let mut asm = Asm::default();
+        let mut outer = Routine::new("outer".to_string());
+        outer.set_frame(Frame {
+            calls: true,
+            callee_saved: vec![Reg::X19],
+            ..Frame::default()
+        });
+        // outer(x) = inner(x) + inner(x + 1)
+        outer.mov_reg(Reg::X19, Reg::X0);
+        outer.br_link("inner".to_string());
+        outer.mov_reg(Reg::X1, Reg::X0);
+        outer.add_imm12(Reg::X0, Reg::X19, 1);
+        outer.mov_reg(Reg::X19, Reg::X1);
+        outer.br_link("inner".to_string());
+        outer.add_reg(Reg::X0, Reg::X0, Reg::X19);
+        outer.ret();
+        asm.push_routine(outer);
+        let mut inner = Routine::new("inner".to_string());
+        // inner(x) = x * x
+        inner.mul(Reg::X0, Reg::X0, Reg::X0);
+        inner.ret();
+        asm.push_routine(inner);
+        let mut emu = emulate(asm);
+        emu.set_reg(19, 0xDEAD);
+        assert_eq!(emu.call("outer", &[3]), Ok(9 + 16));
+        assert_eq!(emu.reg(19), 0xDEAD, "Callee-saved register was clobbered");
+        assert_eq!(emu.sp(), DEFAULT_MEMORY_SIZE as u64, "Stack is unbalanced");
+    }
+
+    #[test]
+    fn symbol_hooks() {
+        let mut asm = Asm::default();
+        let mut caller = Routine::new("caller".to_string());
+        caller.set_frame(Frame {
+            calls: true,
+            ..Frame::default()
+        });
+        caller.br_link("host".to_string());
+        caller.add_imm12(Reg::X0, Reg::X0, 1);
+        caller.ret();
+        asm.push_routine(caller);
+        let mut host = Routine::new("host".to_string());
+        host.ret();
+        asm.push_routine(host);
+        let mut emu = emulate(asm);
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        emu.hook_symbol("host", move |emu| {
+            counter.set(counter.get() + 1);
+            emu.set_reg(0, emu.reg(0) * 10);
+        });
+        assert_eq!(emu.call("caller", &[4]), Ok(41));
+        assert_eq!(emu.call("caller", &[5]), Ok(51));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn host_address_hooks() {
+        // Host functions are called through pointers from the constant pool
+        const HOST: u64 = 0x7FFF_0000_1000;
+        let mut emu = single(|r| {
+            r.set_frame(Frame {
+                calls: true,
+                ..Frame::default()
+            });
+            let pointer = r.const_64(HOST);
+            r.ldr_const(Reg::X9, pointer);
+            r.br_reg_link(Reg::X9);
+            r.ret();
+        });
+        emu.hook(HOST, |emu| emu.set_reg(0, emu.reg(0) + emu.reg(1)));
+        assert_eq!(emu.call("f", &[20, 22]), Ok(42));
+    }
+
+    #[test]
+    fn faults() {
+        let mut emu = single(|r| {
+            let head = r.new_label();
+            r.bind(head);
+            r.b(head);
+        });
+        emu.set_step_limit(100);
+        assert_eq!(emu.call("f", &[]), Err(Fault::StepLimit));
+        assert_eq!(
+            emu.call("g", &[]),
+            Err(Fault::UnknownSymbol("g".to_string()))
+        );
+
+        let mut emu = single(|r| {
+            r.mov_imm64(Reg::X1, DEFAULT_MEMORY_SIZE as u64);
+            r.ldr_uimm12_offset(Reg::X0, Reg::X1, 0);
+            r.ret();
+        });
+        assert_eq!(
+            emu.call("f", &[]),
+            Err(Fault::Unmapped {
+                address: DEFAULT_MEMORY_SIZE as u64,
+                size: 8
+            })
+        );
+    }
+}
@@ -0,0 +1,41 @@
+/// Optional architecture extensions a routine may emit instructions of
+///
+/// The default is the ARMv8.0 baseline without any extension.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features {
+    /// Large System Extensions with single instruction atomics
+    pub lse: bool,
+    /// CRC32 and CRC32C checksum instructions
+    pub crc: bool,
+    /// AES encryption and decryption rounds
+    pub aes: bool,
+    /// Polynomial multiplication of 64-bit lanes into 128 bits
+    pub pmull: bool,
+    /// SHA1 hash updates
+    pub sha1: bool,
+    /// SHA256 hash updates
+    pub sha2: bool,
+}
+
+impl Features {
+    /// Returns the extensions supported by the CPU running this process, which are none on other
+    /// architectures
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "aarch64")]
+        {
+            Self {
+                lse: std::arch::is_aarch64_feature_detected!("lse"),
+                crc: std::arch::is_aarch64_feature_detected!("crc"),
+                aes: std::arch::is_aarch64_feature_detected!("aes"),
+                pmull: std::arch::is_aarch64_feature_detected!("pmull"),
+                // The standard library only reports SHA1 together with SHA256
+                sha1: std::arch::is_aarch64_feature_detected!("sha2"),
+                sha2: std::arch::is_aarch64_feature_detected!("sha2"),
+            }
+        }
+        #[cfg(not(target_arch = "aarch64"))]
+        {
+            Self::default()
+        }
+    }
+}
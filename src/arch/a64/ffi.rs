@@ -0,0 +1,424 @@
+use super::{
+    asm::Asm,
+    call::{Arg, ArgType, CallTarget, Signature},
+    frame::Frame,
+    reg::Reg,
+    routine::Routine,
+};
+use crate::assembler::{Assembler, MacroAssembler, VTable, Width};
+use std::{collections::HashMap, mem::transmute};
+
+/// Name of the routine generated for a signature
+const THUNK: &str = "ffi_thunk";
+
+/// Thunk calling `target` with the arguments stored in `args` and storing the result into `ret`
+type Entry = unsafe extern "C" fn(target: usize, args: *const u64, ret: *mut u64);
+
+/// Argument or return value of a foreign function called through an `FfiCache`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Ptr(usize),
+    F32(f32),
+    F64(f64),
+    /// Bytes of a struct in its C layout
+    Struct(Vec<u8>),
+}
+
+impl Value {
+    pub fn matches(&self, ty: &ArgType) -> bool {
+        match (self, ty) {
+            (Self::I8(_), ArgType::I8)
+            | (Self::I16(_), ArgType::I16)
+            | (Self::I32(_), ArgType::I32)
+            | (Self::I64(_), ArgType::I64)
+            | (Self::Ptr(_), ArgType::Ptr)
+            | (Self::F32(_), ArgType::F32)
+            | (Self::F64(_), ArgType::F64) => true,
+            (Self::Struct(bytes), ArgType::Struct(_)) => bytes.len() == ty.size(),
+            _ => false,
+        }
+    }
+
+    /// Stores the value into the words it occupies in an argument buffer
+    fn write(&self, words: &mut [u64]) {
+        words[0] = match self {
+            Self::I8(value) => *value as u64,
+            Self::I16(value) => *value as u64,
+            Self::I32(value) => *value as u64,
+            Self::I64(value) => *value as u64,
+            Self::Ptr(value) => *value as u64,
+            Self::F32(value) => value.to_bits() as u64,
+            Self::F64(value) => value.to_bits(),
+            Self::Struct(bytes) => {
+                for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+                    let mut buf = [0; 8];
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    *word = u64::from_ne_bytes(buf);
+                }
+                return;
+            }
+        };
+    }
+
+    /// Reads a value of the type from the words it occupies in a result buffer
+    fn read(ty: &ArgType, words: &[u64]) -> Self {
+        let word = words[0];
+        match ty {
+            ArgType::I8 => Self::I8(word as i8),
+            ArgType::I16 => Self::I16(word as i16),
+            ArgType::I32 => Self::I32(word as i32),
+            ArgType::I64 => Self::I64(word as i64),
+            ArgType::Ptr => Self::Ptr(word as usize),
+            ArgType::F32 => Self::F32(f32::from_bits(word as u32)),
+            ArgType::F64 => Self::F64(f64::from_bits(word)),
+            ArgType::Struct(_) => {
+                let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_ne_bytes()).collect();
+                bytes.truncate(ty.size());
+                Self::Struct(bytes)
+            }
+        }
+    }
+}
+
+/// Returns the number of 8-byte words a value of the type occupies in a buffer
+fn words(ty: &ArgType) -> usize {
+    ty.size().div_ceil(8)
+}
+
+/// Returns the argument buffer of a thunk holding the arguments
+fn arg_buffer(sig: &Signature, args: &[Value]) -> Vec<u64> {
+    assert_eq!(
+        args.len(),
+        sig.params.len(),
+        "Argument count does not match the signature"
+    );
+    let mut buffer = vec![0; sig.params.iter().map(words).sum()];
+    let mut offset = 0;
+    for (arg, ty) in args.iter().zip(&sig.params) {
+        assert!(arg.matches(ty), "Argument does not match the signature");
+        arg.write(&mut buffer[offset..offset + words(ty)]);
+        offset += words(ty);
+    }
+    buffer
+}
+
+/// Generates a routine with the C signature of `Entry` calling its target with `sig`
+///
+/// Every argument occupies as many 8-byte words of the argument buffer as it needs and the
+/// result buffer holds the result the same way.
+pub fn thunk(sig: &Signature) -> Routine {
+    let mut routine = Routine::new(THUNK.to_string());
+    routine.set_frame(Frame {
+        callee_saved: vec![Reg::X19, Reg::X20, Reg::X21],
+        calls: true,
+        ..Frame::default()
+    });
+    let slots: Vec<_> = sig
+        .params
+        .iter()
+        .map(|ty| routine.alloc_slot(words(ty) * 8, 8))
+        .collect();
+    let ret = match &sig.ret {
+        Some(ty @ ArgType::Struct(_)) => Some(routine.alloc_slot(words(ty) * 8, 8)),
+        _ => None,
+    };
+    routine.mov_reg(Reg::X21, Reg::X0);
+    routine.mov_reg(Reg::X19, Reg::X1);
+    routine.mov_reg(Reg::X20, Reg::X2);
+
+    let mut offset = 0;
+    for (ty, slot) in sig.params.iter().zip(&slots) {
+        for word in 0..words(ty) {
+            routine.load(Width::Double, Reg::X9, Reg::X19, (offset + word * 8) as i32);
+            routine.store_slot(Width::Double, Reg::X9, *slot, word * 8);
+        }
+        offset += words(ty) * 8;
+    }
+    let args: Vec<_> = slots.into_iter().map(Arg::Slot).collect();
+    routine.call_with(sig, CallTarget::Reg(Reg::X21), &args, ret);
+
+    match (&sig.ret, ret) {
+        (Some(ty), Some(slot)) => {
+            for word in 0..words(ty) {
+                routine.load_slot(Width::Double, Reg::X9, slot, word * 8);
+                routine.store(Width::Double, Reg::X9, Reg::X20, (word * 8) as i32);
+            }
+        }
+        (Some(_), None) => routine.store(Width::Double, Reg::X0, Reg::X20, 0),
+        _ => {}
+    }
+    routine.ret();
+    routine
+}
+
+struct Thunk {
+    /// Keeps the code of the thunk alive
+    _vtable: VTable,
+    entry: Entry,
+}
+
+/// Calls foreign functions whose signatures are only known at runtime through thunks generated
+/// once per signature
+#[derive(Default)]
+pub struct FfiCache {
+    thunks: HashMap<Signature, Thunk>,
+}
+
+impl FfiCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `target` with the arguments, generating the thunk for the signature on first use
+    ///
+    /// Returns `None` if the signature has no return type.
+    ///
+    /// # Safety
+    /// `target` must be a function following AAPCS64 with the given signature and the thunks can
+    /// only be run on AArch64.
+    pub unsafe fn call(&mut self, sig: &Signature, target: usize, args: &[Value]) -> Option<Value> {
+        let buffer = arg_buffer(sig, args);
+        let mut ret = vec![0; sig.ret.as_ref().map_or(0, words)];
+
+        let entry = self.thunk(sig);
+        unsafe { entry(target, buffer.as_ptr(), ret.as_mut_ptr()) };
+        sig.ret.as_ref().map(|ty| Value::read(ty, &ret))
+    }
+
+    /// Returns whether a thunk has already been generated for the signature
+    pub fn contains(&self, sig: &Signature) -> bool {
+        self.thunks.contains_key(sig)
+    }
+
+    fn thunk(&mut self, sig: &Signature) -> Entry {
+        if let Some(thunk) = self.thunks.get(sig) {
+            return thunk.entry;
+        }
+        let mut asm = Asm::default();
+        asm.push_routine(thunk(sig));
+        let vtable = asm.jit().expect("Could not JIT the call thunk");
+        let entry = vtable.lookup(THUNK).unwrap();
+        let entry = unsafe { transmute::<fn(), Entry>(entry) };
+        self.thunks.insert(
+            sig.clone(),
+            Thunk {
+                _vtable: vtable,
+                entry,
+            },
+        );
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::emu::Emulator;
+
+    const HOST: u64 = 0x7FFF_0000_1000;
+    const ARGS: u64 = 0x8000;
+    const RET: u64 = 0x9000;
+
+    /// Runs the thunk for the signature on the emulator calling `host` with the arguments
+    ///
+    /// Returns the result read back from the result buffer.
+    fn emulate(
+        sig: &Signature,
+        args: &[Value],
+        host: impl FnMut(&mut Emulator) + 'static,
+    ) -> Option<Value> {
+        let mut asm = Asm::default();
+        asm.push_routine(thunk(sig));
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        emu.hook(HOST, host);
+        for (index, word) in arg_buffer(sig, args).into_iter().enumerate() {
+            emu.write(ARGS + index as u64 * 8, 8, word).unwrap();
+        }
+        for reg in 19..=28 {
+            emu.set_reg(reg, reg as u64);
+        }
+        emu.call(THUNK, &[HOST, ARGS, RET]).unwrap();
+        for reg in 19..=28 {
+            assert_eq!(emu.reg(reg), reg as u64);
+        }
+        assert_eq!(emu.sp(), emu.memory().len() as u64);
+        sig.ret.as_ref().map(|ty| {
+            let words: Vec<_> = (0..words(ty) as u64)
+                .map(|index| emu.read(RET + index * 8, 8).unwrap())
+                .collect();
+            Value::read(ty, &words)
+        })
+    }
+
+    fn signature(params: Vec<ArgType>, ret: Option<ArgType>) -> Signature {
+        Signature {
+            params,
+            ret,
+            named: None,
+        }
+    }
+
+    #[test]
+    fn scalars() {
+        let sig = signature(
+            vec![ArgType::I32, ArgType::I64, ArgType::I8, ArgType::Ptr],
+            Some(ArgType::I64),
+        );
+        let args = [
+            Value::I32(-3),
+            Value::I64(1 << 40),
+            Value::I8(-1),
+            Value::Ptr(0x1000),
+        ];
+        let result = emulate(&sig, &args, |emu| {
+            let sum = emu.reg(0) as i32 as i64
+                + emu.reg(1) as i64
+                + emu.reg(2) as i8 as i64
+                + emu.reg(3) as i64;
+            emu.set_reg(0, sum as u64);
+        });
+        assert_eq!(result, Some(Value::I64((1 << 40) + 0x1000 - 4)));
+
+        // Without a result nothing is written to the result buffer
+        let sig = signature(vec![ArgType::I16], None);
+        let result = emulate(&sig, &[Value::I16(-2)], |emu| {
+            assert_eq!(emu.reg(0), 0xFFFE);
+        });
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn floats() {
+        let sig = signature(
+            vec![ArgType::F32, ArgType::I32, ArgType::F64],
+            Some(ArgType::F64),
+        );
+        let args = [Value::F32(1.5), Value::I32(3), Value::F64(0.25)];
+        let result = emulate(&sig, &args, |emu| {
+            let lhs = f32::from_bits(emu.vreg(0) as u32) as f64 * emu.reg(0) as f64;
+            let rhs = f64::from_bits(emu.vreg(1) as u64);
+            emu.set_vreg(0, (lhs + rhs).to_bits() as u128);
+        });
+        assert_eq!(result, Some(Value::F64(4.75)));
+
+        let sig = signature(vec![ArgType::F64], Some(ArgType::F32));
+        let result = emulate(&sig, &[Value::F64(2.0)], |emu| {
+            let value = f64::from_bits(emu.vreg(0) as u64) as f32;
+            emu.set_vreg(0, (value * 0.5).to_bits() as u128);
+        });
+        assert_eq!(result, Some(Value::F32(1.0)));
+    }
+
+    #[test]
+    fn struct_in_registers() {
+        // A 16-byte struct in X1 and X2 after an integer, returning 8 bytes in X0
+        let pair = ArgType::Struct(vec![ArgType::I32, ArgType::I32, ArgType::I64]);
+        let sig = signature(
+            vec![ArgType::I64, pair, ArgType::I64],
+            Some(ArgType::Struct(vec![ArgType::I32; 2])),
+        );
+        let mut bytes = Vec::new();
+        bytes.extend(1i32.to_ne_bytes());
+        bytes.extend(2i32.to_ne_bytes());
+        bytes.extend(3i64.to_ne_bytes());
+        let args = [Value::I64(10), Value::Struct(bytes), Value::I64(20)];
+        let result = emulate(&sig, &args, |emu| {
+            assert_eq!(emu.reg(0), 10);
+            assert_eq!(emu.reg(1), 2 << 32 | 1);
+            assert_eq!(emu.reg(2), 3);
+            assert_eq!(emu.reg(3), 20);
+            emu.set_reg(0, 0x0000_0022_0000_0011);
+        });
+        let expected = [0x11i32.to_ne_bytes(), 0x22i32.to_ne_bytes()].concat();
+        assert_eq!(result, Some(Value::Struct(expected)));
+    }
+
+    #[test]
+    fn indirect_struct_and_struct_result() {
+        // The 24-byte argument is passed by reference and the 24-byte result through X8
+        let triple = ArgType::Struct(vec![ArgType::I64; 3]);
+        let sig = signature(
+            vec![ArgType::I32, ArgType::I64, triple.clone()],
+            Some(triple),
+        );
+        let fields = |values: [i64; 3]| {
+            values
+                .iter()
+                .flat_map(|value| value.to_ne_bytes())
+                .collect()
+        };
+        let args = [
+            Value::I32(1),
+            Value::I64(2),
+            Value::Struct(fields([10, 20, 30])),
+        ];
+        let result = emulate(&sig, &args, |emu| {
+            let (ptr, ret) = (emu.reg(2), emu.reg(8));
+            assert_ne!(ptr, ARGS + 16, "The struct is passed as a copy");
+            for index in 0..3 {
+                let field = emu.read(ptr + index * 8, 8).unwrap();
+                let value = field + emu.reg(0) + emu.reg(1);
+                emu.write(ret + index * 8, 8, value).unwrap();
+            }
+        });
+        assert_eq!(result, Some(Value::Struct(fields([13, 23, 33]))));
+    }
+
+    #[test]
+    fn thunks_are_cached_per_signature() {
+        let sig = signature(vec![ArgType::I64], Some(ArgType::I64));
+        let other = signature(vec![ArgType::I32], Some(ArgType::I64));
+        let mut cache = FfiCache::new();
+        assert!(!cache.contains(&sig));
+        let entry = cache.thunk(&sig) as usize;
+        assert!(cache.contains(&sig));
+        assert!(!cache.contains(&other));
+        assert_eq!(cache.thunk(&sig) as usize, entry);
+        assert_eq!(cache.thunks.len(), 1);
+        assert_ne!(cache.thunk(&other) as usize, entry);
+        assert_eq!(cache.thunks.len(), 2);
+    }
+
+    #[test]
+    fn struct_values() {
+        let ty = ArgType::Struct(vec![ArgType::I32; 3]);
+        let bytes: Vec<u8> = (1..=12).collect();
+        let value = Value::Struct(bytes.clone());
+        assert!(value.matches(&ty));
+        assert!(!Value::Struct(bytes[..8].to_vec()).matches(&ty));
+        assert!(!Value::I32(0).matches(&ty));
+        // The padding of the last word is zeroed
+        let mut words = [u64::MAX; 2];
+        value.write(&mut words);
+        assert_eq!(
+            words.map(u64::to_ne_bytes).concat(),
+            [&bytes[..], &[0; 4]].concat()
+        );
+        assert_eq!(Value::read(&ty, &words), value);
+    }
+
+    #[test]
+    #[should_panic(expected = "Argument does not match the signature")]
+    fn mismatched_argument() {
+        let sig = signature(vec![ArgType::I64], None);
+        arg_buffer(&sig, &[Value::I32(1)]);
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn calls_natively() {
+        extern "C" fn scale(value: i32, factor: f64) -> f64 {
+            value as f64 * factor
+        }
+        let sig = signature(vec![ArgType::I32, ArgType::F64], Some(ArgType::F64));
+        let mut cache = FfiCache::new();
+        let args = [Value::I32(3), Value::F64(0.5)];
+        let result = unsafe { cache.call(&sig, scale as *const () as usize, &args) };
+        assert_eq!(result, Some(Value::F64(1.5)));
+    }
+}
@@ -0,0 +1,723 @@
+use super::{
+    cond::Cond,
+    load_store::Address,
+    reg::{f_bytes, is_64_bit, FReg, Reg},
+    routine::{Op, Routine},
+};
+
+/// Opcodes of the two-source instructions in bits 12 to 15
+const FMUL: u32 = 0b0000;
+const FDIV: u32 = 0b0001;
+const FADD: u32 = 0b0010;
+const FSUB: u32 = 0b0011;
+const FMAX: u32 = 0b0100;
+const FMIN: u32 = 0b0101;
+const FNMUL: u32 = 0b1000;
+
+/// Opcodes of the one-source instructions in bits 15 to 20
+const FMOV: u32 = 0b000000;
+const FABS: u32 = 0b000001;
+const FNEG: u32 = 0b000010;
+const FSQRT: u32 = 0b000011;
+
+/// Bits of the three-source instructions negating the product and the addend
+const NEGATE_PRODUCT: u32 = 1 << 15;
+const NEGATE_ALL: u32 = 1 << 21;
+
+/// Bit of the load and store encodings selecting SIMD&FP registers
+const SIMD: u32 = 1 << 26;
+
+impl Routine {
+    /// Copies the value of the source register into the destination register
+    pub fn fmov_reg(&mut self, dst_reg: FReg, src_reg: FReg) {
+        self.fp_one_source(FMOV, dst_reg, src_reg);
+    }
+
+    /// Moves the bit pattern of a general-purpose register into the floating-point register
+    ///
+    /// `W` registers go into `S` or `H` registers and `X` registers into `D` or `H` registers.
+    pub fn fmov_to_fp(&mut self, dst_reg: FReg, src_reg: Reg) {
+        let opcode = fmov_general(dst_reg, src_reg) | (0b111 << 16);
+        self.int_insn(opcode | ((src_reg as u32 & 0x1F) << 5) | (dst_reg as u32 & 0x1F));
+    }
+
+    /// Moves the bit pattern of the floating-point register into a general-purpose register,
+    /// zero-extending a half-precision value
+    pub fn fmov_from_fp(&mut self, dst_reg: Reg, src_reg: FReg) {
+        let opcode = fmov_general(src_reg, dst_reg) | (0b110 << 16);
+        self.int_insn(opcode | ((src_reg as u32 & 0x1F) << 5) | (dst_reg as u32 & 0x1F));
+    }
+
+    /// Moves a floating-point immediate into the register
+    ///
+    /// Only values of the form `±n/16 * 2^r` with `16 <= n <= 31` and `-3 <= r <= 4` can be
+    /// encoded, which can be checked with `encode_fp_imm`. Zero is not one of them.
+    pub fn fmov_imm(&mut self, dst_reg: FReg, imm: f64) {
+        let Some(imm8) = encode_fp_imm(imm) else {
+            panic!("Immediate cannot be encoded as an 8-bit floating-point immediate");
+        };
+        self.int_insn(
+            0x1E201000 | (ftype(dst_reg) << 22) | ((imm8 as u32) << 13) | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    /// Moves the value into the register with the shortest sequence
+    ///
+    /// Uses `fmov_imm` if the value can be encoded, a move from the zero register for positive
+    /// zero and a literal load from the constant pool otherwise. Panics if the value is not
+    /// exactly representable in the precision of the register.
+    pub fn mov_fp(&mut self, dst_reg: FReg, imm: f64) {
+        if encode_fp_imm(imm).is_some() {
+            self.fmov_imm(dst_reg, imm);
+        } else if imm.to_bits() == 0 {
+            let zero = if f_bytes(dst_reg) == 8 {
+                Reg::X31
+            } else {
+                Reg::W31
+            };
+            self.fmov_to_fp(dst_reg, zero);
+        } else if f_bytes(dst_reg) == 8 {
+            let offset = self.const_f64(imm);
+            self.ldr_fp_const(dst_reg, offset);
+        } else {
+            let single = imm as f32;
+            assert!(
+                single as f64 == imm || imm.is_nan(),
+                "Value is not representable in single precision"
+            );
+            assert!(
+                f_bytes(dst_reg) == 4,
+                "Half-precision literals are not supported"
+            );
+            let offset = self.const_f32(single);
+            self.ldr_fp_const(dst_reg, offset);
+        }
+    }
+
+    /// Computes `lhs + rhs`
+    pub fn fadd(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        self.fp_two_source(FADD, dst_reg, lhs, rhs);
+    }
+
+    /// Computes `lhs - rhs`
+    pub fn fsub(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        self.fp_two_source(FSUB, dst_reg, lhs, rhs);
+    }
+
+    /// Computes `lhs * rhs`
+    pub fn fmul(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        self.fp_two_source(FMUL, dst_reg, lhs, rhs);
+    }
+
+    /// Computes `-(lhs * rhs)`
+    pub fn fnmul(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        self.fp_two_source(FNMUL, dst_reg, lhs, rhs);
+    }
+
+    /// Computes `lhs / rhs`
+    pub fn fdiv(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        self.fp_two_source(FDIV, dst_reg, lhs, rhs);
+    }
+
+    /// Computes the maximum of `lhs` and `rhs`, which is NaN if either is NaN
+    pub fn fmax(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        self.fp_two_source(FMAX, dst_reg, lhs, rhs);
+    }
+
+    /// Computes the minimum of `lhs` and `rhs`, which is NaN if either is NaN
+    pub fn fmin(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        self.fp_two_source(FMIN, dst_reg, lhs, rhs);
+    }
+
+    /// Computes `addend + lhs * rhs` with a single rounding
+    pub fn fmadd(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg, addend: FReg) {
+        self.fp_three_source(0, dst_reg, lhs, rhs, addend);
+    }
+
+    /// Computes `addend - lhs * rhs` with a single rounding
+    pub fn fmsub(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg, addend: FReg) {
+        self.fp_three_source(NEGATE_PRODUCT, dst_reg, lhs, rhs, addend);
+    }
+
+    /// Computes `-addend - lhs * rhs` with a single rounding
+    pub fn fnmadd(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg, addend: FReg) {
+        self.fp_three_source(NEGATE_ALL, dst_reg, lhs, rhs, addend);
+    }
+
+    /// Computes `-addend + lhs * rhs` with a single rounding
+    pub fn fnmsub(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg, addend: FReg) {
+        self.fp_three_source(NEGATE_ALL | NEGATE_PRODUCT, dst_reg, lhs, rhs, addend);
+    }
+
+    /// Computes the absolute value
+    pub fn fabs(&mut self, dst_reg: FReg, src_reg: FReg) {
+        self.fp_one_source(FABS, dst_reg, src_reg);
+    }
+
+    /// Negates the value
+    pub fn fneg(&mut self, dst_reg: FReg, src_reg: FReg) {
+        self.fp_one_source(FNEG, dst_reg, src_reg);
+    }
+
+    /// Computes the square root
+    pub fn fsqrt(&mut self, dst_reg: FReg, src_reg: FReg) {
+        self.fp_one_source(FSQRT, dst_reg, src_reg);
+    }
+
+    /// Converts between half, single and double precision, rounding according to FPCR
+    pub fn fcvt(&mut self, dst_reg: FReg, src_reg: FReg) {
+        assert!(
+            f_bytes(dst_reg) != f_bytes(src_reg),
+            "Registers must be of different precision"
+        );
+        self.int_insn(
+            0x1E204000
+                | (ftype(src_reg) << 22)
+                | ((0b000100 | ftype(dst_reg)) << 15)
+                | ((src_reg as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    /// Compares `lhs` with `rhs` setting the flags, where unordered operands set C and V
+    ///
+    /// Afterwards `Cond::MI` means less than, `Cond::LS` less than or equal, `Cond::GT` greater
+    /// than, `Cond::GE` greater than or equal and `Cond::VS` unordered.
+    pub fn fcmp(&mut self, lhs: FReg, rhs: FReg) {
+        self.fp_compare(0, lhs, rhs);
+    }
+
+    /// Compares `lhs` with positive zero like `fcmp`
+    pub fn fcmp_zero(&mut self, lhs: FReg) {
+        // The `Rm` field must be zero for the comparison with zero
+        let zero = match f_bytes(lhs) {
+            2 => FReg::H0,
+            4 => FReg::S0,
+            _ => FReg::D0,
+        };
+        self.fp_compare(0b01000, lhs, zero);
+    }
+
+    /// Compares `lhs` with `rhs` like `fcmp`, raising Invalid Operation for quiet NaNs as well
+    pub fn fcmpe(&mut self, lhs: FReg, rhs: FReg) {
+        self.fp_compare(0b10000, lhs, rhs);
+    }
+
+    /// Moves `lhs` into the destination register if the condition holds and `rhs` otherwise
+    pub fn fcsel(&mut self, dst_reg: FReg, lhs: FReg, rhs: FReg, cond: Cond) {
+        self.fp_three_reg(0x1E200C00 | ((cond as u32) << 12), dst_reg, lhs, rhs);
+    }
+
+    /// Converts the signed integer to floating-point, rounding according to FPCR
+    pub fn scvtf(&mut self, dst_reg: FReg, src_reg: Reg) {
+        self.fp_convert(0x1E220000, src_reg, dst_reg as u32, src_reg as u32, dst_reg);
+    }
+
+    /// Converts the unsigned integer to floating-point, rounding according to FPCR
+    pub fn ucvtf(&mut self, dst_reg: FReg, src_reg: Reg) {
+        self.fp_convert(0x1E230000, src_reg, dst_reg as u32, src_reg as u32, dst_reg);
+    }
+
+    /// Converts the floating-point value to a signed integer rounding towards zero, saturating
+    /// out-of-range values and converting NaN to zero
+    pub fn fcvtzs(&mut self, dst_reg: Reg, src_reg: FReg) {
+        self.fp_convert(0x1E380000, dst_reg, dst_reg as u32, src_reg as u32, src_reg);
+    }
+
+    /// Converts the floating-point value to an unsigned integer rounding towards zero like
+    /// `fcvtzs`
+    pub fn fcvtzu(&mut self, dst_reg: Reg, src_reg: FReg) {
+        self.fp_convert(0x1E390000, dst_reg, dst_reg as u32, src_reg as u32, src_reg);
+    }
+
+    /// Loads the register from memory
+    pub fn ldr_fp(&mut self, dst_reg: FReg, addr: Address) {
+        self.load_store_fp_reg(true, dst_reg, addr);
+    }
+
+    /// Stores the register to memory
+    pub fn str_fp(&mut self, src_reg: FReg, addr: Address) {
+        self.load_store_fp_reg(false, src_reg, addr);
+    }
+
+    /// Loads two consecutive `S` or `D` values into the registers
+    pub fn ldp_fp(&mut self, a_reg: FReg, b_reg: FReg, addr: Address) {
+        self.load_store_fp_pair(true, a_reg, b_reg, addr);
+    }
+
+    /// Stores the registers to two consecutive `S` or `D` values
+    pub fn stp_fp(&mut self, a_reg: FReg, b_reg: FReg, addr: Address) {
+        self.load_store_fp_pair(false, a_reg, b_reg, addr);
+    }
+
+    /// Loads the value of a constant into an `S` or `D` register
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn ldr_fp_const(&mut self, dst_reg: FReg, offset: usize) {
+        assert!(
+            f_bytes(dst_reg) != 2,
+            "Half-precision registers cannot be loaded from literals"
+        );
+        self.post_ops.push(Op::LoadFpConst {
+            insn_offset: self.code.len(),
+            dst_reg,
+            const_offset: offset,
+        });
+        self.nop();
+    }
+
+    /// Stores a single-precision constant in front of the code
+    ///
+    /// Return the index of the constant
+    pub fn const_f32(&mut self, value: f32) -> usize {
+        self.const_32(value.to_bits())
+    }
+
+    /// Stores a double-precision constant in front of the code
+    ///
+    /// Return the index of the constant
+    pub fn const_f64(&mut self, value: f64) -> usize {
+        self.const_64(value.to_bits())
+    }
+
+    fn fp_one_source(&mut self, opcode: u32, dst_reg: FReg, src_reg: FReg) {
+        assert!(
+            f_bytes(dst_reg) == f_bytes(src_reg),
+            "Both registers must be of equal precision"
+        );
+        self.int_insn(
+            0x1E204000
+                | (ftype(dst_reg) << 22)
+                | (opcode << 15)
+                | ((src_reg as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    fn fp_two_source(&mut self, opcode: u32, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        self.fp_three_reg(0x1E200800 | (opcode << 12), dst_reg, lhs, rhs);
+    }
+
+    fn fp_three_reg(&mut self, opcode: u32, dst_reg: FReg, lhs: FReg, rhs: FReg) {
+        assert!(
+            f_bytes(dst_reg) == f_bytes(lhs) && f_bytes(dst_reg) == f_bytes(rhs),
+            "All registers must be of equal precision"
+        );
+        self.int_insn(
+            opcode
+                | (ftype(dst_reg) << 22)
+                | ((rhs as u32 & 0x1F) << 16)
+                | ((lhs as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    fn fp_three_source(&mut self, opcode: u32, dst_reg: FReg, lhs: FReg, rhs: FReg, addend: FReg) {
+        assert!(
+            [lhs, rhs, addend]
+                .iter()
+                .all(|reg| f_bytes(*reg) == f_bytes(dst_reg)),
+            "All registers must be of equal precision"
+        );
+        self.int_insn(
+            0x1F000000
+                | opcode
+                | (ftype(dst_reg) << 22)
+                | ((rhs as u32 & 0x1F) << 16)
+                | ((addend as u32 & 0x1F) << 10)
+                | ((lhs as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    fn fp_compare(&mut self, opcode: u32, lhs: FReg, rhs: FReg) {
+        assert!(
+            f_bytes(lhs) == f_bytes(rhs),
+            "Both registers must be of equal precision"
+        );
+        self.int_insn(
+            0x1E202000
+                | (ftype(lhs) << 22)
+                | ((rhs as u32 & 0x1F) << 16)
+                | ((lhs as u32 & 0x1F) << 5)
+                | opcode,
+        );
+    }
+
+    /// Emits a conversion between the general-purpose register `int_reg` and the floating-point
+    /// register `fp_reg`, where `rd` and `rn` are the destination and source register numbers
+    fn fp_convert(&mut self, opcode: u32, int_reg: Reg, rd: u32, rn: u32, fp_reg: FReg) {
+        self.int_insn(
+            opcode
+                | ((is_64_bit(int_reg) as u32) << 31)
+                | (ftype(fp_reg) << 22)
+                | ((rn & 0x1F) << 5)
+                | (rd & 0x1F),
+        );
+    }
+
+    fn load_store_fp_reg(&mut self, load: bool, reg: FReg, addr: Address) {
+        let bytes = f_bytes(reg);
+        let Some(addr) = addr.encode(bytes) else {
+            panic!("Address cannot be encoded for an access of {bytes} bytes");
+        };
+        self.int_insn(
+            addr | SIMD
+                | (bytes.trailing_zeros() << 30)
+                | ((load as u32) << 22)
+                | (reg as u32 & 0x1F),
+        );
+    }
+
+    fn load_store_fp_pair(&mut self, load: bool, a_reg: FReg, b_reg: FReg, addr: Address) {
+        let bytes = f_bytes(a_reg);
+        assert!(
+            bytes == f_bytes(b_reg),
+            "Both registers must be of equal precision"
+        );
+        assert!(bytes != 2, "Half-precision registers cannot be paired");
+        if load {
+            assert!(
+                a_reg != b_reg,
+                "Loading a pair into one register is unpredictable"
+            );
+        }
+        let Some(addr) = addr.encode_pair(bytes) else {
+            panic!("Address cannot be encoded for a pair access of {bytes} bytes each");
+        };
+        self.int_insn(
+            addr | SIMD
+                | ((bytes as u32 / 8) << 30)
+                | ((load as u32) << 22)
+                | ((b_reg as u32 & 0x1F) << 10)
+                | (a_reg as u32 & 0x1F),
+        );
+    }
+}
+
+/// Returns the 8-bit immediate of `fmov_imm` encoding the value
+pub fn encode_fp_imm(value: f64) -> Option<u8> {
+    (0..=u8::MAX).find(|imm8| decode_fp_imm(*imm8).to_bits() == value.to_bits())
+}
+
+/// Expands the 8-bit immediate `abcdefgh` to `(-1)^a * 2^(NOT(b):c:d - 3) * 1.efgh`
+pub(super) fn decode_fp_imm(imm8: u8) -> f64 {
+    let imm8 = imm8 as u64;
+    let b = (imm8 >> 6) & 1;
+    let exponent = ((b ^ 1) << 10) | (if b == 1 { 0xFF << 2 } else { 0 }) | ((imm8 >> 4) & 0b11);
+    f64::from_bits(((imm8 >> 7) << 63) | (exponent << 52) | ((imm8 & 0xF) << 48))
+}
+
+/// Returns the `ftype` field of the register, which is `0b00` for single, `0b01` for double and
+/// `0b11` for half precision
+fn ftype(reg: FReg) -> u32 {
+    match f_bytes(reg) {
+        4 => 0b00,
+        8 => 0b01,
+        _ => 0b11,
+    }
+}
+
+/// Returns the opcode of `fmov` between the registers without the direction, which only differs in
+/// the value of bit 16
+fn fmov_general(fp_reg: FReg, int_reg: Reg) -> u32 {
+    let bits_64 = is_64_bit(int_reg);
+    assert!(
+        match f_bytes(fp_reg) {
+            2 => true,
+            4 => !bits_64,
+            _ => bits_64,
+        },
+        "Single-precision registers pair with W and double-precision registers with X registers"
+    );
+    0x1E200000 | ((bits_64 as u32) << 31) | (ftype(fp_reg) << 22)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::a64::{asm::Asm, emu::Emulator, routine::tests::assemble},
+        assembler::Assembler,
+    };
+
+    #[test]
+    fn moves() {
+        let words = assemble(|r| {
+            r.fmov_reg(FReg::S0, FReg::S1);
+            r.fmov_reg(FReg::D2, FReg::D3);
+            r.fmov_reg(FReg::H4, FReg::H5);
+            r.fmov_to_fp(FReg::S6, Reg::W7);
+            r.fmov_to_fp(FReg::D8, Reg::X9);
+            r.fmov_to_fp(FReg::H10, Reg::W11);
+            r.fmov_to_fp(FReg::H12, Reg::X13);
+            r.fmov_from_fp(Reg::W14, FReg::S15);
+            r.fmov_from_fp(Reg::X16, FReg::D17);
+            r.fmov_from_fp(Reg::W18, FReg::H19);
+            r.fmov_imm(FReg::S0, 1.0);
+            r.fmov_imm(FReg::D1, -0.125);
+            r.fmov_imm(FReg::S2, 31.0);
+            r.fmov_imm(FReg::H3, 0.5);
+            r.mov_fp(FReg::D0, 0.0);
+            r.mov_fp(FReg::S1, 0.0);
+        });
+        assert_eq!(
+            words,
+            [
+                0x1E204020, // fmov s0, s1
+                0x1E604062, // fmov d2, d3
+                0x1EE040A4, // fmov h4, h5
+                0x1E2700E6, // fmov s6, w7
+                0x9E670128, // fmov d8, x9
+                0x1EE7016A, // fmov h10, w11
+                0x9EE701AC, // fmov h12, x13
+                0x1E2601EE, // fmov w14, s15
+                0x9E660230, // fmov x16, d17
+                0x1EE60272, // fmov w18, h19
+                0x1E2E1000, // fmov s0, #1.0
+                0x1E781001, // fmov d1, #-0.125
+                0x1E27F002, // fmov s2, #31.0
+                0x1EEC1003, // fmov h3, #0.5
+                0x9E6703E0, // fmov d0, xzr
+                0x1E2703E1, // fmov s1, wzr
+            ]
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        let words = assemble(|r| {
+            r.fadd(FReg::S0, FReg::S1, FReg::S2);
+            r.fsub(FReg::D3, FReg::D4, FReg::D5);
+            r.fmul(FReg::H6, FReg::H7, FReg::H8);
+            r.fnmul(FReg::S9, FReg::S10, FReg::S11);
+            r.fdiv(FReg::D12, FReg::D13, FReg::D14);
+            r.fmax(FReg::S15, FReg::S16, FReg::S17);
+            r.fmin(FReg::D18, FReg::D19, FReg::D20);
+            r.fmadd(FReg::S0, FReg::S1, FReg::S2, FReg::S3);
+            r.fmsub(FReg::D4, FReg::D5, FReg::D6, FReg::D7);
+            r.fnmadd(FReg::S8, FReg::S9, FReg::S10, FReg::S11);
+            r.fnmsub(FReg::D12, FReg::D13, FReg::D14, FReg::D15);
+            r.fabs(FReg::S0, FReg::S1);
+            r.fneg(FReg::D2, FReg::D3);
+            r.fsqrt(FReg::H4, FReg::H5);
+        });
+        assert_eq!(
+            words,
+            [
+                0x1E222820, // fadd s0, s1, s2
+                0x1E653883, // fsub d3, d4, d5
+                0x1EE808E6, // fmul h6, h7, h8
+                0x1E2B8949, // fnmul s9, s10, s11
+                0x1E6E19AC, // fdiv d12, d13, d14
+                0x1E314A0F, // fmax s15, s16, s17
+                0x1E745A72, // fmin d18, d19, d20
+                0x1F020C20, // fmadd s0, s1, s2, s3
+                0x1F469CA4, // fmsub d4, d5, d6, d7
+                0x1F2A2D28, // fnmadd s8, s9, s10, s11
+                0x1F6EBDAC, // fnmsub d12, d13, d14, d15
+                0x1E20C020, // fabs s0, s1
+                0x1E614062, // fneg d2, d3
+                0x1EE1C0A4, // fsqrt h4, h5
+            ]
+        );
+    }
+
+    #[test]
+    fn conversions_and_comparisons() {
+        let words = assemble(|r| {
+            r.fcvt(FReg::D0, FReg::S1);
+            r.fcvt(FReg::S2, FReg::D3);
+            r.fcvt(FReg::H4, FReg::D5);
+            r.fcvt(FReg::S6, FReg::H7);
+            r.fcmp(FReg::S0, FReg::S1);
+            r.fcmp_zero(FReg::D2);
+            r.fcmpe(FReg::H3, FReg::H4);
+            r.fcsel(FReg::D5, FReg::D6, FReg::D7, Cond::LT);
+            r.fcsel(FReg::S8, FReg::S9, FReg::S10, Cond::VS);
+            r.scvtf(FReg::S0, Reg::W1);
+            r.scvtf(FReg::D2, Reg::X3);
+            r.ucvtf(FReg::S4, Reg::X5);
+            r.ucvtf(FReg::H6, Reg::W7);
+            r.fcvtzs(Reg::W8, FReg::D9);
+            r.fcvtzs(Reg::X10, FReg::S11);
+            r.fcvtzu(Reg::X12, FReg::D13);
+            r.fcvtzu(Reg::W14, FReg::H15);
+        });
+        assert_eq!(
+            words,
+            [
+                0x1E22C020, // fcvt d0, s1
+                0x1E624062, // fcvt s2, d3
+                0x1E63C0A4, // fcvt h4, d5
+                0x1EE240E6, // fcvt s6, h7
+                0x1E212000, // fcmp s0, s1
+                0x1E602048, // fcmp d2, #0.0
+                0x1EE42070, // fcmpe h3, h4
+                0x1E67BCC5, // fcsel d5, d6, d7, lt
+                0x1E2A6D28, // fcsel s8, s9, s10, vs
+                0x1E220020, // scvtf s0, w1
+                0x9E620062, // scvtf d2, x3
+                0x9E2300A4, // ucvtf s4, x5
+                0x1EE300E6, // ucvtf h6, w7
+                0x1E780128, // fcvtzs w8, d9
+                0x9E38016A, // fcvtzs x10, s11
+                0x9E7901AC, // fcvtzu x12, d13
+                0x1EF901EE, // fcvtzu w14, h15
+            ]
+        );
+    }
+
+    #[test]
+    fn loads_and_stores() {
+        let words = assemble(|r| {
+            r.ldr_fp(FReg::S0, Address::Offset(Reg::X1, 4));
+            r.ldr_fp(FReg::D2, Address::PreIndex(Reg::X31, -8));
+            r.ldr_fp(FReg::H3, Address::reg(Reg::X4, Reg::X5));
+            r.str_fp(FReg::D6, Address::PostIndex(Reg::X7, 16));
+            r.str_fp(FReg::S8, Address::Offset(Reg::X9, 16380));
+            r.ldp_fp(FReg::D8, FReg::D9, Address::Offset(Reg::X31, 16));
+            r.ldp_fp(FReg::S10, FReg::S11, Address::PostIndex(Reg::X12, -8));
+            r.stp_fp(FReg::D14, FReg::D15, Address::PreIndex(Reg::X31, -32));
+            r.stp_fp(FReg::S16, FReg::S17, Address::Offset(Reg::X18, 252));
+        });
+        assert_eq!(
+            words,
+            [
+                0xBD400420, // ldr s0, [x1, #4]
+                0xFC5F8FE2, // ldr d2, [sp, #-8]!
+                0x7C656883, // ldr h3, [x4, x5]
+                0xFC0104E6, // str d6, [x7], #16
+                0xBD3FFD28, // str s8, [x9, #16380]
+                0x6D4127E8, // ldp d8, d9, [sp, #16]
+                0x2CFF2D8A, // ldp s10, s11, [x12], #-8
+                0x6DBE3FEE, // stp d14, d15, [sp, #-32]!
+                0x2D1FC650, // stp s16, s17, [x18, #252]
+            ]
+        );
+    }
+
+    #[test]
+    fn immediates() {
+        let encodable = (0..=u8::MAX).map(decode_fp_imm).collect::<Vec<_>>();
+        assert!(encodable.contains(&0.125) && encodable.contains(&-31.0));
+        for (imm8, value) in encodable.iter().enumerate() {
+            assert_eq!(encode_fp_imm(*value), Some(imm8 as u8));
+        }
+        for value in [0.0, -0.0, 0.1, 32.0, 0.0625, f64::INFINITY, f64::NAN] {
+            assert_eq!(encode_fp_imm(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn literal_values() {
+        let doubles = [1.0, 0.0, -0.0, 0.1, -1e300, f64::MIN_POSITIVE, f64::NAN];
+        let singles = [3.0f32, 0.0, -0.0, 0.1, f32::MAX, f32::INFINITY];
+        let mut asm = Asm::default();
+        for (index, value) in doubles.iter().enumerate() {
+            let mut routine = Routine::new(format!("d{index}"));
+            routine.mov_fp(FReg::D0, *value);
+            routine.fmov_from_fp(Reg::X0, FReg::D0);
+            routine.ret();
+            asm.push_routine(routine);
+        }
+        for (index, value) in singles.iter().enumerate() {
+            let mut routine = Routine::new(format!("s{index}"));
+            routine.mov_fp(FReg::S0, *value as f64);
+            routine.fmov_from_fp(Reg::W0, FReg::S0);
+            routine.ret();
+            asm.push_routine(routine);
+        }
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        for (index, value) in doubles.iter().enumerate() {
+            assert_eq!(emu.call(&format!("d{index}"), &[]), Ok(value.to_bits()));
+        }
+        for (index, value) in singles.iter().enumerate() {
+            assert_eq!(
+                emu.call(&format!("s{index}"), &[]),
+                Ok(value.to_bits() as u64)
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate cannot be encoded as an 8-bit floating-point immediate")]
+    fn zero_immediate() {
+        assemble(|r| r.fmov_imm(FReg::D0, 0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Value is not representable in single precision")]
+    fn inexact_single_precision_literal() {
+        assemble(|r| r.mov_fp(FReg::S0, 0.1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Half-precision literals are not supported")]
+    fn half_precision_literal() {
+        assemble(|r| r.mov_fp(FReg::H0, 0.25 + 1.0 / 1024.0));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Single-precision registers pair with W and double-precision registers with X registers"
+    )]
+    fn move_between_different_sizes() {
+        assemble(|r| r.fmov_to_fp(FReg::S0, Reg::X1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Both registers must be of equal precision")]
+    fn one_source_of_different_precision() {
+        assemble(|r| r.fneg(FReg::S0, FReg::D1));
+    }
+
+    #[test]
+    #[should_panic(expected = "All registers must be of equal precision")]
+    fn two_sources_of_different_precision() {
+        assemble(|r| r.fadd(FReg::D0, FReg::D1, FReg::S2));
+    }
+
+    #[test]
+    #[should_panic(expected = "All registers must be of equal precision")]
+    fn three_sources_of_different_precision() {
+        assemble(|r| r.fmadd(FReg::S0, FReg::S1, FReg::S2, FReg::H3));
+    }
+
+    #[test]
+    #[should_panic(expected = "Registers must be of different precision")]
+    fn conversion_to_same_precision() {
+        assemble(|r| r.fcvt(FReg::D0, FReg::D1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Both registers must be of equal precision")]
+    fn comparison_of_different_precision() {
+        assemble(|r| r.fcmp(FReg::S0, FReg::D1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Half-precision registers cannot be paired")]
+    fn half_precision_pair() {
+        assemble(|r| r.stp_fp(FReg::H0, FReg::H1, Address::Offset(Reg::X2, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Loading a pair into one register is unpredictable")]
+    fn pair_load_into_one_register() {
+        assemble(|r| r.ldp_fp(FReg::D0, FReg::D0, Address::Offset(Reg::X2, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Address cannot be encoded for a pair access of 4 bytes each")]
+    fn pair_offset_out_of_range() {
+        assemble(|r| r.stp_fp(FReg::S0, FReg::S1, Address::Offset(Reg::X2, 256)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Half-precision registers cannot be loaded from literals")]
+    fn half_precision_literal_load() {
+        assemble(|r| r.ldr_fp_const(FReg::H0, 0));
+    }
+}
@@ -0,0 +1,545 @@
+use super::{
+    cond::Cond,
+    reg::{is_64_bit, is_callee_saved, Reg},
+    routine::Routine,
+};
+use crate::assembler::Width;
+
+/// Size of the guard pages stack probes have to touch
+const PAGE_SIZE: usize = 0x1000;
+
+/// Pages below which a probed frame touches every page without a loop
+const UNROLLED_PROBES: usize = 4;
+
+/// Stack frame of a routine from which it generates an AAPCS64 prologue and the epilogue in front
+/// of every `ret`
+///
+/// ```text
+/// caller's SP -> +--------------------------+
+///                | X29, X30                 | <- X29
+///                | callee-saved registers   |
+///                | locals                   |
+///         SP  -> +--------------------------+
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Frame {
+    /// Callee-saved registers the routine modifies
+    pub callee_saved: Vec<Reg>,
+    /// Bytes of local data addressed relative to SP, rounded up to 16
+    pub locals: usize,
+    /// Whether the routine calls other routines, which requires saving the link register
+    pub calls: bool,
+    /// Whether locals larger than a page are reserved one page at a time, touching every page so
+    /// a guard page cannot be skipped
+    pub probe_stack: bool,
+}
+
+/// Region of the locals of a frame handed out by `Routine::alloc_slot`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StackSlot {
+    offset: usize,
+    size: usize,
+}
+
+impl StackSlot {
+    /// Returns the offset of the slot from SP after the prologue
+    pub fn offset(self) -> usize {
+        self.offset
+    }
+
+    pub fn size(self) -> usize {
+        self.size
+    }
+}
+
+impl Frame {
+    /// Whether the routine can leave the stack and the frame pointer untouched
+    pub fn is_empty(&self) -> bool {
+        !self.calls && self.callee_saved.is_empty() && self.locals == 0
+    }
+
+    /// Returns the bytes of the callee-saved registers, which are stored in pairs
+    pub fn saved_size(&self) -> usize {
+        self.callee_saved.len().div_ceil(2) * 16
+    }
+
+    pub fn locals_size(&self) -> usize {
+        (self.locals + 15) & !15
+    }
+
+    /// Returns the bytes the frame occupies including the frame record
+    pub fn size(&self) -> usize {
+        if self.is_empty() {
+            0
+        } else {
+            16 + self.saved_size() + self.locals_size()
+        }
+    }
+
+    /// Returns the callee-saved registers in pairs, padding an odd one with the zero register
+    fn pairs(&self) -> impl DoubleEndedIterator<Item = (Reg, Reg)> + '_ {
+        self.callee_saved
+            .chunks(2)
+            .map(|pair| (pair[0], pair.get(1).copied().unwrap_or(Reg::X31)))
+    }
+
+    pub(super) fn validate(&mut self) {
+        for reg in &self.callee_saved {
+            assert!(
+                is_64_bit(*reg) && is_callee_saved(*reg) && *reg != Reg::X29,
+                "Only X19 to X28 can be saved by the frame"
+            );
+        }
+        self.callee_saved.sort_by_key(|reg| *reg as i8);
+        self.callee_saved.dedup();
+    }
+}
+
+impl Routine {
+    /// Sets up the frame record, saves the callee-saved registers and reserves the locals
+    pub(super) fn emit_prologue(&mut self, frame: &Frame) {
+        if frame.is_empty() {
+            return;
+        }
+        self.stp_imm7_pre_offset(Reg::X29, Reg::X30, Reg::X31, -2);
+        self.mov_sp_to(Reg::X29);
+        for (a, b) in frame.pairs() {
+            self.stp_imm7_pre_offset(a, b, Reg::X31, -2);
+        }
+        let locals = frame.locals_size();
+        if frame.probe_stack && locals > PAGE_SIZE {
+            let pages = locals / PAGE_SIZE;
+            assert!(pages <= 0xFFFF, "Frame is too large");
+            if pages <= UNROLLED_PROBES {
+                for _ in 0..pages {
+                    self.probe_page();
+                }
+            } else {
+                self.mov_imm16(Reg::X16, pages as u16);
+                let probe = self.new_label();
+                self.bind(probe);
+                self.probe_page();
+                // subs x16, x16, #1
+                self.int_insn(
+                    0xF1000400 | ((Reg::X16 as u32 & 0x1F) << 5) | (Reg::X16 as u32 & 0x1F),
+                );
+                self.b_cond(Cond::NE, probe);
+            }
+            self.sp_imm(true, Reg::X31, Reg::X31, locals % PAGE_SIZE);
+        } else {
+            self.sp_imm(true, Reg::X31, Reg::X31, locals);
+        }
+    }
+
+    /// Moves SP a page down and stores zero at it
+    fn probe_page(&mut self) {
+        self.sp_imm(true, Reg::X31, Reg::X31, PAGE_SIZE);
+        self.str_uimm12_offset(Reg::X31, Reg::X31, 0);
+    }
+
+    /// Adds or subtracts an immediate below 16 MiB where `X31` is SP, using the shifted form of the
+    /// immediate for the upper 12 bits
+    pub(super) fn sp_imm(&mut self, sub: bool, dst_reg: Reg, src_reg: Reg, imm: usize) {
+        assert!(imm < 1 << 24, "Immediate is too large");
+        let opcode = if sub { 0xD1000000 } else { 0x91000000 };
+        let regs = ((src_reg as u32 & 0x1F) << 5) | (dst_reg as u32 & 0x1F);
+        let mut src = regs;
+        if imm >> 12 != 0 {
+            self.int_insn(opcode | (1 << 22) | (((imm >> 12) as u32) << 10) | regs);
+            src = ((dst_reg as u32 & 0x1F) << 5) | (dst_reg as u32 & 0x1F);
+        }
+        if imm & 0xFFF != 0 || imm == 0 && dst_reg != src_reg {
+            self.int_insn(opcode | (((imm & 0xFFF) as u32) << 10) | src);
+        }
+    }
+
+    /// Restores SP from the frame pointer, so it does not depend on the size of the locals, and
+    /// restores the saved registers
+    pub(super) fn emit_epilogue(&mut self, frame: &Frame) {
+        if frame.is_empty() {
+            return;
+        }
+        self.sub_imm12(Reg::X31, Reg::X29, frame.saved_size() as u16);
+        for (a, b) in frame.pairs().rev() {
+            self.ldp_imm7_post_offset(a, b, Reg::X31, 2);
+        }
+        self.ldp_imm7_post_offset(Reg::X29, Reg::X30, Reg::X31, 2);
+    }
+}
+
+impl Routine {
+    /// Reserves `size` bytes of locals aligned to `align` bytes, which must be at most 16
+    ///
+    /// Routines without a frame get a default one, so slots have to be allocated before emitting
+    /// code unless the frame already sets up a frame record.
+    pub fn alloc_slot(&mut self, size: usize, align: usize) -> StackSlot {
+        assert!(
+            align.is_power_of_two() && align <= 16,
+            "Alignment must be a power of two up to 16"
+        );
+        let empty_code = self.code.is_empty();
+        let frame = self.frame.get_or_insert_with(Frame::default);
+        assert!(
+            empty_code || !frame.is_empty(),
+            "Slots must be allocated before emitting code if the routine has no frame record"
+        );
+        let offset = frame.locals.next_multiple_of(align);
+        frame.locals = offset + size;
+        StackSlot { offset, size }
+    }
+
+    /// Loads `width` bytes at `offset` within the slot zero-extending them into `dst_reg`
+    pub fn load_slot(&mut self, width: Width, dst_reg: Reg, slot: StackSlot, offset: usize) {
+        let offset = Self::slot_access(width, slot, offset);
+        self.load_store(true, width, dst_reg, Reg::X31, offset);
+    }
+
+    /// Stores the lowest `width` bytes of `src_reg` at `offset` within the slot
+    pub fn store_slot(&mut self, width: Width, src_reg: Reg, slot: StackSlot, offset: usize) {
+        let offset = Self::slot_access(width, slot, offset);
+        self.load_store(false, width, src_reg, Reg::X31, offset);
+    }
+
+    /// Puts the address of the slot into `dst_reg`
+    pub fn slot_address(&mut self, dst_reg: Reg, slot: StackSlot) {
+        assert!(is_64_bit(dst_reg), "Destination register must be 64-bit");
+        self.sp_imm(false, dst_reg, Reg::X31, slot.offset);
+    }
+
+    /// Returns the offset of the access from SP after checking it stays within the slot
+    fn slot_access(width: Width, slot: StackSlot, offset: usize) -> i32 {
+        assert!(
+            offset + width.bytes() <= slot.size,
+            "Access exceeds the stack slot"
+        );
+        i32::try_from(slot.offset + offset).expect("Stack slot is too far from SP")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::a64::{asm::Asm, emu::Emulator, routine::tests::assemble},
+        assembler::Assembler,
+    };
+
+    #[test]
+    fn odd_callee_saved_count() {
+        let words = assemble(|r| {
+            r.set_frame(Frame {
+                callee_saved: vec![Reg::X21, Reg::X19, Reg::X20, Reg::X19],
+                ..Frame::default()
+            });
+            r.mov_imm16(Reg::X19, 1);
+            r.ret();
+        });
+        assert_eq!(
+            words,
+            [
+                0xA9BF7BFD, // stp x29, x30, [sp, #-16]!
+                0x910003FD, // mov x29, sp
+                0xA9BF53F3, // stp x19, x20, [sp, #-16]!
+                0xA9BF7FF5, // stp x21, xzr, [sp, #-16]!
+                0xD2800033, // mov x19, #1
+                0xD10083BF, // sub sp, x29, #32
+                0xA8C17FF5, // ldp x21, xzr, [sp], #16
+                0xA8C153F3, // ldp x19, x20, [sp], #16
+                0xA8C17BFD, // ldp x29, x30, [sp], #16
+                0xD65F03C0, // ret
+            ]
+        );
+    }
+
+    #[test]
+    fn locals_only() {
+        let words = assemble(|r| {
+            r.set_frame(Frame {
+                locals: 24,
+                ..Frame::default()
+            });
+            r.str_uimm12_offset(Reg::X31, Reg::X0, 1);
+            r.ldr_uimm12_offset(Reg::X0, Reg::X31, 1);
+            r.ret();
+        });
+        assert_eq!(
+            words,
+            [
+                0xA9BF7BFD, // stp x29, x30, [sp, #-16]!
+                0x910003FD, // mov x29, sp
+                0xD10083FF, // sub sp, sp, #32
+                0xF90007E0, // str x0, [sp, #8]
+                0xF94007E0, // ldr x0, [sp, #8]
+                0xD10003BF, // sub sp, x29, #0
+                0xA8C17BFD, // ldp x29, x30, [sp], #16
+                0xD65F03C0, // ret
+            ]
+        );
+    }
+
+    #[test]
+    fn epilogue_before_every_ret() {
+        let words = assemble(|r| {
+            r.set_frame(Frame {
+                callee_saved: vec![Reg::X19],
+                ..Frame::default()
+            });
+            let zero = r.new_label();
+            r.cbz(Reg::X0, zero);
+            r.ret();
+            r.bind(zero);
+            r.ret();
+        });
+        let epilogue = [
+            0xD10043BF, // sub sp, x29, #16
+            0xA8C17FF3, // ldp x19, xzr, [sp], #16
+            0xA8C17BFD, // ldp x29, x30, [sp], #16
+            0xD65F03C0, // ret
+        ];
+        let prologue = [
+            0xA9BF7BFD, // stp x29, x30, [sp, #-16]!
+            0x910003FD, // mov x29, sp
+            0xA9BF7FF3, // stp x19, xzr, [sp, #-16]!
+            0xB40000A0, // cbz x0, #20
+        ];
+        assert_eq!(words, [prologue, epilogue, epilogue].concat());
+    }
+
+    #[test]
+    fn restores_callee_saved_registers_and_sp() {
+        // f(x) = x == 0 ? 1 : x + 2, clobbering X19 to X21 and passing x through a local
+        let mut routine = Routine::new("f".to_string());
+        routine.set_frame(Frame {
+            callee_saved: vec![Reg::X19, Reg::X20, Reg::X21],
+            locals: 40,
+            ..Frame::default()
+        });
+        let zero = routine.new_label();
+        routine.cbz(Reg::X0, zero);
+        routine.mov_reg(Reg::X19, Reg::X0);
+        routine.str_uimm12_offset(Reg::X31, Reg::X19, 4);
+        routine.mov_imm16(Reg::X20, 2);
+        routine.ldr_uimm12_offset(Reg::X21, Reg::X31, 4);
+        routine.add_reg(Reg::X0, Reg::X21, Reg::X20);
+        routine.ret();
+        routine.bind(zero);
+        routine.mov_imm16(Reg::X19, 1);
+        routine.mov_reg(Reg::X0, Reg::X19);
+        routine.ret();
+        let mut asm = Asm::default();
+        asm.push_routine(routine);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+
+        let mut emu = Emulator::new(code, vtable);
+        for (input, output) in [(0, 1), (40, 42)] {
+            for reg in 19..=21 {
+                emu.set_reg(reg, reg as u64 * 0x101);
+            }
+            emu.set_reg(29, 0x2929);
+            assert_eq!(emu.call("f", &[input]), Ok(output));
+            for reg in 19..=21 {
+                assert_eq!(emu.reg(reg), reg as u64 * 0x101);
+            }
+            assert_eq!(emu.reg(29), 0x2929);
+            assert_eq!(emu.sp(), emu.memory().len() as u64);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Only X19 to X28 can be saved by the frame")]
+    fn frame_pointer_is_not_callee_saved() {
+        let mut routine = Routine::new("f".to_string());
+        routine.set_frame(Frame {
+            callee_saved: vec![Reg::X19, Reg::X29],
+            ..Frame::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Only X19 to X28 can be saved by the frame")]
+    fn caller_saved_register() {
+        let mut routine = Routine::new("f".to_string());
+        routine.set_frame(Frame {
+            callee_saved: vec![Reg::X9],
+            ..Frame::default()
+        });
+    }
+
+    const PROLOGUE: [u32; 2] = [
+        0xA9BF7BFD, // stp x29, x30, [sp, #-16]!
+        0x910003FD, // mov x29, sp
+    ];
+    const EPILOGUE: [u32; 3] = [
+        0xD10003BF, // sub sp, x29, #0
+        0xA8C17BFD, // ldp x29, x30, [sp], #16
+        0xD65F03C0, // ret
+    ];
+
+    /// Returns the words of an empty routine whose locals are `locals` bytes
+    fn locals(locals: usize, probe_stack: bool) -> Vec<u32> {
+        let words = assemble(|r| {
+            r.set_frame(Frame {
+                locals,
+                probe_stack,
+                ..Frame::default()
+            });
+            r.ret();
+        });
+        assert_eq!(words[..2], PROLOGUE);
+        assert_eq!(words[words.len() - 3..], EPILOGUE);
+        words[2..words.len() - 3].to_vec()
+    }
+
+    #[test]
+    fn slot_alignment() {
+        let mut routine = Routine::new("f".to_string());
+        let slots = [(3, 1), (8, 8), (1, 1), (2, 2), (16, 16), (4, 4)]
+            .map(|(size, align)| routine.alloc_slot(size, align).offset());
+        assert_eq!(slots, [0, 8, 16, 18, 32, 48]);
+        assert_eq!(routine.frame().unwrap().locals, 52);
+        assert_eq!(routine.frame().unwrap().locals_size(), 64);
+        // A frame record allows allocating slots after code was emitted
+        routine.nop();
+        routine.frame.as_mut().unwrap().calls = true;
+        assert_eq!(routine.alloc_slot(8, 8).offset(), 56);
+    }
+
+    #[test]
+    fn slots_beyond_immediate_offsets() {
+        let words = assemble(|r| {
+            let big = r.alloc_slot(0x8000, 8);
+            let small = r.alloc_slot(16, 8);
+            r.load_slot(Width::Double, Reg::X0, small, 8);
+            r.store_slot(Width::Byte, Reg::X1, small, 1);
+            r.load_slot(Width::Double, Reg::X2, big, 0x7FF8);
+            r.load_slot(Width::Word, Reg::X3, big, 3);
+            r.store_slot(Width::Half, Reg::X4, big, 0x2001);
+            r.slot_address(Reg::X5, small);
+            r.ret();
+        });
+        assert_eq!(
+            words[2..words.len() - 3],
+            [
+                0xD14023FF, // sub sp, sp, #8, lsl #12
+                0xD10043FF, // sub sp, sp, #0x10
+                0xD2900110, // mov x16, #0x8008
+                0xF8706BE0, // ldr x0, [sp, x16]
+                0xD2900030, // mov x16, #0x8001
+                0x38306BE1, // strb w1, [sp, x16]
+                0xF97FFFE2, // ldr x2, [sp, #0x7FF8]
+                0xB84033E3, // ldur w3, [sp, #3]
+                0xD2840030, // mov x16, #0x2001
+                0x78306BE4, // strh w4, [sp, x16]
+                0x914023E5, // add x5, sp, #8, lsl #12
+            ]
+        );
+    }
+
+    #[test]
+    fn unrolled_probes() {
+        let probe = [
+            0xD14007FF, // sub sp, sp, #1, lsl #12
+            0xF90003FF, // str xzr, [sp]
+        ];
+        // A single page needs no probe
+        assert_eq!(
+            locals(0x1000, true),
+            [0xD14007FF] // sub sp, sp, #1, lsl #12
+        );
+        assert_eq!(
+            locals(0x3010, true),
+            [
+                &probe[..],
+                &probe,
+                &probe,
+                &[0xD10043FF], // sub sp, sp, #0x10
+            ]
+            .concat()
+        );
+        assert_eq!(locals(0x4000, true), probe.repeat(4));
+    }
+
+    #[test]
+    fn looped_probes() {
+        assert_eq!(
+            locals(0x5020, true),
+            [
+                0xD28000B0, // mov x16, #5
+                0xD14007FF, // sub sp, sp, #1, lsl #12
+                0xF90003FF, // str xzr, [sp]
+                0xF1000610, // subs x16, x16, #1
+                0x54FFFFA1, // b.ne #-12
+                0xD10083FF, // sub sp, sp, #0x20
+            ]
+        );
+        assert_eq!(
+            locals(0x5020, false),
+            [
+                0xD14017FF, // sub sp, sp, #5, lsl #12
+                0xD10083FF, // sub sp, sp, #0x20
+            ]
+        );
+    }
+
+    #[test]
+    fn large_slots_run() {
+        for probe_stack in [false, true] {
+            // f(x) = x + 2x + 3x through three slots spanning more than 18 pages
+            let mut routine = Routine::new("f".to_string());
+            routine.set_frame(Frame {
+                probe_stack,
+                ..Frame::default()
+            });
+            let small = routine.alloc_slot(0x100, 8);
+            let large = routine.alloc_slot(0x12345, 16);
+            let last = routine.alloc_slot(8, 8);
+            assert_eq!(
+                [small, large, last].map(StackSlot::offset),
+                [0, 0x100, 0x12448]
+            );
+            routine.store_slot(Width::Double, Reg::X0, small, 0xF8);
+            routine.add_reg(Reg::X1, Reg::X0, Reg::X0);
+            routine.store_slot(Width::Double, Reg::X1, large, 0x12338);
+            routine.add_reg(Reg::X1, Reg::X1, Reg::X0);
+            routine.store_slot(Width::Double, Reg::X1, last, 0);
+            routine.load_slot(Width::Double, Reg::X0, small, 0xF8);
+            routine.load_slot(Width::Double, Reg::X1, large, 0x12338);
+            routine.add_reg(Reg::X0, Reg::X0, Reg::X1);
+            routine.load_slot(Width::Double, Reg::X1, last, 0);
+            routine.add_reg(Reg::X0, Reg::X0, Reg::X1);
+            routine.slot_address(Reg::X2, large);
+            routine.ret();
+            let mut asm = Asm::default();
+            asm.push_routine(routine);
+            let (code, vtable) = asm.virtual_jit().unwrap();
+
+            let mut emu = Emulator::new(code, vtable);
+            let top = emu.memory().len() - 16;
+            emu.memory_mut()[top - 0x13000..top].fill(0xFF);
+            assert_eq!(emu.call("f", &[7]), Ok(42));
+            assert_eq!(emu.sp(), emu.memory().len() as u64);
+            assert_eq!(emu.reg(2), (top - 0x12450 + 0x100) as u64);
+            // Every page below the frame record was touched from the top down
+            let probed =
+                (1..=0x12).all(|page| emu.read((top - page * PAGE_SIZE) as u64, 8) == Ok(0));
+            assert_eq!(probed, probe_stack);
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Slots must be allocated before emitting code if the routine has no frame record"
+    )]
+    fn slot_after_code() {
+        let mut routine = Routine::new("f".to_string());
+        routine.nop();
+        routine.alloc_slot(8, 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "Alignment must be a power of two up to 16")]
+    fn slot_alignment_above_16() {
+        let mut routine = Routine::new("f".to_string());
+        routine.alloc_slot(8, 32);
+    }
+}
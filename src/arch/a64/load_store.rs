@@ -0,0 +1,561 @@
+use super::{
+    operand::Extend,
+    reg::{is_64_bit, Reg},
+    routine::Routine,
+};
+
+/// Values of the `opc` field of single register loads and stores
+const STORE: u32 = 0b00;
+const LOAD: u32 = 0b01;
+const LOAD_SIGNED_64: u32 = 0b10;
+const LOAD_SIGNED_32: u32 = 0b11;
+
+/// Memory operand of a load or store, where the base register is 64-bit and `X31` is SP
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Address {
+    /// Accesses `base + offset`
+    Offset(Reg, i32),
+    /// Accesses `base + offset` and writes the address back to the base register
+    PreIndex(Reg, i32),
+    /// Accesses `base` and adds `offset` to the base register afterwards
+    PostIndex(Reg, i32),
+    /// Accesses `base` plus the extended index register, which is shifted left by the log2 of the
+    /// access size if `scaled` is set
+    ///
+    /// The index is 32-bit for `UXTW` and `SXTW` and 64-bit for `UXTX` and `SXTX`, the other
+    /// extensions are not available.
+    Register {
+        base: Reg,
+        index: Reg,
+        extend: Extend,
+        scaled: bool,
+    },
+}
+
+impl Address {
+    /// Accesses `base + index` with a 64-bit index register
+    pub fn reg(base: Reg, index: Reg) -> Self {
+        Self::Register {
+            base,
+            index,
+            extend: Extend::UXTX,
+            scaled: false,
+        }
+    }
+
+    /// Returns whether a single register access of `bytes` can use the address
+    pub fn fits(self, bytes: usize) -> bool {
+        self.encode(bytes).is_some()
+    }
+
+    /// Returns whether a pair access of two registers of `bytes` each can use the address
+    pub fn fits_pair(self, bytes: usize) -> bool {
+        self.encode_pair(bytes).is_some()
+    }
+
+    fn base(self) -> Reg {
+        match self {
+            Self::Offset(base, _)
+            | Self::PreIndex(base, _)
+            | Self::PostIndex(base, _)
+            | Self::Register { base, .. } => base,
+        }
+    }
+
+    fn writes_back(self) -> bool {
+        matches!(self, Self::PreIndex(..) | Self::PostIndex(..))
+    }
+
+    /// Returns the encoding of the address for single register accesses of `bytes` without the
+    /// size, `opc` and the transferred register
+    ///
+    /// Offsets prefer the scaled unsigned 12-bit form and fall back to the unscaled signed 9-bit
+    /// form of `ldur` and `stur`.
+    pub(super) fn encode(self, bytes: usize) -> Option<u32> {
+        assert!(is_64_bit(self.base()), "Base register must be 64-bit");
+        let base = (self.base() as u32 & 0x1F) << 5;
+        let imm9 = |offset: i32| {
+            (-0x100..=0xFF)
+                .contains(&offset)
+                .then_some((offset as u32 & 0x1FF) << 12)
+        };
+        match self {
+            Self::Offset(_, offset) => {
+                let scale = bytes as i32;
+                if offset >= 0 && offset % scale == 0 && offset / scale <= 0xFFF {
+                    Some(0x39000000 | (((offset / scale) as u32) << 10) | base)
+                } else {
+                    imm9(offset).map(|imm| 0x38000000 | imm | base)
+                }
+            }
+            Self::PreIndex(_, offset) => imm9(offset).map(|imm| 0x38000C00 | imm | base),
+            Self::PostIndex(_, offset) => imm9(offset).map(|imm| 0x38000400 | imm | base),
+            Self::Register {
+                index,
+                extend,
+                scaled,
+                ..
+            } => {
+                let index_64 = match extend {
+                    Extend::UXTW | Extend::SXTW => false,
+                    Extend::UXTX | Extend::SXTX => true,
+                    _ => return None,
+                };
+                if is_64_bit(index) != index_64 {
+                    return None;
+                }
+                Some(
+                    0x38200800
+                        | ((index as u32 & 0x1F) << 16)
+                        | ((extend as u32) << 13)
+                        | ((scaled as u32) << 12)
+                        | base,
+                )
+            }
+        }
+    }
+
+    /// Returns the encoding of the address for pair accesses of two registers of `bytes` each
+    /// without `opc`, `L` and the transferred registers
+    pub(super) fn encode_pair(self, bytes: usize) -> Option<u32> {
+        assert!(is_64_bit(self.base()), "Base register must be 64-bit");
+        let base = (self.base() as u32 & 0x1F) << 5;
+        let (opcode, offset) = match self {
+            Self::Offset(_, offset) => (0x29000000, offset),
+            Self::PreIndex(_, offset) => (0x29800000, offset),
+            Self::PostIndex(_, offset) => (0x28800000, offset),
+            Self::Register { .. } => return None,
+        };
+        let scale = bytes as i32;
+        if offset % scale != 0 || !(-0x40..=0x3F).contains(&(offset / scale)) {
+            return None;
+        }
+        Some(opcode | (((offset / scale) as u32 & 0x7F) << 15) | base)
+    }
+}
+
+impl Routine {
+    /// Loads a value of the size of the destination register
+    pub fn ldr(&mut self, dst_reg: Reg, addr: Address) {
+        let size = if is_64_bit(dst_reg) { 3 } else { 2 };
+        self.load_store_reg(size, LOAD, dst_reg, addr);
+    }
+
+    /// Loads a byte zero-extending it into the 32-bit destination register
+    pub fn ldrb(&mut self, dst_reg: Reg, addr: Address) {
+        assert!(!is_64_bit(dst_reg), "Destination register must be 32-bit");
+        self.load_store_reg(0, LOAD, dst_reg, addr);
+    }
+
+    /// Loads a halfword zero-extending it into the 32-bit destination register
+    pub fn ldrh(&mut self, dst_reg: Reg, addr: Address) {
+        assert!(!is_64_bit(dst_reg), "Destination register must be 32-bit");
+        self.load_store_reg(1, LOAD, dst_reg, addr);
+    }
+
+    /// Loads a byte sign-extending it into the destination register
+    pub fn ldrsb(&mut self, dst_reg: Reg, addr: Address) {
+        self.load_store_reg(0, signed_opc(dst_reg), dst_reg, addr);
+    }
+
+    /// Loads a halfword sign-extending it into the destination register
+    pub fn ldrsh(&mut self, dst_reg: Reg, addr: Address) {
+        self.load_store_reg(1, signed_opc(dst_reg), dst_reg, addr);
+    }
+
+    /// Loads a word sign-extending it into the 64-bit destination register
+    pub fn ldrsw(&mut self, dst_reg: Reg, addr: Address) {
+        assert!(is_64_bit(dst_reg), "Destination register must be 64-bit");
+        self.load_store_reg(2, LOAD_SIGNED_64, dst_reg, addr);
+    }
+
+    /// Stores the whole source register
+    pub fn str(&mut self, src_reg: Reg, addr: Address) {
+        let size = if is_64_bit(src_reg) { 3 } else { 2 };
+        self.load_store_reg(size, STORE, src_reg, addr);
+    }
+
+    /// Stores the lowest byte of the 32-bit source register
+    pub fn strb(&mut self, src_reg: Reg, addr: Address) {
+        assert!(!is_64_bit(src_reg), "Source register must be 32-bit");
+        self.load_store_reg(0, STORE, src_reg, addr);
+    }
+
+    /// Stores the lowest halfword of the 32-bit source register
+    pub fn strh(&mut self, src_reg: Reg, addr: Address) {
+        assert!(!is_64_bit(src_reg), "Source register must be 32-bit");
+        self.load_store_reg(1, STORE, src_reg, addr);
+    }
+
+    /// Loads a value of the size of the destination register from `base + offset` with the
+    /// unscaled signed 9-bit form
+    pub fn ldur(&mut self, dst_reg: Reg, base: Reg, offset: i16) {
+        let size = if is_64_bit(dst_reg) { 3 } else { 2 };
+        self.unscaled(size, LOAD, dst_reg, base, offset);
+    }
+
+    /// Loads a byte from `base + offset` zero-extending it into the 32-bit destination register
+    pub fn ldurb(&mut self, dst_reg: Reg, base: Reg, offset: i16) {
+        assert!(!is_64_bit(dst_reg), "Destination register must be 32-bit");
+        self.unscaled(0, LOAD, dst_reg, base, offset);
+    }
+
+    /// Loads a halfword from `base + offset` zero-extending it into the 32-bit destination
+    /// register
+    pub fn ldurh(&mut self, dst_reg: Reg, base: Reg, offset: i16) {
+        assert!(!is_64_bit(dst_reg), "Destination register must be 32-bit");
+        self.unscaled(1, LOAD, dst_reg, base, offset);
+    }
+
+    /// Loads a byte from `base + offset` sign-extending it into the destination register
+    pub fn ldursb(&mut self, dst_reg: Reg, base: Reg, offset: i16) {
+        self.unscaled(0, signed_opc(dst_reg), dst_reg, base, offset);
+    }
+
+    /// Loads a halfword from `base + offset` sign-extending it into the destination register
+    pub fn ldursh(&mut self, dst_reg: Reg, base: Reg, offset: i16) {
+        self.unscaled(1, signed_opc(dst_reg), dst_reg, base, offset);
+    }
+
+    /// Loads a word from `base + offset` sign-extending it into the 64-bit destination register
+    pub fn ldursw(&mut self, dst_reg: Reg, base: Reg, offset: i16) {
+        assert!(is_64_bit(dst_reg), "Destination register must be 64-bit");
+        self.unscaled(2, LOAD_SIGNED_64, dst_reg, base, offset);
+    }
+
+    /// Stores the whole source register at `base + offset` with the unscaled signed 9-bit form
+    pub fn stur(&mut self, src_reg: Reg, base: Reg, offset: i16) {
+        let size = if is_64_bit(src_reg) { 3 } else { 2 };
+        self.unscaled(size, STORE, src_reg, base, offset);
+    }
+
+    /// Stores the lowest byte of the 32-bit source register at `base + offset`
+    pub fn sturb(&mut self, src_reg: Reg, base: Reg, offset: i16) {
+        assert!(!is_64_bit(src_reg), "Source register must be 32-bit");
+        self.unscaled(0, STORE, src_reg, base, offset);
+    }
+
+    /// Stores the lowest halfword of the 32-bit source register at `base + offset`
+    pub fn sturh(&mut self, src_reg: Reg, base: Reg, offset: i16) {
+        assert!(!is_64_bit(src_reg), "Source register must be 32-bit");
+        self.unscaled(1, STORE, src_reg, base, offset);
+    }
+
+    /// Loads two consecutive values of the size of the registers
+    pub fn ldp(&mut self, a_reg: Reg, b_reg: Reg, addr: Address) {
+        assert!(a_reg != b_reg, "Pair loads need two different registers");
+        let opc = if is_64_bit(a_reg) { 0b10 } else { 0b00 };
+        self.load_store_pair(opc, true, a_reg, b_reg, addr);
+    }
+
+    /// Loads two consecutive words sign-extending them into the 64-bit registers
+    pub fn ldpsw(&mut self, a_reg: Reg, b_reg: Reg, addr: Address) {
+        assert!(is_64_bit(a_reg), "Destination registers must be 64-bit");
+        assert!(a_reg != b_reg, "Pair loads need two different registers");
+        self.load_store_pair(0b01, true, a_reg, b_reg, addr);
+    }
+
+    /// Stores both registers to consecutive locations
+    pub fn stp(&mut self, a_reg: Reg, b_reg: Reg, addr: Address) {
+        let opc = if is_64_bit(a_reg) { 0b10 } else { 0b00 };
+        self.load_store_pair(opc, false, a_reg, b_reg, addr);
+    }
+
+    /// Emits a single register load or store of `1 << size` bytes
+    fn load_store_reg(&mut self, size: u32, opc: u32, reg: Reg, addr: Address) {
+        check_write_back(addr, reg);
+        let Some(addr) = addr.encode(1 << size) else {
+            panic!(
+                "Address cannot be encoded for an access of {} bytes",
+                1 << size
+            );
+        };
+        self.int_insn(addr | (size << 30) | (opc << 22) | (reg as u32 & 0x1F));
+    }
+
+    fn unscaled(&mut self, size: u32, opc: u32, reg: Reg, base: Reg, offset: i16) {
+        assert!(is_64_bit(base), "Base register must be 64-bit");
+        assert!(
+            (-0x100..=0xFF).contains(&offset),
+            "Offset must fit into 9 signed bits"
+        );
+        self.int_insn(
+            0x38000000
+                | (size << 30)
+                | (opc << 22)
+                | ((offset as u32 & 0x1FF) << 12)
+                | ((base as u32 & 0x1F) << 5)
+                | (reg as u32 & 0x1F),
+        );
+    }
+
+    fn load_store_pair(&mut self, opc: u32, load: bool, a_reg: Reg, b_reg: Reg, addr: Address) {
+        assert!(
+            is_64_bit(a_reg) == is_64_bit(b_reg),
+            "Both registers must be of equal size"
+        );
+        check_write_back(addr, a_reg);
+        check_write_back(addr, b_reg);
+        let bytes = if opc == 0b10 { 8 } else { 4 };
+        let Some(addr) = addr.encode_pair(bytes) else {
+            panic!("Address cannot be encoded for a pair access of {bytes} bytes each");
+        };
+        self.int_insn(
+            addr | (opc << 30)
+                | ((load as u32) << 22)
+                | ((b_reg as u32 & 0x1F) << 10)
+                | (a_reg as u32 & 0x1F),
+        );
+    }
+}
+
+/// Returns the `opc` of sign-extending loads into the register
+fn signed_opc(reg: Reg) -> u32 {
+    if is_64_bit(reg) {
+        LOAD_SIGNED_64
+    } else {
+        LOAD_SIGNED_32
+    }
+}
+
+/// Rejects accesses writing back to the base register that also transfer it, which are
+/// unpredictable
+fn check_write_back(addr: Address, reg: Reg) {
+    let base = addr.base() as u32 & 0x1F;
+    assert!(
+        !addr.writes_back() || base == 31 || reg as u32 & 0x1F != base,
+        "Base register cannot be transferred when writing back"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::routine::tests::assemble;
+
+    #[test]
+    fn single_register() {
+        let words = assemble(|r| {
+            r.ldr(Reg::X0, Address::Offset(Reg::X1, 0));
+            r.ldr(Reg::W2, Address::Offset(Reg::X31, 16380));
+            r.ldr(Reg::X3, Address::Offset(Reg::X4, 32760));
+            r.ldr(Reg::X5, Address::Offset(Reg::X6, -8));
+            r.ldr(Reg::X7, Address::Offset(Reg::X8, 3));
+            r.ldr(Reg::W9, Address::PreIndex(Reg::X10, 16));
+            r.ldr(Reg::X11, Address::PostIndex(Reg::X31, -256));
+            r.ldr(Reg::X12, Address::reg(Reg::X13, Reg::X14));
+            r.ldr(
+                Reg::W15,
+                Address::Register {
+                    base: Reg::X16,
+                    index: Reg::W17,
+                    extend: Extend::UXTW,
+                    scaled: true,
+                },
+            );
+            r.ldr(
+                Reg::X18,
+                Address::Register {
+                    base: Reg::X19,
+                    index: Reg::W20,
+                    extend: Extend::SXTW,
+                    scaled: false,
+                },
+            );
+            r.ldr(
+                Reg::X21,
+                Address::Register {
+                    base: Reg::X22,
+                    index: Reg::X23,
+                    extend: Extend::SXTX,
+                    scaled: true,
+                },
+            );
+            r.ldrb(Reg::W0, Address::Offset(Reg::X1, 4095));
+            r.ldrh(Reg::W2, Address::Offset(Reg::X3, 8190));
+            r.ldrh(Reg::W4, Address::Offset(Reg::X5, 1));
+            r.ldrsb(Reg::X6, Address::Offset(Reg::X7, -1));
+            r.ldrsb(Reg::W8, Address::Offset(Reg::X9, 0));
+            r.ldrsh(Reg::X10, Address::PreIndex(Reg::X11, 2));
+            r.ldrsh(
+                Reg::W12,
+                Address::Register {
+                    base: Reg::X13,
+                    index: Reg::X14,
+                    extend: Extend::UXTX,
+                    scaled: true,
+                },
+            );
+            r.ldrsw(Reg::X15, Address::Offset(Reg::X16, 4));
+            r.str(Reg::X0, Address::PreIndex(Reg::X31, -16));
+            r.str(Reg::W1, Address::PostIndex(Reg::X2, 4));
+            r.strb(Reg::W3, Address::reg(Reg::X4, Reg::X5));
+            r.strh(Reg::W6, Address::Offset(Reg::X7, 254));
+        });
+        assert_eq!(
+            words,
+            [
+                0xF9400020, // ldr x0, [x1]
+                0xB97FFFE2, // ldr w2, [sp, #16380]
+                0xF97FFC83, // ldr x3, [x4, #32760]
+                0xF85F80C5, // ldr x5, [x6, #-8]
+                0xF8403107, // ldr x7, [x8, #3]
+                0xB8410D49, // ldr w9, [x10, #16]!
+                0xF85007EB, // ldr x11, [sp], #-256
+                0xF86E69AC, // ldr x12, [x13, x14]
+                0xB8715A0F, // ldr w15, [x16, w17, uxtw #2]
+                0xF874CA72, // ldr x18, [x19, w20, sxtw]
+                0xF877FAD5, // ldr x21, [x22, x23, sxtx #3]
+                0x397FFC20, // ldrb w0, [x1, #4095]
+                0x797FFC62, // ldrh w2, [x3, #8190]
+                0x784010A4, // ldrh w4, [x5, #1]
+                0x389FF0E6, // ldrsb x6, [x7, #-1]
+                0x39C00128, // ldrsb w8, [x9]
+                0x78802D6A, // ldrsh x10, [x11, #2]!
+                0x78EE79AC, // ldrsh w12, [x13, x14, lsl #1]
+                0xB980060F, // ldrsw x15, [x16, #4]
+                0xF81F0FE0, // str x0, [sp, #-16]!
+                0xB8004441, // str w1, [x2], #4
+                0x38256883, // strb w3, [x4, x5]
+                0x7901FCE6, // strh w6, [x7, #254]
+            ]
+        );
+    }
+
+    #[test]
+    fn unscaled() {
+        let words = assemble(|r| {
+            r.ldur(Reg::X0, Reg::X1, -256);
+            r.ldur(Reg::W2, Reg::X3, 255);
+            r.ldurb(Reg::W4, Reg::X5, 1);
+            r.ldurh(Reg::W6, Reg::X7, -2);
+            r.ldursb(Reg::X8, Reg::X9, 3);
+            r.ldursh(Reg::W10, Reg::X11, -5);
+            r.ldursw(Reg::X12, Reg::X31, 7);
+            r.stur(Reg::X13, Reg::X14, -8);
+            r.sturb(Reg::W15, Reg::X16, 0);
+            r.sturh(Reg::W17, Reg::X18, 100);
+        });
+        assert_eq!(
+            words,
+            [
+                0xF8500020, // ldur x0, [x1, #-256]
+                0xB84FF062, // ldur w2, [x3, #255]
+                0x384010A4, // ldurb w4, [x5, #1]
+                0x785FE0E6, // ldurh w6, [x7, #-2]
+                0x38803128, // ldursb x8, [x9, #3]
+                0x78DFB16A, // ldursh w10, [x11, #-5]
+                0xB88073EC, // ldursw x12, [sp, #7]
+                0xF81F81CD, // stur x13, [x14, #-8]
+                0x3800020F, // sturb w15, [x16, #0]
+                0x78064251, // sturh w17, [x18, #100]
+            ]
+        );
+    }
+
+    #[test]
+    fn pairs() {
+        let words = assemble(|r| {
+            r.ldp(Reg::X29, Reg::X30, Address::PostIndex(Reg::X31, 16));
+            r.ldp(Reg::W0, Reg::W1, Address::Offset(Reg::X2, -256));
+            r.ldpsw(Reg::X3, Reg::X4, Address::Offset(Reg::X5, 252));
+            r.stp(Reg::X29, Reg::X30, Address::PreIndex(Reg::X31, -16));
+            r.stp(Reg::W6, Reg::W7, Address::Offset(Reg::X8, 4));
+            r.stp(Reg::X9, Reg::X10, Address::Offset(Reg::X11, 504));
+        });
+        assert_eq!(
+            words,
+            [
+                0xA8C17BFD, // ldp x29, x30, [sp], #16
+                0x29600440, // ldp w0, w1, [x2, #-256]
+                0x695F90A3, // ldpsw x3, x4, [x5, #252]
+                0xA9BF7BFD, // stp x29, x30, [sp, #-16]!
+                0x29009D06, // stp w6, w7, [x8, #4]
+                0xA91FA969, // stp x9, x10, [x11, #504]
+            ]
+        );
+    }
+
+    #[test]
+    fn address_ranges() {
+        assert!(Address::Offset(Reg::X0, 0xFFF * 8).fits(8));
+        assert!(!Address::Offset(Reg::X0, 0x1000 * 8).fits(8));
+        assert!(Address::Offset(Reg::X0, -0x100).fits(8));
+        assert!(!Address::Offset(Reg::X0, -0x101).fits(8));
+        assert!(!Address::PreIndex(Reg::X0, 0x100).fits(1));
+        assert!(!Address::reg(Reg::X0, Reg::W1).fits(8));
+        let sign_extended_byte = Address::Register {
+            base: Reg::X0,
+            index: Reg::W1,
+            extend: Extend::SXTB,
+            scaled: false,
+        };
+        assert!(!sign_extended_byte.fits(1));
+        assert!(Address::Offset(Reg::X0, -0x200).fits_pair(8));
+        assert!(!Address::Offset(Reg::X0, -0x208).fits_pair(8));
+        assert!(Address::PostIndex(Reg::X0, 0xFC).fits_pair(4));
+        assert!(!Address::PostIndex(Reg::X0, 0x100).fits_pair(4));
+        assert!(!Address::Offset(Reg::X0, 4).fits_pair(8));
+        assert!(!Address::reg(Reg::X0, Reg::X1).fits_pair(8));
+    }
+
+    #[test]
+    #[should_panic(expected = "Destination register must be 32-bit")]
+    fn byte_load_into_64_bit_register() {
+        assemble(|r| r.ldrb(Reg::X0, Address::Offset(Reg::X1, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Destination register must be 64-bit")]
+    fn word_sign_extension_into_32_bit_register() {
+        assemble(|r| r.ldursw(Reg::W0, Reg::X1, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Source register must be 32-bit")]
+    fn halfword_store_from_64_bit_register() {
+        assemble(|r| r.strh(Reg::X0, Address::Offset(Reg::X1, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Base register must be 64-bit")]
+    fn base_in_32_bit_register() {
+        assemble(|r| r.ldr(Reg::X0, Address::Offset(Reg::W1, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Address cannot be encoded for an access of 8 bytes")]
+    fn offset_out_of_range() {
+        assemble(|r| r.ldr(Reg::X0, Address::Offset(Reg::X1, 0x8000)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Offset must fit into 9 signed bits")]
+    fn unscaled_offset_out_of_range() {
+        assemble(|r| r.stur(Reg::X0, Reg::X1, 256));
+    }
+
+    #[test]
+    #[should_panic(expected = "Base register cannot be transferred when writing back")]
+    fn write_back_to_transferred_register() {
+        assemble(|r| r.ldr(Reg::X0, Address::PostIndex(Reg::X0, 8)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Pair loads need two different registers")]
+    fn pair_load_into_one_register() {
+        assemble(|r| r.ldp(Reg::X0, Reg::X0, Address::Offset(Reg::X1, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Both registers must be of equal size")]
+    fn pair_of_mixed_sizes() {
+        assemble(|r| r.stp(Reg::X0, Reg::W1, Address::Offset(Reg::X2, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Address cannot be encoded for a pair access of 8 bytes each")]
+    fn misaligned_pair_offset() {
+        assemble(|r| r.stp(Reg::X0, Reg::X1, Address::Offset(Reg::X2, 4)));
+    }
+}
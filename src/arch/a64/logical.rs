@@ -0,0 +1,262 @@
+use super::{
+    bitmask,
+    operand::Shift,
+    reg::{is_64_bit, zero_reg, Reg},
+    routine::Routine,
+};
+
+/// Opcodes of the logical instructions in bits 29 and 30
+const AND: u32 = 0b00 << 29;
+const ORR: u32 = 0b01 << 29;
+const EOR: u32 = 0b10 << 29;
+const ANDS: u32 = 0b11 << 29;
+
+/// Bit of the shifted register forms inverting the shifted operand
+const NOT: u32 = 1 << 21;
+
+impl Routine {
+    /// Computes the bitwise and of `lhs` and the shifted `rhs`
+    pub fn and_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.logical_shifted(AND, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Computes the bitwise or of `lhs` and the shifted `rhs`
+    pub fn orr_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.logical_shifted(ORR, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Computes the bitwise exclusive or of `lhs` and the shifted `rhs`
+    pub fn eor_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.logical_shifted(EOR, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Computes the bitwise and of `lhs` and the shifted `rhs` setting N and Z according to the
+    /// result and clearing C and V
+    pub fn ands_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.logical_shifted(ANDS, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Computes the bitwise and of `lhs` and the inverted shifted `rhs`
+    pub fn bic_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.logical_shifted(AND | NOT, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Computes the bitwise or of `lhs` and the inverted shifted `rhs`
+    pub fn orn_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.logical_shifted(ORR | NOT, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Computes the bitwise exclusive or of `lhs` and the inverted shifted `rhs`
+    pub fn eon_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.logical_shifted(EOR | NOT, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Computes the bitwise and of `lhs` and the inverted shifted `rhs` setting the flags like
+    /// `ands`
+    pub fn bics_shifted(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg, shift: Shift, amount: u8) {
+        self.logical_shifted(ANDS | NOT, dst_reg, lhs, rhs, shift, amount);
+    }
+
+    /// Computes the bitwise and of `lhs` and `rhs` setting the flags like `ands_shifted`
+    pub fn ands_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.ands_shifted(dst_reg, lhs, rhs, Shift::LSL, 0);
+    }
+
+    /// Computes the bitwise and of `lhs` and the inverted `rhs`
+    pub fn bic_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.bic_shifted(dst_reg, lhs, rhs, Shift::LSL, 0);
+    }
+
+    /// Computes the bitwise or of `lhs` and the inverted `rhs`
+    pub fn orn_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.orn_shifted(dst_reg, lhs, rhs, Shift::LSL, 0);
+    }
+
+    /// Sets the flags according to the bitwise and of `lhs` and `rhs`
+    pub fn tst_reg(&mut self, lhs: Reg, rhs: Reg) {
+        self.ands_reg(zero_reg(lhs), lhs, rhs);
+    }
+
+    /// Moves the inverted value of the source register into the destination register
+    pub fn mvn(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.orn_reg(dst_reg, zero_reg(dst_reg), src_reg);
+    }
+
+    /// Computes the bitwise and of `lhs` and a bitmask immediate
+    ///
+    /// `X31` as destination register is SP. Panics if the immediate cannot be encoded, which can
+    /// be checked with `bitmask::encode`.
+    pub fn and_imm(&mut self, dst_reg: Reg, lhs: Reg, imm: u64) {
+        self.logical_imm(AND, dst_reg, lhs, imm);
+    }
+
+    /// Computes the bitwise or of `lhs` and a bitmask immediate
+    ///
+    /// `X31` as destination register is SP.
+    pub fn orr_imm(&mut self, dst_reg: Reg, lhs: Reg, imm: u64) {
+        self.logical_imm(ORR, dst_reg, lhs, imm);
+    }
+
+    /// Computes the bitwise exclusive or of `lhs` and a bitmask immediate
+    ///
+    /// `X31` as destination register is SP.
+    pub fn eor_imm(&mut self, dst_reg: Reg, lhs: Reg, imm: u64) {
+        self.logical_imm(EOR, dst_reg, lhs, imm);
+    }
+
+    /// Computes the bitwise and of `lhs` and a bitmask immediate setting the flags like
+    /// `ands_shifted`
+    pub fn ands_imm(&mut self, dst_reg: Reg, lhs: Reg, imm: u64) {
+        self.logical_imm(ANDS, dst_reg, lhs, imm);
+    }
+
+    /// Computes the bitwise and of `lhs` and the inverted immediate, which has to be a bitmask
+    /// immediate once inverted
+    pub fn bic_imm(&mut self, dst_reg: Reg, lhs: Reg, imm: u64) {
+        let mask = if is_64_bit(dst_reg) {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        };
+        self.and_imm(dst_reg, lhs, !imm & mask);
+    }
+
+    /// Sets the flags according to the bitwise and of `lhs` and a bitmask immediate
+    pub fn tst_imm(&mut self, lhs: Reg, imm: u64) {
+        self.ands_imm(zero_reg(lhs), lhs, imm);
+    }
+
+    fn logical_shifted(
+        &mut self,
+        opcode: u32,
+        dst_reg: Reg,
+        lhs: Reg,
+        rhs: Reg,
+        shift: Shift,
+        amount: u8,
+    ) {
+        let bits = if is_64_bit(dst_reg) { 64 } else { 32 };
+        assert!(amount < bits, "Shift amount exceeds the register size");
+        self.reg_op(
+            0x0A000000 | opcode | ((shift as u32) << 22) | ((amount as u32) << 10),
+            dst_reg,
+            lhs,
+            rhs,
+        );
+    }
+
+    fn logical_imm(&mut self, opcode: u32, dst_reg: Reg, lhs: Reg, imm: u64) {
+        let bits_64 = is_64_bit(dst_reg);
+        assert!(
+            bits_64 == is_64_bit(lhs),
+            "Both registers must be of equal size"
+        );
+        let Some(field) = bitmask::encode(imm, bits_64) else {
+            panic!("Immediate is not a bitmask immediate");
+        };
+        self.int_insn(
+            0x12000000
+                | opcode
+                | ((bits_64 as u32) << 31)
+                | (field << 10)
+                | ((lhs as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::routine::tests::assemble;
+
+    #[test]
+    fn shifted_register() {
+        let words = assemble(|r| {
+            r.and_shifted(Reg::X0, Reg::X1, Reg::X2, Shift::LSL, 3);
+            r.orr_shifted(Reg::W3, Reg::W4, Reg::W5, Shift::LSR, 31);
+            r.eor_shifted(Reg::X6, Reg::X7, Reg::X8, Shift::ASR, 63);
+            r.ands_shifted(Reg::W9, Reg::W10, Reg::W11, Shift::ROR, 7);
+            r.bic_shifted(Reg::X12, Reg::X13, Reg::X14, Shift::LSL, 0);
+            r.orn_shifted(Reg::W15, Reg::W16, Reg::W17, Shift::LSL, 1);
+            r.eon_shifted(Reg::X18, Reg::X19, Reg::X20, Shift::LSR, 2);
+            r.bics_shifted(Reg::W21, Reg::W22, Reg::W23, Shift::LSL, 0);
+            r.and_reg(Reg::X0, Reg::X1, Reg::X2);
+            r.orr_reg(Reg::W3, Reg::W4, Reg::W5);
+            r.eor_reg(Reg::X6, Reg::X7, Reg::X8);
+            r.ands_reg(Reg::X0, Reg::X1, Reg::X2);
+            r.orn_reg(Reg::W0, Reg::W1, Reg::W2);
+            r.tst_reg(Reg::X3, Reg::X4);
+            r.mvn(Reg::W5, Reg::W6);
+        });
+        assert_eq!(
+            words,
+            [
+                0x8A020C20, // and x0, x1, x2, lsl #3
+                0x2A457C83, // orr w3, w4, w5, lsr #31
+                0xCA88FCE6, // eor x6, x7, x8, asr #63
+                0x6ACB1D49, // ands w9, w10, w11, ror #7
+                0x8A2E01AC, // bic x12, x13, x14
+                0x2A31060F, // orn w15, w16, w17, lsl #1
+                0xCA740A72, // eon x18, x19, x20, lsr #2
+                0x6A3702D5, // bics w21, w22, w23
+                0x8A020020, // and x0, x1, x2
+                0x2A050083, // orr w3, w4, w5
+                0xCA0800E6, // eor x6, x7, x8
+                0xEA020020, // ands x0, x1, x2
+                0x2A220020, // orn w0, w1, w2
+                0xEA04007F, // tst x3, x4
+                0x2A2603E5, // mvn w5, w6
+            ]
+        );
+    }
+
+    #[test]
+    fn bitmask_immediate() {
+        let words = assemble(|r| {
+            r.and_imm(Reg::X31, Reg::X1, 0xFF);
+            r.orr_imm(Reg::X2, Reg::X3, 0x5555_5555_5555_5555);
+            r.eor_imm(Reg::W4, Reg::W5, 0x8000_0001);
+            r.ands_imm(Reg::X6, Reg::X7, !1);
+            r.bic_imm(Reg::W8, Reg::W9, 0xF);
+            r.tst_imm(Reg::W10, 0x3);
+            r.bic_imm(Reg::X11, Reg::X12, u32::MAX as u64);
+        });
+        assert_eq!(
+            words,
+            [
+                0x92401C3F, // and sp, x1, #0xff
+                0xB200F062, // orr x2, x3, #0x5555555555555555
+                0x520104A4, // eor w4, w5, #0x80000001
+                0xF27FF8E6, // ands x6, x7, #0xfffffffffffffffe
+                0x121C6D28, // and w8, w9, #0xfffffff0
+                0x7200055F, // tst w10, #0x3
+                0x92607D8B, // and x11, x12, #0xffffffff00000000
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate is not a bitmask immediate")]
+    fn not_a_bitmask_immediate() {
+        assemble(|r| r.orr_imm(Reg::X0, Reg::X1, 0x1234));
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate is not a bitmask immediate")]
+    fn all_ones_immediate() {
+        assemble(|r| r.bic_imm(Reg::W0, Reg::W1, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Shift amount exceeds the register size")]
+    fn shift_out_of_range() {
+        assemble(|r| r.and_shifted(Reg::W0, Reg::W1, Reg::W2, Shift::LSL, 32));
+    }
+
+    #[test]
+    #[should_panic(expected = "Both registers must be of equal size")]
+    fn mixed_register_sizes() {
+        assemble(|r| r.eor_imm(Reg::X0, Reg::W1, 1));
+    }
+}
@@ -0,0 +1,258 @@
+use super::{
+    cond::Cond,
+    frame::Frame,
+    load_store::Address,
+    reg::{f_bytes, is_64_bit, FReg, Reg},
+    routine::Routine,
+};
+use crate::assembler::{Condition, Label, MacroAssembler, Width};
+
+/// Intra-procedure-call scratch register used for immediates and offsets that do not fit
+const SCRATCH: Reg = Reg::X16;
+
+impl Routine {
+    /// Emits a load or store of `width` addressing `base + offset` with the scaled unsigned
+    /// offset form, the unscaled form or a register offset in `SCRATCH`
+    pub(super) fn load_store(
+        &mut self,
+        load: bool,
+        width: Width,
+        reg: Reg,
+        base: Reg,
+        offset: i32,
+    ) {
+        assert!(is_64_bit(reg), "Register must be 64-bit");
+        self.load_store_rt(load, false, width, reg as u32 & 0x1F, base, offset);
+    }
+
+    /// Same as `load_store` for a floating-point register, whose size is the access width
+    pub(super) fn load_store_fp(&mut self, load: bool, reg: FReg, base: Reg, offset: i32) {
+        let width = match f_bytes(reg) {
+            2 => Width::Half,
+            4 => Width::Word,
+            _ => Width::Double,
+        };
+        self.load_store_rt(load, true, width, reg as u32 & 0x1F, base, offset);
+    }
+
+    /// Same as `load_store` for the register number `rt`, which is a SIMD&FP register if `simd` is
+    /// set
+    fn load_store_rt(
+        &mut self,
+        load: bool,
+        simd: bool,
+        width: Width,
+        rt: u32,
+        base: Reg,
+        offset: i32,
+    ) {
+        let size = width.bytes().trailing_zeros();
+        let opc = ((load as u32) << 22) | ((simd as u32) << 26);
+        let addr = match Address::Offset(base, offset).encode(width.bytes()) {
+            Some(addr) => addr,
+            None => {
+                MacroAssembler::mov_imm(self, SCRATCH, offset as i64 as u64);
+                Address::reg(base, SCRATCH).encode(width.bytes()).unwrap()
+            }
+        };
+        self.int_insn(addr | (size << 30) | opc | rt);
+    }
+}
+
+impl MacroAssembler for Routine {
+    type Reg = Reg;
+
+    fn arg_reg(index: usize) -> Option<Reg> {
+        [
+            Reg::X0,
+            Reg::X1,
+            Reg::X2,
+            Reg::X3,
+            Reg::X4,
+            Reg::X5,
+            Reg::X6,
+            Reg::X7,
+        ]
+        .get(index)
+        .copied()
+    }
+
+    fn ret_reg() -> Reg {
+        Reg::X0
+    }
+
+    fn temp_regs() -> &'static [Reg] {
+        &[
+            Reg::X9,
+            Reg::X10,
+            Reg::X11,
+            Reg::X12,
+            Reg::X13,
+            Reg::X14,
+            Reg::X15,
+        ]
+    }
+
+    fn new_label(&mut self) -> Label {
+        Routine::new_label(self)
+    }
+
+    fn bind(&mut self, label: Label) {
+        Routine::bind(self, label);
+    }
+
+    fn mov(&mut self, dst: Reg, src: Reg) {
+        if dst != src {
+            self.mov_reg(dst, src);
+        }
+    }
+
+    fn mov_imm(&mut self, dst: Reg, imm: u64) {
+        assert!(is_64_bit(dst), "Destination register must be 64-bit");
+        self.mov_imm64(dst, imm);
+    }
+
+    fn load(&mut self, width: Width, dst: Reg, base: Reg, offset: i32) {
+        self.load_store(true, width, dst, base, offset);
+    }
+
+    fn store(&mut self, width: Width, src: Reg, base: Reg, offset: i32) {
+        self.load_store(false, width, src, base, offset);
+    }
+
+    fn add(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.add_reg(dst, lhs, rhs);
+    }
+
+    fn add_imm(&mut self, dst: Reg, lhs: Reg, imm: i32) {
+        if (0..=0xFFF).contains(&imm) {
+            self.add_imm12(dst, lhs, imm as u16);
+        } else if (-0xFFF..0).contains(&imm) {
+            self.sub_imm12(dst, lhs, imm.unsigned_abs() as u16);
+        } else {
+            MacroAssembler::mov_imm(self, SCRATCH, imm as i64 as u64);
+            self.add_reg(dst, lhs, SCRATCH);
+        }
+    }
+
+    fn sub(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.sub_reg(dst, lhs, rhs);
+    }
+
+    fn mul(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        Routine::mul(self, dst, lhs, rhs);
+    }
+
+    fn and(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.and_reg(dst, lhs, rhs);
+    }
+
+    fn or(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.orr_reg(dst, lhs, rhs);
+    }
+
+    fn xor(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.eor_reg(dst, lhs, rhs);
+    }
+
+    fn jump(&mut self, label: Label) {
+        self.b(label);
+    }
+
+    fn branch_if(&mut self, cond: Condition, lhs: Reg, rhs: Reg, label: Label) {
+        self.cmp_reg(lhs, rhs);
+        self.b_cond(Cond::from(cond), label);
+    }
+
+    fn call(&mut self, label: String) {
+        self.br_link(label);
+    }
+
+    /// Makes the frame of the routine save the link register, so the frame record is set up
+    /// once even if the routine already has a frame
+    fn prologue(&mut self) {
+        let frame = self.frame().cloned().unwrap_or_default();
+        self.set_frame(Frame {
+            calls: true,
+            ..frame
+        });
+    }
+
+    /// Does nothing as `ret` tears down the frame
+    fn epilogue(&mut self) {}
+
+    fn ret(&mut self) {
+        Routine::ret(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::a64::{asm::Asm, emu::Emulator},
+        assembler::Assembler,
+    };
+
+    /// `stp x29, x30, [sp, #-16]!`
+    const PUSH_FRAME_RECORD: u32 = 0xA9BF7BFD;
+
+    #[test]
+    fn prologue_extends_the_frame() {
+        let mut outer = Routine::new("outer".to_string());
+        outer.set_frame(Frame {
+            callee_saved: vec![Reg::X19],
+            ..Frame::default()
+        });
+        MacroAssembler::prologue(&mut outer);
+        assert_eq!(
+            outer.frame(),
+            Some(&Frame {
+                callee_saved: vec![Reg::X19],
+                calls: true,
+                ..Frame::default()
+            })
+        );
+        // outer(x) = inner(x) + x with x kept in X19 across the call
+        outer.mov_reg(Reg::X19, Reg::X0);
+        MacroAssembler::call(&mut outer, "inner".to_string());
+        outer.add_reg(Reg::X0, Reg::X0, Reg::X19);
+        MacroAssembler::epilogue(&mut outer);
+        MacroAssembler::ret(&mut outer);
+
+        let mut inner = Routine::new("inner".to_string());
+        inner.set_frame(Frame {
+            callee_saved: vec![Reg::X19],
+            ..Frame::default()
+        });
+        MacroAssembler::prologue(&mut inner);
+        inner.mov_imm16(Reg::X19, 7);
+        inner.add_reg(Reg::X0, Reg::X0, Reg::X0);
+        MacroAssembler::epilogue(&mut inner);
+        MacroAssembler::ret(&mut inner);
+
+        let mut asm = Asm::default();
+        asm.push_routine(inner);
+        asm.push_routine(outer);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let pushes = code
+            .chunks(4)
+            .filter(|insn| u32::from_le_bytes((*insn).try_into().unwrap()) == PUSH_FRAME_RECORD)
+            .count();
+        assert_eq!(pushes, 2, "Frame records are pushed once per routine");
+
+        let mut emu = Emulator::new(code, vtable);
+        emu.set_reg(19, 0x1919);
+        assert_eq!(emu.call("outer", &[5]), Ok(15));
+        assert_eq!(emu.reg(19), 0x1919);
+        assert_eq!(emu.sp(), emu.memory().len() as u64);
+    }
+
+    #[test]
+    #[should_panic(expected = "Frame must be set before emitting code")]
+    fn prologue_after_code() {
+        let mut routine = Routine::new("f".to_string());
+        routine.nop();
+        MacroAssembler::prologue(&mut routine);
+    }
+}
@@ -1,4 +1,24 @@
+pub mod arith;
 pub mod asm;
+pub mod atomic;
+pub mod bitfield;
+pub mod bitmask;
+pub mod call;
+pub mod compare;
+pub mod cond;
+pub mod crypto;
+pub mod emu;
+pub mod features;
+pub mod ffi;
+pub mod float;
+pub mod frame;
+pub mod load_store;
+pub mod logical;
+mod masm;
+pub mod operand;
+pub mod qemu;
 mod raw;
 pub mod reg;
 pub mod routine;
+pub mod simd;
+pub mod trampoline;
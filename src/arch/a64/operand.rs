@@ -0,0 +1,28 @@
+/// Shift applied to the last register operand of data-processing instructions
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shift {
+    /// Logical shift left
+    LSL = 0b00,
+    /// Logical shift right
+    LSR = 0b01,
+    /// Arithmetic shift right
+    ASR = 0b10,
+    /// Rotate right, only available for logical instructions
+    ROR = 0b11,
+}
+
+/// Extension applied to the last register operand of the extended-register add and subtract
+/// forms before it is shifted left
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extend {
+    UXTB = 0b000,
+    UXTH = 0b001,
+    UXTW = 0b010,
+    UXTX = 0b011,
+    SXTB = 0b100,
+    SXTH = 0b101,
+    SXTW = 0b110,
+    SXTX = 0b111,
+}
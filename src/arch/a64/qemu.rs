@@ -0,0 +1,132 @@
+use super::{reg::Reg, routine::Routine};
+use crate::{
+    arch::qemu::{Layout, Machine},
+    assembler::Subroutine,
+};
+use std::{collections::HashMap, io::Result};
+
+/// Runs AArch64 Linux executables through `qemu-aarch64`, whose path can be overridden through
+/// `QEMU_AARCH64`
+pub const MACHINE: Machine = Machine {
+    qemu: "qemu-aarch64",
+    env: "QEMU_AARCH64",
+    machine: 183, // EM_AARCH64
+    flags: 0,
+    word_size: 8,
+    max_args: 8,
+};
+
+/// Runs the routine with the given label of an image produced by `virtual_jit` through
+/// `qemu-aarch64` passing `args` in `X0` to `X7`
+///
+/// Returns the value of `X0` after the routine returned
+pub fn run(code: &[u8], vtable: &HashMap<String, usize>, label: &str, args: &[u64]) -> Result<u64> {
+    MACHINE.run(&elf(code, vtable, label, args)?)
+}
+
+/// Wraps an image produced by `virtual_jit` into a static AArch64 Linux executable
+///
+/// The entry stub calls the routine with the given label passing `args` in `X0` to `X7`, writes
+/// the 8 bytes of `X0` to stdout and exits with the lowest byte of `X0` as status code.
+pub fn elf(
+    code: &[u8],
+    vtable: &HashMap<String, usize>,
+    label: &str,
+    args: &[u64],
+) -> Result<Vec<u8>> {
+    MACHINE.elf(code, vtable, label, args, |layout| stub(layout, args.len()))
+}
+
+fn stub(layout: &Layout, args: usize) -> Routine {
+    let mut stub = Routine::new("_start".to_string());
+    for index in 0..args {
+        let rel = (layout.args + index * 8) as i32 - (layout.entry + stub.code().len()) as i32;
+        stub.ldr_rel19(arg_reg(index), rel / 4);
+    }
+    let rel = layout.target as i32 - (layout.entry + stub.code().len()) as i32;
+    stub.br_rel_link(rel / 4);
+    stub.str_imm9_pre_offset(Reg::X0, Reg::X31, -16);
+    // write(1, sp, 8)
+    stub.mov_reg(Reg::X19, Reg::X0);
+    stub.mov_imm16(Reg::X0, 1);
+    stub.mov_sp_to(Reg::X1);
+    stub.mov_imm16(Reg::X2, 8);
+    stub.mov_imm16(Reg::X8, 64);
+    stub.svc(0);
+    // exit_group(x0)
+    stub.mov_reg(Reg::X0, Reg::X19);
+    stub.mov_imm16(Reg::X8, 94);
+    stub.svc(0);
+    stub
+}
+
+fn arg_reg(index: usize) -> Reg {
+    [
+        Reg::X0,
+        Reg::X1,
+        Reg::X2,
+        Reg::X3,
+        Reg::X4,
+        Reg::X5,
+        Reg::X6,
+        Reg::X7,
+    ][index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::{
+            a64::{asm::Asm, frame::Frame},
+            qemu::LOAD_ADDRESS,
+        },
+        assembler::Assembler,
+        mem,
+    };
+
+    fn image() -> (Vec<u8>, HashMap<String, usize>) {
+        let mut asm = Asm::default();
+        let mut outer = Routine::new("outer".to_string());
+        outer.set_frame(Frame {
+            calls: true,
+            ..Frame::default()
+        });
+        // outer(a, b) = square(a) - b
+        outer.br_link("square".to_string());
+        outer.sub_reg(Reg::X0, Reg::X0, Reg::X1);
+        outer.ret();
+        asm.push_routine(outer);
+        let mut square = Routine::new("square".to_string());
+        square.mul(Reg::X0, Reg::X0, Reg::X0);
+        square.ret();
+        asm.push_routine(square);
+        asm.virtual_jit().unwrap()
+    }
+
+    #[test]
+    fn elf_layout() {
+        let (code, vtable) = image();
+        let file = elf(&code, &vtable, "outer", &[7, 9]).unwrap();
+        assert_eq!(&file[..4], b"\x7FELF");
+        assert_eq!(file[4], 2);
+        assert_eq!(u16::from_le_bytes([file[18], file[19]]), 183);
+        // The image directly follows the headers, the arguments follow the image
+        assert_eq!(file[128..128 + code.len()], code);
+        let args = mem::align(128 + code.len(), 8);
+        assert_eq!(file[args..args + 8], 7u64.to_le_bytes());
+        assert_eq!(file[args + 8..args + 16], 9u64.to_le_bytes());
+        let entry = u64::from_le_bytes(file[24..32].try_into().unwrap());
+        assert_eq!(entry, LOAD_ADDRESS + args as u64 + 16);
+        assert!(elf(&code, &vtable, "missing", &[]).is_err());
+        assert!(elf(&code, &vtable, "outer", &[0; 9]).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs qemu-aarch64"]
+    fn runs_routine() {
+        let (code, vtable) = image();
+        assert_eq!(run(&code, &vtable, "outer", &[7, 9]).unwrap(), 40);
+        assert_eq!(run(&code, &vtable, "square", &[1 << 20]).unwrap(), 1 << 40);
+    }
+}
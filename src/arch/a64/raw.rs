@@ -1,5 +1,5 @@
 use crate::{
-    arch::a64::reg::{is_64_bit, Reg},
+    arch::a64::reg::{f_bytes, is_64_bit, FReg, Reg},
     assembler::Assembler,
 };
 
@@ -22,21 +22,38 @@ pub fn br_reg_link(bytes: &mut [u8], insn_offset: usize, dst_reg: Reg) {
 }
 
 pub fn load_const(bytes: &mut [u8], insn_offset: usize, dst_reg: Reg, const_offset: usize) {
-    let rel = const_offset as isize - (insn_offset as isize / 4);
-    assert!(
-        (-0x40000..=0x3FFFF).contains(&rel),
-        "Tried to load constant that is not in range"
-    );
     write_ne_32(
         bytes,
         insn_offset,
         0x18000000
             | ((is_64_bit(dst_reg) as u32) << 30)
-            | ((rel as u32 & 0x7FFFF) << 5)
+            | (literal_offset(insn_offset, const_offset) << 5)
+            | (dst_reg as u32 & 0x1F),
+    );
+}
+
+/// Patches the literal load of an `S` or `D` register
+pub fn load_fp_const(bytes: &mut [u8], insn_offset: usize, dst_reg: FReg, const_offset: usize) {
+    write_ne_32(
+        bytes,
+        insn_offset,
+        0x1C000000
+            | (((f_bytes(dst_reg) == 8) as u32) << 30)
+            | (literal_offset(insn_offset, const_offset) << 5)
             | (dst_reg as u32 & 0x1F),
     );
 }
 
+/// Returns the 19-bit field of a literal load of the constant in front of the code
+fn literal_offset(insn_offset: usize, const_offset: usize) -> u32 {
+    let rel = const_offset as isize - (insn_offset as isize / 4);
+    assert!(
+        (-0x40000..=0x3FFFF).contains(&rel),
+        "Tried to load constant that is not in range"
+    );
+    rel as u32 & 0x7FFFF
+}
+
 pub fn load_global_const(
     asm: &impl Assembler,
     abs_addr: usize,
@@ -45,7 +62,7 @@ pub fn load_global_const(
     dst_reg: Reg,
     const_offset: usize,
 ) {
-    let rel = (asm.global_const_address() as isize + const_offset as isize
+    let rel = (asm.global_const_address() as isize + const_offset as isize * 4
         - abs_addr as isize
         - insn_offset as isize)
         / 4;
@@ -64,12 +81,12 @@ pub fn load_global_const(
 }
 
 pub fn ldr_imm9_post_offset(dst_reg: Reg, src_reg: Reg, imm9: i16) -> u32 {
-    assert!(is_64_bit(dst_reg), "Destination register must be 64-bit");
+    assert!(is_64_bit(src_reg), "Source register must be 64-bit");
     0xB8400400
-        | ((is_64_bit(src_reg) as u32) << 30)
+        | ((is_64_bit(dst_reg) as u32) << 30)
         | ((imm9 as u32 & 0x1FF) << 12)
-        | ((dst_reg as u32 & 0x1F) << 5)
-        | (src_reg as u32 & 0x1F)
+        | ((src_reg as u32 & 0x1F) << 5)
+        | (dst_reg as u32 & 0x1F)
 }
 
 pub fn ldp_imm7_post_offset(a_reg: Reg, b_reg: Reg, src_reg: Reg, imm7: i8) -> u32 {
@@ -114,3 +131,12 @@ pub fn write_ne_32(slice: &mut [u8], index: usize, value: u32) {
         slice[index + offset] = byte;
     }
 }
+
+pub fn read_ne_32(slice: &[u8], index: usize) -> u32 {
+    u32::from_ne_bytes([
+        slice[index],
+        slice[index + 1],
+        slice[index + 2],
+        slice[index + 3],
+    ])
+}
@@ -1,5 +1,5 @@
 #[repr(i8)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Reg {
     W0 = 0,
     X0 = 32,
@@ -70,3 +70,227 @@ pub enum Reg {
 pub fn is_64_bit(reg: Reg) -> bool {
     reg as i8 & 32 != 0
 }
+
+/// Returns the zero register of the same size as the register
+pub fn zero_reg(reg: Reg) -> Reg {
+    if is_64_bit(reg) {
+        Reg::X31
+    } else {
+        Reg::W31
+    }
+}
+
+/// Returns the 32-bit view of the register
+pub fn low_32(reg: Reg) -> Reg {
+    // W and X registers only differ in bit 5 of the discriminant
+    unsafe { std::mem::transmute::<i8, Reg>(reg as i8 & !32) }
+}
+
+/// Scalar view of a SIMD&FP register, which is the 16-bit H, the 32-bit S or the 64-bit D view
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FReg {
+    H0 = 0,
+    S0 = 32,
+    D0 = 64,
+    H1 = 1,
+    S1 = 1 | 32,
+    D1 = 1 | 64,
+    H2 = 2,
+    S2 = 2 | 32,
+    D2 = 2 | 64,
+    H3 = 3,
+    S3 = 3 | 32,
+    D3 = 3 | 64,
+    H4 = 4,
+    S4 = 4 | 32,
+    D4 = 4 | 64,
+    H5 = 5,
+    S5 = 5 | 32,
+    D5 = 5 | 64,
+    H6 = 6,
+    S6 = 6 | 32,
+    D6 = 6 | 64,
+    H7 = 7,
+    S7 = 7 | 32,
+    D7 = 7 | 64,
+    H8 = 8,
+    S8 = 8 | 32,
+    D8 = 8 | 64,
+    H9 = 9,
+    S9 = 9 | 32,
+    D9 = 9 | 64,
+    H10 = 10,
+    S10 = 10 | 32,
+    D10 = 10 | 64,
+    H11 = 11,
+    S11 = 11 | 32,
+    D11 = 11 | 64,
+    H12 = 12,
+    S12 = 12 | 32,
+    D12 = 12 | 64,
+    H13 = 13,
+    S13 = 13 | 32,
+    D13 = 13 | 64,
+    H14 = 14,
+    S14 = 14 | 32,
+    D14 = 14 | 64,
+    H15 = 15,
+    S15 = 15 | 32,
+    D15 = 15 | 64,
+    H16 = 16,
+    S16 = 16 | 32,
+    D16 = 16 | 64,
+    H17 = 17,
+    S17 = 17 | 32,
+    D17 = 17 | 64,
+    H18 = 18,
+    S18 = 18 | 32,
+    D18 = 18 | 64,
+    H19 = 19,
+    S19 = 19 | 32,
+    D19 = 19 | 64,
+    H20 = 20,
+    S20 = 20 | 32,
+    D20 = 20 | 64,
+    H21 = 21,
+    S21 = 21 | 32,
+    D21 = 21 | 64,
+    H22 = 22,
+    S22 = 22 | 32,
+    D22 = 22 | 64,
+    H23 = 23,
+    S23 = 23 | 32,
+    D23 = 23 | 64,
+    H24 = 24,
+    S24 = 24 | 32,
+    D24 = 24 | 64,
+    H25 = 25,
+    S25 = 25 | 32,
+    D25 = 25 | 64,
+    H26 = 26,
+    S26 = 26 | 32,
+    D26 = 26 | 64,
+    H27 = 27,
+    S27 = 27 | 32,
+    D27 = 27 | 64,
+    H28 = 28,
+    S28 = 28 | 32,
+    D28 = 28 | 64,
+    H29 = 29,
+    S29 = 29 | 32,
+    D29 = 29 | 64,
+    H30 = 30,
+    S30 = 30 | 32,
+    D30 = 30 | 64,
+    H31 = 31,
+    S31 = 31 | 32,
+    D31 = 31 | 64,
+}
+
+/// Returns the size of the register in bytes
+pub fn f_bytes(reg: FReg) -> usize {
+    match reg as u8 >> 5 {
+        0 => 2,
+        1 => 4,
+        _ => 8,
+    }
+}
+
+/// Returns the H view of `V<index>`
+pub fn h_reg(index: u32) -> FReg {
+    f_reg(index, 0)
+}
+
+/// Returns the S view of `V<index>`
+pub fn s_reg(index: u32) -> FReg {
+    f_reg(index, 32)
+}
+
+/// Returns the D view of `V<index>`
+pub fn d_reg(index: u32) -> FReg {
+    f_reg(index, 64)
+}
+
+fn f_reg(index: u32, view: u8) -> FReg {
+    assert!(index < 32, "There are only 32 SIMD&FP registers");
+    // The views only differ in bits 5 and 6 of the discriminant
+    unsafe { std::mem::transmute::<u8, FReg>(index as u8 | view) }
+}
+
+/// Lanes of a vector register, where `T8B` are eight bytes in the low 64 bits and `T16B` sixteen
+/// bytes filling all 128 bits
+///
+/// The discriminant holds the lane size as log2 of the bytes above the Q bit selecting 128 bits.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Arrangement {
+    T8B = 0b000,
+    T16B = 0b001,
+    T4H = 0b010,
+    T8H = 0b011,
+    T2S = 0b100,
+    T4S = 0b101,
+    T2D = 0b111,
+}
+
+impl Arrangement {
+    /// Returns whether the arrangement uses all 128 bits of the register
+    pub fn is_128_bit(self) -> bool {
+        self as u8 & 1 == 1
+    }
+
+    /// Returns the log2 of the bytes of a lane, which is the `size` field of the encodings
+    pub fn lane_size(self) -> u32 {
+        self as u32 >> 1
+    }
+
+    pub fn lanes(self) -> usize {
+        (if self.is_128_bit() { 16 } else { 8 }) >> self.lane_size()
+    }
+}
+
+/// Vector view of the SIMD&FP register `V<index>` with the arrangement of its lanes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VReg {
+    index: u8,
+    arrangement: Arrangement,
+}
+
+impl VReg {
+    pub fn index(self) -> u32 {
+        self.index as u32
+    }
+
+    pub fn arrangement(self) -> Arrangement {
+        self.arrangement
+    }
+}
+
+/// Returns the vector view of `V<index>` with the arrangement
+pub fn v_reg(index: u32, arrangement: Arrangement) -> VReg {
+    assert!(index < 32, "There are only 32 SIMD&FP registers");
+    VReg {
+        index: index as u8,
+        arrangement,
+    }
+}
+
+/// Registers a routine has to preserve for its caller according to AAPCS64, besides the frame
+/// pointer `X29`
+pub const CALLEE_SAVED: [Reg; 10] = [
+    Reg::X19,
+    Reg::X20,
+    Reg::X21,
+    Reg::X22,
+    Reg::X23,
+    Reg::X24,
+    Reg::X25,
+    Reg::X26,
+    Reg::X27,
+    Reg::X28,
+];
+
+pub fn is_callee_saved(reg: Reg) -> bool {
+    (19..=29).contains(&(reg as i8 & 31))
+}
@@ -1,14 +1,30 @@
 use super::{
+    bitmask,
+    cond::Cond,
+    features::Features,
+    frame::Frame,
     raw::{self, write_ne_32},
-    reg::{is_64_bit, Reg},
+    reg::{is_64_bit, zero_reg, FReg, Reg},
 };
-use crate::assembler::{Assembler, PostOp, Subroutine};
+use crate::assembler::{Assembler, Label, PostOp, Subroutine};
 
 pub struct Routine {
     pub(super) name: String,
     pub(super) constants: Vec<u8>,
     pub(super) code: Vec<u8>,
     pub(super) post_ops: Vec<Op>,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<Fixup>,
+    pub(super) frame: Option<Frame>,
+    pub(super) features: Features,
+}
+
+/// Branch to a local label whose offset is patched when the routine is finalized
+struct Fixup {
+    insn_offset: usize,
+    label: Label,
+    /// Whether the offset is the 26-bit one of `b` instead of the 19-bit one of `b.cond`/`cbz`
+    imm26: bool,
 }
 
 impl Routine {
@@ -18,11 +34,46 @@ impl Routine {
             constants: Vec::with_capacity(0),
             code: Vec::with_capacity(0),
             post_ops: Vec::with_capacity(0),
+            labels: Vec::with_capacity(0),
+            fixups: Vec::with_capacity(0),
+            frame: None,
+            features: Features::default(),
         }
     }
 
-    /// Returns from a routine
+    /// Allows following instructions to use the extensions, which the instructions requiring them
+    /// check
+    pub fn set_features(&mut self, features: Features) {
+        self.features = features;
+    }
+
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    /// Makes the routine generate the prologue for the frame when it is finalized and the
+    /// epilogue on every `ret`
+    ///
+    /// Must be called before any code is emitted.
+    pub fn set_frame(&mut self, mut frame: Frame) {
+        assert!(
+            self.code.is_empty(),
+            "Frame must be set before emitting code"
+        );
+        frame.validate();
+        self.frame = Some(frame);
+    }
+
+    pub fn frame(&self) -> Option<&Frame> {
+        self.frame.as_ref()
+    }
+
+    /// Returns from a routine, tearing down its frame first
     pub fn ret(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            self.emit_epilogue(&frame);
+            self.frame = Some(frame);
+        }
         self.int_insn(0xD65F03C0);
     }
 
@@ -31,6 +82,11 @@ impl Routine {
         self.int_insn(0xD503201F);
     }
 
+    /// Generates a supervisor call exception with the 16-bit immediate
+    pub fn svc(&mut self, imm: u16) {
+        self.int_insn(0xD4000001 | ((imm as u32) << 5));
+    }
+
     /// Moves the 16-bit integer into the specified register
     pub fn mov_imm16(&mut self, dst_reg: Reg, imm: u16) {
         self.int_insn(
@@ -41,6 +97,67 @@ impl Routine {
         );
     }
 
+    /// Moves the 16-bit immediate shifted left by `hw * 16` into the register, zeroing the other
+    /// bits
+    pub fn movz(&mut self, dst_reg: Reg, imm: u16, hw: u8) {
+        self.move_wide(0x52800000, dst_reg, imm, hw);
+    }
+
+    /// Moves the inverse of the 16-bit immediate shifted left by `hw * 16` into the register
+    pub fn movn(&mut self, dst_reg: Reg, imm: u16, hw: u8) {
+        self.move_wide(0x12800000, dst_reg, imm, hw);
+    }
+
+    /// Replaces the 16 bits at `hw * 16` of the register with the immediate, keeping the other
+    /// bits
+    pub fn movk(&mut self, dst_reg: Reg, imm: u16, hw: u8) {
+        self.move_wide(0x72800000, dst_reg, imm, hw);
+    }
+
+    /// Moves the immediate into the register with the shortest sequence
+    ///
+    /// Uses a single `orr` if the immediate is a bitmask immediate, otherwise a `movz` or `movn`
+    /// followed by a `movk` for every remaining 16-bit part. An immediate that would need four
+    /// instructions is loaded from the constant pool instead, which takes 12 bytes instead of 16.
+    pub fn mov_imm64(&mut self, dst_reg: Reg, imm: u64) {
+        let bits_64 = is_64_bit(dst_reg);
+        assert!(
+            dst_reg as u32 & 0x1F != 31,
+            "Destination register cannot be the zero register"
+        );
+        let parts: Vec<u16> = (0..if bits_64 { 4 } else { 2 })
+            .map(|hw| (imm >> (hw * 16)) as u16)
+            .collect();
+        assert!(
+            bits_64 || imm <= u32::MAX as u64,
+            "Immediate must fit into 32 bits"
+        );
+        let with_movz = parts.iter().filter(|part| **part != 0).count().max(1);
+        let with_movn = parts.iter().filter(|part| **part != 0xFFFF).count().max(1);
+        if with_movz.min(with_movn) > 1 && bitmask::encode(imm, bits_64).is_some() {
+            self.orr_imm(dst_reg, zero_reg(dst_reg), imm);
+            return;
+        }
+        if with_movz.min(with_movn) == 4 {
+            let offset = self.const_64(imm);
+            self.ldr_const(dst_reg, offset);
+            return;
+        }
+        let inverted = with_movn < with_movz;
+        let skip = if inverted { 0xFFFF } else { 0 };
+        let mut rest = parts.iter().enumerate().filter(|(_, part)| **part != skip);
+        // Zero and all ones still need the first instruction
+        let (hw, part) = rest.next().unwrap_or((0, &skip));
+        if inverted {
+            self.movn(dst_reg, !part, hw as u8);
+        } else {
+            self.movz(dst_reg, *part, hw as u8);
+        }
+        for (hw, part) in rest {
+            self.movk(dst_reg, *part, hw as u8);
+        }
+    }
+
     /// Moves the the value stored in the source register into the destination register
     pub fn mov_reg(&mut self, dst_reg: Reg, src_reg: Reg) {
         let bits_64 = is_64_bit(dst_reg);
@@ -107,6 +224,84 @@ impl Routine {
         self.nop();
     }
 
+    /// Creates a new label that has to be bound before the routine is finalized
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds the label to the position of the next instruction
+    pub fn bind(&mut self, label: Label) {
+        let slot = &mut self.labels[label.0];
+        assert!(slot.is_none(), "Tried to bind label twice");
+        *slot = Some(self.code.len());
+    }
+
+    /// Branches to a local label within ±128 MiB
+    pub fn b(&mut self, label: Label) {
+        self.branch(0x14000000, label, true);
+    }
+
+    /// Branches to a local label within ±1 MiB if the condition holds
+    pub fn b_cond(&mut self, cond: Cond, label: Label) {
+        self.branch(0x54000000 | cond as u32, label, false);
+    }
+
+    /// Branches to a local label within ±1 MiB if `reg` is zero
+    pub fn cbz(&mut self, reg: Reg, label: Label) {
+        self.branch(
+            0x34000000 | ((is_64_bit(reg) as u32) << 31) | (reg as u32 & 0x1F),
+            label,
+            false,
+        );
+    }
+
+    /// Branches to a local label within ±1 MiB if `reg` is not zero
+    pub fn cbnz(&mut self, reg: Reg, label: Label) {
+        self.branch(
+            0x35000000 | ((is_64_bit(reg) as u32) << 31) | (reg as u32 & 0x1F),
+            label,
+            false,
+        );
+    }
+
+    /// Adds the values of `lhs` and `rhs` and puts the result into the destination register
+    pub fn add_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x0B000000, dst_reg, lhs, rhs);
+    }
+
+    /// Subtracts the value of `rhs` from `lhs` and puts the result into the destination register
+    pub fn sub_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x4B000000, dst_reg, lhs, rhs);
+    }
+
+    /// Compares `lhs` with `rhs` setting the flags according to `lhs - rhs`
+    pub fn cmp_reg(&mut self, lhs: Reg, rhs: Reg) {
+        let zero = if is_64_bit(lhs) { Reg::X31 } else { Reg::W31 };
+        self.reg_op(0x6B000000, zero, lhs, rhs);
+    }
+
+    /// Multiplies the values of `lhs` and `rhs` and puts the lower half of the result into the
+    /// destination register
+    pub fn mul(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x1B007C00, dst_reg, lhs, rhs);
+    }
+
+    /// Computes the bitwise and of `lhs` and `rhs`
+    pub fn and_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x0A000000, dst_reg, lhs, rhs);
+    }
+
+    /// Computes the bitwise or of `lhs` and `rhs`
+    pub fn orr_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x2A000000, dst_reg, lhs, rhs);
+    }
+
+    /// Computes the bitwise exclusive or of `lhs` and `rhs`
+    pub fn eor_reg(&mut self, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        self.reg_op(0x4A000000, dst_reg, lhs, rhs);
+    }
+
     /// Subtracts the immediate 12-bit value from the value stored in lhs and puts the result
     /// into the destination register
     pub fn sub_imm12(&mut self, dst_reg: Reg, lhs: Reg, imm12: u16) {
@@ -186,14 +381,15 @@ impl Routine {
         }
         self.int_insn(
             0xB9400000
-                | ((is_64_bit(src_reg) as u32) << 30)
+                | ((is_64_bit(dst_reg) as u32) << 30)
                 | ((imm12 as u32 & 0xFFF) << 10)
                 | ((src_reg as u32 & 0x1F) << 5)
                 | (dst_reg as u32 & 0x1F),
         );
     }
 
-    /// Loads the value of address `src_reg + imm9` into `dst_reg` where `imm9` is signed
+    /// Loads the value of address `src_reg` into `dst_reg` and adds `imm9` to `src_reg`
+    /// afterwards where `imm9` is signed
     pub fn ldr_uimm9_post_offset(&mut self, dst_reg: Reg, src_reg: Reg, imm9: i16) {
         write_ne_32(
             self.alloc_insn(),
@@ -268,7 +464,94 @@ impl Routine {
         index
     }
 
-    fn int_insn(&mut self, value: u32) {
+    /// Inserts the prologue of the frame and patches the branches to local labels
+    ///
+    /// This is done by the assembler when the routine is pushed
+    pub fn finalize(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            let mut prologue = Routine::new(String::new());
+            prologue.emit_prologue(&frame);
+            prologue.finalize();
+            self.prepend(prologue.code);
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(target) = self.labels[fixup.label.0] else {
+                panic!("Tried to branch to unbound label");
+            };
+            let rel = (target as isize - fixup.insn_offset as isize) / 4;
+            let imm = if fixup.imm26 {
+                assert!(
+                    (-0x2000000..=0x1FFFFFF).contains(&rel),
+                    "Tried to branch to label not in range"
+                );
+                rel as u32 & 0x3FFFFFF
+            } else {
+                assert!(
+                    (-0x40000..=0x3FFFF).contains(&rel),
+                    "Tried to branch to label not in range"
+                );
+                (rel as u32 & 0x7FFFF) << 5
+            };
+            let insn = raw::read_ne_32(&self.code, fixup.insn_offset);
+            write_ne_32(&mut self.code, fixup.insn_offset, insn | imm);
+        }
+    }
+
+    /// Inserts code in front of the routine moving everything referring to offsets in the code
+    fn prepend(&mut self, code: Vec<u8>) {
+        let len = code.len();
+        self.code.splice(0..0, code);
+        for offset in self.labels.iter_mut().flatten() {
+            *offset += len;
+        }
+        for fixup in &mut self.fixups {
+            fixup.insn_offset += len;
+        }
+        for op in &mut self.post_ops {
+            *op.insn_offset_mut() += len;
+        }
+    }
+
+    fn move_wide(&mut self, opcode: u32, dst_reg: Reg, imm: u16, hw: u8) {
+        let bits_64 = is_64_bit(dst_reg);
+        assert!(
+            hw < if bits_64 { 4 } else { 2 },
+            "Shift exceeds the register size"
+        );
+        self.int_insn(
+            opcode
+                | ((bits_64 as u32) << 31)
+                | ((hw as u32) << 21)
+                | ((imm as u32) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    fn branch(&mut self, insn: u32, label: Label, imm26: bool) {
+        self.fixups.push(Fixup {
+            insn_offset: self.code.len(),
+            label,
+            imm26,
+        });
+        self.int_insn(insn);
+    }
+
+    /// Emits a data-processing instruction on three registers of equal size
+    pub(super) fn reg_op(&mut self, opcode: u32, dst_reg: Reg, lhs: Reg, rhs: Reg) {
+        let bits_64 = is_64_bit(dst_reg);
+        if bits_64 != is_64_bit(lhs) || bits_64 != is_64_bit(rhs) {
+            panic!("All registers must be of equal size");
+        }
+        self.int_insn(
+            opcode
+                | ((bits_64 as u32) << 31)
+                | ((rhs as u32 & 0x1F) << 16)
+                | ((lhs as u32 & 0x1F) << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    pub(super) fn int_insn(&mut self, value: u32) {
         for byte in value.to_ne_bytes() {
             self.code.push(byte);
         }
@@ -285,6 +568,10 @@ impl Routine {
 }
 
 impl Subroutine for Routine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     fn constants(&self) -> &[u8] {
         &self.constants
     }
@@ -325,6 +612,23 @@ pub enum Op {
         dst_reg: Reg,
         const_offset: usize,
     },
+    LoadFpConst {
+        insn_offset: usize,
+        dst_reg: FReg,
+        const_offset: usize,
+    },
+}
+
+impl Op {
+    fn insn_offset_mut(&mut self) -> &mut usize {
+        match self {
+            Self::Branch { insn_offset, .. }
+            | Self::BranchWithLink { insn_offset, .. }
+            | Self::LoadConst { insn_offset, .. }
+            | Self::LoadGlobalConst { insn_offset, .. }
+            | Self::LoadFpConst { insn_offset, .. } => insn_offset,
+        }
+    }
 }
 
 impl PostOp for Op {
@@ -393,6 +697,195 @@ impl PostOp for Op {
                     *const_offset,
                 );
             }
+            Self::LoadFpConst {
+                insn_offset,
+                dst_reg,
+                const_offset,
+            } => {
+                raw::load_fp_const(bytes, code_offset + *insn_offset, *dst_reg, *const_offset);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::arch::a64::{asm::Asm, emu::Emulator};
+
+    /// Returns the instruction words `build` emits with all extensions enabled
+    pub(in crate::arch::a64) fn assemble(build: impl FnOnce(&mut Routine)) -> Vec<u32> {
+        assemble_with(
+            Features {
+                lse: true,
+                crc: true,
+                aes: true,
+                pmull: true,
+                sha1: true,
+                sha2: true,
+            },
+            build,
+        )
+    }
+
+    /// Returns the instruction words `build` emits with the extensions
+    pub(in crate::arch::a64) fn assemble_with(
+        features: Features,
+        build: impl FnOnce(&mut Routine),
+    ) -> Vec<u32> {
+        let mut routine = Routine::new("f".to_string());
+        routine.set_features(features);
+        build(&mut routine);
+        routine.finalize();
+        routine
+            .code
+            .chunks(4)
+            .map(|insn| raw::read_ne_32(insn, 0))
+            .collect()
+    }
+
+    #[test]
+    fn move_wide() {
+        let words = assemble(|r| {
+            r.movz(Reg::X0, 0x1234, 0);
+            r.movz(Reg::X1, 0xABCD, 3);
+            r.movz(Reg::W2, 0xFFFF, 1);
+            r.movn(Reg::X3, 0, 0);
+            r.movn(Reg::W4, 0x1234, 1);
+            r.movk(Reg::X5, 0xBEEF, 2);
+            r.movk(Reg::W30, 1, 0);
+            r.mov_imm16(Reg::X28, 0xFFFF);
+        });
+        assert_eq!(
+            words,
+            [
+                0xD2824680, // movz x0, #0x1234
+                0xD2F579A1, // movz x1, #0xabcd, lsl #48
+                0x52BFFFE2, // movz w2, #0xffff, lsl #16
+                0x92800003, // movn x3, #0
+                0x12A24684, // movn w4, #0x1234, lsl #16
+                0xF2D7DDE5, // movk x5, #0xbeef, lsl #32
+                0x7280003E, // movk w30, #1
+                0xD29FFFFC, // movz x28, #0xffff
+            ]
+        );
+    }
+
+    #[test]
+    fn immediate_synthesis() {
+        let cases: [(Reg, u64, &[u32]); 11] = [
+            (Reg::X0, 0, &[0xD2800000]),                     // movz x0, #0
+            (Reg::X0, u64::MAX, &[0x92800000]),              // movn x0, #0
+            (Reg::W0, u32::MAX as u64, &[0x12800000]),       // movn w0, #0
+            (Reg::X0, 0x1234_0000, &[0xD2A24680]),           // movz x0, #0x1234, lsl #16
+            (Reg::X0, 0xFFFF_FFFF_FFFF_1234, &[0x929DB960]), // movn x0, #0xedcb
+            (Reg::W0, 0xFFFF_1234, &[0x129DB960]),           // movn w0, #0xedcb
+            // orr x0, xzr, #0x5555555555555555
+            (Reg::X0, 0x5555_5555_5555_5555, &[0xB200F3E0]),
+            (Reg::W9, 0x00FF_00FF, &[0x32009FE9]), // orr w9, wzr, #0xff00ff
+            (
+                Reg::X0,
+                0x1234_5678,
+                &[
+                    0xD28ACF00, // movz x0, #0x5678
+                    0xF2A24680, // movk x0, #0x1234, lsl #16
+                ],
+            ),
+            (
+                Reg::X0,
+                0xFFFF_1234_FFFF_5678,
+                &[
+                    0x929530E0, // movn x0, #0xa987
+                    0xF2C24680, // movk x0, #0x1234, lsl #32
+                ],
+            ),
+            (
+                Reg::X0,
+                0x0002_0000_0001_0000,
+                &[
+                    0xD2A00020, // movz x0, #0x1, lsl #16
+                    0xF2E00040, // movk x0, #0x2, lsl #48
+                ],
+            ),
+        ];
+        for (reg, imm, expected) in cases {
+            assert_eq!(assemble(|r| r.mov_imm64(reg, imm)), expected, "{imm:#x}");
+        }
+
+        // Four parts are loaded from the constant pool with an `ldr` literal
+        let mut routine = Routine::new("f".to_string());
+        routine.mov_imm64(Reg::X0, 0x1234_5678_9ABC_DEF0);
+        assert_eq!(routine.code.len(), 4);
+        let mut asm = Asm::default();
+        asm.push_routine(routine);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let address = vtable["f"];
+        let ldr = raw::read_ne_32(&code, address);
+        assert_eq!(ldr & 0xFF00001F, 0x58000000);
+        let literal = address as i64 + (((ldr << 8) as i32 >> 13) * 4) as i64;
+        let literal = &code[literal as usize..literal as usize + 8];
+        assert_eq!(literal, 0x1234_5678_9ABC_DEF0u64.to_ne_bytes());
+    }
+
+    #[test]
+    fn immediate_values() {
+        let mut values = vec![
+            0,
+            1,
+            u64::MAX,
+            0xFFFF,
+            0xFFFF_0000,
+            0x8000_0000_0000_0000,
+            0x0F0F_0F0F_0F0F_0F0F,
+            0x1234_5678_9ABC_DEF0,
+            0xFFFF_FFFF_0000_FFFF,
+        ];
+        let mut state = 0x9E37_79B9_7F4A_7C15_u64;
+        for _ in 0..64 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            // Masking out parts covers the sequences of every length
+            values.push(state & [u64::MAX, 0xFFFF_0000_FFFF, 0xFFFF_0000_0000][state as usize % 3]);
         }
+        let mut asm = Asm::default();
+        for (index, value) in values.iter().enumerate() {
+            let mut routine = Routine::new(format!("x{index}"));
+            routine.mov_imm64(Reg::X0, *value);
+            routine.ret();
+            asm.push_routine(routine);
+            let mut routine = Routine::new(format!("w{index}"));
+            routine.mov_imm16(Reg::X0, 0xDEAD);
+            routine.mov_imm64(Reg::W0, *value & u32::MAX as u64);
+            routine.ret();
+            asm.push_routine(routine);
+        }
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        for (index, value) in values.iter().enumerate() {
+            assert_eq!(emu.call(&format!("x{index}"), &[]), Ok(*value));
+            assert_eq!(
+                emu.call(&format!("w{index}"), &[]),
+                Ok(*value & u32::MAX as u64)
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Destination register cannot be the zero register")]
+    fn immediate_into_zero_register() {
+        assemble(|r| r.mov_imm64(Reg::X31, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate must fit into 32 bits")]
+    fn wide_immediate_into_32_bit_register() {
+        assemble(|r| r.mov_imm64(Reg::W0, 1 << 32));
+    }
+
+    #[test]
+    #[should_panic(expected = "Shift exceeds the register size")]
+    fn move_wide_shift_out_of_range() {
+        assemble(|r| r.movk(Reg::W0, 1, 2));
     }
 }
@@ -0,0 +1,655 @@
+use super::{
+    reg::{is_64_bit, Arrangement, Reg, VReg},
+    routine::Routine,
+};
+
+/// Values of the `opcode` field of `ld1` and `st1` by the number of registers
+const LD1_OPCODES: [u32; 4] = [0b0111, 0b1010, 0b0110, 0b0010];
+
+/// Bit of the structure loads and stores selecting the post-index form
+const POST_INDEX: u32 = 1 << 23;
+
+impl Routine {
+    /// Loads `count` consecutive registers starting at `first` from `base` without interleaving
+    ///
+    /// The register numbers wrap around from `V31` to `V0`.
+    pub fn ld1(&mut self, first: VReg, count: u32, base: Reg) {
+        self.load_store_multiple(true, false, first, count, base);
+    }
+
+    /// Loads like `ld1` and adds the number of bytes loaded to the base register afterwards
+    pub fn ld1_post(&mut self, first: VReg, count: u32, base: Reg) {
+        self.load_store_multiple(true, true, first, count, base);
+    }
+
+    /// Stores `count` consecutive registers starting at `first` to `base` without interleaving
+    pub fn st1(&mut self, first: VReg, count: u32, base: Reg) {
+        self.load_store_multiple(false, false, first, count, base);
+    }
+
+    /// Stores like `st1` and adds the number of bytes stored to the base register afterwards
+    pub fn st1_post(&mut self, first: VReg, count: u32, base: Reg) {
+        self.load_store_multiple(false, true, first, count, base);
+    }
+
+    /// Adds the lanes of `lhs` and `rhs`
+    pub fn add_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x0E208400, dst, lhs, rhs);
+    }
+
+    /// Subtracts the lanes of `rhs` from the lanes of `lhs`
+    pub fn sub_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x2E208400, dst, lhs, rhs);
+    }
+
+    /// Multiplies the lanes of `lhs` and `rhs`, which cannot be 64-bit
+    pub fn mul_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        check_not_64_bit_lanes(dst);
+        self.vec_three_same(0x0E209C00, dst, lhs, rhs);
+    }
+
+    /// Adds the products of the lanes of `lhs` and `rhs` to the lanes of `dst`, which cannot be
+    /// 64-bit
+    pub fn mla_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        check_not_64_bit_lanes(dst);
+        self.vec_three_same(0x0E209400, dst, lhs, rhs);
+    }
+
+    /// Adds the single or double-precision lanes of `lhs` and `rhs`
+    pub fn fadd_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_float(0x0E20D400, dst, lhs, rhs);
+    }
+
+    /// Multiplies the single or double-precision lanes of `lhs` and `rhs`
+    pub fn fmul_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_float(0x2E20DC00, dst, lhs, rhs);
+    }
+
+    /// Adds the products of the single or double-precision lanes of `lhs` and `rhs` to the lanes
+    /// of `dst` with a single rounding
+    pub fn fmla_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_float(0x0E20CC00, dst, lhs, rhs);
+    }
+
+    /// Computes the bitwise and of the byte lanes of `lhs` and `rhs`
+    pub fn and_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_bitwise(0x0E201C00, dst, lhs, rhs);
+    }
+
+    /// Computes the bitwise or of the byte lanes of `lhs` and `rhs`
+    pub fn orr_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_bitwise(0x0EA01C00, dst, lhs, rhs);
+    }
+
+    /// Computes the bitwise exclusive or of the byte lanes of `lhs` and `rhs`
+    pub fn eor_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_bitwise(0x2E201C00, dst, lhs, rhs);
+    }
+
+    /// Selects the bits of `lhs` where `dst` has set bits and the bits of `rhs` otherwise,
+    /// overwriting the mask in `dst`
+    pub fn bsl_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_bitwise(0x2E601C00, dst, lhs, rhs);
+    }
+
+    /// Copies the source register into the destination register
+    pub fn mov_vec(&mut self, dst: VReg, src: VReg) {
+        self.orr_vec(dst, src, src);
+    }
+
+    /// Sets the lanes of `dst` to all ones where the lanes of `lhs` and `rhs` are equal and to
+    /// zero otherwise
+    pub fn cmeq_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x2E208C00, dst, lhs, rhs);
+    }
+
+    /// Sets the lanes of `dst` to all ones where the lanes of `src` are zero
+    pub fn cmeq_zero(&mut self, dst: VReg, src: VReg) {
+        self.vec_two_reg(0x0E209800, dst, src);
+    }
+
+    /// Sets the lanes of `dst` to all ones where the signed lanes of `lhs` are greater than the
+    /// ones of `rhs`
+    pub fn cmgt_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x0E203400, dst, lhs, rhs);
+    }
+
+    /// Sets the lanes of `dst` to all ones where the signed lanes of `lhs` are greater than or
+    /// equal to the ones of `rhs`
+    pub fn cmge_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x0E203C00, dst, lhs, rhs);
+    }
+
+    /// Sets the lanes of `dst` to all ones where the unsigned lanes of `lhs` are higher than the
+    /// ones of `rhs`
+    pub fn cmhi_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x2E203400, dst, lhs, rhs);
+    }
+
+    /// Sets the lanes of `dst` to all ones where the unsigned lanes of `lhs` are higher than or
+    /// the same as the ones of `rhs`
+    pub fn cmhs_vec(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x2E203C00, dst, lhs, rhs);
+    }
+
+    /// Sets all lanes to the low bits of the general-purpose register, which is `X` for 64-bit
+    /// lanes and `W` otherwise
+    pub fn dup_reg(&mut self, dst: VReg, src_reg: Reg) {
+        let arrangement = dst.arrangement();
+        check_general_size(arrangement, src_reg);
+        self.int_insn(
+            0x0E000C00
+                | q_bit(arrangement)
+                | (lane_imm5(arrangement, 0) << 16)
+                | ((src_reg as u32 & 0x1F) << 5)
+                | dst.index(),
+        );
+    }
+
+    /// Sets all lanes of `dst` to the lane of `src` with the lane size of `dst`
+    pub fn dup_lane(&mut self, dst: VReg, src: VReg, lane: u32) {
+        let arrangement = dst.arrangement();
+        self.int_insn(
+            0x0E000400
+                | q_bit(arrangement)
+                | (lane_imm5(arrangement, lane) << 16)
+                | (src.index() << 5)
+                | dst.index(),
+        );
+    }
+
+    /// Replaces the lane of `dst` with the low bits of the general-purpose register, keeping the
+    /// other lanes
+    pub fn ins_reg(&mut self, dst: VReg, lane: u32, src_reg: Reg) {
+        let arrangement = dst.arrangement();
+        check_general_size(arrangement, src_reg);
+        self.int_insn(
+            0x4E001C00
+                | (lane_imm5(arrangement, lane) << 16)
+                | ((src_reg as u32 & 0x1F) << 5)
+                | dst.index(),
+        );
+    }
+
+    /// Replaces the lane of `dst` with the lane of `src` with the lane size of `dst`, keeping the
+    /// other lanes
+    pub fn ins_lane(&mut self, dst: VReg, dst_lane: u32, src: VReg, src_lane: u32) {
+        let arrangement = dst.arrangement();
+        check_lane(arrangement, src_lane);
+        // `imm4` holds the source lane above the unused low bits of the lane size
+        let src_index = src_lane << arrangement.lane_size();
+        self.int_insn(
+            0x6E000400
+                | (lane_imm5(arrangement, dst_lane) << 16)
+                | (src_index << 11)
+                | (src.index() << 5)
+                | dst.index(),
+        );
+    }
+
+    /// Moves the lane of `src` zero-extended into the general-purpose register, which is `X` for
+    /// 64-bit lanes and `W` otherwise
+    pub fn umov(&mut self, dst_reg: Reg, src: VReg, lane: u32) {
+        let arrangement = src.arrangement();
+        check_general_size(arrangement, dst_reg);
+        self.int_insn(
+            0x0E003C00
+                | ((is_64_bit(dst_reg) as u32) << 30)
+                | (lane_imm5(arrangement, lane) << 16)
+                | (src.index() << 5)
+                | (dst_reg as u32 & 0x1F),
+        );
+    }
+
+    /// Sums the lanes of `src` into the lowest lane of `dst`, zeroing the other lanes
+    pub fn addv(&mut self, dst: VReg, src: VReg) {
+        self.vec_across(0x0E31B800, dst, src);
+    }
+
+    /// Moves the smallest unsigned lane of `src` into the lowest lane of `dst`, zeroing the other
+    /// lanes
+    pub fn uminv(&mut self, dst: VReg, src: VReg) {
+        self.vec_across(0x2E31A800, dst, src);
+    }
+
+    /// Moves the largest unsigned lane of `src` into the lowest lane of `dst`, zeroing the other
+    /// lanes
+    pub fn umaxv(&mut self, dst: VReg, src: VReg) {
+        self.vec_across(0x2E30A800, dst, src);
+    }
+
+    /// Looks up the bytes of `indices` in the table of `table_len` consecutive 16-byte registers
+    /// starting at `table`, where out-of-range indices yield zero
+    pub fn tbl(&mut self, dst: VReg, table: VReg, table_len: u32, indices: VReg) {
+        self.table_lookup(0x0E000000, dst, table, table_len, indices);
+    }
+
+    /// Looks up the bytes like `tbl`, keeping the byte of `dst` for out-of-range indices
+    pub fn tbx(&mut self, dst: VReg, table: VReg, table_len: u32, indices: VReg) {
+        self.table_lookup(0x0E001000, dst, table, table_len, indices);
+    }
+
+    /// Interleaves the lanes of the lower halves of `lhs` and `rhs`
+    pub fn zip1(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x0E003800, dst, lhs, rhs);
+    }
+
+    /// Interleaves the lanes of the upper halves of `lhs` and `rhs`
+    pub fn zip2(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x0E007800, dst, lhs, rhs);
+    }
+
+    /// Concatenates the even lanes of `lhs` and `rhs`
+    pub fn uzp1(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x0E001800, dst, lhs, rhs);
+    }
+
+    /// Concatenates the odd lanes of `lhs` and `rhs`
+    pub fn uzp2(&mut self, dst: VReg, lhs: VReg, rhs: VReg) {
+        self.vec_three_same(0x0E005800, dst, lhs, rhs);
+    }
+
+    fn load_store_multiple(
+        &mut self,
+        load: bool,
+        post_index: bool,
+        first: VReg,
+        count: u32,
+        base: Reg,
+    ) {
+        assert!(is_64_bit(base), "Base register must be 64-bit");
+        assert!(
+            (1..=4).contains(&count),
+            "Between 1 and 4 registers can be transferred"
+        );
+        let arrangement = first.arrangement();
+        // The immediate post-index form encodes `X31` as offset register
+        let post_index = if post_index {
+            POST_INDEX | (0x1F << 16)
+        } else {
+            0
+        };
+        self.int_insn(
+            0x0C000000
+                | post_index
+                | q_bit(arrangement)
+                | ((load as u32) << 22)
+                | (LD1_OPCODES[count as usize - 1] << 12)
+                | (arrangement.lane_size() << 10)
+                | ((base as u32 & 0x1F) << 5)
+                | first.index(),
+        );
+    }
+
+    fn vec_three_same(&mut self, opcode: u32, dst: VReg, lhs: VReg, rhs: VReg) {
+        let arrangement = dst.arrangement();
+        check_same_arrangement(arrangement, &[lhs, rhs]);
+        self.int_insn(
+            opcode
+                | q_bit(arrangement)
+                | (arrangement.lane_size() << 22)
+                | (rhs.index() << 16)
+                | (lhs.index() << 5)
+                | dst.index(),
+        );
+    }
+
+    fn vec_float(&mut self, opcode: u32, dst: VReg, lhs: VReg, rhs: VReg) {
+        let arrangement = dst.arrangement();
+        check_same_arrangement(arrangement, &[lhs, rhs]);
+        assert!(
+            arrangement.lane_size() >= 2,
+            "Floating-point lanes must be single or double precision"
+        );
+        self.int_insn(
+            opcode
+                | q_bit(arrangement)
+                | ((arrangement.lane_size() & 1) << 22)
+                | (rhs.index() << 16)
+                | (lhs.index() << 5)
+                | dst.index(),
+        );
+    }
+
+    fn vec_bitwise(&mut self, opcode: u32, dst: VReg, lhs: VReg, rhs: VReg) {
+        let arrangement = dst.arrangement();
+        check_same_arrangement(arrangement, &[lhs, rhs]);
+        assert!(
+            arrangement.lane_size() == 0,
+            "Bitwise operations require the 8B or 16B arrangement"
+        );
+        self.int_insn(
+            opcode | q_bit(arrangement) | (rhs.index() << 16) | (lhs.index() << 5) | dst.index(),
+        );
+    }
+
+    fn vec_two_reg(&mut self, opcode: u32, dst: VReg, src: VReg) {
+        let arrangement = dst.arrangement();
+        check_same_arrangement(arrangement, &[src]);
+        self.int_insn(
+            opcode
+                | q_bit(arrangement)
+                | (arrangement.lane_size() << 22)
+                | (src.index() << 5)
+                | dst.index(),
+        );
+    }
+
+    fn vec_across(&mut self, opcode: u32, dst: VReg, src: VReg) {
+        let arrangement = src.arrangement();
+        assert!(
+            arrangement.lanes() >= 4,
+            "Reductions require at least 4 lanes"
+        );
+        self.vec_two_reg(opcode, dst, src);
+    }
+
+    fn table_lookup(&mut self, opcode: u32, dst: VReg, table: VReg, table_len: u32, indices: VReg) {
+        let arrangement = dst.arrangement();
+        check_same_arrangement(arrangement, &[indices]);
+        assert!(
+            arrangement.lane_size() == 0,
+            "Table lookups require the 8B or 16B arrangement"
+        );
+        assert!(
+            table.arrangement() == Arrangement::T16B,
+            "Tables consist of 16B registers"
+        );
+        assert!(
+            (1..=4).contains(&table_len),
+            "Tables consist of 1 to 4 registers"
+        );
+        self.int_insn(
+            opcode
+                | q_bit(arrangement)
+                | (indices.index() << 16)
+                | ((table_len - 1) << 13)
+                | (table.index() << 5)
+                | dst.index(),
+        );
+    }
+}
+
+/// Returns the Q bit selecting all 128 bits of the registers
+fn q_bit(arrangement: Arrangement) -> u32 {
+    (arrangement.is_128_bit() as u32) << 30
+}
+
+/// Returns the `imm5` field selecting the lane size of the arrangement and the lane
+fn lane_imm5(arrangement: Arrangement, lane: u32) -> u32 {
+    check_lane(arrangement, lane);
+    ((lane << 1) | 1) << arrangement.lane_size()
+}
+
+/// Checks that the lane exists in a 128-bit register with the lane size of the arrangement
+fn check_lane(arrangement: Arrangement, lane: u32) {
+    assert!(
+        lane < 16 >> arrangement.lane_size(),
+        "Lane index out of range"
+    );
+}
+
+fn check_same_arrangement(arrangement: Arrangement, regs: &[VReg]) {
+    assert!(
+        regs.iter().all(|reg| reg.arrangement() == arrangement),
+        "All registers must have the same arrangement"
+    );
+}
+
+fn check_not_64_bit_lanes(reg: VReg) {
+    assert!(reg.arrangement().lane_size() != 3, "Lanes cannot be 64-bit");
+}
+
+/// Checks that 64-bit lanes pair with `X` and the other lanes with `W` registers
+fn check_general_size(arrangement: Arrangement, reg: Reg) {
+    assert!(
+        is_64_bit(reg) == (arrangement.lane_size() == 3),
+        "64-bit lanes pair with X and the other lanes with W registers"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::{reg::v_reg, routine::tests::assemble};
+    use Arrangement::*;
+
+    #[test]
+    fn structure_loads_and_stores() {
+        let words = assemble(|r| {
+            r.ld1(v_reg(0, T16B), 1, Reg::X1);
+            r.ld1(v_reg(2, T4S), 2, Reg::X31);
+            r.ld1(v_reg(30, T2D), 3, Reg::X4);
+            r.ld1(v_reg(5, T8B), 4, Reg::X9);
+            r.ld1_post(v_reg(10, T8H), 1, Reg::X11);
+            r.ld1_post(v_reg(12, T2S), 2, Reg::X14);
+            r.st1(v_reg(15, T4H), 1, Reg::X16);
+            r.st1(v_reg(17, T16B), 4, Reg::X21);
+            r.st1_post(v_reg(22, T2D), 3, Reg::X25);
+        });
+        assert_eq!(
+            words,
+            [
+                0x4C407020, // ld1 {v0.16b}, [x1]
+                0x4C40ABE2, // ld1 {v2.4s, v3.4s}, [sp]
+                0x4C406C9E, // ld1 {v30.2d, v31.2d, v0.2d}, [x4]
+                0x0C402125, // ld1 {v5.8b, v6.8b, v7.8b, v8.8b}, [x9]
+                0x4CDF756A, // ld1 {v10.8h}, [x11], #16
+                0x0CDFA9CC, // ld1 {v12.2s, v13.2s}, [x14], #16
+                0x0C00760F, // st1 {v15.4h}, [x16]
+                0x4C0022B1, // st1 {v17.16b, v18.16b, v19.16b, v20.16b}, [x21]
+                0x4C9F6F36, // st1 {v22.2d, v23.2d, v24.2d}, [x25], #48
+            ]
+        );
+    }
+
+    #[test]
+    fn arithmetic_and_bitwise() {
+        let words = assemble(|r| {
+            r.add_vec(v_reg(0, T16B), v_reg(1, T16B), v_reg(2, T16B));
+            r.add_vec(v_reg(3, T2D), v_reg(4, T2D), v_reg(5, T2D));
+            r.sub_vec(v_reg(6, T4H), v_reg(7, T4H), v_reg(8, T4H));
+            r.mul_vec(v_reg(9, T4S), v_reg(10, T4S), v_reg(11, T4S));
+            r.mla_vec(v_reg(12, T8B), v_reg(13, T8B), v_reg(14, T8B));
+            r.fadd_vec(v_reg(15, T4S), v_reg(16, T4S), v_reg(17, T4S));
+            r.fmul_vec(v_reg(18, T2D), v_reg(19, T2D), v_reg(20, T2D));
+            r.fmla_vec(v_reg(21, T2S), v_reg(22, T2S), v_reg(23, T2S));
+            r.and_vec(v_reg(0, T16B), v_reg(1, T16B), v_reg(2, T16B));
+            r.orr_vec(v_reg(3, T8B), v_reg(4, T8B), v_reg(5, T8B));
+            r.eor_vec(v_reg(6, T16B), v_reg(7, T16B), v_reg(8, T16B));
+            r.bsl_vec(v_reg(9, T16B), v_reg(10, T16B), v_reg(11, T16B));
+            r.mov_vec(v_reg(12, T16B), v_reg(13, T16B));
+        });
+        assert_eq!(
+            words,
+            [
+                0x4E228420, // add v0.16b, v1.16b, v2.16b
+                0x4EE58483, // add v3.2d, v4.2d, v5.2d
+                0x2E6884E6, // sub v6.4h, v7.4h, v8.4h
+                0x4EAB9D49, // mul v9.4s, v10.4s, v11.4s
+                0x0E2E95AC, // mla v12.8b, v13.8b, v14.8b
+                0x4E31D60F, // fadd v15.4s, v16.4s, v17.4s
+                0x6E74DE72, // fmul v18.2d, v19.2d, v20.2d
+                0x0E37CED5, // fmla v21.2s, v22.2s, v23.2s
+                0x4E221C20, // and v0.16b, v1.16b, v2.16b
+                0x0EA51C83, // orr v3.8b, v4.8b, v5.8b
+                0x6E281CE6, // eor v6.16b, v7.16b, v8.16b
+                0x6E6B1D49, // bsl v9.16b, v10.16b, v11.16b
+                0x4EAD1DAC, // mov v12.16b, v13.16b
+            ]
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let words = assemble(|r| {
+            r.cmeq_vec(v_reg(0, T8H), v_reg(1, T8H), v_reg(2, T8H));
+            r.cmeq_zero(v_reg(3, T2D), v_reg(4, T2D));
+            r.cmgt_vec(v_reg(5, T4S), v_reg(6, T4S), v_reg(7, T4S));
+            r.cmge_vec(v_reg(8, T8B), v_reg(9, T8B), v_reg(10, T8B));
+            r.cmhi_vec(v_reg(11, T2D), v_reg(12, T2D), v_reg(13, T2D));
+            r.cmhs_vec(v_reg(14, T4H), v_reg(15, T4H), v_reg(16, T4H));
+        });
+        assert_eq!(
+            words,
+            [
+                0x6E628C20, // cmeq v0.8h, v1.8h, v2.8h
+                0x4EE09883, // cmeq v3.2d, v4.2d, #0
+                0x4EA734C5, // cmgt v5.4s, v6.4s, v7.4s
+                0x0E2A3D28, // cmge v8.8b, v9.8b, v10.8b
+                0x6EED358B, // cmhi v11.2d, v12.2d, v13.2d
+                0x2E703DEE, // cmhs v14.4h, v15.4h, v16.4h
+            ]
+        );
+    }
+
+    #[test]
+    fn lanes() {
+        let words = assemble(|r| {
+            r.dup_reg(v_reg(0, T16B), Reg::W1);
+            r.dup_reg(v_reg(2, T2D), Reg::X3);
+            r.dup_lane(v_reg(4, T4H), v_reg(5, T8H), 7);
+            r.dup_lane(v_reg(6, T4S), v_reg(7, T4S), 3);
+            r.dup_lane(v_reg(8, T2D), v_reg(9, T2D), 1);
+            r.ins_reg(v_reg(10, T16B), 15, Reg::W11);
+            r.ins_reg(v_reg(12, T2D), 1, Reg::X13);
+            r.ins_lane(v_reg(14, T8H), 3, v_reg(15, T8H), 6);
+            r.ins_lane(v_reg(16, T4S), 0, v_reg(17, T4S), 2);
+            r.umov(Reg::W18, v_reg(19, T16B), 9);
+            r.umov(Reg::W20, v_reg(21, T8H), 0);
+            r.umov(Reg::W22, v_reg(23, T4S), 3);
+            r.umov(Reg::X24, v_reg(25, T2D), 1);
+        });
+        assert_eq!(
+            words,
+            [
+                0x4E010C20, // dup v0.16b, w1
+                0x4E080C62, // dup v2.2d, x3
+                0x0E1E04A4, // dup v4.4h, v5.h[7]
+                0x4E1C04E6, // dup v6.4s, v7.s[3]
+                0x4E180528, // dup v8.2d, v9.d[1]
+                0x4E1F1D6A, // mov v10.b[15], w11
+                0x4E181DAC, // mov v12.d[1], x13
+                0x6E0E65EE, // mov v14.h[3], v15.h[6]
+                0x6E044630, // mov v16.s[0], v17.s[2]
+                0x0E133E72, // umov w18, v19.b[9]
+                0x0E023EB4, // umov w20, v21.h[0]
+                0x0E1C3EF6, // mov w22, v23.s[3]
+                0x4E183F38, // mov x24, v25.d[1]
+            ]
+        );
+    }
+
+    #[test]
+    fn reductions_and_permutations() {
+        let words = assemble(|r| {
+            r.addv(v_reg(0, T16B), v_reg(1, T16B));
+            r.addv(v_reg(2, T4H), v_reg(3, T4H));
+            r.addv(v_reg(4, T4S), v_reg(5, T4S));
+            r.uminv(v_reg(6, T8B), v_reg(7, T8B));
+            r.umaxv(v_reg(8, T8H), v_reg(9, T8H));
+            r.tbl(v_reg(0, T16B), v_reg(1, T16B), 1, v_reg(2, T16B));
+            r.tbl(v_reg(3, T8B), v_reg(30, T16B), 2, v_reg(4, T8B));
+            r.tbx(v_reg(5, T16B), v_reg(6, T16B), 4, v_reg(10, T16B));
+            r.zip1(v_reg(0, T4S), v_reg(1, T4S), v_reg(2, T4S));
+            r.zip2(v_reg(3, T8B), v_reg(4, T8B), v_reg(5, T8B));
+            r.uzp1(v_reg(6, T2D), v_reg(7, T2D), v_reg(8, T2D));
+            r.uzp2(v_reg(9, T8H), v_reg(10, T8H), v_reg(11, T8H));
+        });
+        assert_eq!(
+            words,
+            [
+                0x4E31B820, // addv b0, v1.16b
+                0x0E71B862, // addv h2, v3.4h
+                0x4EB1B8A4, // addv s4, v5.4s
+                0x2E31A8E6, // uminv b6, v7.8b
+                0x6E70A928, // umaxv h8, v9.8h
+                0x4E020020, // tbl v0.16b, {v1.16b}, v2.16b
+                0x0E0423C3, // tbl v3.8b, {v30.16b, v31.16b}, v4.8b
+                0x4E0A70C5, // tbx v5.16b, {v6.16b, v7.16b, v8.16b, v9.16b}, v10.16b
+                0x4E823820, // zip1 v0.4s, v1.4s, v2.4s
+                0x0E057883, // zip2 v3.8b, v4.8b, v5.8b
+                0x4EC818E6, // uzp1 v6.2d, v7.2d, v8.2d
+                0x4E4B5949, // uzp2 v9.8h, v10.8h, v11.8h
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Between 1 and 4 registers can be transferred")]
+    fn five_register_structure() {
+        assemble(|r| r.ld1(v_reg(0, T16B), 5, Reg::X1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Base register must be 64-bit")]
+    fn structure_base_in_32_bit_register() {
+        assemble(|r| r.st1(v_reg(0, T16B), 1, Reg::W1));
+    }
+
+    #[test]
+    #[should_panic(expected = "All registers must have the same arrangement")]
+    fn mixed_arrangements() {
+        assemble(|r| r.add_vec(v_reg(0, T4S), v_reg(1, T4S), v_reg(2, T2S)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Lanes cannot be 64-bit")]
+    fn multiplication_of_64_bit_lanes() {
+        assemble(|r| r.mul_vec(v_reg(0, T2D), v_reg(1, T2D), v_reg(2, T2D)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Floating-point lanes must be single or double precision")]
+    fn floating_point_halfword_lanes() {
+        assemble(|r| r.fadd_vec(v_reg(0, T8H), v_reg(1, T8H), v_reg(2, T8H)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Bitwise operations require the 8B or 16B arrangement")]
+    fn bitwise_word_lanes() {
+        assemble(|r| r.eor_vec(v_reg(0, T4S), v_reg(1, T4S), v_reg(2, T4S)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Reductions require at least 4 lanes")]
+    fn reduction_of_two_lanes() {
+        assemble(|r| r.addv(v_reg(0, T2S), v_reg(1, T2S)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Lane index out of range")]
+    fn lane_out_of_range() {
+        assemble(|r| r.umov(Reg::W0, v_reg(1, T4S), 4));
+    }
+
+    #[test]
+    #[should_panic(expected = "Lane index out of range")]
+    fn source_lane_out_of_range() {
+        assemble(|r| r.ins_lane(v_reg(0, T2D), 0, v_reg(1, T2D), 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "64-bit lanes pair with X and the other lanes with W registers")]
+    fn word_lane_from_64_bit_register() {
+        assemble(|r| r.dup_reg(v_reg(0, T4S), Reg::X1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Table lookups require the 8B or 16B arrangement")]
+    fn table_lookup_of_halfwords() {
+        assemble(|r| r.tbl(v_reg(0, T8H), v_reg(1, T16B), 1, v_reg(2, T8H)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Tables consist of 16B registers")]
+    fn table_of_8b_registers() {
+        assemble(|r| r.tbl(v_reg(0, T8B), v_reg(1, T8B), 1, v_reg(2, T8B)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Tables consist of 1 to 4 registers")]
+    fn empty_table() {
+        assemble(|r| r.tbx(v_reg(0, T16B), v_reg(1, T16B), 0, v_reg(2, T16B)));
+    }
+}
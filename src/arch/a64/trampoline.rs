@@ -0,0 +1,181 @@
+use super::{asm::Asm, reg::Reg, routine::Routine};
+use crate::assembler::{Assembler, MacroAssembler, VTable};
+
+/// Name of the routine generated for a closure
+const TRAMPOLINE: &str = "trampoline";
+
+/// Most integer arguments a trampoline forwards, as the closure takes `X0`
+pub const MAX_ARGS: usize = 7;
+
+type Closure = dyn Fn(&[u64]) -> u64 + Send + Sync;
+
+/// Boxed closure together with the number of arguments it is called with
+struct Callback {
+    arity: usize,
+    closure: Box<Closure>,
+}
+
+/// Function the trampolines branch to with the callback in `X0` and the arguments of the
+/// trampoline moved up by one register
+extern "C" fn dispatch(
+    callback: *const Callback,
+    a0: u64,
+    a1: u64,
+    a2: u64,
+    a3: u64,
+    a4: u64,
+    a5: u64,
+    a6: u64,
+) -> u64 {
+    // Only shared references are created, as calls can overlap on several threads or reenter
+    let callback = unsafe { &*callback };
+    let args = [a0, a1, a2, a3, a4, a5, a6];
+    (callback.closure)(&args[..callback.arity])
+}
+
+/// Generates the routine moving the arguments up, loading the callback into `X0` and tail-calling
+/// `dispatch`
+fn trampoline(callback: *const Callback, arity: usize) -> Routine {
+    let mut routine = Routine::new(TRAMPOLINE.to_string());
+    for index in (0..arity).rev() {
+        let dst = Routine::arg_reg(index + 1).unwrap();
+        routine.mov_reg(dst, Routine::arg_reg(index).unwrap());
+    }
+    let callback = routine.const_64(callback as u64);
+    routine.ldr_const(Reg::X0, callback);
+    let dispatch = routine.const_64(dispatch as *const () as u64);
+    routine.ldr_const(Reg::X16, dispatch);
+    routine.br_reg(Reg::X16);
+    routine
+}
+
+/// Closure callable through a generated `extern "C"` function taking up to `MAX_ARGS` integer or
+/// pointer arguments and returning an integer, for C callbacks without a user-data argument
+///
+/// The function pointer is only valid while the trampoline is alive, dropping it frees the code
+/// and the closure. Panics escaping the closure abort the process.
+///
+/// C code may call the function from any thread, from several threads at once and reentrantly
+/// from within the closure, so the closure is `Fn + Send + Sync` and keeps mutable state behind
+/// atomics or a lock. A `Mutex` deadlocks if the closure calls back into itself while holding it.
+pub struct Trampoline {
+    /// Keeps the code of the trampoline alive
+    vtable: VTable,
+    callback: *const Callback,
+}
+
+impl Trampoline {
+    pub fn new(arity: usize, closure: impl Fn(&[u64]) -> u64 + Send + Sync + 'static) -> Self {
+        assert!(arity <= MAX_ARGS, "Trampolines take at most 7 arguments");
+        let callback: *const Callback = Box::into_raw(Box::new(Callback {
+            arity,
+            closure: Box::new(closure),
+        }));
+        let mut asm = Asm::default();
+        asm.push_routine(trampoline(callback, arity));
+        let Some(vtable) = asm.jit() else {
+            drop(unsafe { Box::from_raw(callback.cast_mut()) });
+            panic!("Could not JIT the trampoline");
+        };
+        Self { vtable, callback }
+    }
+
+    /// Returns the address of the generated function
+    pub fn code_ptr(&self) -> *const () {
+        self.vtable.lookup(TRAMPOLINE).unwrap() as *const ()
+    }
+
+    /// Returns the generated function as a function pointer type such as
+    /// `extern "C" fn(u64, u64) -> u64`
+    ///
+    /// # Safety
+    /// `F` must be an `extern "C"` function pointer type taking as many integer or pointer
+    /// arguments as the trampoline was created with, and it must not be called after the
+    /// trampoline is dropped.
+    pub unsafe fn as_fn<F: Copy>(&self) -> F {
+        assert_eq!(
+            size_of::<F>(),
+            size_of::<*const ()>(),
+            "Type must be a function pointer"
+        );
+        let ptr = self.code_ptr();
+        unsafe { std::mem::transmute_copy(&ptr) }
+    }
+}
+
+impl Drop for Trampoline {
+    fn drop(&mut self) {
+        // The code is freed with the V-Table afterwards, which leaves nothing referring to the
+        // callback once the trampoline is gone
+        drop(unsafe { Box::from_raw(self.callback.cast_mut()) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::a64::emu::Emulator;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn call_dispatch(callback: *const Callback, args: [u64; 7]) -> u64 {
+        let [a0, a1, a2, a3, a4, a5, a6] = args;
+        dispatch(callback, a0, a1, a2, a3, a4, a5, a6)
+    }
+
+    #[test]
+    fn forwards_arguments() {
+        let callback: *const Callback = Box::into_raw(Box::new(Callback {
+            arity: 3,
+            closure: Box::new(|args| {
+                assert_eq!(args.len(), 3);
+                args.iter()
+                    .enumerate()
+                    .map(|(index, arg)| arg << (index * 8))
+                    .sum()
+            }),
+        }));
+        let mut asm = Asm::default();
+        asm.push_routine(trampoline(callback, 3));
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        // The host function is run natively with the registers of the emulated tail call
+        let address = callback as usize;
+        emu.hook(dispatch as *const () as u64, move |emu| {
+            assert_eq!(emu.reg(0), address as u64);
+            let args = std::array::from_fn(|index| emu.reg(index + 1));
+            let result = call_dispatch(emu.reg(0) as *const Callback, args);
+            emu.set_reg(0, result);
+        });
+        assert_eq!(emu.call(TRAMPOLINE, &[1, 2, 3]), Ok(0x030201));
+        assert_eq!(emu.call(TRAMPOLINE, &[4, 5, 6, 7]), Ok(0x060504));
+        drop(unsafe { Box::from_raw(callback.cast_mut()) });
+    }
+
+    #[test]
+    fn concurrent_calls() {
+        let total = AtomicU64::new(0);
+        let trampoline = Trampoline::new(1, move |args| {
+            total.fetch_add(args[0], Ordering::Relaxed) + args[0]
+        });
+        let callback = trampoline.callback as usize;
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        call_dispatch(callback as *const Callback, [1; 7]);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(call_dispatch(trampoline.callback, [0; 7]), 4000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Trampolines take at most 7 arguments")]
+    fn too_many_arguments() {
+        Trampoline::new(MAX_ARGS + 1, |_| 0);
+    }
+}
@@ -1,2 +1,5 @@
+pub mod a32;
 pub mod a64;
+pub mod qemu;
+pub mod rv64;
 pub mod x64;
@@ -0,0 +1,152 @@
+use crate::{assembler::Subroutine, mem};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{Error, ErrorKind, Result},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Virtual address the ELF files are loaded at
+pub const LOAD_ADDRESS: u64 = 0x40_0000;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Linux target whose static executables are run through qemu user mode emulation
+pub struct Machine {
+    /// Name of the qemu binary
+    pub qemu: &'static str,
+    /// Environment variable overriding the path of the qemu binary
+    pub env: &'static str,
+    /// ELF machine type
+    pub machine: u16,
+    /// Processor specific ELF flags
+    pub flags: u32,
+    /// Size of a register in bytes, 4 selects ELF32 and 8 ELF64
+    pub word_size: usize,
+    /// Number of arguments the entry stub can pass in registers
+    pub max_args: usize,
+}
+
+/// File offsets of the parts of an executable, which are also their offsets from `LOAD_ADDRESS`
+pub struct Layout {
+    /// Offset of the image produced by `virtual_jit`
+    pub image: usize,
+    /// Offset of the routine to call
+    pub target: usize,
+    /// Offset of the arguments, one word each
+    pub args: usize,
+    /// Offset of the entry stub
+    pub entry: usize,
+}
+
+impl Machine {
+    /// Wraps an image produced by `virtual_jit` into a static Linux executable
+    ///
+    /// The image directly follows the headers, then come the arguments and the entry stub, which
+    /// is built by `stub` once the layout is known. The stub is expected to call the routine at
+    /// `target` with the arguments, write the result word to stdout and exit.
+    pub fn elf<R: Subroutine>(
+        &self,
+        code: &[u8],
+        vtable: &HashMap<String, usize>,
+        label: &str,
+        args: &[u64],
+        stub: impl FnOnce(&Layout) -> R,
+    ) -> Result<Vec<u8>> {
+        if args.len() > self.max_args {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("At most {} arguments can be passed", self.max_args),
+            ));
+        }
+        let Some(&target) = vtable.get(label) else {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "Tried to run non-existent label",
+            ));
+        };
+        let elf64 = self.word_size == 8;
+        let (header_size, program_header_size) = if elf64 { (64, 56) } else { (52, 32) };
+        let image = mem::align(header_size + program_header_size, 16);
+        let args_offset = mem::align(image + code.len(), 8);
+        let layout = Layout {
+            image,
+            target: image + target,
+            args: args_offset,
+            entry: args_offset + args.len() * self.word_size,
+        };
+        let stub = stub(&layout);
+        let size = layout.entry + stub.code().len();
+
+        let mut elf = Vec::with_capacity(size);
+        let word = |elf: &mut Vec<u8>, value: u64| {
+            elf.extend_from_slice(&value.to_le_bytes()[..self.word_size]);
+        };
+        // ELF header
+        let class = if elf64 { 2 } else { 1 };
+        elf.extend_from_slice(&[
+            0x7F, b'E', b'L', b'F', class, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        elf.extend_from_slice(&self.machine.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        let entry = LOAD_ADDRESS + (layout.entry | stub.entry_bits()) as u64;
+        word(&mut elf, entry);
+        word(&mut elf, header_size as u64);
+        word(&mut elf, 0);
+        elf.extend_from_slice(&self.flags.to_le_bytes());
+        elf.extend_from_slice(&(header_size as u16).to_le_bytes());
+        elf.extend_from_slice(&(program_header_size as u16).to_le_bytes());
+        elf.extend_from_slice(&1u16.to_le_bytes());
+        elf.extend_from_slice(&[0; 6]);
+        // Program header, ELF64 moves the flags in front of the offset
+        elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        if elf64 {
+            elf.extend_from_slice(&5u32.to_le_bytes()); // PF_R | PF_X
+        }
+        word(&mut elf, 0);
+        word(&mut elf, LOAD_ADDRESS);
+        word(&mut elf, LOAD_ADDRESS);
+        word(&mut elf, size as u64);
+        word(&mut elf, size as u64);
+        if !elf64 {
+            elf.extend_from_slice(&5u32.to_le_bytes()); // PF_R | PF_X
+        }
+        word(&mut elf, 0x1000);
+        elf.resize(layout.image, 0);
+        elf.extend_from_slice(code);
+        elf.resize(layout.args, 0);
+        for &arg in args {
+            word(&mut elf, arg);
+        }
+        elf.extend_from_slice(stub.code());
+        Ok(elf)
+    }
+
+    /// Runs an executable produced by `elf` through qemu
+    ///
+    /// Returns the word the entry stub wrote to stdout
+    pub fn run(&self, elf: &[u8]) -> Result<u64> {
+        let path = env::temp_dir().join(format!(
+            "jit-{}-{}.elf",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, elf)?;
+        let qemu = env::var(self.env).unwrap_or_else(|_| self.qemu.to_string());
+        let output = Command::new(qemu).arg(&path).output();
+        fs::remove_file(&path)?;
+        let output = output?;
+        if output.stdout.len() != self.word_size {
+            return Err(Error::other(format!(
+                "Routine did not return ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        let mut result = [0; 8];
+        result[..self.word_size].copy_from_slice(&output.stdout);
+        Ok(u64::from_le_bytes(result))
+    }
+}
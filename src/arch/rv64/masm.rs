@@ -0,0 +1,261 @@
+use super::{reg::Reg, routine::Routine};
+use crate::assembler::{Condition, Label, MacroAssembler, Width};
+
+/// Temporary register used for immediates and offsets that do not fit into 12 bits
+const SCRATCH: Reg = Reg::T6;
+
+impl Routine {
+    /// Returns a base register and a 12-bit offset addressing `base + offset`, computing the
+    /// address into `SCRATCH` if the offset does not fit
+    fn address(&mut self, base: Reg, offset: i32) -> (Reg, i32) {
+        if (-0x800..=0x7FF).contains(&offset) {
+            (base, offset)
+        } else {
+            self.li(SCRATCH, offset as i64);
+            Routine::add(self, SCRATCH, SCRATCH, base);
+            (SCRATCH, 0)
+        }
+    }
+}
+
+impl MacroAssembler for Routine {
+    type Reg = Reg;
+
+    fn arg_reg(index: usize) -> Option<Reg> {
+        [
+            Reg::A0,
+            Reg::A1,
+            Reg::A2,
+            Reg::A3,
+            Reg::A4,
+            Reg::A5,
+            Reg::A6,
+            Reg::A7,
+        ]
+        .get(index)
+        .copied()
+    }
+
+    fn ret_reg() -> Reg {
+        Reg::A0
+    }
+
+    fn temp_regs() -> &'static [Reg] {
+        &[Reg::T0, Reg::T1, Reg::T2, Reg::T3, Reg::T4, Reg::T5]
+    }
+
+    fn new_label(&mut self) -> Label {
+        Routine::new_label(self)
+    }
+
+    fn bind(&mut self, label: Label) {
+        Routine::bind(self, label);
+    }
+
+    fn mov(&mut self, dst: Reg, src: Reg) {
+        if dst != src {
+            self.mv(dst, src);
+        }
+    }
+
+    fn mov_imm(&mut self, dst: Reg, imm: u64) {
+        self.li(dst, imm as i64);
+    }
+
+    fn load(&mut self, width: Width, dst: Reg, base: Reg, offset: i32) {
+        let (base, offset) = self.address(base, offset);
+        match width {
+            Width::Byte => self.lbu(dst, base, offset),
+            Width::Half => self.lhu(dst, base, offset),
+            Width::Word => self.lwu(dst, base, offset),
+            Width::Double => self.ld(dst, base, offset),
+        }
+    }
+
+    fn store(&mut self, width: Width, src: Reg, base: Reg, offset: i32) {
+        let (base, offset) = self.address(base, offset);
+        match width {
+            Width::Byte => self.sb(src, base, offset),
+            Width::Half => self.sh(src, base, offset),
+            Width::Word => self.sw(src, base, offset),
+            Width::Double => self.sd(src, base, offset),
+        }
+    }
+
+    fn add(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        Routine::add(self, dst, lhs, rhs);
+    }
+
+    fn add_imm(&mut self, dst: Reg, lhs: Reg, imm: i32) {
+        if (-0x800..=0x7FF).contains(&imm) {
+            self.addi(dst, lhs, imm);
+        } else {
+            self.li(SCRATCH, imm as i64);
+            Routine::add(self, dst, lhs, SCRATCH);
+        }
+    }
+
+    fn sub(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        Routine::sub(self, dst, lhs, rhs);
+    }
+
+    fn mul(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        Routine::mul(self, dst, lhs, rhs);
+    }
+
+    fn and(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        Routine::and(self, dst, lhs, rhs);
+    }
+
+    fn or(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        Routine::or(self, dst, lhs, rhs);
+    }
+
+    fn xor(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        Routine::xor(self, dst, lhs, rhs);
+    }
+
+    fn jump(&mut self, label: Label) {
+        self.j(label);
+    }
+
+    /// Branches within ±4 KiB
+    fn branch_if(&mut self, cond: Condition, lhs: Reg, rhs: Reg, label: Label) {
+        match cond {
+            Condition::Eq => self.beq(lhs, rhs, label),
+            Condition::Ne => self.bne(lhs, rhs, label),
+            Condition::Lt => self.blt(lhs, rhs, label),
+            Condition::Le => self.bge(rhs, lhs, label),
+            Condition::Gt => self.blt(rhs, lhs, label),
+            Condition::Ge => self.bge(lhs, rhs, label),
+            Condition::Below => self.bltu(lhs, rhs, label),
+            Condition::BelowEq => self.bgeu(rhs, lhs, label),
+            Condition::Above => self.bltu(rhs, lhs, label),
+            Condition::AboveEq => self.bgeu(lhs, rhs, label),
+        }
+    }
+
+    fn call(&mut self, label: String) {
+        Routine::call(self, label);
+    }
+
+    fn prologue(&mut self) {
+        self.addi(Reg::SP, Reg::SP, -16);
+        self.sd(Reg::RA, Reg::SP, 8);
+        self.sd(Reg::S0, Reg::SP, 0);
+        self.addi(Reg::S0, Reg::SP, 16);
+    }
+
+    fn epilogue(&mut self) {
+        self.ld(Reg::RA, Reg::SP, 8);
+        self.ld(Reg::S0, Reg::SP, 0);
+        self.addi(Reg::SP, Reg::SP, 16);
+    }
+
+    fn ret(&mut self) {
+        Routine::ret(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(build: impl FnOnce(&mut Routine)) -> Vec<u32> {
+        let mut routine = Routine::new("f".to_string());
+        build(&mut routine);
+        routine.finalize();
+        routine
+            .code
+            .chunks(4)
+            .map(|it| u32::from_le_bytes(it.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn branches_swap_operands() {
+        let words = assemble(|m| {
+            let top = MacroAssembler::new_label(m);
+            MacroAssembler::bind(m, top);
+            m.branch_if(Condition::Le, Reg::A0, Reg::A1, top);
+            m.branch_if(Condition::Gt, Reg::A0, Reg::A1, top);
+            m.branch_if(Condition::BelowEq, Reg::A0, Reg::A1, top);
+            m.branch_if(Condition::Above, Reg::A0, Reg::A1, top);
+            m.branch_if(Condition::Eq, Reg::A0, Reg::A1, top);
+        });
+        assert_eq!(
+            words,
+            [
+                0x00A5D063, // bge a1, a0, 0
+                0xFEA5CEE3, // blt a1, a0, -4
+                0xFEA5FCE3, // bgeu a1, a0, -8
+                0xFEA5EAE3, // bltu a1, a0, -12
+                0xFEB508E3, // beq a0, a1, -16
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets_outside_of_12_bits() {
+        let words = assemble(|m| {
+            m.load(Width::Byte, Reg::A0, Reg::A1, 0x7FF);
+            m.load(Width::Half, Reg::A0, Reg::A1, -0x800);
+            m.load(Width::Word, Reg::A0, Reg::SP, 0x800);
+            m.load(Width::Double, Reg::A0, Reg::A1, 0x12345);
+            m.store(Width::Byte, Reg::A2, Reg::S0, -0x801);
+            m.store(Width::Double, Reg::RA, Reg::SP, 8);
+            MacroAssembler::add_imm(m, Reg::A0, Reg::A1, 100);
+            MacroAssembler::add_imm(m, Reg::A0, Reg::A1, 0x1000);
+        });
+        assert_eq!(
+            words,
+            [
+                0x7FF5C503, // lbu a0, 2047(a1)
+                0x8005D503, // lhu a0, -2048(a1)
+                0x00001FB7, // lui t6, 1
+                0x800F8F9B, // addiw t6, t6, -2048
+                0x002F8FB3, // add t6, t6, sp
+                0x000FE503, // lwu a0, 0(t6)
+                0x00012FB7, // lui t6, 0x12
+                0x345F8F9B, // addiw t6, t6, 0x345
+                0x00BF8FB3, // add t6, t6, a1
+                0x000FB503, // ld a0, 0(t6)
+                0xFFFFFFB7, // lui t6, 0xFFFFF
+                0x7FFF8F9B, // addiw t6, t6, 2047
+                0x008F8FB3, // add t6, t6, s0
+                0x00CF8023, // sb a2, 0(t6)
+                0x00113423, // sd ra, 8(sp)
+                0x06458513, // addi a0, a1, 100
+                0x00001FB7, // lui t6, 1
+                0x01F58533, // add a0, a1, t6
+            ]
+        );
+    }
+
+    #[test]
+    fn moves_and_frame() {
+        let words = assemble(|m| {
+            m.prologue();
+            MacroAssembler::mov(m, Reg::A0, Reg::A0);
+            MacroAssembler::mov(m, Reg::A0, Reg::A1);
+            m.mov_imm(Reg::A2, u64::MAX);
+            m.epilogue();
+            MacroAssembler::ret(m);
+        });
+        assert_eq!(
+            words,
+            [
+                0xFF010113, // addi sp, sp, -16
+                0x00113423, // sd ra, 8(sp)
+                0x00813023, // sd s0, 0(sp)
+                0x01010413, // addi s0, sp, 16
+                0x00058513, // addi a0, a1, 0
+                0xFFF00613, // addi a2, zero, -1
+                0x00813083, // ld ra, 8(sp)
+                0x00013403, // ld s0, 0(sp)
+                0x01010113, // addi sp, sp, 16
+                0x00008067, // jalr zero, 0(ra)
+            ]
+        );
+    }
+}
@@ -0,0 +1,6 @@
+pub mod asm;
+mod masm;
+pub mod qemu;
+mod raw;
+pub mod reg;
+pub mod routine;
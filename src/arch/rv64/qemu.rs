@@ -0,0 +1,159 @@
+use super::{raw, reg::Reg, routine::Routine};
+use crate::arch::qemu::{Layout, Machine};
+use std::{collections::HashMap, io::Result};
+
+/// Runs RISC-V Linux executables through `qemu-riscv64`, whose path can be overridden through
+/// `QEMU_RISCV64`
+pub const MACHINE: Machine = Machine {
+    qemu: "qemu-riscv64",
+    env: "QEMU_RISCV64",
+    machine: 243, // EM_RISCV
+    flags: 0x5,   // EF_RISCV_RVC | EF_RISCV_FLOAT_ABI_DOUBLE
+    word_size: 8,
+    max_args: 8,
+};
+
+/// Runs the routine with the given label of an image produced by `virtual_jit` through
+/// `qemu-riscv64` passing `args` in `A0` to `A7`
+///
+/// Returns the value of `A0` after the routine returned
+pub fn run(code: &[u8], vtable: &HashMap<String, usize>, label: &str, args: &[u64]) -> Result<u64> {
+    MACHINE.run(&elf(code, vtable, label, args)?)
+}
+
+/// Wraps an image produced by `virtual_jit` into a static RV64 Linux executable
+///
+/// The entry stub calls the routine with the given label passing `args` in `A0` to `A7`, writes
+/// the 8 bytes of `A0` to stdout and exits with the lowest byte of `A0` as status code.
+pub fn elf(
+    code: &[u8],
+    vtable: &HashMap<String, usize>,
+    label: &str,
+    args: &[u64],
+) -> Result<Vec<u8>> {
+    MACHINE.elf(code, vtable, label, args, |layout| stub(layout, args.len()))
+}
+
+fn stub(layout: &Layout, args: usize) -> Routine {
+    let mut stub = Routine::new("_start".to_string());
+    // The `auipc` pairs are patched once their offsets are known
+    let pc_rel = |stub: &mut Routine, target: usize| {
+        let offset = stub.code.len() - 8;
+        raw::pc_rel(&mut stub.code, offset, layout.entry + offset, target);
+    };
+    for index in 0..args {
+        let reg = arg_reg(index);
+        stub.auipc(reg, 0);
+        stub.ld(reg, reg, 0);
+        pc_rel(&mut stub, layout.args + index * 8);
+    }
+    stub.auipc(Reg::RA, 0);
+    stub.jalr(Reg::RA, Reg::RA, 0);
+    pc_rel(&mut stub, layout.target);
+    stub.addi(Reg::SP, Reg::SP, -16);
+    stub.sd(Reg::A0, Reg::SP, 0);
+    // write(1, sp, 8)
+    stub.mv(Reg::S1, Reg::A0);
+    stub.li(Reg::A0, 1);
+    stub.mv(Reg::A1, Reg::SP);
+    stub.li(Reg::A2, 8);
+    stub.li(Reg::A7, 64);
+    stub.ecall();
+    // exit_group(a0)
+    stub.mv(Reg::A0, Reg::S1);
+    stub.li(Reg::A7, 94);
+    stub.ecall();
+    stub
+}
+
+fn arg_reg(index: usize) -> Reg {
+    [
+        Reg::A0,
+        Reg::A1,
+        Reg::A2,
+        Reg::A3,
+        Reg::A4,
+        Reg::A5,
+        Reg::A6,
+        Reg::A7,
+    ][index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::{qemu::LOAD_ADDRESS, rv64::asm::Asm},
+        assembler::Assembler,
+        mem,
+    };
+
+    fn image() -> (Vec<u8>, HashMap<String, usize>) {
+        let mut asm = Asm::default();
+        // outer(a, b) = square(a) - b
+        let mut outer = Routine::new("outer".to_string());
+        outer.set_compressed(true);
+        outer.addi(Reg::SP, Reg::SP, -16);
+        outer.sd(Reg::RA, Reg::SP, 8);
+        outer.sd(Reg::A1, Reg::SP, 0);
+        outer.call("square".to_string());
+        outer.ld(Reg::A1, Reg::SP, 0);
+        outer.sub(Reg::A0, Reg::A0, Reg::A1);
+        outer.ld(Reg::RA, Reg::SP, 8);
+        outer.addi(Reg::SP, Reg::SP, 16);
+        outer.ret();
+        asm.push_routine(outer);
+        let mut square = Routine::new("square".to_string());
+        square.mul(Reg::A0, Reg::A0, Reg::A0);
+        square.ret();
+        asm.push_routine(square);
+        let mut constant = Routine::new("constant".to_string());
+        constant.li(Reg::A0, 0x1234_5678_9ABC_DEF0);
+        constant.ret();
+        asm.push_routine(constant);
+        asm.virtual_jit().unwrap()
+    }
+
+    #[test]
+    fn elf_layout() {
+        let (code, vtable) = image();
+        let file = elf(&code, &vtable, "outer", &[7, 9]).unwrap();
+        assert_eq!(&file[..4], b"\x7FELF");
+        assert_eq!(file[4], 2);
+        assert_eq!(u16::from_le_bytes([file[18], file[19]]), 243);
+        assert_eq!(file[128..128 + code.len()], code);
+        let args = mem::align(128 + code.len(), 8);
+        assert_eq!(file[args..args + 8], 7u64.to_le_bytes());
+        assert_eq!(file[args + 8..args + 16], 9u64.to_le_bytes());
+        let entry = args + 16;
+        assert_eq!(
+            u64::from_le_bytes(file[24..32].try_into().unwrap()),
+            LOAD_ADDRESS + entry as u64
+        );
+        // The `auipc` pairs of the stub reference the arguments and the routine
+        let target = |offset: usize| {
+            let auipc = raw::read_le_32(&file, offset);
+            let next = raw::read_le_32(&file, offset + 4);
+            let hi = (auipc & !0xFFF) as i32 as isize;
+            let lo = (next as i32 >> 20) as isize;
+            (offset as isize + hi + lo) as usize
+        };
+        assert_eq!(target(entry), args);
+        assert_eq!(target(entry + 8), args + 8);
+        assert_eq!(target(entry + 16), 128 + vtable["outer"]);
+        assert!(elf(&code, &vtable, "missing", &[]).is_err());
+        assert!(elf(&code, &vtable, "outer", &[0; 9]).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs qemu-riscv64"]
+    fn runs_routines() {
+        let (code, vtable) = image();
+        assert_eq!(run(&code, &vtable, "outer", &[7, 9]).unwrap(), 40);
+        assert_eq!(run(&code, &vtable, "square", &[1 << 20]).unwrap(), 1 << 40);
+        assert_eq!(
+            run(&code, &vtable, "constant", &[]).unwrap(),
+            0x1234_5678_9ABC_DEF0
+        );
+    }
+}
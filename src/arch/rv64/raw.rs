@@ -0,0 +1,250 @@
+pub fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+pub fn i_type(imm12: i32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    assert!(
+        (-0x800..=0x7FF).contains(&imm12),
+        "Immediate must fit into 12 bits"
+    );
+    ((imm12 as u32 & 0xFFF) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+pub fn s_type(imm12: i32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    assert!(
+        (-0x800..=0x7FF).contains(&imm12),
+        "Immediate must fit into 12 bits"
+    );
+    let imm = imm12 as u32;
+    (((imm >> 5) & 0x7F) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm & 0x1F) << 7)
+        | opcode
+}
+
+pub fn u_type(imm20: u32, rd: u32, opcode: u32) -> u32 {
+    assert!(imm20 <= 0xFFFFF, "Immediate must fit into 20 bits");
+    (imm20 << 12) | (rd << 7) | opcode
+}
+
+/// Returns the immediate bits of a conditional branch to the byte offset `rel`
+pub fn b_imm(rel: isize) -> u32 {
+    assert!(
+        (-0x1000..=0xFFF).contains(&rel) && rel % 2 == 0,
+        "Tried to branch to label not in range"
+    );
+    let imm = rel as u32;
+    (((imm >> 12) & 1) << 31)
+        | (((imm >> 5) & 0x3F) << 25)
+        | (((imm >> 1) & 0xF) << 8)
+        | (((imm >> 11) & 1) << 7)
+}
+
+/// Returns the immediate bits of a `jal` to the byte offset `rel`
+pub fn j_imm(rel: isize) -> u32 {
+    assert!(
+        (-0x100000..=0xFFFFF).contains(&rel) && rel % 2 == 0,
+        "Tried to jump to label not in range"
+    );
+    let imm = rel as u32;
+    (((imm >> 20) & 1) << 31)
+        | (((imm >> 1) & 0x3FF) << 21)
+        | (((imm >> 11) & 1) << 20)
+        | (((imm >> 12) & 0xFF) << 12)
+}
+
+/// Patches the `auipc` at `insn_offset` and the I-type instruction following it so that they
+/// reference `target`
+pub fn pc_rel(bytes: &mut [u8], insn_offset: usize, insn_addr: usize, target: usize) {
+    let rel = target as i64 - insn_addr as i64;
+    assert!(
+        (-0x8000_0800..=0x7FFF_F7FF).contains(&rel),
+        "Tried to reference address not in range"
+    );
+    let hi = ((rel + 0x800) >> 12) as u32 & 0xFFFFF;
+    let lo = (rel & 0xFFF) as u32;
+    let auipc = read_le_32(bytes, insn_offset);
+    write_le_32(bytes, insn_offset, (auipc & 0xFFF) | (hi << 12));
+    let next = read_le_32(bytes, insn_offset + 4);
+    write_le_32(bytes, insn_offset + 4, (next & 0xFFFFF) | (lo << 20));
+}
+
+/// Returns the compressed form of a 32-bit instruction if there is one
+pub fn compress(insn: u32) -> Option<u16> {
+    let opcode = insn & 0x7F;
+    let rd = (insn >> 7) & 0x1F;
+    let funct3 = (insn >> 12) & 0x7;
+    let rs1 = (insn >> 15) & 0x1F;
+    let rs2 = (insn >> 20) & 0x1F;
+    let funct7 = insn >> 25;
+    let imm_i = (insn as i32) >> 20;
+    let imm_s = (((insn as i32) >> 25) << 5) | rd as i32;
+    let small = (-32..32).contains(&imm_i);
+    // Registers `X8` to `X15` have 3-bit encodings
+    let c = |reg: u32| (8..16).contains(&reg).then(|| reg - 8);
+    let ci = |funct3: u32, rd: u32, imm: i32, op: u32| {
+        (funct3 << 13)
+            | (((imm as u32 >> 5) & 1) << 12)
+            | (rd << 7)
+            | ((imm as u32 & 0x1F) << 2)
+            | op
+    };
+    let insn = match (opcode, funct3) {
+        // addi
+        (0x13, 0) => {
+            if rd == 0 && rs1 == 0 && imm_i == 0 {
+                0x0001
+            } else if rd != 0 && rs1 == 0 && small {
+                ci(0b010, rd, imm_i, 0b01)
+            } else if rd != 0 && rs1 != 0 && imm_i == 0 {
+                (0b1000 << 12) | (rd << 7) | (rs1 << 2) | 0b10
+            } else if rd != 0 && rd == rs1 && imm_i != 0 && small {
+                ci(0b000, rd, imm_i, 0b01)
+            } else if rd == 2
+                && rs1 == 2
+                && imm_i != 0
+                && imm_i % 16 == 0
+                && (-512..=496).contains(&imm_i)
+            {
+                let imm = imm_i as u32;
+                (0b011 << 13)
+                    | (((imm >> 9) & 1) << 12)
+                    | (2 << 7)
+                    | (((imm >> 4) & 1) << 6)
+                    | (((imm >> 6) & 1) << 5)
+                    | (((imm >> 7) & 3) << 3)
+                    | (((imm >> 5) & 1) << 2)
+                    | 0b01
+            } else if rs1 == 2 && imm_i > 0 && imm_i % 4 == 0 && imm_i < 1024 {
+                let imm = imm_i as u32;
+                (((imm >> 4) & 3) << 11)
+                    | (((imm >> 6) & 0xF) << 7)
+                    | (((imm >> 2) & 1) << 6)
+                    | (((imm >> 3) & 1) << 5)
+                    | (c(rd)? << 2)
+            } else {
+                return None;
+            }
+        }
+        // addiw
+        (0x1B, 0) if rd != 0 && rd == rs1 && small => ci(0b001, rd, imm_i, 0b01),
+        // slli
+        (0x13, 1) if rd != 0 && rd == rs1 && imm_i != 0 => ci(0b000, rd, imm_i, 0b10),
+        // srli, srai
+        (0x13, 5) if rd == rs1 && imm_i & 0x3F != 0 => {
+            let kind = (funct7 >> 5) & 1;
+            ci(0b100, (kind << 3) | c(rd)?, imm_i & 0x3F, 0b01)
+        }
+        // andi
+        (0x13, 7) if rd == rs1 && small => ci(0b100, (0b10 << 3) | c(rd)?, imm_i, 0b01),
+        // lui
+        (0x37, _) if rd != 0 && rd != 2 => {
+            let imm = (insn as i32) >> 12;
+            if imm == 0 || !(-32..32).contains(&imm) {
+                return None;
+            }
+            ci(0b011, rd, imm, 0b01)
+        }
+        // add
+        (0x33, 0) if funct7 == 0 && rd != 0 && rs2 != 0 => {
+            if rs1 == 0 {
+                (0b1000 << 12) | (rd << 7) | (rs2 << 2) | 0b10
+            } else if rs1 == rd {
+                (0b1001 << 12) | (rd << 7) | (rs2 << 2) | 0b10
+            } else {
+                return None;
+            }
+        }
+        // sub, xor, or, and
+        (0x33, 0 | 4 | 6 | 7) if rd == rs1 && (funct7 == 0 || (funct7 == 0x20 && funct3 == 0)) => {
+            let funct2 = match funct3 {
+                0 if funct7 == 0x20 => 0b00,
+                4 => 0b01,
+                6 => 0b10,
+                7 => 0b11,
+                _ => return None,
+            };
+            (0b100011 << 10) | (c(rd)? << 7) | (funct2 << 5) | (c(rs2)? << 2) | 0b01
+        }
+        // subw, addw
+        (0x3B, 0) if rd == rs1 && (funct7 == 0 || funct7 == 0x20) => {
+            let funct2 = if funct7 == 0x20 { 0b00 } else { 0b01 };
+            (0b100111 << 10) | (c(rd)? << 7) | (funct2 << 5) | (c(rs2)? << 2) | 0b01
+        }
+        // jalr
+        (0x67, 0) if rs1 != 0 && imm_i == 0 && (rd == 0 || rd == 1) => {
+            (0b100 << 13) | (rd << 12) | (rs1 << 7) | 0b10
+        }
+        // ebreak
+        (0x73, 0) if insn == 0x00100073 => 0x9002,
+        // lw, ld
+        (0x03, 2 | 3) => {
+            let imm = imm_i as u32;
+            if imm_i < 0 || imm & ((1 << funct3) - 1) != 0 {
+                return None;
+            }
+            if rs1 == 2 && rd != 0 && imm < (64 << funct3) {
+                let low = if funct3 == 2 {
+                    (((imm >> 2) & 7) << 4) | (((imm >> 6) & 3) << 2)
+                } else {
+                    (((imm >> 3) & 3) << 5) | (((imm >> 6) & 7) << 2)
+                };
+                (funct3 << 13) | (((imm >> 5) & 1) << 12) | (rd << 7) | low | 0b10
+            } else if imm < (32 << funct3) {
+                let low = if funct3 == 2 {
+                    (((imm >> 2) & 1) << 6) | (((imm >> 6) & 1) << 5)
+                } else {
+                    ((imm >> 6) & 3) << 5
+                };
+                (funct3 << 13) | (((imm >> 3) & 7) << 10) | (c(rs1)? << 7) | low | (c(rd)? << 2)
+            } else {
+                return None;
+            }
+        }
+        // sw, sd
+        (0x23, 2 | 3) => {
+            let imm = imm_s as u32;
+            if imm_s < 0 || imm & ((1 << funct3) - 1) != 0 {
+                return None;
+            }
+            if rs1 == 2 && imm < (64 << funct3) {
+                let high = if funct3 == 2 {
+                    (((imm >> 2) & 0xF) << 9) | (((imm >> 6) & 3) << 7)
+                } else {
+                    (((imm >> 3) & 7) << 10) | (((imm >> 6) & 7) << 7)
+                };
+                ((funct3 | 4) << 13) | high | (rs2 << 2) | 0b10
+            } else if imm < (32 << funct3) {
+                let low = if funct3 == 2 {
+                    (((imm >> 2) & 1) << 6) | (((imm >> 6) & 1) << 5)
+                } else {
+                    ((imm >> 6) & 3) << 5
+                };
+                ((funct3 | 4) << 13)
+                    | (((imm >> 3) & 7) << 10)
+                    | (c(rs1)? << 7)
+                    | low
+                    | (c(rs2)? << 2)
+            } else {
+                return None;
+            }
+        }
+        _ => return None,
+    };
+    Some(insn as u16)
+}
+
+pub fn read_le_32(slice: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes([
+        slice[index],
+        slice[index + 1],
+        slice[index + 2],
+        slice[index + 3],
+    ])
+}
+
+pub fn write_le_32(slice: &mut [u8], index: usize, value: u32) {
+    slice[index..index + 4].copy_from_slice(&value.to_le_bytes());
+}
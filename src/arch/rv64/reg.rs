@@ -0,0 +1,152 @@
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    X0 = 0,
+    X1 = 1,
+    X2 = 2,
+    X3 = 3,
+    X4 = 4,
+    X5 = 5,
+    X6 = 6,
+    X7 = 7,
+    X8 = 8,
+    X9 = 9,
+    X10 = 10,
+    X11 = 11,
+    X12 = 12,
+    X13 = 13,
+    X14 = 14,
+    X15 = 15,
+    X16 = 16,
+    X17 = 17,
+    X18 = 18,
+    X19 = 19,
+    X20 = 20,
+    X21 = 21,
+    X22 = 22,
+    X23 = 23,
+    X24 = 24,
+    X25 = 25,
+    X26 = 26,
+    X27 = 27,
+    X28 = 28,
+    X29 = 29,
+    X30 = 30,
+    X31 = 31,
+}
+
+impl Reg {
+    pub const ZERO: Reg = Reg::X0;
+    pub const RA: Reg = Reg::X1;
+    pub const SP: Reg = Reg::X2;
+    pub const GP: Reg = Reg::X3;
+    pub const TP: Reg = Reg::X4;
+    pub const T0: Reg = Reg::X5;
+    pub const T1: Reg = Reg::X6;
+    pub const T2: Reg = Reg::X7;
+    pub const S0: Reg = Reg::X8;
+    pub const S1: Reg = Reg::X9;
+    pub const A0: Reg = Reg::X10;
+    pub const A1: Reg = Reg::X11;
+    pub const A2: Reg = Reg::X12;
+    pub const A3: Reg = Reg::X13;
+    pub const A4: Reg = Reg::X14;
+    pub const A5: Reg = Reg::X15;
+    pub const A6: Reg = Reg::X16;
+    pub const A7: Reg = Reg::X17;
+    pub const S2: Reg = Reg::X18;
+    pub const S3: Reg = Reg::X19;
+    pub const S4: Reg = Reg::X20;
+    pub const S5: Reg = Reg::X21;
+    pub const S6: Reg = Reg::X22;
+    pub const S7: Reg = Reg::X23;
+    pub const S8: Reg = Reg::X24;
+    pub const S9: Reg = Reg::X25;
+    pub const S10: Reg = Reg::X26;
+    pub const S11: Reg = Reg::X27;
+    pub const T3: Reg = Reg::X28;
+    pub const T4: Reg = Reg::X29;
+    pub const T5: Reg = Reg::X30;
+    pub const T6: Reg = Reg::X31;
+
+    /// Returns the 3-bit register number used by compressed instructions if the register is one
+    /// of `X8` to `X15`
+    pub fn compressed(self) -> Option<u32> {
+        match self as u32 {
+            8..=15 => Some(self as u32 - 8),
+            _ => None,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FReg {
+    F0 = 0,
+    F1 = 1,
+    F2 = 2,
+    F3 = 3,
+    F4 = 4,
+    F5 = 5,
+    F6 = 6,
+    F7 = 7,
+    F8 = 8,
+    F9 = 9,
+    F10 = 10,
+    F11 = 11,
+    F12 = 12,
+    F13 = 13,
+    F14 = 14,
+    F15 = 15,
+    F16 = 16,
+    F17 = 17,
+    F18 = 18,
+    F19 = 19,
+    F20 = 20,
+    F21 = 21,
+    F22 = 22,
+    F23 = 23,
+    F24 = 24,
+    F25 = 25,
+    F26 = 26,
+    F27 = 27,
+    F28 = 28,
+    F29 = 29,
+    F30 = 30,
+    F31 = 31,
+}
+
+impl FReg {
+    pub const FT0: FReg = FReg::F0;
+    pub const FT1: FReg = FReg::F1;
+    pub const FT2: FReg = FReg::F2;
+    pub const FT3: FReg = FReg::F3;
+    pub const FT4: FReg = FReg::F4;
+    pub const FT5: FReg = FReg::F5;
+    pub const FT6: FReg = FReg::F6;
+    pub const FT7: FReg = FReg::F7;
+    pub const FS0: FReg = FReg::F8;
+    pub const FS1: FReg = FReg::F9;
+    pub const FA0: FReg = FReg::F10;
+    pub const FA1: FReg = FReg::F11;
+    pub const FA2: FReg = FReg::F12;
+    pub const FA3: FReg = FReg::F13;
+    pub const FA4: FReg = FReg::F14;
+    pub const FA5: FReg = FReg::F15;
+    pub const FA6: FReg = FReg::F16;
+    pub const FA7: FReg = FReg::F17;
+    pub const FS2: FReg = FReg::F18;
+    pub const FS3: FReg = FReg::F19;
+    pub const FS4: FReg = FReg::F20;
+    pub const FS5: FReg = FReg::F21;
+    pub const FS6: FReg = FReg::F22;
+    pub const FS7: FReg = FReg::F23;
+    pub const FS8: FReg = FReg::F24;
+    pub const FS9: FReg = FReg::F25;
+    pub const FS10: FReg = FReg::F26;
+    pub const FS11: FReg = FReg::F27;
+    pub const FT8: FReg = FReg::F28;
+    pub const FT9: FReg = FReg::F29;
+    pub const FT10: FReg = FReg::F30;
+    pub const FT11: FReg = FReg::F31;
+}
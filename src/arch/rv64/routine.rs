@@ -0,0 +1,1529 @@
+use super::{
+    raw,
+    reg::{FReg, Reg},
+};
+use crate::assembler::{Assembler, Label, PostOp, Subroutine};
+use std::sync::atomic::Ordering;
+
+/// Dynamic rounding mode as selected by `frm`
+const RM_DYN: u32 = 7;
+/// Rounding towards zero as used by casts from float to integer
+const RM_RTZ: u32 = 1;
+
+pub struct Routine {
+    pub(super) name: String,
+    pub(super) constants: Vec<u8>,
+    pub(super) code: Vec<u8>,
+    pub(super) post_ops: Vec<Op>,
+    compressed: bool,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<Fixup>,
+}
+
+/// Branch or jump to a local label whose immediate is patched when the routine is finalized
+struct Fixup {
+    insn_offset: usize,
+    label: Label,
+    jump: bool,
+}
+
+impl Routine {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            constants: Vec::with_capacity(0),
+            code: Vec::with_capacity(0),
+            post_ops: Vec::with_capacity(0),
+            compressed: false,
+            labels: Vec::with_capacity(0),
+            fixups: Vec::with_capacity(0),
+        }
+    }
+
+    /// Emits the 16-bit encodings of the C extension for following instructions where possible
+    pub fn set_compressed(&mut self, compressed: bool) {
+        self.compressed = compressed;
+    }
+
+    /// Returns from a routine
+    pub fn ret(&mut self) {
+        self.jalr(Reg::ZERO, Reg::RA, 0);
+    }
+
+    /// Placeholder
+    pub fn nop(&mut self) {
+        self.addi(Reg::ZERO, Reg::ZERO, 0);
+    }
+
+    /// Moves the value stored in the source register into the destination register
+    pub fn mv(&mut self, rd: Reg, rs: Reg) {
+        self.addi(rd, rs, 0);
+    }
+
+    /// Inverts all bits of `rs`
+    pub fn not(&mut self, rd: Reg, rs: Reg) {
+        self.xori(rd, rs, -1);
+    }
+
+    /// Negates `rs`
+    pub fn neg(&mut self, rd: Reg, rs: Reg) {
+        self.sub(rd, Reg::ZERO, rs);
+    }
+
+    /// Moves the 64-bit immediate into `rd` using a `lui`/`addi`/`slli` sequence or a constant
+    /// if the sequence would be longer than loading it
+    pub fn li(&mut self, rd: Reg, imm: i64) {
+        let mut seq = Vec::new();
+        li_sequence(imm, &mut seq);
+        if seq.len() > 4 {
+            let offset = self.const_64(imm as u64);
+            self.ld_const(rd, offset);
+            return;
+        }
+        let mut src = Reg::ZERO;
+        for (kind, value) in seq {
+            match kind {
+                LiKind::Lui => self.lui(rd, value as u32 & 0xFFFFF),
+                LiKind::Addi => self.addi(rd, src, value as i32),
+                LiKind::Addiw => self.addiw(rd, src, value as i32),
+                LiKind::Slli => self.slli(rd, src, value as u32),
+            }
+            src = rd;
+        }
+    }
+
+    /// Loads the 20-bit immediate into the upper bits of `rd` sign-extending the result
+    pub fn lui(&mut self, rd: Reg, imm20: u32) {
+        self.insn(raw::u_type(imm20, rd as u32, 0x37));
+    }
+
+    /// Adds the 20-bit immediate shifted by 12 bits to the address of this instruction
+    pub fn auipc(&mut self, rd: Reg, imm20: u32) {
+        self.insn(raw::u_type(imm20, rd as u32, 0x17));
+    }
+
+    /// Jumps to the address `rs1 + imm12` storing the address of the next instruction in `rd`
+    pub fn jalr(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 0, rd as u32, 0x67));
+    }
+
+    /// Jumps to a local label within ±1 MiB storing the address of the next instruction in `rd`
+    pub fn jal(&mut self, rd: Reg, label: Label) {
+        self.fixups.push(Fixup {
+            insn_offset: self.code.len(),
+            label,
+            jump: true,
+        });
+        self.fixed_insn(((rd as u32) << 7) | 0x6F);
+    }
+
+    /// Jumps to a local label within ±1 MiB
+    pub fn j(&mut self, label: Label) {
+        self.jal(Reg::ZERO, label);
+    }
+
+    /// Calls label that must be present in the V-Table through an `auipc`/`jalr` pair
+    pub fn call(&mut self, label: String) {
+        self.post_ops.push(Op::Routine {
+            insn_offset: self.code.len(),
+            label,
+        });
+        self.fixed_insn(raw::u_type(0, Reg::RA as u32, 0x17));
+        self.fixed_insn(raw::i_type(0, Reg::RA as u32, 0, Reg::RA as u32, 0x67));
+    }
+
+    /// Jumps to label that must be present in the V-Table through an `auipc`/`jalr` pair
+    /// clobbering `T1`
+    pub fn tail(&mut self, label: String) {
+        self.post_ops.push(Op::Routine {
+            insn_offset: self.code.len(),
+            label,
+        });
+        self.fixed_insn(raw::u_type(0, Reg::T1 as u32, 0x17));
+        self.fixed_insn(raw::i_type(0, Reg::T1 as u32, 0, Reg::ZERO as u32, 0x67));
+    }
+
+    /// Creates a new label that has to be bound before the routine is finalized
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds the label to the position of the next instruction
+    pub fn bind(&mut self, label: Label) {
+        let slot = &mut self.labels[label.0];
+        assert!(slot.is_none(), "Tried to bind label twice");
+        *slot = Some(self.code.len());
+    }
+
+    /// Branches to a local label within ±4 KiB if `rs1` equals `rs2`
+    pub fn beq(&mut self, rs1: Reg, rs2: Reg, label: Label) {
+        self.branch(0, rs1, rs2, label);
+    }
+
+    /// Branches to a local label within ±4 KiB if `rs1` does not equal `rs2`
+    pub fn bne(&mut self, rs1: Reg, rs2: Reg, label: Label) {
+        self.branch(1, rs1, rs2, label);
+    }
+
+    /// Branches to a local label within ±4 KiB if `rs1` is less than `rs2` as signed integers
+    pub fn blt(&mut self, rs1: Reg, rs2: Reg, label: Label) {
+        self.branch(4, rs1, rs2, label);
+    }
+
+    /// Branches to a local label within ±4 KiB if `rs1` is greater than or equal to `rs2` as signed integers
+    pub fn bge(&mut self, rs1: Reg, rs2: Reg, label: Label) {
+        self.branch(5, rs1, rs2, label);
+    }
+
+    /// Branches to a local label within ±4 KiB if `rs1` is less than `rs2` as unsigned integers
+    pub fn bltu(&mut self, rs1: Reg, rs2: Reg, label: Label) {
+        self.branch(6, rs1, rs2, label);
+    }
+
+    /// Branches to a local label within ±4 KiB if `rs1` is greater than or equal to `rs2` as unsigned integers
+    pub fn bgeu(&mut self, rs1: Reg, rs2: Reg, label: Label) {
+        self.branch(7, rs1, rs2, label);
+    }
+
+    /// Adds `rs1` and `rs2`
+    pub fn add(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 0, rd as u32, 0x33));
+    }
+
+    /// Subtracts `rs2` from `rs1`
+    pub fn sub(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(
+            0x20, rs2 as u32, rs1 as u32, 0, rd as u32, 0x33,
+        ));
+    }
+
+    /// Shifts `rs1` left by the lowest 6 bits of `rs2`
+    pub fn sll(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 1, rd as u32, 0x33));
+    }
+
+    /// Sets `rd` to 1 if `rs1` is less than `rs2` as signed integers
+    pub fn slt(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 2, rd as u32, 0x33));
+    }
+
+    /// Sets `rd` to 1 if `rs1` is less than `rs2` as unsigned integers
+    pub fn sltu(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 3, rd as u32, 0x33));
+    }
+
+    /// Computes the bitwise exclusive or of `rs1` and `rs2`
+    pub fn xor(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 4, rd as u32, 0x33));
+    }
+
+    /// Shifts `rs1` logically right by the lowest 6 bits of `rs2`
+    pub fn srl(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 5, rd as u32, 0x33));
+    }
+
+    /// Shifts `rs1` arithmetically right by the lowest 6 bits of `rs2`
+    pub fn sra(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(
+            0x20, rs2 as u32, rs1 as u32, 5, rd as u32, 0x33,
+        ));
+    }
+
+    /// Computes the bitwise or of `rs1` and `rs2`
+    pub fn or(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 6, rd as u32, 0x33));
+    }
+
+    /// Computes the bitwise and of `rs1` and `rs2`
+    pub fn and(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 7, rd as u32, 0x33));
+    }
+
+    /// Adds the lower 32 bits of `rs1` and `rs2` sign-extending the result
+    pub fn addw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 0, rd as u32, 0x3b));
+    }
+
+    /// Subtracts the lower 32 bits of `rs2` from `rs1` sign-extending the result
+    pub fn subw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(
+            0x20, rs2 as u32, rs1 as u32, 0, rd as u32, 0x3b,
+        ));
+    }
+
+    /// Shifts the lower 32 bits of `rs1` left sign-extending the result
+    pub fn sllw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 1, rd as u32, 0x3b));
+    }
+
+    /// Shifts the lower 32 bits of `rs1` logically right sign-extending the result
+    pub fn srlw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x0, rs2 as u32, rs1 as u32, 5, rd as u32, 0x3b));
+    }
+
+    /// Shifts the lower 32 bits of `rs1` arithmetically right sign-extending the result
+    pub fn sraw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(
+            0x20, rs2 as u32, rs1 as u32, 5, rd as u32, 0x3b,
+        ));
+    }
+
+    /// Multiplies `rs1` and `rs2` keeping the lower 64 bits
+    pub fn mul(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 0, rd as u32, 0x33));
+    }
+
+    /// Multiplies `rs1` and `rs2` as signed integers keeping the upper 64 bits
+    pub fn mulh(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 1, rd as u32, 0x33));
+    }
+
+    /// Multiplies signed `rs1` and unsigned `rs2` keeping the upper 64 bits
+    pub fn mulhsu(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 2, rd as u32, 0x33));
+    }
+
+    /// Multiplies `rs1` and `rs2` as unsigned integers keeping the upper 64 bits
+    pub fn mulhu(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 3, rd as u32, 0x33));
+    }
+
+    /// Divides `rs1` by `rs2` as signed integers
+    pub fn div(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 4, rd as u32, 0x33));
+    }
+
+    /// Divides `rs1` by `rs2` as unsigned integers
+    pub fn divu(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 5, rd as u32, 0x33));
+    }
+
+    /// Computes the signed remainder of `rs1` divided by `rs2`
+    pub fn rem(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 6, rd as u32, 0x33));
+    }
+
+    /// Computes the unsigned remainder of `rs1` divided by `rs2`
+    pub fn remu(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 7, rd as u32, 0x33));
+    }
+
+    /// Multiplies the lower 32 bits of `rs1` and `rs2` sign-extending the result
+    pub fn mulw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 0, rd as u32, 0x3b));
+    }
+
+    /// Divides the lower 32 bits of `rs1` by `rs2` as signed integers
+    pub fn divw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 4, rd as u32, 0x3b));
+    }
+
+    /// Divides the lower 32 bits of `rs1` by `rs2` as unsigned integers
+    pub fn divuw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 5, rd as u32, 0x3b));
+    }
+
+    /// Computes the signed remainder of the lower 32 bits of `rs1` divided by `rs2`
+    pub fn remw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 6, rd as u32, 0x3b));
+    }
+
+    /// Computes the unsigned remainder of the lower 32 bits of `rs1` divided by `rs2`
+    pub fn remuw(&mut self, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.insn(raw::r_type(0x1, rs2 as u32, rs1 as u32, 7, rd as u32, 0x3b));
+    }
+
+    /// Adds the sign-extended 12-bit immediate to `rs1`
+    pub fn addi(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 0, rd as u32, 0x13));
+    }
+
+    /// Sets `rd` to 1 if `rs1` is less than the immediate as signed integers
+    pub fn slti(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 2, rd as u32, 0x13));
+    }
+
+    /// Sets `rd` to 1 if `rs1` is less than the sign-extended immediate as unsigned integers
+    pub fn sltiu(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 3, rd as u32, 0x13));
+    }
+
+    /// Computes the bitwise exclusive or of `rs1` and the immediate
+    pub fn xori(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 4, rd as u32, 0x13));
+    }
+
+    /// Computes the bitwise or of `rs1` and the immediate
+    pub fn ori(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 6, rd as u32, 0x13));
+    }
+
+    /// Computes the bitwise and of `rs1` and the immediate
+    pub fn andi(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 7, rd as u32, 0x13));
+    }
+
+    /// Adds the immediate to the lower 32 bits of `rs1` sign-extending the result
+    pub fn addiw(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 0, rd as u32, 0x1b));
+    }
+
+    /// Shifts `rs1` left by `shamt`
+    pub fn slli(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        assert!(shamt <= 63, "Shift amount must be at most 63");
+        self.insn(raw::i_type(shamt as i32, rs1 as u32, 1, rd as u32, 0x13));
+    }
+
+    /// Shifts `rs1` logically right by `shamt`
+    pub fn srli(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        assert!(shamt <= 63, "Shift amount must be at most 63");
+        self.insn(raw::i_type(shamt as i32, rs1 as u32, 5, rd as u32, 0x13));
+    }
+
+    /// Shifts `rs1` arithmetically right by `shamt`
+    pub fn srai(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        assert!(shamt <= 63, "Shift amount must be at most 63");
+        self.insn(raw::i_type(
+            0x400 | shamt as i32,
+            rs1 as u32,
+            5,
+            rd as u32,
+            0x13,
+        ));
+    }
+
+    /// Shifts the lower 32 bits of `rs1` left by `shamt` sign-extending the result
+    pub fn slliw(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        assert!(shamt <= 31, "Shift amount must be at most 31");
+        self.insn(raw::i_type(shamt as i32, rs1 as u32, 1, rd as u32, 0x1b));
+    }
+
+    /// Shifts the lower 32 bits of `rs1` logically right by `shamt` sign-extending the result
+    pub fn srliw(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        assert!(shamt <= 31, "Shift amount must be at most 31");
+        self.insn(raw::i_type(shamt as i32, rs1 as u32, 5, rd as u32, 0x1b));
+    }
+
+    /// Shifts the lower 32 bits of `rs1` arithmetically right by `shamt` sign-extending the result
+    pub fn sraiw(&mut self, rd: Reg, rs1: Reg, shamt: u32) {
+        assert!(shamt <= 31, "Shift amount must be at most 31");
+        self.insn(raw::i_type(
+            0x400 | shamt as i32,
+            rs1 as u32,
+            5,
+            rd as u32,
+            0x1b,
+        ));
+    }
+
+    /// Loads the sign-extended byte at address `rs1 + imm12` into `rd`
+    pub fn lb(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 0, rd as u32, 0x03));
+    }
+
+    /// Loads the sign-extended 16-bit value at address `rs1 + imm12` into `rd`
+    pub fn lh(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 1, rd as u32, 0x03));
+    }
+
+    /// Loads the sign-extended 32-bit value at address `rs1 + imm12` into `rd`
+    pub fn lw(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 2, rd as u32, 0x03));
+    }
+
+    /// Loads the 64-bit value at address `rs1 + imm12` into `rd`
+    pub fn ld(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 3, rd as u32, 0x03));
+    }
+
+    /// Loads the zero-extended byte at address `rs1 + imm12` into `rd`
+    pub fn lbu(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 4, rd as u32, 0x03));
+    }
+
+    /// Loads the zero-extended 16-bit value at address `rs1 + imm12` into `rd`
+    pub fn lhu(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 5, rd as u32, 0x03));
+    }
+
+    /// Loads the zero-extended 32-bit value at address `rs1 + imm12` into `rd`
+    pub fn lwu(&mut self, rd: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 6, rd as u32, 0x03));
+    }
+
+    /// Stores the lowest byte of `rs2` into the address `rs1 + imm12`
+    pub fn sb(&mut self, rs2: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::s_type(imm12, rs2 as u32, rs1 as u32, 0, 0x23));
+    }
+
+    /// Stores the lower 16 bits of `rs2` into the address `rs1 + imm12`
+    pub fn sh(&mut self, rs2: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::s_type(imm12, rs2 as u32, rs1 as u32, 1, 0x23));
+    }
+
+    /// Stores the lower 32 bits of `rs2` into the address `rs1 + imm12`
+    pub fn sw(&mut self, rs2: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::s_type(imm12, rs2 as u32, rs1 as u32, 2, 0x23));
+    }
+
+    /// Stores the 64-bit value of `rs2` into the address `rs1 + imm12`
+    pub fn sd(&mut self, rs2: Reg, rs1: Reg, imm12: i32) {
+        self.insn(raw::s_type(imm12, rs2 as u32, rs1 as u32, 3, 0x23));
+    }
+
+    /// Raises an environment call exception
+    pub fn ecall(&mut self) {
+        self.insn(0x00000073);
+    }
+
+    /// Raises a breakpoint exception
+    pub fn ebreak(&mut self) {
+        self.insn(0x00100073);
+    }
+
+    /// Orders all prior memory accesses before all following ones
+    pub fn fence(&mut self) {
+        self.insn(0x0FF0000F);
+    }
+
+    /// Synchronizes the instruction fetches of this hart with prior stores
+    pub fn fence_i(&mut self) {
+        self.insn(0x0000100F);
+    }
+
+    /// Atomically swaps `rs2` with the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoswap_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x1, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically swaps `rs2` with the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoswap_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x1, 3, rd, rs2, rs1, order);
+    }
+
+    /// Atomically adds `rs2` to the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoadd_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x0, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically adds `rs2` to the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoadd_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x0, 3, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the exclusive or of `rs2` and the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoxor_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x4, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the exclusive or of `rs2` and the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoxor_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x4, 3, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the and of `rs2` and the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoand_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0xc, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the and of `rs2` and the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoand_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0xc, 3, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the or of `rs2` and the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoor_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x8, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the or of `rs2` and the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amoor_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x8, 3, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the signed minimum of `rs2` and the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amomin_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x10, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the signed minimum of `rs2` and the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amomin_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x10, 3, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the signed maximum of `rs2` and the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amomax_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x14, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the signed maximum of `rs2` and the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amomax_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x14, 3, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the unsigned minimum of `rs2` and the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amominu_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x18, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the unsigned minimum of `rs2` and the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amominu_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x18, 3, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the unsigned maximum of `rs2` and the 32-bit value at address `rs1` loading the old value into `rd`
+    pub fn amomaxu_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x1c, 2, rd, rs2, rs1, order);
+    }
+
+    /// Atomically computes the unsigned maximum of `rs2` and the 64-bit value at address `rs1` loading the old value into `rd`
+    pub fn amomaxu_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x1c, 3, rd, rs2, rs1, order);
+    }
+
+    /// Loads the 32-bit value at address `rs1` and registers a reservation on it
+    pub fn lr_w(&mut self, rd: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x02, 2, rd, Reg::X0, rs1, order);
+    }
+
+    /// Stores the 32-bit value of `rs2` at address `rs1` if the reservation is still valid, `rd` is set to 0 on success
+    pub fn sc_w(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x03, 2, rd, rs2, rs1, order);
+    }
+
+    /// Loads the 64-bit value at address `rs1` and registers a reservation on it
+    pub fn lr_d(&mut self, rd: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x02, 3, rd, Reg::X0, rs1, order);
+    }
+
+    /// Stores the 64-bit value of `rs2` at address `rs1` if the reservation is still valid, `rd` is set to 0 on success
+    pub fn sc_d(&mut self, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        self.amo(0x03, 3, rd, rs2, rs1, order);
+    }
+
+    /// Adds the 32-bit floats `rs1` and `rs2`
+    pub fn fadd_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x0, rs2 as u32, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Subtracts `rs2` from `rs1` as 32-bit floats
+    pub fn fsub_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x4, rs2 as u32, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Multiplies the 32-bit floats `rs1` and `rs2`
+    pub fn fmul_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x8, rs2 as u32, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Divides `rs1` by `rs2` as 32-bit floats
+    pub fn fdiv_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0xc, rs2 as u32, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Computes the square root of the 32-bit float `rs1`
+    pub fn fsqrt_s(&mut self, rd: FReg, rs1: FReg) {
+        self.fp_op(0x2c, 0, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Computes the minimum of the 32-bit floats `rs1` and `rs2`
+    pub fn fmin_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x14, rs2 as u32, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Computes the maximum of the 32-bit floats `rs1` and `rs2`
+    pub fn fmax_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x14, rs2 as u32, rs1 as u32, 1, rd as u32);
+    }
+
+    /// Combines the magnitude of the 32-bit float `rs1` with the sign of `rs2`, `fmv` if both are equal
+    pub fn fsgnj_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x10, rs2 as u32, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Combines the magnitude of the 32-bit float `rs1` with the negated sign of `rs2`, `fneg` if both are equal
+    pub fn fsgnjn_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x10, rs2 as u32, rs1 as u32, 1, rd as u32);
+    }
+
+    /// Combines the magnitude of the 32-bit float `rs1` with the xor of both signs, `fabs` if both are equal
+    pub fn fsgnjx_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x10, rs2 as u32, rs1 as u32, 2, rd as u32);
+    }
+
+    /// Sets `rd` to 1 if the 32-bit floats are equal
+    pub fn feq_s(&mut self, rd: Reg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x50, rs2 as u32, rs1 as u32, 2, rd as u32);
+    }
+
+    /// Sets `rd` to 1 if the 32-bit float `rs1` is less than `rs2`
+    pub fn flt_s(&mut self, rd: Reg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x50, rs2 as u32, rs1 as u32, 1, rd as u32);
+    }
+
+    /// Sets `rd` to 1 if the 32-bit float `rs1` is less than or equal to `rs2`
+    pub fn fle_s(&mut self, rd: Reg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x50, rs2 as u32, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Computes `rs1 * rs2 + rs3` on 32-bit floats with a single rounding
+    pub fn fmadd_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        self.fp_fused(0x43, 0, rd, rs1, rs2, rs3);
+    }
+
+    /// Computes `rs1 * rs2 - rs3` on 32-bit floats with a single rounding
+    pub fn fmsub_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        self.fp_fused(0x47, 0, rd, rs1, rs2, rs3);
+    }
+
+    /// Computes `-(rs1 * rs2) + rs3` on 32-bit floats with a single rounding
+    pub fn fnmsub_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        self.fp_fused(0x4B, 0, rd, rs1, rs2, rs3);
+    }
+
+    /// Computes `-(rs1 * rs2) - rs3` on 32-bit floats with a single rounding
+    pub fn fnmadd_s(&mut self, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        self.fp_fused(0x4F, 0, rd, rs1, rs2, rs3);
+    }
+
+    /// Converts the 32-bit float `rs1` into a signed 32-bit integer rounding towards zero
+    pub fn fcvt_w_s(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x60, 0, rs1 as u32, RM_RTZ, rd as u32);
+    }
+
+    /// Converts the signed 32-bit integer `rs1` into a 32-bit float
+    pub fn fcvt_s_w(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x68, 0, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Converts the 32-bit float `rs1` into a unsigned 32-bit integer rounding towards zero
+    pub fn fcvt_wu_s(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x60, 1, rs1 as u32, RM_RTZ, rd as u32);
+    }
+
+    /// Converts the unsigned 32-bit integer `rs1` into a 32-bit float
+    pub fn fcvt_s_wu(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x68, 1, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Converts the 32-bit float `rs1` into a signed 64-bit integer rounding towards zero
+    pub fn fcvt_l_s(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x60, 2, rs1 as u32, RM_RTZ, rd as u32);
+    }
+
+    /// Converts the signed 64-bit integer `rs1` into a 32-bit float
+    pub fn fcvt_s_l(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x68, 2, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Converts the 32-bit float `rs1` into a unsigned 64-bit integer rounding towards zero
+    pub fn fcvt_lu_s(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x60, 3, rs1 as u32, RM_RTZ, rd as u32);
+    }
+
+    /// Converts the unsigned 64-bit integer `rs1` into a 32-bit float
+    pub fn fcvt_s_lu(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x68, 3, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Moves the bits of the 32-bit float `rs1` into `rd`
+    pub fn fmv_x_w(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x70, 0, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Moves the lower 32 bits of `rs1` into the float register `rd`
+    pub fn fmv_w_x(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x78, 0, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Adds the 64-bit floats `rs1` and `rs2`
+    pub fn fadd_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x1, rs2 as u32, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Subtracts `rs2` from `rs1` as 64-bit floats
+    pub fn fsub_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x5, rs2 as u32, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Multiplies the 64-bit floats `rs1` and `rs2`
+    pub fn fmul_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x9, rs2 as u32, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Divides `rs1` by `rs2` as 64-bit floats
+    pub fn fdiv_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0xd, rs2 as u32, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Computes the square root of the 64-bit float `rs1`
+    pub fn fsqrt_d(&mut self, rd: FReg, rs1: FReg) {
+        self.fp_op(0x2d, 0, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Computes the minimum of the 64-bit floats `rs1` and `rs2`
+    pub fn fmin_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x15, rs2 as u32, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Computes the maximum of the 64-bit floats `rs1` and `rs2`
+    pub fn fmax_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x15, rs2 as u32, rs1 as u32, 1, rd as u32);
+    }
+
+    /// Combines the magnitude of the 64-bit float `rs1` with the sign of `rs2`, `fmv` if both are equal
+    pub fn fsgnj_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x11, rs2 as u32, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Combines the magnitude of the 64-bit float `rs1` with the negated sign of `rs2`, `fneg` if both are equal
+    pub fn fsgnjn_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x11, rs2 as u32, rs1 as u32, 1, rd as u32);
+    }
+
+    /// Combines the magnitude of the 64-bit float `rs1` with the xor of both signs, `fabs` if both are equal
+    pub fn fsgnjx_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x11, rs2 as u32, rs1 as u32, 2, rd as u32);
+    }
+
+    /// Sets `rd` to 1 if the 64-bit floats are equal
+    pub fn feq_d(&mut self, rd: Reg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x51, rs2 as u32, rs1 as u32, 2, rd as u32);
+    }
+
+    /// Sets `rd` to 1 if the 64-bit float `rs1` is less than `rs2`
+    pub fn flt_d(&mut self, rd: Reg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x51, rs2 as u32, rs1 as u32, 1, rd as u32);
+    }
+
+    /// Sets `rd` to 1 if the 64-bit float `rs1` is less than or equal to `rs2`
+    pub fn fle_d(&mut self, rd: Reg, rs1: FReg, rs2: FReg) {
+        self.fp_op(0x51, rs2 as u32, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Computes `rs1 * rs2 + rs3` on 64-bit floats with a single rounding
+    pub fn fmadd_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        self.fp_fused(0x43, 1, rd, rs1, rs2, rs3);
+    }
+
+    /// Computes `rs1 * rs2 - rs3` on 64-bit floats with a single rounding
+    pub fn fmsub_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        self.fp_fused(0x47, 1, rd, rs1, rs2, rs3);
+    }
+
+    /// Computes `-(rs1 * rs2) + rs3` on 64-bit floats with a single rounding
+    pub fn fnmsub_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        self.fp_fused(0x4B, 1, rd, rs1, rs2, rs3);
+    }
+
+    /// Computes `-(rs1 * rs2) - rs3` on 64-bit floats with a single rounding
+    pub fn fnmadd_d(&mut self, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        self.fp_fused(0x4F, 1, rd, rs1, rs2, rs3);
+    }
+
+    /// Converts the 64-bit float `rs1` into a signed 32-bit integer rounding towards zero
+    pub fn fcvt_w_d(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x61, 0, rs1 as u32, RM_RTZ, rd as u32);
+    }
+
+    /// Converts the signed 32-bit integer `rs1` into a 64-bit float
+    pub fn fcvt_d_w(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x69, 0, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Converts the 64-bit float `rs1` into a unsigned 32-bit integer rounding towards zero
+    pub fn fcvt_wu_d(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x61, 1, rs1 as u32, RM_RTZ, rd as u32);
+    }
+
+    /// Converts the unsigned 32-bit integer `rs1` into a 64-bit float
+    pub fn fcvt_d_wu(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x69, 1, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Converts the 64-bit float `rs1` into a signed 64-bit integer rounding towards zero
+    pub fn fcvt_l_d(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x61, 2, rs1 as u32, RM_RTZ, rd as u32);
+    }
+
+    /// Converts the signed 64-bit integer `rs1` into a 64-bit float
+    pub fn fcvt_d_l(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x69, 2, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Converts the 64-bit float `rs1` into a unsigned 64-bit integer rounding towards zero
+    pub fn fcvt_lu_d(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x61, 3, rs1 as u32, RM_RTZ, rd as u32);
+    }
+
+    /// Converts the unsigned 64-bit integer `rs1` into a 64-bit float
+    pub fn fcvt_d_lu(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x69, 3, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Moves the bits of the 64-bit float `rs1` into `rd`
+    pub fn fmv_x_d(&mut self, rd: Reg, rs1: FReg) {
+        self.fp_op(0x71, 0, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Moves the lower 64 bits of `rs1` into the float register `rd`
+    pub fn fmv_d_x(&mut self, rd: FReg, rs1: Reg) {
+        self.fp_op(0x79, 0, rs1 as u32, 0, rd as u32);
+    }
+
+    /// Converts the 64-bit float `rs1` into a 32-bit float
+    pub fn fcvt_s_d(&mut self, rd: FReg, rs1: FReg) {
+        self.fp_op(0x20, 1, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Converts the 32-bit float `rs1` into a 64-bit float
+    pub fn fcvt_d_s(&mut self, rd: FReg, rs1: FReg) {
+        self.fp_op(0x21, 0, rs1 as u32, RM_DYN, rd as u32);
+    }
+
+    /// Loads the 32-bit float at address `rs1 + imm12` into `rd`
+    pub fn flw(&mut self, rd: FReg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 2, rd as u32, 0x07));
+    }
+
+    /// Loads the 64-bit float at address `rs1 + imm12` into `rd`
+    pub fn fld(&mut self, rd: FReg, rs1: Reg, imm12: i32) {
+        self.insn(raw::i_type(imm12, rs1 as u32, 3, rd as u32, 0x07));
+    }
+
+    /// Stores the 32-bit float `rs2` into the address `rs1 + imm12`
+    pub fn fsw(&mut self, rs2: FReg, rs1: Reg, imm12: i32) {
+        self.insn(raw::s_type(imm12, rs2 as u32, rs1 as u32, 2, 0x27));
+    }
+
+    /// Stores the 64-bit float `rs2` into the address `rs1 + imm12`
+    pub fn fsd(&mut self, rs2: FReg, rs1: Reg, imm12: i32) {
+        self.insn(raw::s_type(imm12, rs2 as u32, rs1 as u32, 3, 0x27));
+    }
+
+    /// Loads the value of a 64-bit constant through an `auipc`/`ld` pair
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn ld_const(&mut self, rd: Reg, offset: usize) {
+        self.post_ops.push(Op::Const {
+            insn_offset: self.code.len(),
+            const_offset: offset,
+        });
+        self.fixed_insn(raw::u_type(0, rd as u32, 0x17));
+        self.fixed_insn(raw::i_type(0, rd as u32, 3, rd as u32, 0x03));
+    }
+
+    /// Loads the value of a 64-bit global constant through an `auipc`/`ld` pair
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn ld_global_const(&mut self, rd: Reg, offset: usize) {
+        self.post_ops.push(Op::GlobalConst {
+            insn_offset: self.code.len(),
+            const_offset: offset,
+        });
+        self.fixed_insn(raw::u_type(0, rd as u32, 0x17));
+        self.fixed_insn(raw::i_type(0, rd as u32, 3, rd as u32, 0x03));
+    }
+
+    /// Loads the value of a sign-extended 32-bit constant through an `auipc`/`lw` pair
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn lw_const(&mut self, rd: Reg, offset: usize) {
+        self.post_ops.push(Op::Const {
+            insn_offset: self.code.len(),
+            const_offset: offset,
+        });
+        self.fixed_insn(raw::u_type(0, rd as u32, 0x17));
+        self.fixed_insn(raw::i_type(0, rd as u32, 2, rd as u32, 0x03));
+    }
+
+    /// Loads the value of a 64-bit float constant through an `auipc`/`fld` pair using `tmp` for
+    /// the address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn fld_const(&mut self, rd: FReg, tmp: Reg, offset: usize) {
+        self.post_ops.push(Op::Const {
+            insn_offset: self.code.len(),
+            const_offset: offset,
+        });
+        self.fixed_insn(raw::u_type(0, tmp as u32, 0x17));
+        self.fixed_insn(raw::i_type(0, tmp as u32, 3, rd as u32, 0x07));
+    }
+
+    /// Loads the value of a 32-bit float constant through an `auipc`/`flw` pair using `tmp` for
+    /// the address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn flw_const(&mut self, rd: FReg, tmp: Reg, offset: usize) {
+        self.post_ops.push(Op::Const {
+            insn_offset: self.code.len(),
+            const_offset: offset,
+        });
+        self.fixed_insn(raw::u_type(0, tmp as u32, 0x17));
+        self.fixed_insn(raw::i_type(0, tmp as u32, 2, rd as u32, 0x07));
+    }
+
+    /// Stores a 32-bit constant in front of the code
+    ///
+    /// Return the index of the constant
+    pub fn const_32(&mut self, value: u32) -> usize {
+        let index = self.constants.len() / 4;
+        for byte in value.to_le_bytes() {
+            self.constants.push(byte);
+        }
+        index
+    }
+
+    /// Stores a 64-bit constant in front of the code, aligned to 8 bytes inside of the constants
+    ///
+    /// Return the index of the constant
+    pub fn const_64(&mut self, value: u64) -> usize {
+        if !self.constants.len().is_multiple_of(8) {
+            self.const_32(0);
+        }
+        let index = self.constants.len() / 4;
+        for byte in value.to_le_bytes() {
+            self.constants.push(byte);
+        }
+        index
+    }
+
+    /// Patches the branches and jumps to local labels
+    ///
+    /// This is done by the assembler when the routine is pushed
+    pub fn finalize(&mut self) {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(target) = self.labels[fixup.label.0] else {
+                panic!("Tried to branch to unbound label");
+            };
+            let rel = target as isize - fixup.insn_offset as isize;
+            let imm = if fixup.jump {
+                raw::j_imm(rel)
+            } else {
+                raw::b_imm(rel)
+            };
+            let insn = raw::read_le_32(&self.code, fixup.insn_offset);
+            raw::write_le_32(&mut self.code, fixup.insn_offset, insn | imm);
+        }
+    }
+
+    fn branch(&mut self, funct3: u32, rs1: Reg, rs2: Reg, label: Label) {
+        self.fixups.push(Fixup {
+            insn_offset: self.code.len(),
+            label,
+            jump: false,
+        });
+        self.fixed_insn(raw::r_type(0, rs2 as u32, rs1 as u32, funct3, 0, 0x63));
+    }
+
+    fn amo(&mut self, funct5: u32, funct3: u32, rd: Reg, rs2: Reg, rs1: Reg, order: Ordering) {
+        let (aq, rl) = match order {
+            Ordering::Relaxed => (0, 0),
+            Ordering::Acquire => (1, 0),
+            Ordering::Release => (0, 1),
+            _ => (1, 1),
+        };
+        let funct7 = (funct5 << 2) | (aq << 1) | rl;
+        self.insn(raw::r_type(
+            funct7, rs2 as u32, rs1 as u32, funct3, rd as u32, 0x2F,
+        ));
+    }
+
+    fn fp_fused(&mut self, opcode: u32, fmt: u32, rd: FReg, rs1: FReg, rs2: FReg, rs3: FReg) {
+        let funct7 = ((rs3 as u32) << 2) | fmt;
+        self.insn(raw::r_type(
+            funct7, rs2 as u32, rs1 as u32, RM_DYN, rd as u32, opcode,
+        ));
+    }
+
+    fn fp_op(&mut self, funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32) {
+        self.insn(raw::r_type(funct7, rs2, rs1, funct3, rd, 0x53));
+    }
+
+    /// Emits an instruction in its compressed form if enabled and possible
+    fn insn(&mut self, value: u32) {
+        match raw::compress(value) {
+            Some(value) if self.compressed => self.code.extend_from_slice(&value.to_le_bytes()),
+            _ => self.fixed_insn(value),
+        }
+    }
+
+    /// Emits an instruction that is never compressed so it can be patched later
+    fn fixed_insn(&mut self, value: u32) {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+#[derive(Clone, Copy)]
+enum LiKind {
+    Lui,
+    Addi,
+    Addiw,
+    Slli,
+}
+
+/// Appends the instructions that materialize `value` starting from `X0`
+fn li_sequence(value: i64, seq: &mut Vec<(LiKind, i64)>) {
+    if let Ok(value) = i32::try_from(value) {
+        let hi20 = ((value as i64 + 0x800) >> 12) & 0xFFFFF;
+        let lo12 = ((value << 20) >> 20) as i64;
+        if hi20 != 0 {
+            seq.push((LiKind::Lui, hi20));
+        }
+        if lo12 != 0 || hi20 == 0 {
+            let kind = if hi20 != 0 {
+                LiKind::Addiw
+            } else {
+                LiKind::Addi
+            };
+            seq.push((kind, lo12));
+        }
+        return;
+    }
+    let lo12 = (value << 52) >> 52;
+    let hi52 = value.wrapping_add(0x800) >> 12;
+    let shift = 12 + hi52.trailing_zeros();
+    let hi = (hi52 >> (shift - 12) << shift) >> shift;
+    li_sequence(hi, seq);
+    seq.push((LiKind::Slli, shift as i64));
+    if lo12 != 0 {
+        seq.push((LiKind::Addi, lo12));
+    }
+}
+
+impl Subroutine for Routine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn constants(&self) -> &[u8] {
+        &self.constants
+    }
+
+    fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    fn process(
+        &self,
+        assembler: &impl Assembler,
+        abs_addr: usize,
+        code_offset: usize,
+        bytes: &mut [u8],
+    ) {
+        for op in &self.post_ops {
+            op.process(assembler, abs_addr, code_offset, bytes);
+        }
+    }
+}
+
+/// `auipc` followed by an I-type instruction that is patched to reference a target
+pub enum Op {
+    Routine {
+        insn_offset: usize,
+        label: String,
+    },
+    Const {
+        insn_offset: usize,
+        const_offset: usize,
+    },
+    GlobalConst {
+        insn_offset: usize,
+        const_offset: usize,
+    },
+}
+
+impl PostOp for Op {
+    fn process(
+        &self,
+        assembler: &impl Assembler,
+        abs_addr: usize,
+        code_offset: usize,
+        bytes: &mut [u8],
+    ) {
+        let (insn_offset, target) = match self {
+            Self::Routine { insn_offset, label } => {
+                let Some(addr) = assembler.get_label_address(label) else {
+                    panic!("Tried to branch to non-existent label");
+                };
+                (*insn_offset, addr)
+            }
+            Self::Const {
+                insn_offset,
+                const_offset,
+            } => (*insn_offset, abs_addr + const_offset * 4),
+            Self::GlobalConst {
+                insn_offset,
+                const_offset,
+            } => (
+                *insn_offset,
+                assembler.global_const_address() + const_offset * 4,
+            ),
+        };
+        raw::pc_rel(
+            bytes,
+            code_offset + insn_offset,
+            abs_addr + code_offset + insn_offset,
+            target,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::rv64::asm::Asm;
+    use std::collections::HashMap;
+
+    fn assemble(compressed: bool, build: impl FnOnce(&mut Routine)) -> Vec<u8> {
+        let mut routine = Routine::new("f".to_string());
+        routine.set_compressed(compressed);
+        build(&mut routine);
+        routine.finalize();
+        routine.code
+    }
+
+    fn words(code: &[u8]) -> Vec<u32> {
+        code.chunks(4).map(|it| raw::read_le_32(it, 0)).collect()
+    }
+
+    fn halves(code: &[u8]) -> Vec<u16> {
+        code.chunks(2)
+            .map(|it| u16::from_le_bytes([it[0], it[1]]))
+            .collect()
+    }
+
+    /// Returns the address an `auipc` at `address` and the I-type instruction following it
+    /// reference
+    fn pc_rel_target(code: &[u8], address: usize) -> usize {
+        let auipc = raw::read_le_32(code, address);
+        let next = raw::read_le_32(code, address + 4);
+        let hi = (auipc & !0xFFF) as i32 as i64;
+        let lo = (next as i32 >> 20) as i64;
+        (address as i64 + hi + lo) as usize
+    }
+
+    #[test]
+    fn base_encodings() {
+        let code = assemble(false, |r| {
+            r.add(Reg::A0, Reg::A1, Reg::A2);
+            r.sub(Reg::A0, Reg::A1, Reg::A2);
+            r.mul(Reg::A0, Reg::A1, Reg::A2);
+            r.divu(Reg::A0, Reg::A1, Reg::A2);
+            r.addi(Reg::A0, Reg::A1, -5);
+            r.slli(Reg::A0, Reg::A1, 3);
+            r.srai(Reg::A0, Reg::A1, 63);
+            r.sraiw(Reg::A0, Reg::A1, 31);
+            r.lui(Reg::A0, 0x12345);
+            r.lw(Reg::A0, Reg::SP, 8);
+            r.sd(Reg::RA, Reg::S0, -16);
+            r.lbu(Reg::T0, Reg::A5, 2047);
+            r.amoadd_d(Reg::A0, Reg::A1, Reg::A2, Ordering::SeqCst);
+            r.lr_w(Reg::T0, Reg::A0, Ordering::Acquire);
+            r.sc_d(Reg::T1, Reg::T2, Reg::A0, Ordering::Release);
+            r.fadd_d(FReg::FA0, FReg::FA1, FReg::FA2);
+            r.fmadd_s(FReg::FA0, FReg::FA1, FReg::FA2, FReg::FA3);
+            r.fcvt_l_d(Reg::A0, FReg::FA0);
+            r.fmv_x_d(Reg::A0, FReg::FA1);
+            r.fld(FReg::FS0, Reg::SP, 24);
+            r.ecall();
+            r.fence();
+        });
+        assert_eq!(
+            words(&code),
+            [
+                0x00C58533, 0x40C58533, 0x02C58533, 0x02C5D533, 0xFFB58513, 0x00359513, 0x43F5D513,
+                0x41F5D51B, 0x12345537, 0x00812503, 0xFE143823, 0x7FF7C283, 0x06B6352F, 0x140522AF,
+                0x1A75332F, 0x02C5F553, 0x68C5F543, 0xC2251553, 0xE2058553, 0x01813407, 0x00000073,
+                0x0FF0000F,
+            ]
+        );
+    }
+
+    #[test]
+    fn compressed_encodings() {
+        let code = assemble(true, |r| {
+            r.addi(Reg::A0, Reg::A0, 1);
+            r.addi(Reg::A0, Reg::ZERO, -3);
+            r.mv(Reg::A0, Reg::A1);
+            r.add(Reg::A0, Reg::A0, Reg::A1);
+            r.add(Reg::A0, Reg::ZERO, Reg::A1);
+            r.sub(Reg::S0, Reg::S0, Reg::S1);
+            r.and(Reg::A5, Reg::A5, Reg::A4);
+            r.addw(Reg::A0, Reg::A0, Reg::A1);
+            r.ld(Reg::A0, Reg::SP, 8);
+            r.sd(Reg::RA, Reg::SP, 0);
+            r.lw(Reg::A0, Reg::A1, 4);
+            r.sd(Reg::S1, Reg::A5, 248);
+            r.addi(Reg::SP, Reg::SP, -32);
+            r.addi(Reg::A0, Reg::SP, 16);
+            r.ret();
+            r.jalr(Reg::RA, Reg::T0, 0);
+            r.nop();
+            r.srai(Reg::S0, Reg::S0, 3);
+            r.andi(Reg::S0, Reg::S0, -1);
+            r.slli(Reg::A0, Reg::A0, 12);
+            r.lui(Reg::A0, 0x1F);
+            r.addiw(Reg::A0, Reg::A0, -1);
+            r.ebreak();
+        });
+        assert_eq!(
+            halves(&code),
+            [
+                0x0505, 0x5575, 0x852E, 0x952E, 0x852E, 0x8C05, 0x8FF9, 0x9D2D, 0x6522, 0xE006,
+                0x41C8, 0xFFE4, 0x1101, 0x0808, 0x8082, 0x9282, 0x0001, 0x840D, 0x987D, 0x0532,
+                0x657D, 0x357D, 0x9002,
+            ]
+        );
+    }
+
+    #[test]
+    fn uncompressible_encodings() {
+        // Operands outside of the compressed forms keep the 32-bit encodings
+        let code = assemble(true, |r| {
+            r.add(Reg::A0, Reg::A1, Reg::A2);
+            r.addi(Reg::A0, Reg::A1, -5);
+            r.sub(Reg::A0, Reg::A0, Reg::T0);
+            r.ld(Reg::A0, Reg::SP, 4);
+            r.lw(Reg::T0, Reg::A0, 4);
+            r.lui(Reg::SP, 1);
+        });
+        assert_eq!(
+            words(&code),
+            [0x00C58533, 0xFFB58513, 0x40550533, 0x00413503, 0x00452283, 0x00001137]
+        );
+    }
+
+    #[test]
+    fn branch_fixups() {
+        let code = assemble(false, |r| {
+            let forward = r.new_label();
+            let backward = r.new_label();
+            r.beq(Reg::A0, Reg::A1, forward);
+            r.nop();
+            r.bind(forward);
+            r.bind(backward);
+            r.nop();
+            r.bne(Reg::A0, Reg::ZERO, backward);
+        });
+        assert_eq!(
+            words(&code),
+            [0x00B50463, 0x00000013, 0x00000013, 0xFE051EE3]
+        );
+
+        let code = assemble(false, |r| {
+            let target = r.new_label();
+            r.bind(target);
+            for _ in 0..1024 {
+                r.nop();
+            }
+            r.bltu(Reg::T0, Reg::T1, target);
+        });
+        assert_eq!(words(&code)[1024], 0x8062E063);
+
+        // Branches stay 32-bit, the instructions they skip do not
+        let code = assemble(true, |r| {
+            let target = r.new_label();
+            r.bge(Reg::A0, Reg::A1, target);
+            for _ in 0..2045 {
+                r.nop();
+            }
+            r.bind(target);
+        });
+        assert_eq!(code.len(), 4 + 2045 * 2);
+        assert_eq!(raw::read_le_32(&code, 0), 0x7EB55FE3);
+    }
+
+    #[test]
+    fn jump_fixups() {
+        let code = assemble(true, |r| {
+            let forward = r.new_label();
+            let backward = r.new_label();
+            r.bind(backward);
+            r.nop();
+            r.nop();
+            r.jal(Reg::RA, forward);
+            r.nop();
+            r.nop();
+            r.bind(forward);
+            r.j(backward);
+        });
+        assert_eq!(halves(&code[..4]), [0x0001, 0x0001]);
+        assert_eq!(raw::read_le_32(&code, 4), 0x008000EF);
+        assert_eq!(raw::read_le_32(&code, 12), 0xFF5FF06F);
+    }
+
+    #[test]
+    fn immediate_sequences() {
+        let code = assemble(false, |r| {
+            r.li(Reg::A0, 0);
+            r.li(Reg::A1, 2047);
+            r.li(Reg::A2, -2048);
+            r.li(Reg::A3, 0x800);
+            r.li(Reg::A4, 0x1234_5678);
+            // `addiw` keeps the result positive, `addi` would leave the upper bits set
+            r.li(Reg::A5, 0x7FFF_F800);
+            r.li(Reg::T0, i32::MIN as i64);
+            r.li(Reg::T1, 0x8000_0000);
+            r.li(Reg::T2, -1);
+            r.li(Reg::S0, i64::MIN);
+            r.li(Reg::S1, 0xFFFF_FFFF);
+            r.li(Reg::A0, 0x1_2345_0000_0000);
+            r.li(Reg::A1, 0x1_2345_6789);
+        });
+        assert_eq!(
+            words(&code),
+            [
+                0x00000513, // addi a0, zero, 0
+                0x7FF00593, // addi a1, zero, 2047
+                0x80000613, // addi a2, zero, -2048
+                0x000016B7, // lui a3, 1
+                0x8006869B, // addiw a3, a3, -2048
+                0x12345737, // lui a4, 0x12345
+                0x6787071B, // addiw a4, a4, 1656
+                0x800007B7, // lui a5, 0x80000
+                0x8007879B, // addiw a5, a5, -2048
+                0x800002B7, // lui t0, 0x80000
+                0x00100313, // addi t1, zero, 1
+                0x01F31313, // slli t1, t1, 31
+                0xFFF00393, // addi t2, zero, -1
+                0xFFF00413, // addi s0, zero, -1
+                0x03F41413, // slli s0, s0, 63
+                0x00100493, // addi s1, zero, 1
+                0x02049493, // slli s1, s1, 32
+                0xFFF48493, // addi s1, s1, -1
+                0x00012537, // lui a0, 18
+                0x3455051B, // addiw a0, a0, 837
+                0x02051513, // slli a0, a0, 32
+                0x000925B7, // lui a1, 146
+                0xA2B5859B, // addiw a1, a1, -1493
+                0x00D59593, // slli a1, a1, 13
+                0x78958593, // addi a1, a1, 1929
+            ]
+        );
+    }
+
+    #[test]
+    fn immediate_sequence_values() {
+        let evaluate = |value: i64| {
+            let mut seq = Vec::new();
+            li_sequence(value, &mut seq);
+            let result = seq.iter().fold(0i64, |reg, &(kind, imm)| match kind {
+                LiKind::Lui => ((imm << 12) as i32) as i64,
+                LiKind::Addi => reg.wrapping_add(imm),
+                LiKind::Addiw => (reg.wrapping_add(imm) as i32) as i64,
+                LiKind::Slli => reg << imm,
+            });
+            assert_eq!(result, value, "{value:#X} materialized as {result:#X}");
+        };
+        for shift in 0..64 {
+            for base in [
+                1i64,
+                -1,
+                0x7FF,
+                0x800,
+                0xFFF,
+                -0x801,
+                0x1234_5678,
+                0x7FFF_F800,
+            ] {
+                let value = base.wrapping_shl(shift);
+                for delta in [-1, 0, 1, 0x7FF, -0x800] {
+                    evaluate(value.wrapping_add(delta));
+                }
+            }
+        }
+        for value in [
+            i64::MIN,
+            i64::MAX,
+            i32::MIN as i64,
+            i32::MAX as i64,
+            0x1234_5678_9ABC_DEF0,
+        ] {
+            evaluate(value);
+        }
+    }
+
+    #[test]
+    fn immediate_from_constant() {
+        // Five instructions are longer than loading the value
+        let mut asm = Asm::default();
+        let mut routine = Routine::new("f".to_string());
+        routine.set_compressed(true);
+        routine.li(Reg::A0, 0x8000_0000_0000_0ABCu64 as i64);
+        routine.li(Reg::A1, 0x1234_5678_9ABC_DEF0);
+        routine.ret();
+        asm.push_routine(routine);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let entry = vtable["f"];
+        assert_eq!(code.len(), entry + 18);
+        for (index, value) in [0x8000_0000_0000_0ABCu64, 0x1234_5678_9ABC_DEF0]
+            .into_iter()
+            .enumerate()
+        {
+            let address = entry + index * 8;
+            let target = pc_rel_target(&code, address);
+            // auipc followed by ld
+            assert_eq!(raw::read_le_32(&code, address) & 0x7F, 0x17);
+            assert_eq!(raw::read_le_32(&code, address + 4) & 0x707F, 0x3003);
+            assert_eq!(
+                u64::from_le_bytes(code[target..target + 8].try_into().unwrap()),
+                value
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Tried to branch to label not in range")]
+    fn branch_out_of_range() {
+        assemble(false, |r| {
+            let target = r.new_label();
+            r.beq(Reg::A0, Reg::A1, target);
+            for _ in 0..1024 {
+                r.nop();
+            }
+            r.bind(target);
+        });
+    }
+
+    #[test]
+    fn call_and_const_fixups() {
+        let mut asm = Asm::default();
+        let global = asm.const_64(0xDEAD_BEEF);
+        let mut caller = Routine::new("caller".to_string());
+        caller.set_compressed(true);
+        let local = caller.const_64(0x1234_5678_9ABC_DEF0);
+        caller.nop();
+        caller.call("callee".to_string());
+        caller.ld_const(Reg::A0, local);
+        caller.ld_global_const(Reg::A1, global);
+        caller.tail("callee".to_string());
+        asm.push_routine(caller);
+        // Puts the callee far enough away that the low part of the offset is negative
+        let mut callee = Routine::new("callee".to_string());
+        for _ in 0..0x300 {
+            callee.nop();
+        }
+        callee.ret();
+        asm.push_routine(callee);
+        let (code, vtable): (Vec<u8>, HashMap<String, usize>) = asm.virtual_jit().unwrap();
+        let caller = vtable["caller"];
+        let callee = vtable["callee"];
+        assert!(caller - callee > 0x800);
+
+        let call = caller + 2;
+        assert_eq!(raw::read_le_32(&code, call) & 0xFFF, 0x097);
+        assert_eq!(raw::read_le_32(&code, call + 4) & 0xFFFFF, 0x080E7);
+        assert_eq!(pc_rel_target(&code, call), callee);
+        let local = pc_rel_target(&code, call + 8);
+        assert_eq!(local % 8, 0, "64-bit constant is not aligned");
+        assert_eq!(
+            u64::from_le_bytes(code[local..local + 8].try_into().unwrap()),
+            0x1234_5678_9ABC_DEF0
+        );
+        assert_eq!(pc_rel_target(&code, call + 16), 0);
+        assert_eq!(raw::read_le_32(&code, call + 28) & 0xFFFFF, 0x30067);
+        assert_eq!(pc_rel_target(&code, call + 24), callee);
+    }
+}
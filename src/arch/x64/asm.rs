@@ -0,0 +1,83 @@
+use super::routine::Routine;
+use crate::{
+    assembler::{Assembler, Image, VTable},
+    mem,
+};
+use std::collections::HashMap;
+
+pub struct Asm {
+    image: Image<Routine>,
+}
+
+impl Asm {
+    pub fn const_32(&mut self, value: u32) -> usize {
+        let constants = self.image.constants_mut();
+        let index = constants.len() / 4;
+        for byte in value.to_ne_bytes() {
+            constants.push(byte);
+        }
+        index
+    }
+
+    pub fn const_64(&mut self, value: u64) -> usize {
+        let constants = self.image.constants_mut();
+        let index = constants.len() / 4;
+        for byte in value.to_ne_bytes() {
+            constants.push(byte);
+        }
+        index
+    }
+
+    /// Stores a 128-bit vector constant aligned to 16 bytes
+    pub fn const_128(&mut self, value: [u8; 16]) -> usize {
+        let constants = self.image.constants_mut();
+        constants.resize(mem::align(constants.len(), 16), 0);
+        let index = constants.len() / 4;
+        constants.extend_from_slice(&value);
+        index
+    }
+
+    /// Stores a 256-bit vector constant aligned to 32 bytes
+    pub fn const_256(&mut self, value: [u8; 32]) -> usize {
+        let constants = self.image.constants_mut();
+        constants.resize(mem::align(constants.len(), 32), 0);
+        let index = constants.len() / 4;
+        constants.extend_from_slice(&value);
+        index
+    }
+
+    /// Finalizes the routine and adds it to the assembler
+    pub fn push_routine(&mut self, mut routine: Routine) {
+        routine.finalize();
+        self.image.push_routine(routine);
+    }
+}
+
+impl Default for Asm {
+    fn default() -> Self {
+        Self {
+            // Instructions and RIP-relative constants need no alignment
+            image: Image::new(1),
+        }
+    }
+}
+
+impl Assembler for Asm {
+    type AsmRoutine = Routine;
+
+    fn global_const_address(&self) -> usize {
+        self.image.global_const_address()
+    }
+
+    fn get_label_address(&self, name: &str) -> Option<usize> {
+        self.image.get_label_address(name)
+    }
+
+    fn jit(self) -> Option<VTable> {
+        self.image.jit()
+    }
+
+    fn virtual_jit(self) -> Option<(Vec<u8>, HashMap<String, usize>)> {
+        self.image.virtual_jit()
+    }
+}
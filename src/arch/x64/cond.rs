@@ -0,0 +1,37 @@
+/// Condition codes as encoded in the lowest nibble of `jcc`, `setcc` and `cmovcc`
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    /// Overflow
+    O = 0x0,
+    /// No overflow
+    NO = 0x1,
+    /// Unsigned below
+    B = 0x2,
+    /// Unsigned above or equal
+    AE = 0x3,
+    /// Equal
+    E = 0x4,
+    /// Not equal
+    NE = 0x5,
+    /// Unsigned below or equal
+    BE = 0x6,
+    /// Unsigned above
+    A = 0x7,
+    /// Sign
+    S = 0x8,
+    /// No sign
+    NS = 0x9,
+    /// Parity even
+    P = 0xA,
+    /// Parity odd
+    NP = 0xB,
+    /// Signed less
+    L = 0xC,
+    /// Signed greater or equal
+    GE = 0xD,
+    /// Signed less or equal
+    LE = 0xE,
+    /// Signed greater
+    G = 0xF,
+}
@@ -0,0 +1,242 @@
+use super::{
+    cond::Cond,
+    reg::{is_64_bit, low_32, Reg},
+    routine::Routine,
+};
+use crate::assembler::{Condition, Label, MacroAssembler, Width};
+
+/// Caller-saved register that is not used for arguments, used to keep operands alive
+const SCRATCH: Reg = Reg::R11;
+
+impl Routine {
+    /// Emits `dst = lhs op rhs` through the two-operand form `op`
+    fn three_operand(
+        &mut self,
+        op: fn(&mut Self, Reg, Reg),
+        commutative: bool,
+        dst: Reg,
+        lhs: Reg,
+        rhs: Reg,
+    ) {
+        if dst == lhs {
+            op(self, dst, rhs);
+        } else if dst == rhs && commutative {
+            op(self, dst, lhs);
+        } else if dst == rhs {
+            self.mov_reg(SCRATCH, rhs);
+            self.mov_reg(dst, lhs);
+            op(self, dst, SCRATCH);
+        } else {
+            self.mov_reg(dst, lhs);
+            op(self, dst, rhs);
+        }
+    }
+}
+
+impl MacroAssembler for Routine {
+    type Reg = Reg;
+
+    fn arg_reg(index: usize) -> Option<Reg> {
+        [Reg::RDI, Reg::RSI, Reg::RDX, Reg::RCX, Reg::R8, Reg::R9]
+            .get(index)
+            .copied()
+    }
+
+    fn ret_reg() -> Reg {
+        Reg::RAX
+    }
+
+    fn temp_regs() -> &'static [Reg] {
+        &[Reg::RAX, Reg::R10]
+    }
+
+    fn new_label(&mut self) -> Label {
+        Routine::new_label(self)
+    }
+
+    fn bind(&mut self, label: Label) {
+        Routine::bind(self, label);
+    }
+
+    fn mov(&mut self, dst: Reg, src: Reg) {
+        if dst != src {
+            self.mov_reg(dst, src);
+        }
+    }
+
+    fn mov_imm(&mut self, dst: Reg, imm: u64) {
+        assert!(is_64_bit(dst), "Destination register must be 64-bit");
+        Routine::mov_imm(self, dst, imm);
+    }
+
+    fn load(&mut self, width: Width, dst: Reg, base: Reg, offset: i32) {
+        assert!(is_64_bit(dst), "Destination register must be 64-bit");
+        match width {
+            Width::Byte => self.movzx8_load(dst, base, offset),
+            Width::Half => self.movzx16_load(dst, base, offset),
+            // Writing the 32-bit register zero-extends into the 64-bit register
+            Width::Word => self.mov_load(low_32(dst), base, offset),
+            Width::Double => self.mov_load(dst, base, offset),
+        }
+    }
+
+    fn store(&mut self, width: Width, src: Reg, base: Reg, offset: i32) {
+        assert!(is_64_bit(src), "Source register must be 64-bit");
+        match width {
+            Width::Byte => self.mov8_store(base, offset, src),
+            Width::Half => self.mov16_store(base, offset, src),
+            Width::Word => self.mov_store(base, offset, low_32(src)),
+            Width::Double => self.mov_store(base, offset, src),
+        }
+    }
+
+    fn add(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.three_operand(Self::add_reg, true, dst, lhs, rhs);
+    }
+
+    fn add_imm(&mut self, dst: Reg, lhs: Reg, imm: i32) {
+        MacroAssembler::mov(self, dst, lhs);
+        Routine::add_imm(self, dst, imm);
+    }
+
+    fn sub(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.three_operand(Self::sub_reg, false, dst, lhs, rhs);
+    }
+
+    fn mul(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.three_operand(Self::imul_reg, true, dst, lhs, rhs);
+    }
+
+    fn and(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.three_operand(Self::and_reg, true, dst, lhs, rhs);
+    }
+
+    fn or(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.three_operand(Self::or_reg, true, dst, lhs, rhs);
+    }
+
+    fn xor(&mut self, dst: Reg, lhs: Reg, rhs: Reg) {
+        self.three_operand(Self::xor_reg, true, dst, lhs, rhs);
+    }
+
+    fn jump(&mut self, label: Label) {
+        self.jmp(label);
+    }
+
+    fn branch_if(&mut self, cond: Condition, lhs: Reg, rhs: Reg, label: Label) {
+        self.cmp_reg(lhs, rhs);
+        let cond = match cond {
+            Condition::Eq => Cond::E,
+            Condition::Ne => Cond::NE,
+            Condition::Lt => Cond::L,
+            Condition::Le => Cond::LE,
+            Condition::Gt => Cond::G,
+            Condition::Ge => Cond::GE,
+            Condition::Below => Cond::B,
+            Condition::BelowEq => Cond::BE,
+            Condition::Above => Cond::A,
+            Condition::AboveEq => Cond::AE,
+        };
+        self.jcc(cond, label);
+    }
+
+    fn call(&mut self, label: String) {
+        Routine::call(self, label);
+    }
+
+    fn prologue(&mut self) {
+        self.push(Reg::RBP);
+        self.mov_reg(Reg::RBP, Reg::RSP);
+    }
+
+    fn epilogue(&mut self) {
+        self.pop(Reg::RBP);
+    }
+
+    fn ret(&mut self) {
+        Routine::ret(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(build: impl FnOnce(&mut Routine)) -> Vec<u8> {
+        let mut routine = Routine::new("f".to_string());
+        build(&mut routine);
+        routine.finalize();
+        routine.code
+    }
+
+    #[test]
+    fn three_operand_forms() {
+        let code = assemble(|m| {
+            let top = MacroAssembler::new_label(m);
+            MacroAssembler::bind(m, top);
+            m.branch_if(Condition::Below, Reg::RDI, Reg::RSI, top);
+            MacroAssembler::add(m, Reg::RAX, Reg::RAX, Reg::RSI);
+            // Commutative operations swap the operands instead of overwriting `rhs`
+            MacroAssembler::add(m, Reg::RSI, Reg::RDI, Reg::RSI);
+            // Others keep `rhs` alive in the scratch register
+            MacroAssembler::sub(m, Reg::RAX, Reg::RDI, Reg::RAX);
+            MacroAssembler::sub(m, Reg::RAX, Reg::RDI, Reg::RSI);
+            MacroAssembler::mul(m, Reg::RDX, Reg::RDX, Reg::RCX);
+            MacroAssembler::mov(m, Reg::RAX, Reg::RAX);
+            MacroAssembler::add_imm(m, Reg::R10, Reg::RDI, 16);
+        });
+        let expected: &[&[u8]] = &[
+            &[0x48, 0x39, 0xF7],       // cmp rdi, rsi
+            &[0x72, 0xFB],             // jb top
+            &[0x48, 0x01, 0xF0],       // add rax, rsi
+            &[0x48, 0x01, 0xFE],       // add rsi, rdi
+            &[0x49, 0x89, 0xC3],       // mov r11, rax
+            &[0x48, 0x89, 0xF8],       // mov rax, rdi
+            &[0x4C, 0x29, 0xD8],       // sub rax, r11
+            &[0x48, 0x89, 0xF8],       // mov rax, rdi
+            &[0x48, 0x29, 0xF0],       // sub rax, rsi
+            &[0x48, 0x0F, 0xAF, 0xD1], // imul rdx, rcx
+            &[0x49, 0x89, 0xFA],       // mov r10, rdi
+            &[0x49, 0x83, 0xC2, 0x10], // add r10, 16
+        ];
+        assert_eq!(code, expected.concat());
+    }
+
+    #[test]
+    fn memory_widths_and_frame() {
+        let code = assemble(|m| {
+            m.prologue();
+            m.load(Width::Byte, Reg::RAX, Reg::RDI, 1);
+            m.load(Width::Half, Reg::RAX, Reg::RDI, 2);
+            m.load(Width::Word, Reg::RAX, Reg::RDI, 4);
+            m.load(Width::Double, Reg::RAX, Reg::RDI, 8);
+            m.store(Width::Byte, Reg::RSI, Reg::RDI, 0);
+            m.store(Width::Half, Reg::RSI, Reg::RDI, 0);
+            m.store(Width::Word, Reg::RSI, Reg::RDI, 0);
+            m.store(Width::Double, Reg::RSI, Reg::RDI, 0);
+            m.epilogue();
+            MacroAssembler::ret(m);
+        });
+        let expected: &[&[u8]] = &[
+            &[0x55],                   // push rbp
+            &[0x48, 0x89, 0xE5],       // mov rbp, rsp
+            &[0x0F, 0xB6, 0x47, 0x01], // movzx eax, byte ptr [rdi + 1]
+            &[0x0F, 0xB7, 0x47, 0x02], // movzx eax, word ptr [rdi + 2]
+            &[0x8B, 0x47, 0x04],       // mov eax, dword ptr [rdi + 4]
+            &[0x48, 0x8B, 0x47, 0x08], // mov rax, qword ptr [rdi + 8]
+            &[0x40, 0x88, 0x37],       // mov byte ptr [rdi], sil
+            &[0x66, 0x89, 0x37],       // mov word ptr [rdi], si
+            &[0x89, 0x37],             // mov dword ptr [rdi], esi
+            &[0x48, 0x89, 0x37],       // mov qword ptr [rdi], rsi
+            &[0x5D],                   // pop rbp
+            &[0xC3],                   // ret
+        ];
+        assert_eq!(code, expected.concat());
+    }
+
+    #[test]
+    #[should_panic(expected = "Destination register must be 64-bit")]
+    fn load_into_32_bit_register() {
+        assemble(|m| m.load(Width::Word, Reg::EAX, Reg::RDI, 0));
+    }
+}
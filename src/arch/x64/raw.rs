@@ -0,0 +1,50 @@
+use crate::assembler::Assembler;
+
+/// Writes the 32-bit displacement at `disp_offset` relative to the end of the instruction at
+/// `insn_end`, both given as absolute addresses through `abs_addr`
+pub fn write_rel32(
+    bytes: &mut [u8],
+    abs_addr: usize,
+    disp_offset: usize,
+    insn_end: usize,
+    target: usize,
+) {
+    let rel = target as isize - (abs_addr + insn_end) as isize;
+    assert!(
+        (i32::MIN as isize..=i32::MAX as isize).contains(&rel),
+        "Tried to reference address not in range"
+    );
+    write_ne_32(bytes, disp_offset, rel as u32);
+}
+
+pub fn load_const(bytes: &mut [u8], abs_addr: usize, disp_offset: usize, const_offset: usize) {
+    write_rel32(
+        bytes,
+        abs_addr,
+        disp_offset,
+        disp_offset + 4,
+        abs_addr + const_offset * 4,
+    );
+}
+
+pub fn load_global_const(
+    asm: &impl Assembler,
+    abs_addr: usize,
+    bytes: &mut [u8],
+    disp_offset: usize,
+    const_offset: usize,
+) {
+    write_rel32(
+        bytes,
+        abs_addr,
+        disp_offset,
+        disp_offset + 4,
+        asm.global_const_address() + const_offset * 4,
+    );
+}
+
+pub fn write_ne_32(slice: &mut [u8], index: usize, value: u32) {
+    for (offset, byte) in value.to_ne_bytes().into_iter().enumerate() {
+        slice[index + offset] = byte;
+    }
+}
@@ -0,0 +1,98 @@
+#[repr(i8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    EAX = 0,
+    RAX = 32,
+    ECX = 1,
+    RCX = 1 | 32,
+    EDX = 2,
+    RDX = 2 | 32,
+    EBX = 3,
+    RBX = 3 | 32,
+    ESP = 4,
+    RSP = 4 | 32,
+    EBP = 5,
+    RBP = 5 | 32,
+    ESI = 6,
+    RSI = 6 | 32,
+    EDI = 7,
+    RDI = 7 | 32,
+    R8D = 8,
+    R8 = 8 | 32,
+    R9D = 9,
+    R9 = 9 | 32,
+    R10D = 10,
+    R10 = 10 | 32,
+    R11D = 11,
+    R11 = 11 | 32,
+    R12D = 12,
+    R12 = 12 | 32,
+    R13D = 13,
+    R13 = 13 | 32,
+    R14D = 14,
+    R14 = 14 | 32,
+    R15D = 15,
+    R15 = 15 | 32,
+}
+
+pub fn is_64_bit(reg: Reg) -> bool {
+    reg as i8 & 32 != 0
+}
+
+/// Returns the 4-bit register number used in REX prefixes and ModRM bytes
+pub fn number(reg: Reg) -> u8 {
+    reg as u8 & 0xF
+}
+
+/// Returns the 32-bit view of the register
+pub fn low_32(reg: Reg) -> Reg {
+    // Both views only differ in bit 5 of the discriminant
+    unsafe { std::mem::transmute::<i8, Reg>(reg as i8 & !32) }
+}
+
+/// Vector register that is either the 128-bit XMM or the 256-bit YMM view
+#[repr(i8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum VecReg {
+    XMM0 = 0,
+    YMM0 = 32,
+    XMM1 = 1,
+    YMM1 = 1 | 32,
+    XMM2 = 2,
+    YMM2 = 2 | 32,
+    XMM3 = 3,
+    YMM3 = 3 | 32,
+    XMM4 = 4,
+    YMM4 = 4 | 32,
+    XMM5 = 5,
+    YMM5 = 5 | 32,
+    XMM6 = 6,
+    YMM6 = 6 | 32,
+    XMM7 = 7,
+    YMM7 = 7 | 32,
+    XMM8 = 8,
+    YMM8 = 8 | 32,
+    XMM9 = 9,
+    YMM9 = 9 | 32,
+    XMM10 = 10,
+    YMM10 = 10 | 32,
+    XMM11 = 11,
+    YMM11 = 11 | 32,
+    XMM12 = 12,
+    YMM12 = 12 | 32,
+    XMM13 = 13,
+    YMM13 = 13 | 32,
+    XMM14 = 14,
+    YMM14 = 14 | 32,
+    XMM15 = 15,
+    YMM15 = 15 | 32,
+}
+
+pub fn is_256_bit(reg: VecReg) -> bool {
+    reg as i8 & 32 != 0
+}
+
+/// Returns the 4-bit register number used in REX and VEX prefixes and ModRM bytes
+pub fn vec_number(reg: VecReg) -> u8 {
+    reg as u8 & 0xF
+}
@@ -0,0 +1,1500 @@
+use super::{
+    cond::Cond,
+    raw,
+    reg::{is_256_bit, is_64_bit, number, vec_number, Reg, VecReg},
+};
+use crate::assembler::{Assembler, Label, PostOp, Subroutine};
+
+pub struct Routine {
+    pub(super) name: String,
+    pub(super) constants: Vec<u8>,
+    pub(super) code: Vec<u8>,
+    pub(super) post_ops: Vec<Op>,
+    /// Bound labels as code offset and amount of jumps emitted before binding
+    labels: Vec<Option<(usize, usize)>>,
+    jumps: Vec<Jump>,
+}
+
+/// Jump to a local label that is inserted at `offset` when the routine is finalized
+struct Jump {
+    offset: usize,
+    cond: Option<Cond>,
+    label: Label,
+}
+
+impl Jump {
+    fn size(&self, long: bool) -> usize {
+        match (long, self.cond) {
+            (false, _) => 2,
+            (true, None) => 5,
+            (true, Some(_)) => 6,
+        }
+    }
+}
+
+/// Mandatory prefix, opcode map and opcode of a VEX encoded instruction
+#[derive(Clone, Copy)]
+struct Vex {
+    pp: u8,
+    map: u8,
+    w: bool,
+    opcode: u8,
+}
+
+impl Vex {
+    const fn new(pp: u8, map: u8, w: bool, opcode: u8) -> Self {
+        Self { pp, map, w, opcode }
+    }
+}
+
+impl Routine {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            constants: Vec::with_capacity(0),
+            code: Vec::with_capacity(0),
+            post_ops: Vec::with_capacity(0),
+            labels: Vec::with_capacity(0),
+            jumps: Vec::with_capacity(0),
+        }
+    }
+
+    /// Returns from a routine
+    pub fn ret(&mut self) {
+        self.code.push(0xC3);
+    }
+
+    /// Placeholder
+    pub fn nop(&mut self) {
+        self.code.push(0x90);
+    }
+
+    /// Pushes the value of a 64-bit register onto the stack
+    pub fn push(&mut self, src_reg: Reg) {
+        assert!(is_64_bit(src_reg), "Pushed register must be 64-bit");
+        if number(src_reg) >= 8 {
+            self.code.push(0x41);
+        }
+        self.code.push(0x50 | (number(src_reg) & 7));
+    }
+
+    /// Pops the top of the stack into a 64-bit register
+    pub fn pop(&mut self, dst_reg: Reg) {
+        assert!(is_64_bit(dst_reg), "Popped register must be 64-bit");
+        if number(dst_reg) >= 8 {
+            self.code.push(0x41);
+        }
+        self.code.push(0x58 | (number(dst_reg) & 7));
+    }
+
+    /// Moves the value stored in the source register into the destination register
+    pub fn mov_reg(&mut self, dst_reg: Reg, src_reg: Reg) {
+        let bits_64 = is_64_bit(dst_reg);
+        if bits_64 != is_64_bit(src_reg) {
+            panic!("Both registers must be of equal size");
+        }
+        self.op_rr(bits_64, &[0x89], number(src_reg), number(dst_reg));
+    }
+
+    /// Moves the immediate into the specified register using the shortest encoding
+    ///
+    /// 32-bit registers only accept immediates that fit into 32 bits
+    pub fn mov_imm(&mut self, dst_reg: Reg, imm: u64) {
+        let reg = number(dst_reg);
+        if imm <= u32::MAX as u64 {
+            // Writing the 32-bit register zero-extends into the 64-bit register
+            if reg >= 8 {
+                self.code.push(0x41);
+            }
+            self.code.push(0xB8 | (reg & 7));
+            self.code.extend_from_slice(&(imm as u32).to_le_bytes());
+        } else if !is_64_bit(dst_reg) {
+            panic!("Immediate does not fit into 32-bit register");
+        } else if i32::try_from(imm as i64).is_ok() {
+            self.op_rr(true, &[0xC7], 0, reg);
+            self.code.extend_from_slice(&(imm as u32).to_le_bytes());
+        } else {
+            self.code.push(0x48 | (reg >> 3));
+            self.code.push(0xB8 | (reg & 7));
+            self.code.extend_from_slice(&imm.to_le_bytes());
+        }
+    }
+
+    /// Loads the value of address `base_reg + disp` into `dst_reg`
+    pub fn mov_load(&mut self, dst_reg: Reg, base_reg: Reg, disp: i32) {
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        self.op_rm(is_64_bit(dst_reg), &[0x8B], number(dst_reg), base_reg, disp);
+    }
+
+    /// Stores the value of `src_reg` into the address `base_reg + disp`
+    pub fn mov_store(&mut self, base_reg: Reg, disp: i32, src_reg: Reg) {
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        self.op_rm(is_64_bit(src_reg), &[0x89], number(src_reg), base_reg, disp);
+    }
+
+    /// Loads the byte at address `base_reg + disp` zero-extending it into `dst_reg`
+    pub fn movzx8_load(&mut self, dst_reg: Reg, base_reg: Reg, disp: i32) {
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        self.op_rm(false, &[0x0F, 0xB6], number(dst_reg), base_reg, disp);
+    }
+
+    /// Loads the 16-bit value at address `base_reg + disp` zero-extending it into `dst_reg`
+    pub fn movzx16_load(&mut self, dst_reg: Reg, base_reg: Reg, disp: i32) {
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        self.op_rm(false, &[0x0F, 0xB7], number(dst_reg), base_reg, disp);
+    }
+
+    /// Stores the lowest byte of `src_reg` into the address `base_reg + disp`
+    pub fn mov8_store(&mut self, base_reg: Reg, disp: i32, src_reg: Reg) {
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        let (reg, base) = (number(src_reg), number(base_reg));
+        // Without a REX prefix registers 4 to 7 would select AH, CH, DH and BH
+        if reg >= 4 || base >= 8 {
+            self.code.push(0x40 | ((reg >> 3) << 2) | (base >> 3));
+        }
+        self.code.push(0x88);
+        self.modrm_mem(reg, base, disp);
+    }
+
+    /// Stores the lowest 16 bits of `src_reg` into the address `base_reg + disp`
+    pub fn mov16_store(&mut self, base_reg: Reg, disp: i32, src_reg: Reg) {
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        self.code.push(0x66);
+        self.op_rm(false, &[0x89], number(src_reg), base_reg, disp);
+    }
+
+    /// Adds the value of `src_reg` to `dst_reg`
+    pub fn add_reg(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.alu_rr(0x01, dst_reg, src_reg);
+    }
+
+    /// Subtracts the value of `src_reg` from `dst_reg`
+    pub fn sub_reg(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.alu_rr(0x29, dst_reg, src_reg);
+    }
+
+    /// Compares `lhs` with `rhs` setting the flags like `sub_reg`
+    pub fn cmp_reg(&mut self, lhs: Reg, rhs: Reg) {
+        self.alu_rr(0x39, lhs, rhs);
+    }
+
+    /// Multiplies `dst_reg` with `src_reg` keeping the lower half of the result
+    pub fn imul_reg(&mut self, dst_reg: Reg, src_reg: Reg) {
+        let bits_64 = is_64_bit(dst_reg);
+        if bits_64 != is_64_bit(src_reg) {
+            panic!("Both registers must be of equal size");
+        }
+        self.op_rr(bits_64, &[0x0F, 0xAF], number(dst_reg), number(src_reg));
+    }
+
+    /// Computes the bitwise and of `dst_reg` and `src_reg`
+    pub fn and_reg(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.alu_rr(0x21, dst_reg, src_reg);
+    }
+
+    /// Computes the bitwise or of `dst_reg` and `src_reg`
+    pub fn or_reg(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.alu_rr(0x09, dst_reg, src_reg);
+    }
+
+    /// Computes the bitwise exclusive or of `dst_reg` and `src_reg`
+    pub fn xor_reg(&mut self, dst_reg: Reg, src_reg: Reg) {
+        self.alu_rr(0x31, dst_reg, src_reg);
+    }
+
+    /// Adds the sign-extended 32-bit immediate to `dst_reg`
+    pub fn add_imm(&mut self, dst_reg: Reg, imm: i32) {
+        self.alu_imm(0, dst_reg, imm);
+    }
+
+    /// Subtracts the sign-extended 32-bit immediate from `dst_reg`
+    pub fn sub_imm(&mut self, dst_reg: Reg, imm: i32) {
+        self.alu_imm(5, dst_reg, imm);
+    }
+
+    /// Compares `lhs` with the sign-extended 32-bit immediate setting the flags like `sub_imm`
+    pub fn cmp_imm(&mut self, lhs: Reg, imm: i32) {
+        self.alu_imm(7, lhs, imm);
+    }
+
+    /// Creates a new label that has to be bound before the routine is finalized
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds the label to the position of the next instruction
+    pub fn bind(&mut self, label: Label) {
+        let slot = &mut self.labels[label.0];
+        assert!(slot.is_none(), "Tried to bind label twice");
+        *slot = Some((self.code.len(), self.jumps.len()));
+    }
+
+    /// Jumps to a local label using an 8-bit displacement if it is in range
+    pub fn jmp(&mut self, label: Label) {
+        self.jumps.push(Jump {
+            offset: self.code.len(),
+            cond: None,
+            label,
+        });
+    }
+
+    /// Jumps to a local label if the condition holds, using an 8-bit displacement if it is in
+    /// range
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+        self.jumps.push(Jump {
+            offset: self.code.len(),
+            cond: Some(cond),
+            label,
+        });
+    }
+
+    /// Jumps to the absolute address stored in a register
+    pub fn jmp_reg(&mut self, dst_reg: Reg) {
+        assert!(is_64_bit(dst_reg), "Jump register must be 64-bit");
+        self.op_rr(false, &[0xFF], 4, number(dst_reg));
+    }
+
+    /// Jumps to label that must be present in the V-Table
+    pub fn jmp_routine(&mut self, label: String) {
+        self.post_ops.push(Op::Jump {
+            insn_offset: self.code.len(),
+            label,
+        });
+        self.code.extend_from_slice(&[0xE9, 0, 0, 0, 0]);
+    }
+
+    /// Calls label that must be present in the V-Table
+    pub fn call(&mut self, label: String) {
+        self.post_ops.push(Op::Call {
+            insn_offset: self.code.len(),
+            label,
+        });
+        self.code.extend_from_slice(&[0xE8, 0, 0, 0, 0]);
+    }
+
+    /// Calls the absolute address stored in a register
+    pub fn call_reg(&mut self, dst_reg: Reg) {
+        assert!(is_64_bit(dst_reg), "Call register must be 64-bit");
+        self.op_rr(false, &[0xFF], 2, number(dst_reg));
+    }
+
+    /// Loads the value of a constant through a RIP-relative address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn mov_const(&mut self, dst_reg: Reg, offset: usize) {
+        let disp_offset = self.op_rip(is_64_bit(dst_reg), &[0x8B], number(dst_reg));
+        self.post_ops.push(Op::LoadConst {
+            disp_offset,
+            const_offset: offset,
+        });
+    }
+
+    /// Loads the value of a global constant through a RIP-relative address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn mov_global_const(&mut self, dst_reg: Reg, offset: usize) {
+        let disp_offset = self.op_rip(is_64_bit(dst_reg), &[0x8B], number(dst_reg));
+        self.post_ops.push(Op::LoadGlobalConst {
+            disp_offset,
+            const_offset: offset,
+        });
+    }
+
+    /// Moves the lowest 32-bit lane of `src_reg` into `dst_reg`
+    pub fn movss(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF3, 0x10, dst_reg, src_reg);
+    }
+
+    /// Moves the lowest 64-bit lane of `src_reg` into `dst_reg`
+    pub fn movsd(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF2, 0x10, dst_reg, src_reg);
+    }
+
+    /// Loads the 32-bit float at address `base_reg + disp` into `dst_reg` clearing the upper lanes
+    pub fn movss_load(&mut self, dst_reg: VecReg, base_reg: Reg, disp: i32) {
+        self.sse_load(0xF3, 0x10, dst_reg, base_reg, disp);
+    }
+
+    /// Stores the lowest 32-bit lane of `src_reg` into the address `base_reg + disp`
+    pub fn movss_store(&mut self, base_reg: Reg, disp: i32, src_reg: VecReg) {
+        self.sse_load(0xF3, 0x11, src_reg, base_reg, disp);
+    }
+
+    /// Loads the 64-bit float at address `base_reg + disp` into `dst_reg` clearing the upper lane
+    pub fn movsd_load(&mut self, dst_reg: VecReg, base_reg: Reg, disp: i32) {
+        self.sse_load(0xF2, 0x10, dst_reg, base_reg, disp);
+    }
+
+    /// Stores the lowest 64-bit lane of `src_reg` into the address `base_reg + disp`
+    pub fn movsd_store(&mut self, base_reg: Reg, disp: i32, src_reg: VecReg) {
+        self.sse_load(0xF2, 0x11, src_reg, base_reg, disp);
+    }
+
+    /// Loads a 32-bit float constant through a RIP-relative address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn movss_const(&mut self, dst_reg: VecReg, offset: usize) {
+        self.sse_const(0xF3, 0x10, dst_reg, offset, false);
+    }
+
+    /// Loads a 32-bit float global constant through a RIP-relative address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn movss_global_const(&mut self, dst_reg: VecReg, offset: usize) {
+        self.sse_const(0xF3, 0x10, dst_reg, offset, true);
+    }
+
+    /// Loads a 64-bit float constant through a RIP-relative address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn movsd_const(&mut self, dst_reg: VecReg, offset: usize) {
+        self.sse_const(0xF2, 0x10, dst_reg, offset, false);
+    }
+
+    /// Loads a 64-bit float global constant through a RIP-relative address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn movsd_global_const(&mut self, dst_reg: VecReg, offset: usize) {
+        self.sse_const(0xF2, 0x10, dst_reg, offset, true);
+    }
+
+    /// Moves the bits of a general purpose register into the lowest lane of `dst_reg` clearing
+    /// the upper lanes
+    pub fn mov_to_vec(&mut self, dst_reg: VecReg, src_reg: Reg) {
+        assert!(!is_256_bit(dst_reg), "Destination register must be 128-bit");
+        self.code.push(0x66);
+        self.op_rr(
+            is_64_bit(src_reg),
+            &[0x0F, 0x6E],
+            vec_number(dst_reg),
+            number(src_reg),
+        );
+    }
+
+    /// Moves the bits of the lowest lane of `src_reg` into a general purpose register
+    pub fn mov_from_vec(&mut self, dst_reg: Reg, src_reg: VecReg) {
+        assert!(!is_256_bit(src_reg), "Source register must be 128-bit");
+        self.code.push(0x66);
+        self.op_rr(
+            is_64_bit(dst_reg),
+            &[0x0F, 0x7E],
+            vec_number(src_reg),
+            number(dst_reg),
+        );
+    }
+
+    /// Adds the lowest 32-bit float of `src_reg` to `dst_reg`
+    pub fn addss(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF3, 0x58, dst_reg, src_reg);
+    }
+
+    /// Subtracts the lowest 32-bit float of `src_reg` from `dst_reg`
+    pub fn subss(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF3, 0x5C, dst_reg, src_reg);
+    }
+
+    /// Multiplies the lowest 32-bit float of `dst_reg` by `src_reg`
+    pub fn mulss(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF3, 0x59, dst_reg, src_reg);
+    }
+
+    /// Divides the lowest 32-bit float of `dst_reg` by `src_reg`
+    pub fn divss(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF3, 0x5E, dst_reg, src_reg);
+    }
+
+    /// Computes the square root of the lowest 32-bit float of `src_reg`
+    pub fn sqrtss(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF3, 0x51, dst_reg, src_reg);
+    }
+
+    /// Adds the lowest 64-bit float of `src_reg` to `dst_reg`
+    pub fn addsd(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF2, 0x58, dst_reg, src_reg);
+    }
+
+    /// Subtracts the lowest 64-bit float of `src_reg` from `dst_reg`
+    pub fn subsd(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF2, 0x5C, dst_reg, src_reg);
+    }
+
+    /// Multiplies the lowest 64-bit float of `dst_reg` by `src_reg`
+    pub fn mulsd(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF2, 0x59, dst_reg, src_reg);
+    }
+
+    /// Divides the lowest 64-bit float of `dst_reg` by `src_reg`
+    pub fn divsd(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF2, 0x5E, dst_reg, src_reg);
+    }
+
+    /// Computes the square root of the lowest 64-bit float of `src_reg`
+    pub fn sqrtsd(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF2, 0x51, dst_reg, src_reg);
+    }
+
+    /// Converts the 32-bit float of `src_reg` into a 64-bit float
+    pub fn cvtss2sd(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF3, 0x5A, dst_reg, src_reg);
+    }
+
+    /// Converts the 64-bit float of `src_reg` into a 32-bit float
+    pub fn cvtsd2ss(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        self.sse_arith(0xF2, 0x5A, dst_reg, src_reg);
+    }
+
+    /// Converts the signed integer of `src_reg` into a 32-bit float
+    pub fn cvtsi2ss(&mut self, dst_reg: VecReg, src_reg: Reg) {
+        assert!(!is_256_bit(dst_reg), "Destination register must be 128-bit");
+        self.code.push(0xF3);
+        self.op_rr(
+            is_64_bit(src_reg),
+            &[0x0F, 0x2A],
+            vec_number(dst_reg),
+            number(src_reg),
+        );
+    }
+
+    /// Converts the signed integer of `src_reg` into a 64-bit float
+    pub fn cvtsi2sd(&mut self, dst_reg: VecReg, src_reg: Reg) {
+        assert!(!is_256_bit(dst_reg), "Destination register must be 128-bit");
+        self.code.push(0xF2);
+        self.op_rr(
+            is_64_bit(src_reg),
+            &[0x0F, 0x2A],
+            vec_number(dst_reg),
+            number(src_reg),
+        );
+    }
+
+    /// Converts the 32-bit float of `src_reg` into a signed integer rounding towards zero
+    pub fn cvttss2si(&mut self, dst_reg: Reg, src_reg: VecReg) {
+        assert!(!is_256_bit(src_reg), "Source register must be 128-bit");
+        self.code.push(0xF3);
+        self.op_rr(
+            is_64_bit(dst_reg),
+            &[0x0F, 0x2C],
+            number(dst_reg),
+            vec_number(src_reg),
+        );
+    }
+
+    /// Converts the 64-bit float of `src_reg` into a signed integer rounding towards zero
+    pub fn cvttsd2si(&mut self, dst_reg: Reg, src_reg: VecReg) {
+        assert!(!is_256_bit(src_reg), "Source register must be 128-bit");
+        self.code.push(0xF2);
+        self.op_rr(
+            is_64_bit(dst_reg),
+            &[0x0F, 0x2C],
+            number(dst_reg),
+            vec_number(src_reg),
+        );
+    }
+
+    /// Compares the lowest 32-bit floats setting ZF, PF and CF
+    ///
+    /// Unordered operands set all three flags, so the unsigned conditions apply
+    pub fn ucomiss(&mut self, lhs: VecReg, rhs: VecReg) {
+        self.sse_arith(0, 0x2E, lhs, rhs);
+    }
+
+    /// Compares the lowest 64-bit floats setting ZF, PF and CF
+    ///
+    /// Unordered operands set all three flags, so the unsigned conditions apply
+    pub fn ucomisd(&mut self, lhs: VecReg, rhs: VecReg) {
+        self.sse_arith(0x66, 0x2E, lhs, rhs);
+    }
+
+    /// Adds the packed 32-bit floats of `lhs` and `rhs`
+    pub fn vaddps(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(0, 1, false, 0x58), dst_reg, lhs, rhs);
+    }
+
+    /// Adds the packed 64-bit floats of `lhs` and `rhs`
+    pub fn vaddpd(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(1, 1, false, 0x58), dst_reg, lhs, rhs);
+    }
+
+    /// Subtracts the packed 32-bit floats of `rhs` from `lhs`
+    pub fn vsubps(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(0, 1, false, 0x5C), dst_reg, lhs, rhs);
+    }
+
+    /// Subtracts the packed 64-bit floats of `rhs` from `lhs`
+    pub fn vsubpd(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(1, 1, false, 0x5C), dst_reg, lhs, rhs);
+    }
+
+    /// Multiplies the packed 32-bit floats of `lhs` and `rhs`
+    pub fn vmulps(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(0, 1, false, 0x59), dst_reg, lhs, rhs);
+    }
+
+    /// Multiplies the packed 64-bit floats of `lhs` and `rhs`
+    pub fn vmulpd(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(1, 1, false, 0x59), dst_reg, lhs, rhs);
+    }
+
+    /// Divides the packed 32-bit floats of `lhs` by `rhs`
+    pub fn vdivps(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(0, 1, false, 0x5E), dst_reg, lhs, rhs);
+    }
+
+    /// Divides the packed 64-bit floats of `lhs` by `rhs`
+    pub fn vdivpd(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(1, 1, false, 0x5E), dst_reg, lhs, rhs);
+    }
+
+    /// Computes the bitwise exclusive or of `lhs` and `rhs`, mostly used to clear a register
+    pub fn vxorps(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(0, 1, false, 0x57), dst_reg, lhs, rhs);
+    }
+
+    /// Adds the product of the packed 32-bit floats of `lhs` and `rhs` to `dst_reg` with a single
+    /// rounding
+    pub fn vfmadd231ps(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(1, 2, false, 0xB8), dst_reg, lhs, rhs);
+    }
+
+    /// Adds the product of the packed 64-bit floats of `lhs` and `rhs` to `dst_reg` with a single
+    /// rounding
+    pub fn vfmadd231pd(&mut self, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        self.vex_arith(Vex::new(1, 2, true, 0xB8), dst_reg, lhs, rhs);
+    }
+
+    /// Moves all lanes of `src_reg` into `dst_reg`
+    pub fn vmovups(&mut self, dst_reg: VecReg, src_reg: VecReg) {
+        assert!(
+            is_256_bit(dst_reg) == is_256_bit(src_reg),
+            "Both registers must be of equal size"
+        );
+        self.vex_rr(
+            Vex::new(0, 1, false, 0x10),
+            is_256_bit(dst_reg),
+            vec_number(dst_reg),
+            0,
+            vec_number(src_reg),
+        );
+    }
+
+    /// Loads the possibly unaligned vector at address `base_reg + disp` into `dst_reg`
+    pub fn vmovups_load(&mut self, dst_reg: VecReg, base_reg: Reg, disp: i32) {
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        self.vex_rm(
+            Vex::new(0, 1, false, 0x10),
+            is_256_bit(dst_reg),
+            vec_number(dst_reg),
+            base_reg,
+            disp,
+        );
+    }
+
+    /// Stores `src_reg` into the possibly unaligned address `base_reg + disp`
+    pub fn vmovups_store(&mut self, base_reg: Reg, disp: i32, src_reg: VecReg) {
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        self.vex_rm(
+            Vex::new(0, 1, false, 0x11),
+            is_256_bit(src_reg),
+            vec_number(src_reg),
+            base_reg,
+            disp,
+        );
+    }
+
+    /// Loads a vector constant through a RIP-relative address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn vmovups_const(&mut self, dst_reg: VecReg, offset: usize) {
+        let disp_offset = self.vex_rip(
+            Vex::new(0, 1, false, 0x10),
+            is_256_bit(dst_reg),
+            vec_number(dst_reg),
+        );
+        self.post_ops.push(Op::LoadConst {
+            disp_offset,
+            const_offset: offset,
+        });
+    }
+
+    /// Loads a vector global constant through a RIP-relative address
+    ///
+    /// `offset` is the offset of the constant, represented in 32-bit steps
+    pub fn vmovups_global_const(&mut self, dst_reg: VecReg, offset: usize) {
+        let disp_offset = self.vex_rip(
+            Vex::new(0, 1, false, 0x10),
+            is_256_bit(dst_reg),
+            vec_number(dst_reg),
+        );
+        self.post_ops.push(Op::LoadGlobalConst {
+            disp_offset,
+            const_offset: offset,
+        });
+    }
+
+    /// Clears the upper lanes of all YMM registers, which avoids penalties when switching from
+    /// AVX to legacy SSE code
+    pub fn vzeroupper(&mut self) {
+        self.code.extend_from_slice(&[0xC5, 0xF8, 0x77]);
+    }
+
+    /// Stores a 32-bit constant in front of the code
+    ///
+    /// Return the index of the constant
+    pub fn const_32(&mut self, value: u32) -> usize {
+        let index = self.constants.len() / 4;
+        for byte in value.to_ne_bytes() {
+            self.constants.push(byte);
+        }
+        index
+    }
+
+    /// Stores a 64-bit constant in front of the code
+    ///
+    /// Return the index of the constant
+    pub fn const_64(&mut self, value: u64) -> usize {
+        let index = self.constants.len() / 4;
+        for byte in value.to_ne_bytes() {
+            self.constants.push(byte);
+        }
+        index
+    }
+
+    /// Stores a 128-bit vector constant in front of the code
+    ///
+    /// Unlike the global constants of `Asm::const_128` it is not aligned, as routines are placed
+    /// at arbitrary offsets. The `vmovups` loads do not need alignment.
+    ///
+    /// Return the index of the constant
+    pub fn const_128(&mut self, value: [u8; 16]) -> usize {
+        let index = self.constants.len() / 4;
+        self.constants.extend_from_slice(&value);
+        index
+    }
+
+    /// Stores a 256-bit vector constant in front of the code
+    ///
+    /// Unlike the global constants of `Asm::const_256` it is not aligned, as routines are placed
+    /// at arbitrary offsets. The `vmovups` loads do not need alignment.
+    ///
+    /// Return the index of the constant
+    pub fn const_256(&mut self, value: [u8; 32]) -> usize {
+        let index = self.constants.len() / 4;
+        self.constants.extend_from_slice(&value);
+        index
+    }
+
+    /// Inserts the jumps to local labels choosing the shortest displacement for each of them
+    ///
+    /// This is done by the assembler when the routine is pushed
+    pub fn finalize(&mut self) {
+        if self.jumps.is_empty() {
+            return;
+        }
+        let jumps = std::mem::take(&mut self.jumps);
+        let mut long = vec![false; jumps.len()];
+        // `prefix[n]` is the size of the first `n` jumps
+        let mut prefix = vec![0; jumps.len() + 1];
+        loop {
+            for (index, jump) in jumps.iter().enumerate() {
+                prefix[index + 1] = prefix[index] + jump.size(long[index]);
+            }
+            let mut changed = false;
+            for (index, jump) in jumps.iter().enumerate() {
+                if long[index] {
+                    continue;
+                }
+                let end = jump.offset + prefix[index] + 2;
+                let rel = self.label_position(jump.label, &prefix) as isize - end as isize;
+                if i8::try_from(rel).is_err() {
+                    long[index] = true;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        let mut code = Vec::with_capacity(self.code.len() + prefix[jumps.len()]);
+        let mut last = 0;
+        for (index, jump) in jumps.iter().enumerate() {
+            code.extend_from_slice(&self.code[last..jump.offset]);
+            last = jump.offset;
+            let end = code.len() + jump.size(long[index]);
+            let rel = self.label_position(jump.label, &prefix) as isize - end as isize;
+            match (long[index], jump.cond) {
+                (false, None) => code.extend_from_slice(&[0xEB, rel as u8]),
+                (false, Some(cond)) => code.extend_from_slice(&[0x70 | cond as u8, rel as u8]),
+                (true, None) => {
+                    code.push(0xE9);
+                    code.extend_from_slice(&(rel as i32).to_le_bytes());
+                }
+                (true, Some(cond)) => {
+                    code.extend_from_slice(&[0x0F, 0x80 | cond as u8]);
+                    code.extend_from_slice(&(rel as i32).to_le_bytes());
+                }
+            }
+        }
+        code.extend_from_slice(&self.code[last..]);
+        let relocate =
+            |offset: usize| offset + prefix[jumps.partition_point(|jump| jump.offset <= offset)];
+        for op in &mut self.post_ops {
+            op.relocate(relocate);
+        }
+        for index in 0..self.labels.len() {
+            let position = self.label_position(Label(index), &prefix);
+            self.labels[index] = Some((position, 0));
+        }
+        self.code = code;
+    }
+
+    fn label_position(&self, label: Label, prefix: &[usize]) -> usize {
+        let Some((offset, jumps)) = self.labels[label.0] else {
+            panic!("Tried to jump to unbound label");
+        };
+        offset + prefix[jumps]
+    }
+
+    fn alu_rr(&mut self, opcode: u8, dst_reg: Reg, src_reg: Reg) {
+        let bits_64 = is_64_bit(dst_reg);
+        if bits_64 != is_64_bit(src_reg) {
+            panic!("Both registers must be of equal size");
+        }
+        self.op_rr(bits_64, &[opcode], number(src_reg), number(dst_reg));
+    }
+
+    fn alu_imm(&mut self, ext: u8, dst_reg: Reg, imm: i32) {
+        if let Ok(imm) = i8::try_from(imm) {
+            self.op_rr(is_64_bit(dst_reg), &[0x83], ext, number(dst_reg));
+            self.code.push(imm as u8);
+        } else {
+            self.op_rr(is_64_bit(dst_reg), &[0x81], ext, number(dst_reg));
+            self.code.extend_from_slice(&imm.to_le_bytes());
+        }
+    }
+
+    /// Emits a scalar SSE instruction operating on two 128-bit registers
+    fn sse_arith(&mut self, prefix: u8, opcode: u8, dst_reg: VecReg, src_reg: VecReg) {
+        assert!(
+            !is_256_bit(dst_reg) && !is_256_bit(src_reg),
+            "SSE registers must be 128-bit"
+        );
+        if prefix != 0 {
+            self.code.push(prefix);
+        }
+        self.op_rr(
+            false,
+            &[0x0F, opcode],
+            vec_number(dst_reg),
+            vec_number(src_reg),
+        );
+    }
+
+    fn sse_load(&mut self, prefix: u8, opcode: u8, reg: VecReg, base_reg: Reg, disp: i32) {
+        assert!(!is_256_bit(reg), "SSE registers must be 128-bit");
+        assert!(is_64_bit(base_reg), "Base register must be 64-bit");
+        self.code.push(prefix);
+        self.op_rm(false, &[0x0F, opcode], vec_number(reg), base_reg, disp);
+    }
+
+    fn sse_const(&mut self, prefix: u8, opcode: u8, dst_reg: VecReg, offset: usize, global: bool) {
+        assert!(!is_256_bit(dst_reg), "SSE registers must be 128-bit");
+        self.code.push(prefix);
+        let disp_offset = self.op_rip(false, &[0x0F, opcode], vec_number(dst_reg));
+        self.post_ops.push(if global {
+            Op::LoadGlobalConst {
+                disp_offset,
+                const_offset: offset,
+            }
+        } else {
+            Op::LoadConst {
+                disp_offset,
+                const_offset: offset,
+            }
+        });
+    }
+
+    /// Emits a VEX encoded instruction with three vector registers of equal size
+    fn vex_arith(&mut self, vex: Vex, dst_reg: VecReg, lhs: VecReg, rhs: VecReg) {
+        let l = is_256_bit(dst_reg);
+        assert!(
+            l == is_256_bit(lhs) && l == is_256_bit(rhs),
+            "All registers must be of equal size"
+        );
+        self.vex_rr(
+            vex,
+            l,
+            vec_number(dst_reg),
+            vec_number(lhs),
+            vec_number(rhs),
+        );
+    }
+
+    /// Emits the VEX prefix followed by the opcode, using the 2-byte form where possible
+    fn vex(&mut self, vex: Vex, l: bool, reg: u8, vvvv: u8, base: u8) {
+        let r = (!reg >> 3) & 1;
+        let b = (!base >> 3) & 1;
+        let last = ((!vvvv & 0xF) << 3) | ((l as u8) << 2) | vex.pp;
+        if !vex.w && vex.map == 1 && b == 1 {
+            self.code.push(0xC5);
+            self.code.push((r << 7) | last);
+        } else {
+            self.code.push(0xC4);
+            self.code.push((r << 7) | (1 << 6) | (b << 5) | vex.map);
+            self.code.push(((vex.w as u8) << 7) | last);
+        }
+        self.code.push(vex.opcode);
+    }
+
+    fn vex_rr(&mut self, vex: Vex, l: bool, reg: u8, vvvv: u8, rm: u8) {
+        self.vex(vex, l, reg, vvvv, rm);
+        self.code.push(0xC0 | ((reg & 7) << 3) | (rm & 7));
+    }
+
+    fn vex_rm(&mut self, vex: Vex, l: bool, reg: u8, base_reg: Reg, disp: i32) {
+        let base = number(base_reg);
+        self.vex(vex, l, reg, 0, base);
+        self.modrm_mem(reg, base, disp);
+    }
+
+    /// Emits a VEX encoded instruction with a RIP-relative memory operand
+    ///
+    /// Returns the offset of the displacement which has to be the end of the instruction
+    fn vex_rip(&mut self, vex: Vex, l: bool, reg: u8) -> usize {
+        self.vex(vex, l, reg, 0, 0);
+        self.code.push(0x05 | ((reg & 7) << 3));
+        let disp_offset = self.code.len();
+        self.code.extend_from_slice(&[0; 4]);
+        disp_offset
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, base: u8) {
+        let rex = 0x40 | ((w as u8) << 3) | ((reg >> 3) << 2) | (base >> 3);
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+    }
+
+    /// Emits `opcode` with a register operand in the `rm` field
+    fn op_rr(&mut self, w: bool, opcode: &[u8], reg: u8, rm: u8) {
+        self.rex(w, reg, rm);
+        self.code.extend_from_slice(opcode);
+        self.code.push(0xC0 | ((reg & 7) << 3) | (rm & 7));
+    }
+
+    /// Emits `opcode` with the memory operand `[base_reg + disp]` in the `rm` field
+    fn op_rm(&mut self, w: bool, opcode: &[u8], reg: u8, base_reg: Reg, disp: i32) {
+        let base = number(base_reg);
+        self.rex(w, reg, base);
+        self.code.extend_from_slice(opcode);
+        self.modrm_mem(reg, base, disp);
+    }
+
+    /// Emits `opcode` with a RIP-relative memory operand
+    ///
+    /// Returns the offset of the displacement which has to be the end of the instruction
+    fn op_rip(&mut self, w: bool, opcode: &[u8], reg: u8) -> usize {
+        self.rex(w, reg, 0);
+        self.code.extend_from_slice(opcode);
+        self.code.push(0x05 | ((reg & 7) << 3));
+        let disp_offset = self.code.len();
+        self.code.extend_from_slice(&[0; 4]);
+        disp_offset
+    }
+
+    fn modrm_mem(&mut self, reg: u8, base: u8, disp: i32) {
+        // RBP and R13 can only be addressed with a displacement
+        let mode = if disp == 0 && base & 7 != 5 {
+            0
+        } else if i8::try_from(disp).is_ok() {
+            1
+        } else {
+            2
+        };
+        self.code.push((mode << 6) | ((reg & 7) << 3) | (base & 7));
+        // RSP and R12 can only be addressed through a SIB byte
+        if base & 7 == 4 {
+            self.code.push(0x24);
+        }
+        match mode {
+            1 => self.code.push(disp as u8),
+            2 => self.code.extend_from_slice(&disp.to_le_bytes()),
+            _ => {}
+        }
+    }
+}
+
+impl Subroutine for Routine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn constants(&self) -> &[u8] {
+        &self.constants
+    }
+
+    fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    fn process(
+        &self,
+        assembler: &impl Assembler,
+        abs_addr: usize,
+        code_offset: usize,
+        bytes: &mut [u8],
+    ) {
+        for op in &self.post_ops {
+            op.process(assembler, abs_addr, code_offset, bytes);
+        }
+    }
+}
+
+pub enum Op {
+    Jump {
+        insn_offset: usize,
+        label: String,
+    },
+    Call {
+        insn_offset: usize,
+        label: String,
+    },
+    LoadConst {
+        disp_offset: usize,
+        const_offset: usize,
+    },
+    LoadGlobalConst {
+        disp_offset: usize,
+        const_offset: usize,
+    },
+}
+
+impl Op {
+    fn relocate(&mut self, relocate: impl Fn(usize) -> usize) {
+        match self {
+            Self::Jump { insn_offset, .. } | Self::Call { insn_offset, .. } => {
+                *insn_offset = relocate(*insn_offset);
+            }
+            Self::LoadConst { disp_offset, .. } | Self::LoadGlobalConst { disp_offset, .. } => {
+                *disp_offset = relocate(*disp_offset);
+            }
+        }
+    }
+}
+
+impl PostOp for Op {
+    fn process(
+        &self,
+        assembler: &impl Assembler,
+        abs_addr: usize,
+        code_offset: usize,
+        bytes: &mut [u8],
+    ) {
+        match self {
+            Self::Jump { insn_offset, label } | Self::Call { insn_offset, label } => {
+                let Some(addr) = assembler.get_label_address(label) else {
+                    panic!("Tried to branch to non-existent label");
+                };
+                let insn_offset = code_offset + insn_offset;
+                raw::write_rel32(bytes, abs_addr, insn_offset + 1, insn_offset + 5, addr);
+            }
+            Self::LoadConst {
+                disp_offset,
+                const_offset,
+            } => {
+                raw::load_const(bytes, abs_addr, code_offset + disp_offset, *const_offset);
+            }
+            Self::LoadGlobalConst {
+                disp_offset,
+                const_offset,
+            } => {
+                raw::load_global_const(
+                    assembler,
+                    abs_addr,
+                    bytes,
+                    code_offset + disp_offset,
+                    *const_offset,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x64::asm::Asm;
+
+    fn encode(build: impl FnOnce(&mut Routine)) -> Vec<u8> {
+        let mut routine = Routine::new("test".to_string());
+        build(&mut routine);
+        routine.finalize();
+        routine.code
+    }
+
+    #[test]
+    fn integer_instructions() {
+        let code = encode(|r| {
+            r.push(Reg::RBX);
+            r.push(Reg::R12);
+            r.pop(Reg::R15);
+            r.mov_reg(Reg::RAX, Reg::RCX);
+            r.mov_reg(Reg::R9D, Reg::EAX);
+            r.mov_reg(Reg::RDI, Reg::R14);
+            r.mov_imm(Reg::EAX, 0x12345678);
+            r.mov_imm(Reg::R10, 0xFFFFFFFF);
+            r.mov_imm(Reg::RCX, -2i64 as u64);
+            r.mov_imm(Reg::R11, 0x123456789);
+            r.add_reg(Reg::RAX, Reg::RSI);
+            r.sub_reg(Reg::R8, Reg::RDX);
+            r.cmp_reg(Reg::ECX, Reg::R13D);
+            r.imul_reg(Reg::RAX, Reg::R10);
+            r.and_reg(Reg::RDI, Reg::RSI);
+            r.or_reg(Reg::R11D, Reg::EAX);
+            r.xor_reg(Reg::EAX, Reg::EAX);
+            r.add_imm(Reg::RSP, 8);
+            r.sub_imm(Reg::RSP, 0x1000);
+            r.cmp_imm(Reg::R9, -128);
+            r.jmp_reg(Reg::R11);
+            r.call_reg(Reg::RAX);
+        });
+        let expected: &[&[u8]] = &[
+            &[0x53],                                                       // push rbx
+            &[0x41, 0x54],                                                 // push r12
+            &[0x41, 0x5F],                                                 // pop r15
+            &[0x48, 0x89, 0xC8],                                           // mov rax, rcx
+            &[0x41, 0x89, 0xC1],                                           // mov r9d, eax
+            &[0x4C, 0x89, 0xF7],                                           // mov rdi, r14
+            &[0xB8, 0x78, 0x56, 0x34, 0x12],                               // mov eax, 0x12345678
+            &[0x41, 0xBA, 0xFF, 0xFF, 0xFF, 0xFF],                         // mov r10d, 0xFFFFFFFF
+            &[0x48, 0xC7, 0xC1, 0xFE, 0xFF, 0xFF, 0xFF],                   // mov rcx, -2
+            &[0x49, 0xBB, 0x89, 0x67, 0x45, 0x23, 0x01, 0x00, 0x00, 0x00], // movabs r11, 0x123456789
+            &[0x48, 0x01, 0xF0],                                           // add rax, rsi
+            &[0x49, 0x29, 0xD0],                                           // sub r8, rdx
+            &[0x44, 0x39, 0xE9],                                           // cmp ecx, r13d
+            &[0x49, 0x0F, 0xAF, 0xC2],                                     // imul rax, r10
+            &[0x48, 0x21, 0xF7],                                           // and rdi, rsi
+            &[0x41, 0x09, 0xC3],                                           // or r11d, eax
+            &[0x31, 0xC0],                                                 // xor eax, eax
+            &[0x48, 0x83, 0xC4, 0x08],                                     // add rsp, 8
+            &[0x48, 0x81, 0xEC, 0x00, 0x10, 0x00, 0x00],                   // sub rsp, 0x1000
+            &[0x49, 0x83, 0xF9, 0x80],                                     // cmp r9, -128
+            &[0x41, 0xFF, 0xE3],                                           // jmp r11
+            &[0xFF, 0xD0],                                                 // call rax
+        ];
+        assert_eq!(code, expected.concat());
+    }
+
+    #[test]
+    fn memory_operands() {
+        let code = encode(|r| {
+            // RSP and R12 need a SIB byte, RBP and R13 need a displacement even if it is zero
+            r.mov_load(Reg::RAX, Reg::RSP, 0);
+            r.mov_load(Reg::RAX, Reg::RSP, 8);
+            r.mov_load(Reg::RAX, Reg::R12, -0x200);
+            r.mov_load(Reg::RCX, Reg::RBP, 0);
+            r.mov_load(Reg::ECX, Reg::R13, 0);
+            r.mov_load(Reg::RDX, Reg::RBP, -8);
+            r.mov_load(Reg::RSI, Reg::RDI, 0);
+            r.mov_load(Reg::R8, Reg::R15, 0x12345);
+            r.mov_store(Reg::RSP, 16, Reg::R9);
+            r.mov_store(Reg::R12, 0, Reg::EAX);
+            r.movzx8_load(Reg::EAX, Reg::RSI, 1);
+            r.movzx16_load(Reg::R10D, Reg::R12, 0);
+            // SIL and DIL need a REX prefix, which would select AH and BH otherwise
+            r.mov8_store(Reg::RDI, 0, Reg::RAX);
+            r.mov8_store(Reg::RAX, 0, Reg::RSI);
+            r.mov8_store(Reg::RAX, 1, Reg::RDI);
+            r.mov8_store(Reg::RSP, 0, Reg::RBX);
+            r.mov8_store(Reg::R13, 0, Reg::R8);
+            r.mov16_store(Reg::RBP, -2, Reg::RCX);
+            r.mov16_store(Reg::R8, 0, Reg::R9);
+        });
+        let expected: &[&[u8]] = &[
+            &[0x48, 0x8B, 0x04, 0x24],       // mov rax, qword ptr [rsp]
+            &[0x48, 0x8B, 0x44, 0x24, 0x08], // mov rax, qword ptr [rsp + 8]
+            &[0x49, 0x8B, 0x84, 0x24, 0x00, 0xFE, 0xFF, 0xFF], // mov rax, qword ptr [r12 - 0x200]
+            &[0x48, 0x8B, 0x4D, 0x00],       // mov rcx, qword ptr [rbp]
+            &[0x41, 0x8B, 0x4D, 0x00],       // mov ecx, dword ptr [r13]
+            &[0x48, 0x8B, 0x55, 0xF8],       // mov rdx, qword ptr [rbp - 8]
+            &[0x48, 0x8B, 0x37],             // mov rsi, qword ptr [rdi]
+            &[0x4D, 0x8B, 0x87, 0x45, 0x23, 0x01, 0x00], // mov r8, qword ptr [r15 + 0x12345]
+            &[0x4C, 0x89, 0x4C, 0x24, 0x10], // mov qword ptr [rsp + 16], r9
+            &[0x41, 0x89, 0x04, 0x24],       // mov dword ptr [r12], eax
+            &[0x0F, 0xB6, 0x46, 0x01],       // movzx eax, byte ptr [rsi + 1]
+            &[0x45, 0x0F, 0xB7, 0x14, 0x24], // movzx r10d, word ptr [r12]
+            &[0x88, 0x07],                   // mov byte ptr [rdi], al
+            &[0x40, 0x88, 0x30],             // mov byte ptr [rax], sil
+            &[0x40, 0x88, 0x78, 0x01],       // mov byte ptr [rax + 1], dil
+            &[0x88, 0x1C, 0x24],             // mov byte ptr [rsp], bl
+            &[0x45, 0x88, 0x45, 0x00],       // mov byte ptr [r13], r8b
+            &[0x66, 0x89, 0x4D, 0xFE],       // mov word ptr [rbp - 2], cx
+            &[0x66, 0x45, 0x89, 0x08],       // mov word ptr [r8], r9w
+        ];
+        assert_eq!(code, expected.concat());
+    }
+
+    /// Conditional jump over `nops` bytes followed by a jump back to the start
+    fn loop_with_padding(nops: usize) -> Vec<u8> {
+        encode(|r| {
+            let start = r.new_label();
+            let end = r.new_label();
+            r.bind(start);
+            r.jcc(Cond::NE, end);
+            for _ in 0..nops {
+                r.nop();
+            }
+            r.jmp(start);
+            r.bind(end);
+            r.ret();
+        })
+    }
+
+    #[test]
+    fn short_jumps() {
+        // The backward jump reaches exactly -128
+        let code = loop_with_padding(124);
+        assert_eq!(code[..2], [0x75, 126]);
+        assert_eq!(code[126..], [0xEB, 0x80, 0xC3]);
+    }
+
+    #[test]
+    fn relaxed_jumps() {
+        // One more byte moves the backward jump out of range, which pushes the forward jump out
+        // of range as well
+        let code = loop_with_padding(125);
+        assert_eq!(code[..6], [0x0F, 0x85, 130, 0, 0, 0]);
+        assert!(code[6..131].iter().all(|&byte| byte == 0x90));
+        assert_eq!(code[131..], [0xE9, 0x78, 0xFF, 0xFF, 0xFF, 0xC3]);
+    }
+
+    #[test]
+    fn rip_relative_displacements() {
+        let mut asm = Asm::default();
+        let global = asm.const_64(0x1234);
+        let mut main = Routine::new("main".to_string());
+        let local = main.const_32(0x5678);
+        let skip = main.new_label();
+        // The relaxed jump moves the instructions behind it
+        main.jmp(skip);
+        for _ in 0..200 {
+            main.nop();
+        }
+        main.bind(skip);
+        main.mov_const(Reg::RAX, local);
+        main.mov_global_const(Reg::R9, global);
+        main.call("other".to_string());
+        main.ret();
+        asm.push_routine(main);
+        let mut other = Routine::new("other".to_string());
+        other.ret();
+        asm.push_routine(other);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let main = vtable["main"];
+        let other = vtable["other"] as i32;
+        let insns = main + 205;
+        let disp = |offset: usize| i32::from_le_bytes(code[offset..offset + 4].try_into().unwrap());
+        assert_eq!(code[main..main + 5], [0xE9, 200, 0, 0, 0]);
+        // mov rax, qword ptr [rip + disp]
+        assert_eq!(code[insns..insns + 3], [0x48, 0x8B, 0x05]);
+        assert_eq!(disp(insns + 3), (main - 4) as i32 - (insns + 7) as i32);
+        // mov r9, qword ptr [rip + disp]
+        assert_eq!(code[insns + 7..insns + 10], [0x4C, 0x8B, 0x0D]);
+        assert_eq!(disp(insns + 10), -((insns + 14) as i32));
+        // call other
+        assert_eq!(code[insns + 14], 0xE8);
+        assert_eq!(disp(insns + 15), other - (insns + 19) as i32);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn runs_natively() {
+        let mut asm = Asm::default();
+        let global = asm.const_64(1000);
+        // sum(n, m) = 1 + ... + n + square(m) + 1000
+        let mut sum = Routine::new("sum".to_string());
+        let top = sum.new_label();
+        let done = sum.new_label();
+        sum.mov_imm(Reg::RAX, 0);
+        sum.bind(top);
+        sum.cmp_imm(Reg::RDI, 0);
+        sum.jcc(Cond::E, done);
+        sum.add_reg(Reg::RAX, Reg::RDI);
+        sum.sub_imm(Reg::RDI, 1);
+        // Forces the backward jump to use a 32-bit displacement
+        for _ in 0..200 {
+            sum.nop();
+        }
+        sum.jmp(top);
+        sum.bind(done);
+        // Keeps the partial sum and aligns the stack to 16 bytes for the call
+        sum.push(Reg::RAX);
+        sum.mov_reg(Reg::RDI, Reg::RSI);
+        sum.call("square".to_string());
+        sum.pop(Reg::RCX);
+        sum.add_reg(Reg::RAX, Reg::RCX);
+        sum.mov_global_const(Reg::RCX, global);
+        sum.add_reg(Reg::RAX, Reg::RCX);
+        sum.ret();
+        asm.push_routine(sum);
+        let mut square = Routine::new("square".to_string());
+        square.mov_reg(Reg::RAX, Reg::RDI);
+        square.imul_reg(Reg::RAX, Reg::RDI);
+        square.ret();
+        asm.push_routine(square);
+
+        let vtable = asm.jit().unwrap();
+        let sum = unsafe {
+            std::mem::transmute::<fn(), extern "sysv64" fn(u64, u64) -> u64>(
+                vtable.lookup("sum").unwrap(),
+            )
+        };
+        assert_eq!(sum(10, 7), 55 + 49 + 1000);
+        assert_eq!(sum(0, 0), 1000);
+    }
+
+    #[test]
+    fn sse_instructions() {
+        let code = encode(|r| {
+            r.movss(VecReg::XMM0, VecReg::XMM1);
+            r.movsd(VecReg::XMM9, VecReg::XMM2);
+            r.movss_load(VecReg::XMM3, Reg::RSP, 4);
+            r.movss_store(Reg::R13, 0, VecReg::XMM12);
+            r.movsd_load(VecReg::XMM0, Reg::RDI, 8);
+            r.movsd_store(Reg::RBP, -16, VecReg::XMM1);
+            r.mov_to_vec(VecReg::XMM1, Reg::RAX);
+            r.mov_to_vec(VecReg::XMM2, Reg::R8D);
+            r.mov_from_vec(Reg::R10, VecReg::XMM3);
+            r.addss(VecReg::XMM0, VecReg::XMM1);
+            r.subss(VecReg::XMM2, VecReg::XMM10);
+            r.mulss(VecReg::XMM11, VecReg::XMM3);
+            r.divss(VecReg::XMM4, VecReg::XMM5);
+            r.sqrtss(VecReg::XMM6, VecReg::XMM7);
+            r.addsd(VecReg::XMM0, VecReg::XMM1);
+            r.subsd(VecReg::XMM8, VecReg::XMM9);
+            r.mulsd(VecReg::XMM2, VecReg::XMM15);
+            r.divsd(VecReg::XMM3, VecReg::XMM4);
+            r.sqrtsd(VecReg::XMM5, VecReg::XMM5);
+            r.cvtss2sd(VecReg::XMM0, VecReg::XMM1);
+            r.cvtsd2ss(VecReg::XMM1, VecReg::XMM0);
+            r.cvtsi2ss(VecReg::XMM0, Reg::EAX);
+            r.cvtsi2sd(VecReg::XMM1, Reg::RDI);
+            r.cvtsi2sd(VecReg::XMM9, Reg::R12);
+            r.cvttss2si(Reg::EAX, VecReg::XMM2);
+            r.cvttsd2si(Reg::R11, VecReg::XMM14);
+            r.ucomiss(VecReg::XMM0, VecReg::XMM1);
+            r.ucomisd(VecReg::XMM0, VecReg::XMM1);
+            r.ucomisd(VecReg::XMM10, VecReg::XMM11);
+        });
+        let expected: &[&[u8]] = &[
+            &[0xF3, 0x0F, 0x10, 0xC1],             // movss xmm0, xmm1
+            &[0xF2, 0x44, 0x0F, 0x10, 0xCA],       // movsd xmm9, xmm2
+            &[0xF3, 0x0F, 0x10, 0x5C, 0x24, 0x04], // movss xmm3, dword ptr [rsp + 4]
+            &[0xF3, 0x45, 0x0F, 0x11, 0x65, 0x00], // movss dword ptr [r13], xmm12
+            &[0xF2, 0x0F, 0x10, 0x47, 0x08],       // movsd xmm0, qword ptr [rdi + 8]
+            &[0xF2, 0x0F, 0x11, 0x4D, 0xF0],       // movsd qword ptr [rbp - 16], xmm1
+            &[0x66, 0x48, 0x0F, 0x6E, 0xC8],       // movq xmm1, rax
+            &[0x66, 0x41, 0x0F, 0x6E, 0xD0],       // movd xmm2, r8d
+            &[0x66, 0x49, 0x0F, 0x7E, 0xDA],       // movq r10, xmm3
+            &[0xF3, 0x0F, 0x58, 0xC1],             // addss xmm0, xmm1
+            &[0xF3, 0x41, 0x0F, 0x5C, 0xD2],       // subss xmm2, xmm10
+            &[0xF3, 0x44, 0x0F, 0x59, 0xDB],       // mulss xmm11, xmm3
+            &[0xF3, 0x0F, 0x5E, 0xE5],             // divss xmm4, xmm5
+            &[0xF3, 0x0F, 0x51, 0xF7],             // sqrtss xmm6, xmm7
+            &[0xF2, 0x0F, 0x58, 0xC1],             // addsd xmm0, xmm1
+            &[0xF2, 0x45, 0x0F, 0x5C, 0xC1],       // subsd xmm8, xmm9
+            &[0xF2, 0x41, 0x0F, 0x59, 0xD7],       // mulsd xmm2, xmm15
+            &[0xF2, 0x0F, 0x5E, 0xDC],             // divsd xmm3, xmm4
+            &[0xF2, 0x0F, 0x51, 0xED],             // sqrtsd xmm5, xmm5
+            &[0xF3, 0x0F, 0x5A, 0xC1],             // cvtss2sd xmm0, xmm1
+            &[0xF2, 0x0F, 0x5A, 0xC8],             // cvtsd2ss xmm1, xmm0
+            &[0xF3, 0x0F, 0x2A, 0xC0],             // cvtsi2ss xmm0, eax
+            &[0xF2, 0x48, 0x0F, 0x2A, 0xCF],       // cvtsi2sd xmm1, rdi
+            &[0xF2, 0x4D, 0x0F, 0x2A, 0xCC],       // cvtsi2sd xmm9, r12
+            &[0xF3, 0x0F, 0x2C, 0xC2],             // cvttss2si eax, xmm2
+            &[0xF2, 0x4D, 0x0F, 0x2C, 0xDE],       // cvttsd2si r11, xmm14
+            &[0x0F, 0x2E, 0xC1],                   // ucomiss xmm0, xmm1
+            &[0x66, 0x0F, 0x2E, 0xC1],             // ucomisd xmm0, xmm1
+            &[0x66, 0x45, 0x0F, 0x2E, 0xD3],       // ucomisd xmm10, xmm11
+        ];
+        assert_eq!(code, expected.concat());
+    }
+
+    #[test]
+    fn vex_instructions() {
+        let code = encode(|r| {
+            r.vaddps(VecReg::XMM0, VecReg::XMM1, VecReg::XMM2);
+            r.vaddpd(VecReg::YMM3, VecReg::YMM4, VecReg::YMM5);
+            // Extended registers in `reg` and `vvvv` keep the 2-byte form
+            r.vsubps(VecReg::YMM8, VecReg::YMM9, VecReg::YMM7);
+            r.vsubpd(VecReg::XMM0, VecReg::XMM15, VecReg::XMM1);
+            // An extended register in `rm` needs VEX.B, which only the 3-byte form has
+            r.vmulps(VecReg::YMM0, VecReg::YMM1, VecReg::YMM8);
+            r.vmulpd(VecReg::XMM12, VecReg::XMM1, VecReg::XMM13);
+            r.vdivps(VecReg::XMM1, VecReg::XMM2, VecReg::XMM3);
+            r.vdivpd(VecReg::YMM1, VecReg::YMM2, VecReg::YMM3);
+            r.vxorps(VecReg::XMM0, VecReg::XMM0, VecReg::XMM0);
+            // The 0F38 map and VEX.W of the double precision forms need the 3-byte form
+            r.vfmadd231ps(VecReg::XMM0, VecReg::XMM1, VecReg::XMM2);
+            r.vfmadd231ps(VecReg::YMM8, VecReg::YMM9, VecReg::YMM10);
+            r.vfmadd231pd(VecReg::YMM1, VecReg::YMM2, VecReg::YMM3);
+            r.vfmadd231pd(VecReg::XMM0, VecReg::XMM1, VecReg::XMM12);
+            r.vmovups(VecReg::YMM0, VecReg::YMM1);
+            r.vmovups(VecReg::XMM1, VecReg::XMM9);
+            r.vmovups_load(VecReg::XMM2, Reg::RSP, 0);
+            r.vmovups_load(VecReg::YMM3, Reg::R12, 32);
+            r.vmovups_store(Reg::RBP, 0, VecReg::YMM4);
+            r.vmovups_store(Reg::R13, -16, VecReg::XMM10);
+            r.vzeroupper();
+        });
+        let expected: &[&[u8]] = &[
+            &[0xC5, 0xF0, 0x58, 0xC2],                   // vaddps xmm0, xmm1, xmm2
+            &[0xC5, 0xDD, 0x58, 0xDD],                   // vaddpd ymm3, ymm4, ymm5
+            &[0xC5, 0x34, 0x5C, 0xC7],                   // vsubps ymm8, ymm9, ymm7
+            &[0xC5, 0x81, 0x5C, 0xC1],                   // vsubpd xmm0, xmm15, xmm1
+            &[0xC4, 0xC1, 0x74, 0x59, 0xC0],             // vmulps ymm0, ymm1, ymm8
+            &[0xC4, 0x41, 0x71, 0x59, 0xE5],             // vmulpd xmm12, xmm1, xmm13
+            &[0xC5, 0xE8, 0x5E, 0xCB],                   // vdivps xmm1, xmm2, xmm3
+            &[0xC5, 0xED, 0x5E, 0xCB],                   // vdivpd ymm1, ymm2, ymm3
+            &[0xC5, 0xF8, 0x57, 0xC0],                   // vxorps xmm0, xmm0, xmm0
+            &[0xC4, 0xE2, 0x71, 0xB8, 0xC2],             // vfmadd231ps xmm0, xmm1, xmm2
+            &[0xC4, 0x42, 0x35, 0xB8, 0xC2],             // vfmadd231ps ymm8, ymm9, ymm10
+            &[0xC4, 0xE2, 0xED, 0xB8, 0xCB],             // vfmadd231pd ymm1, ymm2, ymm3
+            &[0xC4, 0xC2, 0xF1, 0xB8, 0xC4],             // vfmadd231pd xmm0, xmm1, xmm12
+            &[0xC5, 0xFC, 0x10, 0xC1],                   // vmovups ymm0, ymm1
+            &[0xC4, 0xC1, 0x78, 0x10, 0xC9],             // vmovups xmm1, xmm9
+            &[0xC5, 0xF8, 0x10, 0x14, 0x24],             // vmovups xmm2, xmmword ptr [rsp]
+            &[0xC4, 0xC1, 0x7C, 0x10, 0x5C, 0x24, 0x20], // vmovups ymm3, ymmword ptr [r12 + 32]
+            &[0xC5, 0xFC, 0x11, 0x65, 0x00],             // vmovups ymmword ptr [rbp], ymm4
+            &[0xC4, 0x41, 0x78, 0x11, 0x55, 0xF0],       // vmovups xmmword ptr [r13 - 16], xmm10
+            &[0xC5, 0xF8, 0x77],                         // vzeroupper
+        ];
+        assert_eq!(code, expected.concat());
+    }
+
+    #[test]
+    fn vector_constants() {
+        let mut asm = Asm::default();
+        asm.const_32(1);
+        // Global vector constants are aligned to their size
+        let global_128 = asm.const_128([0x11; 16]);
+        let global_256 = asm.const_256([0x22; 32]);
+        assert_eq!((global_128, global_256), (4, 8));
+        let mut main = Routine::new("main".to_string());
+        main.const_32(2);
+        // Routine constants are not
+        let local_128 = main.const_128([0x33; 16]);
+        let local_256 = main.const_256([0x44; 32]);
+        assert_eq!((local_128, local_256), (1, 5));
+        main.movss_const(VecReg::XMM1, 0);
+        main.movsd_global_const(VecReg::XMM10, 0);
+        main.vmovups_const(VecReg::YMM2, local_256);
+        main.vmovups_global_const(VecReg::XMM9, global_128);
+        main.ret();
+        asm.push_routine(main);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let main = vtable["main"];
+        let constants = main - 52;
+        assert_eq!(code[constants + 4..constants + 20], [0x33; 16]);
+        assert_eq!(code[16..16 + 16], [0x11; 16]);
+        assert_eq!(code[32..32 + 32], [0x22; 32]);
+        let rel = |target: usize, end: usize| (target as i32 - end as i32).to_le_bytes();
+        let expected: &[&[u8]] = &[
+            &[0xF3, 0x0F, 0x10, 0x0D], // movss xmm1, dword ptr [rip + disp]
+            &rel(constants, main + 8),
+            &[0xF2, 0x44, 0x0F, 0x10, 0x15], // movsd xmm10, qword ptr [rip + disp]
+            &rel(0, main + 17),
+            &[0xC5, 0xFC, 0x10, 0x15], // vmovups ymm2, ymmword ptr [rip + disp]
+            &rel(constants + 20, main + 25),
+            &[0xC5, 0x78, 0x10, 0x0D], // vmovups xmm9, xmmword ptr [rip + disp]
+            &rel(16, main + 33),
+            &[0xC3], // ret
+        ];
+        assert_eq!(code[main..], expected.concat());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn runs_sse_natively() {
+        let mut asm = Asm::default();
+        let half = asm.const_32(0.5f32.to_bits());
+        // f(x, n) = x * 2.5 + n + 0.5, or -1 if x is NaN
+        let mut f = Routine::new("f".to_string());
+        let factor = f.const_64(2.5f64.to_bits());
+        let nan = f.new_label();
+        f.ucomisd(VecReg::XMM0, VecReg::XMM0);
+        f.jcc(Cond::P, nan);
+        f.movsd_const(VecReg::XMM1, factor);
+        f.mulsd(VecReg::XMM0, VecReg::XMM1);
+        f.cvtsi2sd(VecReg::XMM2, Reg::RDI);
+        f.addsd(VecReg::XMM0, VecReg::XMM2);
+        f.movss_global_const(VecReg::XMM3, half);
+        f.cvtss2sd(VecReg::XMM3, VecReg::XMM3);
+        f.addsd(VecReg::XMM0, VecReg::XMM3);
+        f.ret();
+        f.bind(nan);
+        f.mov_imm(Reg::RAX, (-1f64).to_bits());
+        f.mov_to_vec(VecReg::XMM0, Reg::RAX);
+        f.ret();
+        asm.push_routine(f);
+
+        let vtable = asm.jit().unwrap();
+        let f = unsafe {
+            std::mem::transmute::<fn(), extern "sysv64" fn(f64, i64) -> f64>(
+                vtable.lookup("f").unwrap(),
+            )
+        };
+        assert_eq!(f(4.0, 3), 13.5);
+        assert_eq!(f(-1.0, -7), -9.0);
+        assert_eq!(f(f64::NAN, 0), -1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Both registers must be of equal size")]
+    fn mixed_register_sizes() {
+        encode(|r| r.add_reg(Reg::RAX, Reg::ECX));
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate does not fit into 32-bit register")]
+    fn wide_immediate_in_32_bit_register() {
+        encode(|r| r.mov_imm(Reg::EAX, 1 << 32));
+    }
+
+    #[test]
+    #[should_panic(expected = "Pushed register must be 64-bit")]
+    fn push_32_bit_register() {
+        encode(|r| r.push(Reg::EAX));
+    }
+
+    #[test]
+    #[should_panic(expected = "SSE registers must be 128-bit")]
+    fn sse_with_256_bit_register() {
+        encode(|r| r.addsd(VecReg::YMM0, VecReg::XMM1));
+    }
+
+    #[test]
+    #[should_panic(expected = "All registers must be of equal size")]
+    fn vex_with_mixed_register_sizes() {
+        encode(|r| r.vaddps(VecReg::YMM0, VecReg::XMM1, VecReg::YMM2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Tried to jump to unbound label")]
+    fn unbound_label() {
+        encode(|r| {
+            let label = r.new_label();
+            r.jmp(label);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Tried to bind label twice")]
+    fn label_bound_twice() {
+        encode(|r| {
+            let label = r.new_label();
+            r.bind(label);
+            r.bind(label);
+        });
+    }
+}
@@ -1,5 +1,5 @@
-use crate::mem;
-use std::collections::HashMap;
+use crate::mem::{self, MemoryView, RawMemoryView, VecMemoryView};
+use std::{collections::HashMap, mem::transmute};
 
 pub trait Assembler {
     type AsmRoutine: Subroutine;
@@ -14,6 +14,14 @@ pub trait Assembler {
 }
 
 pub trait Subroutine {
+    fn name(&self) -> &str;
+
+    /// Returns the bits set in the entry address of the routine, which selects Thumb code on
+    /// AArch32
+    fn entry_bits(&self) -> usize {
+        0
+    }
+
     fn constants(&self) -> &[u8];
 
     fn code(&self) -> &[u8];
@@ -37,6 +45,282 @@ pub trait PostOp {
     );
 }
 
+/// Position inside of a routine that can be branched to before it is bound
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Label(pub(crate) usize);
+
+impl Label {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Size of a memory access done through a `MacroAssembler`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+    Double,
+}
+
+impl Width {
+    pub fn bytes(self) -> usize {
+        match self {
+            Self::Byte => 1,
+            Self::Half => 2,
+            Self::Word => 4,
+            Self::Double => 8,
+        }
+    }
+}
+
+/// Relation between two integer registers a `MacroAssembler` can branch on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Eq,
+    Ne,
+    /// Signed less than
+    Lt,
+    /// Signed less than or equal
+    Le,
+    /// Signed greater than
+    Gt,
+    /// Signed greater than or equal
+    Ge,
+    /// Unsigned less than
+    Below,
+    /// Unsigned less than or equal
+    BelowEq,
+    /// Unsigned greater than
+    Above,
+    /// Unsigned greater than or equal
+    AboveEq,
+}
+
+/// Portable operations on the 64-bit integer registers of a backend so the same generator code
+/// can target every instruction set
+///
+/// Operations may clobber the scratch registers of the backend to materialize immediates and
+/// offsets that do not fit into an instruction, so those are never handed out as `temp_regs`.
+pub trait MacroAssembler {
+    type Reg: Copy + PartialEq + 'static;
+
+    /// Returns the register of the integer argument with the given index in the C calling
+    /// convention if it is passed in a register
+    fn arg_reg(index: usize) -> Option<Self::Reg>;
+
+    /// Returns the register of the integer return value in the C calling convention
+    fn ret_reg() -> Self::Reg;
+
+    /// Returns caller-saved registers that are neither argument nor scratch registers
+    fn temp_regs() -> &'static [Self::Reg];
+
+    /// Creates a new label that has to be bound before the routine is finalized
+    fn new_label(&mut self) -> Label;
+
+    /// Binds the label to the position of the next instruction
+    fn bind(&mut self, label: Label);
+
+    /// Moves the value of `src` into `dst`
+    fn mov(&mut self, dst: Self::Reg, src: Self::Reg);
+
+    /// Moves the 64-bit immediate into `dst`
+    fn mov_imm(&mut self, dst: Self::Reg, imm: u64);
+
+    /// Loads the value at `base + offset` zero-extending it into `dst`
+    fn load(&mut self, width: Width, dst: Self::Reg, base: Self::Reg, offset: i32);
+
+    /// Stores the lowest bytes of `src` at `base + offset`
+    fn store(&mut self, width: Width, src: Self::Reg, base: Self::Reg, offset: i32);
+
+    /// Computes `lhs + rhs`
+    fn add(&mut self, dst: Self::Reg, lhs: Self::Reg, rhs: Self::Reg);
+
+    /// Computes `lhs + imm`
+    fn add_imm(&mut self, dst: Self::Reg, lhs: Self::Reg, imm: i32);
+
+    /// Computes `lhs - rhs`
+    fn sub(&mut self, dst: Self::Reg, lhs: Self::Reg, rhs: Self::Reg);
+
+    /// Computes the lower 64 bits of `lhs * rhs`
+    fn mul(&mut self, dst: Self::Reg, lhs: Self::Reg, rhs: Self::Reg);
+
+    /// Computes the bitwise and of `lhs` and `rhs`
+    fn and(&mut self, dst: Self::Reg, lhs: Self::Reg, rhs: Self::Reg);
+
+    /// Computes the bitwise or of `lhs` and `rhs`
+    fn or(&mut self, dst: Self::Reg, lhs: Self::Reg, rhs: Self::Reg);
+
+    /// Computes the bitwise exclusive or of `lhs` and `rhs`
+    fn xor(&mut self, dst: Self::Reg, lhs: Self::Reg, rhs: Self::Reg);
+
+    /// Jumps to a local label
+    fn jump(&mut self, label: Label);
+
+    /// Compares `lhs` with `rhs` and branches to a local label if the condition holds
+    fn branch_if(&mut self, cond: Condition, lhs: Self::Reg, rhs: Self::Reg, label: Label);
+
+    /// Calls label that must be present in the V-Table
+    fn call(&mut self, label: String);
+
+    /// Sets up a frame pointer and saves the return address, keeping the stack 16-byte aligned
+    fn prologue(&mut self);
+
+    /// Tears down the frame set up by `prologue`, which backends whose `ret` already does so
+    /// leave empty
+    fn epilogue(&mut self);
+
+    /// Returns from a routine
+    fn ret(&mut self);
+}
+
+/// Global constants followed by the routines of an assembler, which every backend places into
+/// memory the same way
+///
+/// Routines are placed in reverse push order with their constants in front of the code, and the
+/// constants of every routine start at a multiple of `align` bytes.
+pub struct Image<R> {
+    finalizing: bool,
+    constants: Vec<u8>,
+    routines: Vec<R>,
+    vtable: HashMap<String, usize>,
+    const_addr: usize,
+    align: usize,
+}
+
+impl<R: Subroutine> Image<R> {
+    pub fn new(align: usize) -> Self {
+        assert!(align.is_power_of_two(), "Alignment must be a power of two");
+        Self {
+            finalizing: false,
+            constants: Vec::with_capacity(0),
+            routines: Vec::with_capacity(0),
+            vtable: HashMap::with_capacity(0),
+            const_addr: 0,
+            align,
+        }
+    }
+
+    /// Returns the global constants, which backends append to
+    pub fn constants_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.constants
+    }
+
+    /// Adds a finalized routine
+    pub fn push_routine(&mut self, routine: R) {
+        self.routines.push(routine);
+    }
+
+    /// Returns the number of bytes `jit` and `virtual_jit` write
+    pub fn size(&self) -> usize {
+        self.routines
+            .iter()
+            .rev()
+            .fold(self.constants.len(), |size, it| {
+                mem::align(size, self.align) + it.constants().len() + it.code().len()
+            })
+    }
+
+    fn place<'a>(&mut self, view: &mut impl MemoryView<'a>) {
+        self.finalizing = true;
+        let address = view.address();
+        self.const_addr = address;
+        let mut offset = 0usize;
+        for byte in &self.constants {
+            view.push(*byte);
+            offset += 1;
+        }
+        let routines = std::mem::take(&mut self.routines);
+        let mut placed = Vec::with_capacity(routines.len());
+        for routine in routines.into_iter().rev() {
+            while !offset.is_multiple_of(self.align) {
+                view.push(0);
+                offset += 1;
+            }
+            let start = offset;
+            for byte in routine.constants() {
+                view.push(*byte);
+                offset += 1;
+            }
+            let entry = address + offset + routine.entry_bits();
+            self.vtable.insert(routine.name().to_string(), entry);
+            for byte in routine.code() {
+                view.push(*byte);
+                offset += 1;
+            }
+            placed.push((start, routine));
+        }
+        for (offset, routine) in &placed {
+            let const_len = routine.constants().len();
+            routine.process(
+                self,
+                address + offset,
+                const_len,
+                view.slice_at_mut(*offset, const_len + routine.code().len()),
+            );
+        }
+    }
+}
+
+impl<R: Subroutine> Assembler for Image<R> {
+    type AsmRoutine = R;
+
+    fn global_const_address(&self) -> usize {
+        if !self.finalizing {
+            panic!("Illegal access");
+        }
+        self.const_addr
+    }
+
+    fn get_label_address(&self, name: &str) -> Option<usize> {
+        if !self.finalizing {
+            panic!("Illegal access");
+        }
+        self.vtable.get(name).cloned()
+    }
+
+    fn jit(mut self) -> Option<VTable> {
+        let size = self.size();
+        let ptr = mem::alloc_aligned(size);
+        if ptr.is_null() {
+            dbg!("Could not allocate memory");
+            return None;
+        }
+        self.place(&mut RawMemoryView::new(ptr));
+        if !mem::make_executable_aligned(ptr, size) {
+            unsafe {
+                mem::dealloc_aligned(ptr, size);
+            }
+            dbg!("Could not make memory executable");
+            return None;
+        }
+        mem::flush_instruction_cache(ptr, size);
+        Some(VTable::new(
+            ptr,
+            size,
+            self.vtable
+                .into_iter()
+                .map(|(k, v)| (k, unsafe { transmute::<usize, fn()>(v) }))
+                .collect(),
+        ))
+    }
+
+    fn virtual_jit(mut self) -> Option<(Vec<u8>, HashMap<String, usize>)> {
+        let mut vec = Vec::with_capacity(self.size());
+        let mut view = VecMemoryView::new(0, &mut vec);
+        self.place(&mut view);
+        let address = view.address();
+        Some((
+            vec,
+            self.vtable
+                .into_iter()
+                .map(|(k, v)| (k, v - address))
+                .collect(),
+        ))
+    }
+}
+
 pub struct VTable {
     ptr: *mut u8,
     size: usize,
@@ -63,3 +347,199 @@ impl Drop for VTable {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Stub {
+        name: String,
+        constants: Vec<u8>,
+        code: Vec<u8>,
+    }
+
+    impl Subroutine for Stub {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn constants(&self) -> &[u8] {
+            &self.constants
+        }
+
+        fn code(&self) -> &[u8] {
+            &self.code
+        }
+
+        fn process(&self, _: &impl Assembler, _: usize, _: usize, _: &mut [u8]) {}
+    }
+
+    fn stub(name: &str, constants: usize, code: usize) -> Stub {
+        Stub {
+            name: name.to_string(),
+            constants: vec![0xAA; constants],
+            code: vec![0xBB; code],
+        }
+    }
+
+    #[test]
+    fn size_matches_placement() {
+        let mut image = Image::new(8);
+        image.constants_mut().extend([1, 2, 3]);
+        image.push_routine(stub("a", 4, 12));
+        image.push_routine(stub("b", 0, 6));
+        image.push_routine(stub("c", 2, 2));
+        let size = image.size();
+        let (code, vtable) = image.virtual_jit().unwrap();
+        assert_eq!(code.len(), size);
+        // Placed as c, b, a with every routine starting on an 8-byte boundary
+        assert_eq!(vtable["c"], 10);
+        assert_eq!(vtable["b"], 16);
+        assert_eq!(vtable["a"], 28);
+        assert_eq!(code.len(), 40);
+    }
+
+    /// `sum(ptr, len) = scale(2 * (ptr[0] + ... + ptr[len - 1]))`, also storing the plain sum
+    /// as a 32-bit value `0x1000` bytes behind the buffer
+    fn sum<M: MacroAssembler>(m: &mut M) {
+        let [ptr, len, end] = [0, 1, 2].map(|index| M::arg_reg(index).unwrap());
+        let [acc, byte] = [M::temp_regs()[0], M::temp_regs()[1]];
+        let top = m.new_label();
+        let done = m.new_label();
+        m.prologue();
+        m.mov_imm(acc, 0);
+        m.add(end, ptr, len);
+        m.bind(top);
+        m.branch_if(Condition::Eq, ptr, end, done);
+        m.load(Width::Byte, byte, ptr, 0);
+        m.add(acc, acc, byte);
+        m.add_imm(ptr, ptr, 1);
+        m.jump(top);
+        m.bind(done);
+        m.store(Width::Word, acc, ptr, 0x1000);
+        m.load(Width::Word, byte, ptr, 0x1000);
+        m.add(acc, acc, byte);
+        m.mov(M::arg_reg(0).unwrap(), acc);
+        m.call("scale".to_string());
+        m.epilogue();
+        m.ret();
+    }
+
+    /// `scale(x) = (x * 3 + 0x1234_5678_9ABC) ^ 0xFF`
+    fn scale<M: MacroAssembler>(m: &mut M) {
+        let (x, ret, tmp) = (M::arg_reg(0).unwrap(), M::ret_reg(), M::temp_regs()[1]);
+        m.mov_imm(tmp, 3);
+        m.mul(ret, x, tmp);
+        m.mov_imm(tmp, 0x1234_5678_9ABC);
+        m.add(ret, ret, tmp);
+        m.mov_imm(tmp, 0xFF);
+        m.xor(ret, ret, tmp);
+        m.ret();
+    }
+
+    /// Result of `sum` for the bytes 1 to 10
+    const SUM: u64 = (110 * 3 + 0x1234_5678_9ABC) ^ 0xFF;
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn generic_generator_on_x64() {
+        use crate::arch::x64::{asm::Asm, routine::Routine};
+        let mut asm = Asm::default();
+        let mut routine = Routine::new("sum".to_string());
+        sum(&mut routine);
+        asm.push_routine(routine);
+        let mut routine = Routine::new("scale".to_string());
+        scale(&mut routine);
+        asm.push_routine(routine);
+        let vtable = asm.jit().unwrap();
+        let sum = unsafe {
+            std::mem::transmute::<fn(), extern "sysv64" fn(*mut u8, u64) -> u64>(
+                vtable.lookup("sum").unwrap(),
+            )
+        };
+        let mut buffer = vec![0u8; 0x2000];
+        for (index, byte) in buffer[..10].iter_mut().enumerate() {
+            *byte = index as u8 + 1;
+        }
+        assert_eq!(sum(buffer.as_mut_ptr(), 10), SUM);
+        assert_eq!(buffer[0x100A..0x100E], 55u32.to_le_bytes());
+    }
+
+    #[test]
+    fn generic_generator_on_a64() {
+        use crate::arch::a64::{asm::Asm, emu::Emulator, routine::Routine};
+        let mut asm = Asm::default();
+        let mut routine = Routine::new("sum".to_string());
+        sum(&mut routine);
+        asm.push_routine(routine);
+        let mut routine = Routine::new("scale".to_string());
+        scale(&mut routine);
+        asm.push_routine(routine);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        for index in 0..10 {
+            emu.write(0x8000 + index, 1, index + 1).unwrap();
+        }
+        assert_eq!(emu.call("sum", &[0x8000, 10]), Ok(SUM));
+        assert_eq!(emu.read(0x900A, 4), Ok(55));
+    }
+
+    #[test]
+    fn generic_generator_on_rv64() {
+        use crate::arch::rv64::routine::Routine;
+        let words = |generate: fn(&mut Routine)| {
+            let mut routine = Routine::new("f".to_string());
+            generate(&mut routine);
+            routine.finalize();
+            routine
+                .code()
+                .chunks(4)
+                .map(|it| u32::from_le_bytes(it.try_into().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        // The call and the constant load are patched when the routines are placed
+        assert_eq!(
+            words(sum),
+            [
+                0xFF010113, // addi sp, sp, -16
+                0x00113423, // sd ra, 8(sp)
+                0x00813023, // sd s0, 0(sp)
+                0x01010413, // addi s0, sp, 16
+                0x00000293, // li t0, 0
+                0x00B50633, // add a2, a0, a1
+                0x00C50A63, // beq a0, a2, 20
+                0x00054303, // lbu t1, 0(a0)
+                0x006282B3, // add t0, t0, t1
+                0x00150513, // addi a0, a0, 1
+                0xFF1FF06F, // j -16
+                0x00001FB7, // lui t6, 1
+                0x00AF8FB3, // add t6, t6, a0
+                0x005FA023, // sw t0, 0(t6)
+                0x00001FB7, // lui t6, 1
+                0x00AF8FB3, // add t6, t6, a0
+                0x000FE303, // lwu t1, 0(t6)
+                0x006282B3, // add t0, t0, t1
+                0x00028513, // mv a0, t0
+                0x00000097, // auipc ra, 0
+                0x000080E7, // jalr ra
+                0x00813083, // ld ra, 8(sp)
+                0x00013403, // ld s0, 0(sp)
+                0x01010113, // addi sp, sp, 16
+                0x00008067, // ret
+            ]
+        );
+        assert_eq!(
+            words(scale),
+            [
+                0x00300313, // li t1, 3
+                0x02650533, // mul a0, a0, t1
+                0x00000317, // auipc t1, 0
+                0x00033303, // ld t1, 0(t1)
+                0x00650533, // add a0, a0, t1
+                0x0FF00313, // li t1, 255
+                0x00654533, // xor a0, a0, t1
+                0x00008067, // ret
+            ]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use super::{Block, Function};
+
+/// Predecessors and dominators of the blocks reachable from the entry block
+pub struct Cfg {
+    preds: Vec<Vec<Block>>,
+    /// Reachable blocks in reverse postorder
+    order: Vec<Block>,
+    idoms: Vec<Option<Block>>,
+    children: Vec<Vec<Block>>,
+}
+
+impl Cfg {
+    pub fn new(func: &Function) -> Self {
+        let len = func.blocks.len();
+        let succs: Vec<Vec<Block>> = func
+            .blocks
+            .iter()
+            .map(|data| {
+                data.terminator
+                    .iter()
+                    .flat_map(|terminator| terminator.successors())
+                    .map(|call| call.block)
+                    .collect()
+            })
+            .collect();
+
+        let mut visited = vec![false; len];
+        let mut postorder = Vec::with_capacity(len);
+        let mut stack = vec![(Block(0), 0)];
+        visited[0] = true;
+        while let Some(&(block, next)) = stack.last() {
+            if let Some(&succ) = succs[block.index()].get(next) {
+                stack.last_mut().unwrap().1 += 1;
+                if !visited[succ.index()] {
+                    visited[succ.index()] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                postorder.push(block);
+                stack.pop();
+            }
+        }
+
+        let mut preds = vec![Vec::new(); len];
+        for block in &postorder {
+            for succ in &succs[block.index()] {
+                preds[succ.index()].push(*block);
+            }
+        }
+
+        // Cooper, Harvey and Kennedy: "A Simple, Fast Dominance Algorithm"
+        let mut numbers = vec![usize::MAX; len];
+        for (number, block) in postorder.iter().enumerate() {
+            numbers[block.index()] = number;
+        }
+        let order: Vec<Block> = postorder.into_iter().rev().collect();
+        let mut idoms = vec![None; len];
+        idoms[0] = Some(Block(0));
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in order.iter().skip(1) {
+                let mut new_idom = None;
+                for pred in &preds[block.index()] {
+                    if idoms[pred.index()].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => *pred,
+                        Some(mut other) => {
+                            let mut pred = *pred;
+                            while pred != other {
+                                while numbers[pred.index()] < numbers[other.index()] {
+                                    pred = idoms[pred.index()].unwrap();
+                                }
+                                while numbers[other.index()] < numbers[pred.index()] {
+                                    other = idoms[other.index()].unwrap();
+                                }
+                            }
+                            pred
+                        }
+                    });
+                }
+                if idoms[block.index()] != new_idom {
+                    idoms[block.index()] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idoms[0] = None;
+
+        let mut children = vec![Vec::new(); len];
+        for block in &order {
+            if let Some(idom) = idoms[block.index()] {
+                children[idom.index()].push(*block);
+            }
+        }
+        Self {
+            preds,
+            order,
+            idoms,
+            children,
+        }
+    }
+
+    /// Returns the reachable blocks branching to `block`, once for every edge
+    pub fn predecessors(&self, block: Block) -> &[Block] {
+        &self.preds[block.index()]
+    }
+
+    /// Returns the reachable blocks in reverse postorder, starting with the entry block
+    pub fn reverse_postorder(&self) -> &[Block] {
+        &self.order
+    }
+
+    pub fn is_reachable(&self, block: Block) -> bool {
+        block.index() == 0 || self.idoms[block.index()].is_some()
+    }
+
+    /// Returns the immediate dominator of a reachable block other than the entry block
+    pub fn idom(&self, block: Block) -> Option<Block> {
+        self.idoms[block.index()]
+    }
+
+    /// Returns the blocks immediately dominated by `block`
+    pub fn children(&self, block: Block) -> &[Block] {
+        &self.children[block.index()]
+    }
+
+    /// Whether every path from the entry block to `block` passes `dominator`
+    pub fn dominates(&self, dominator: Block, block: Block) -> bool {
+        if !self.is_reachable(block) {
+            return false;
+        }
+        let mut block = block;
+        loop {
+            if block == dominator {
+                return true;
+            }
+            match self.idoms[block.index()] {
+                Some(idom) => block = idom,
+                None => return false,
+            }
+        }
+    }
+}
@@ -0,0 +1,590 @@
+use super::{
+    regalloc::{allocate, is_call, Allocation, Location},
+    BlockCall, Condition, FloatCond, FloatOp, Function, Inst, IntOp, Terminator, Type, Value,
+};
+use crate::{
+    arch::a64::{
+        asm::Asm,
+        cond::Cond,
+        frame::{Frame, StackSlot},
+        reg::{d_reg, low_32, s_reg, FReg, Reg},
+        routine::Routine,
+    },
+    assembler::{Label, MacroAssembler, Width},
+};
+
+/// Lowers every function into a routine of the same name
+///
+/// Values live in the registers or spill slots picked by `regalloc::allocate`, spilled operands
+/// being loaded into `X9` to `X11`. Floats are kept as their bit patterns and moved into the
+/// caller-saved `V16` and `V17` for the floating-point instructions.
+pub fn lower(asm: &mut Asm, functions: &[Function]) {
+    for func in functions {
+        let mut lowering = Lowering::new(func);
+        lowering.function();
+        asm.push_routine(lowering.routine);
+    }
+}
+
+struct Lowering<'a> {
+    func: &'a Function,
+    alloc: Allocation,
+    routine: Routine,
+    labels: Vec<Label>,
+    /// Stack slot of every spill slot of the allocation
+    slots: Vec<StackSlot>,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(func: &'a Function) -> Self {
+        let mut routine = Routine::new(func.name.clone());
+        let labels = func.blocks.iter().map(|_| routine.new_label()).collect();
+        Self {
+            func,
+            alloc: allocate(func),
+            routine,
+            labels,
+            slots: Vec::new(),
+        }
+    }
+
+    fn function(&mut self) {
+        let calls = self
+            .func
+            .blocks
+            .iter()
+            .any(|block| block.insts.iter().any(|inst| is_call(&inst.inst)));
+        self.routine.set_frame(Frame {
+            callee_saved: self.alloc.callee_saved.clone(),
+            locals: 0,
+            calls,
+            probe_stack: false,
+        });
+        self.slots = (0..self.alloc.spill_slots)
+            .map(|_| self.routine.alloc_slot(8, 8))
+            .collect();
+        let func = self.func;
+        let params = &func.blocks[0].params;
+        assert!(params.len() <= 8, "Function has too many parameters");
+        for (index, param) in params.iter().enumerate() {
+            let reg = Routine::arg_reg(index).unwrap();
+            self.move_location(self.alloc.location(*param), Location::Reg(reg));
+        }
+
+        for (index, block) in func.blocks.iter().enumerate() {
+            self.routine.bind(self.labels[index]);
+            for data in &block.insts {
+                self.inst(&data.inst, data.result);
+            }
+            let terminator = block.terminator.as_ref().expect("Block is not terminated");
+            self.terminator(terminator, index + 1);
+        }
+    }
+
+    fn inst(&mut self, inst: &Inst, result: Option<Value>) {
+        let dst = result.map_or(Reg::X9, |result| self.dst(result));
+        match inst {
+            Inst::Iconst { ty, imm } => {
+                MacroAssembler::mov_imm(&mut self.routine, dst, *imm as u64 & ty.mask());
+            }
+            Inst::Fconst { ty, imm } => {
+                let bits = match ty {
+                    Type::F32 => (*imm as f32).to_bits() as u64,
+                    _ => imm.to_bits(),
+                };
+                MacroAssembler::mov_imm(&mut self.routine, dst, bits);
+            }
+            Inst::Int { op, lhs, rhs } => {
+                let lhs = self.operand(*lhs, Reg::X9);
+                let rhs = self.operand(*rhs, Reg::X10);
+                match op {
+                    IntOp::Add => self.routine.add_reg(dst, lhs, rhs),
+                    IntOp::Sub => self.routine.sub_reg(dst, lhs, rhs),
+                    IntOp::Mul => self.routine.mul(dst, lhs, rhs),
+                    IntOp::And => self.routine.and_reg(dst, lhs, rhs),
+                    IntOp::Or => self.routine.orr_reg(dst, lhs, rhs),
+                    IntOp::Xor => self.routine.eor_reg(dst, lhs, rhs),
+                }
+            }
+            Inst::Float { op, lhs, rhs } => {
+                let ty = self.func.type_of(*lhs);
+                let lhs = self.fp_operand(*lhs, 0);
+                let rhs = self.fp_operand(*rhs, 1);
+                let result = fp_scratch(ty, 0);
+                match op {
+                    FloatOp::Add => self.routine.fadd(result, lhs, rhs),
+                    FloatOp::Sub => self.routine.fsub(result, lhs, rhs),
+                    FloatOp::Mul => self.routine.fmul(result, lhs, rhs),
+                    FloatOp::Div => self.routine.fdiv(result, lhs, rhs),
+                }
+                self.routine.fmov_from_fp(int_view(dst, ty), result);
+            }
+            Inst::Icmp { cond, lhs, rhs } => self.icmp(dst, *cond, *lhs, *rhs),
+            Inst::Fcmp { cond, lhs, rhs } => {
+                let lhs = self.fp_operand(*lhs, 0);
+                let rhs = self.fp_operand(*rhs, 1);
+                self.routine.fcmp(lhs, rhs);
+                // Unordered operands set C and V, which only satisfies `NE` of these
+                let cond = match cond {
+                    FloatCond::Eq => Cond::EQ,
+                    FloatCond::Ne => Cond::NE,
+                    FloatCond::Lt => Cond::MI,
+                    FloatCond::Le => Cond::LS,
+                    FloatCond::Gt => Cond::GT,
+                    FloatCond::Ge => Cond::GE,
+                };
+                self.routine.cset(dst, cond);
+            }
+            Inst::IntToFloat { ty, value } => {
+                let src_ty = self.func.type_of(*value);
+                let src = self.operand(*value, Reg::X9);
+                let src = match src_ty {
+                    Type::I8 => {
+                        self.routine.sxtb(Reg::W9, low_32(src));
+                        Reg::W9
+                    }
+                    Type::I16 => {
+                        self.routine.sxth(Reg::W9, low_32(src));
+                        Reg::W9
+                    }
+                    _ => int_view(src, src_ty),
+                };
+                let result = fp_scratch(*ty, 0);
+                self.routine.scvtf(result, src);
+                self.routine.fmov_from_fp(int_view(dst, *ty), result);
+            }
+            Inst::FloatToInt { ty, value } => {
+                let value = self.fp_operand(*value, 0);
+                self.routine.fcvtzs(int_view(dst, *ty), value);
+                if ty.bytes() < 4 {
+                    // `fcvtzs` saturates to 32 bits, so narrower results are clamped afterwards
+                    let dst = low_32(dst);
+                    let max = (1u64 << (ty.bytes() * 8 - 1)) - 1;
+                    self.routine.mov_imm64(Reg::W10, max);
+                    self.routine.cmp_reg(dst, Reg::W10);
+                    self.routine.csel(dst, dst, Reg::W10, Cond::LT);
+                    self.routine.mov_imm64(Reg::W10, !max & u32::MAX as u64);
+                    self.routine.cmp_reg(dst, Reg::W10);
+                    self.routine.csel(dst, dst, Reg::W10, Cond::GT);
+                }
+            }
+            Inst::Load { ty, addr, offset } => {
+                let addr = self.operand(*addr, Reg::X10);
+                MacroAssembler::load(&mut self.routine, width(*ty), dst, addr, *offset);
+            }
+            Inst::Store {
+                value,
+                addr,
+                offset,
+            } => {
+                let ty = self.func.type_of(*value);
+                let value = self.operand(*value, Reg::X9);
+                let addr = self.operand(*addr, Reg::X10);
+                MacroAssembler::store(&mut self.routine, width(ty), value, addr, *offset);
+            }
+            Inst::Call { label, args, .. } => {
+                self.call_args(args);
+                self.routine.br_link(label.clone());
+                if result.is_some() {
+                    MacroAssembler::mov(&mut self.routine, dst, Reg::X0);
+                }
+            }
+        }
+        if let Some(result) = result {
+            self.normalize(dst, self.func.type_of(result));
+            self.move_location(self.alloc.location(result), Location::Reg(dst));
+        }
+    }
+
+    /// Compares two integers leaving 0 or 1 in `dst`
+    fn icmp(&mut self, dst: Reg, cond: Condition, lhs: Value, rhs: Value) {
+        let ty = self.func.type_of(lhs);
+        let lhs = self.operand(lhs, Reg::X9);
+        let rhs = self.operand(rhs, Reg::X10);
+        let mut cond = cond;
+        match ty {
+            Type::I64 => self.routine.cmp_reg(lhs, rhs),
+            Type::I32 => self.routine.cmp_reg(low_32(lhs), low_32(rhs)),
+            _ => {
+                // Flipping the sign bit of zero-extended values turns signed into unsigned order
+                if let Some(unsigned) = unsigned(cond) {
+                    self.routine.mov_imm16(Reg::X11, 1 << (ty.bytes() * 8 - 1));
+                    self.routine.eor_reg(Reg::X9, lhs, Reg::X11);
+                    self.routine.eor_reg(Reg::X10, rhs, Reg::X11);
+                    self.routine.cmp_reg(Reg::X9, Reg::X10);
+                    cond = unsigned;
+                } else {
+                    self.routine.cmp_reg(lhs, rhs);
+                }
+            }
+        }
+        self.routine.cset(dst, Cond::from(cond));
+    }
+
+    /// Moves the bit pattern of a float into the scratch register `V<16 + index>` and returns
+    /// the view of it matching the type of the value
+    fn fp_operand(&mut self, value: Value, index: u32) -> FReg {
+        let ty = self.func.type_of(value);
+        let scratch = [Reg::X9, Reg::X10][index as usize];
+        let src = self.operand(value, scratch);
+        let reg = fp_scratch(ty, index);
+        self.routine.fmov_to_fp(reg, int_view(src, ty));
+        reg
+    }
+
+    /// Moves the arguments of a call into `X0` to `X7`
+    ///
+    /// No value is allocated to an argument register, so the moves cannot overwrite each other.
+    fn call_args(&mut self, args: &[Value]) {
+        assert!(args.len() <= 8, "Call has too many arguments");
+        for (index, arg) in args.iter().enumerate() {
+            let reg = Routine::arg_reg(index).unwrap();
+            self.move_location(Location::Reg(reg), self.alloc.location(*arg));
+        }
+    }
+
+    /// Clears the bits of `reg` above the width of `ty`
+    fn normalize(&mut self, reg: Reg, ty: Type) {
+        match ty {
+            Type::I64 | Type::F64 => {}
+            Type::I32 | Type::F32 => self.routine.mov_reg(low_32(reg), low_32(reg)),
+            Type::I8 => self.routine.uxtb(low_32(reg), low_32(reg)),
+            Type::I16 => self.routine.uxth(low_32(reg), low_32(reg)),
+        }
+    }
+
+    /// Branches to the successors of the block where `next` is the index of the block placed
+    /// right after it
+    fn terminator(&mut self, terminator: &Terminator, next: usize) {
+        match terminator {
+            Terminator::Jump(call) => self.jump(call, next),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let cond = self.operand(*cond, Reg::X9);
+                if then.args.is_empty() {
+                    self.routine.cbnz(cond, self.labels[then.block.index()]);
+                } else {
+                    let edge = self.routine.new_label();
+                    self.routine.cbz(cond, edge);
+                    self.jump(then, usize::MAX);
+                    self.routine.bind(edge);
+                }
+                self.jump(otherwise, next);
+            }
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    self.move_location(Location::Reg(Reg::X0), self.alloc.location(*value));
+                }
+                self.routine.ret();
+            }
+        }
+    }
+
+    /// Moves the arguments into the block parameters and branches to the block unless it is
+    /// `next`
+    fn jump(&mut self, call: &BlockCall, next: usize) {
+        let params = &self.func.blocks[call.block.index()].params;
+        let moves = params
+            .iter()
+            .zip(&call.args)
+            .map(|(param, arg)| (self.alloc.location(*param), self.alloc.location(*arg)))
+            .collect();
+        self.parallel_move(moves);
+        if call.block.index() != next {
+            self.routine.b(self.labels[call.block.index()]);
+        }
+    }
+
+    /// Performs the `(dst, src)` moves as if all of them happened at once
+    fn parallel_move(&mut self, mut moves: Vec<(Location, Location)>) {
+        moves.retain(|(dst, src)| dst != src);
+        while !moves.is_empty() {
+            let ready =
+                (0..moves.len()).find(|index| moves.iter().all(|(_, src)| *src != moves[*index].0));
+            if let Some(index) = ready {
+                let (dst, src) = moves.remove(index);
+                self.move_location(dst, src);
+                continue;
+            }
+            // Only cycles are left, so one destination is parked in `X11` to break one of them
+            let parked = moves[0].0;
+            self.move_location(Location::Reg(Reg::X11), parked);
+            for (_, src) in &mut moves {
+                if *src == parked {
+                    *src = Location::Reg(Reg::X11);
+                }
+            }
+        }
+    }
+
+    /// Returns the register holding `value`, loading it into `scratch` if it is spilled
+    fn operand(&mut self, value: Value, scratch: Reg) -> Reg {
+        match self.alloc.location(value) {
+            Location::Reg(reg) => reg,
+            location => {
+                self.move_location(Location::Reg(scratch), location);
+                scratch
+            }
+        }
+    }
+
+    /// Returns the register the result is computed into, which is `X9` if it is spilled
+    fn dst(&self, value: Value) -> Reg {
+        match self.alloc.location(value) {
+            Location::Reg(reg) => reg,
+            Location::Stack(_) => Reg::X9,
+        }
+    }
+
+    fn move_location(&mut self, dst: Location, src: Location) {
+        match (dst, src) {
+            (Location::Reg(dst), Location::Reg(src)) => {
+                MacroAssembler::mov(&mut self.routine, dst, src);
+            }
+            (Location::Reg(dst), Location::Stack(slot)) => {
+                let slot = self.slots[slot];
+                self.routine.load_slot(Width::Double, dst, slot, 0);
+            }
+            (Location::Stack(slot), Location::Reg(src)) => {
+                let slot = self.slots[slot];
+                self.routine.store_slot(Width::Double, src, slot, 0);
+            }
+            (Location::Stack(_), Location::Stack(_)) => {
+                self.move_location(Location::Reg(Reg::X10), src);
+                self.move_location(dst, Location::Reg(Reg::X10));
+            }
+        }
+    }
+}
+
+/// Returns the unsigned counterpart of a signed condition
+fn unsigned(cond: Condition) -> Option<Condition> {
+    match cond {
+        Condition::Lt => Some(Condition::Below),
+        Condition::Le => Some(Condition::BelowEq),
+        Condition::Gt => Some(Condition::Above),
+        Condition::Ge => Some(Condition::AboveEq),
+        _ => None,
+    }
+}
+
+/// Returns the float scratch register `V<16 + index>` viewed as `ty`
+fn fp_scratch(ty: Type, index: u32) -> FReg {
+    match ty {
+        Type::F32 => s_reg(16 + index),
+        _ => d_reg(16 + index),
+    }
+}
+
+/// Returns the `W` view of the register for values of up to 32 bits and the `X` view otherwise
+fn int_view(reg: Reg, ty: Type) -> Reg {
+    match ty.bytes() {
+        8 => reg,
+        _ => low_32(reg),
+    }
+}
+
+fn width(ty: Type) -> Width {
+    match ty.bytes() {
+        1 => Width::Byte,
+        2 => Width::Half,
+        4 => Width::Word,
+        _ => Width::Double,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arch::a64::emu::Emulator, assembler::Assembler, ir::regalloc::allocate};
+
+    fn emulate(functions: &[Function]) -> Emulator {
+        let mut asm = Asm::default();
+        lower(&mut asm, functions);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        Emulator::new(code, vtable)
+    }
+
+    /// `f(a, b) = (a + b) * (a - b) / b`
+    fn arithmetic(ty: Type) -> Function {
+        let mut func = Function::new("f".to_string(), &[ty, ty], Some(ty));
+        let entry = func.entry();
+        let (a, b) = (func.param(0), func.param(1));
+        let sum = func.float(entry, FloatOp::Add, a, b);
+        let difference = func.float(entry, FloatOp::Sub, a, b);
+        let product = func.float(entry, FloatOp::Mul, sum, difference);
+        let quotient = func.float(entry, FloatOp::Div, product, b);
+        func.ret(entry, Some(quotient));
+        func
+    }
+
+    const INPUTS: [f64; 9] = [
+        0.0,
+        -0.0,
+        1.5,
+        -3.25,
+        1e-310,
+        1e300,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+    ];
+
+    #[test]
+    fn float_arithmetic() {
+        let mut emu = emulate(&[arithmetic(Type::F64)]);
+        for a in INPUTS {
+            for b in INPUTS {
+                let expected = (a + b) * (a - b) / b;
+                let result = f64::from_bits(emu.call("f", &[a.to_bits(), b.to_bits()]).unwrap());
+                assert!(
+                    result.to_bits() == expected.to_bits() || result.is_nan() && expected.is_nan(),
+                    "f({a}, {b}) = {result} instead of {expected}"
+                );
+            }
+        }
+
+        let mut emu = emulate(&[arithmetic(Type::F32)]);
+        for a in INPUTS.map(|it| it as f32) {
+            for b in INPUTS.map(|it| it as f32) {
+                let expected = (a + b) * (a - b) / b;
+                let bits = emu.call("f", &[a.to_bits() as u64, b.to_bits() as u64]);
+                let result = f32::from_bits(bits.unwrap() as u32);
+                assert!(
+                    result.to_bits() == expected.to_bits() || result.is_nan() && expected.is_nan(),
+                    "f({a}, {b}) = {result} instead of {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn float_compare() {
+        const CONDS: [FloatCond; 6] = [
+            FloatCond::Eq,
+            FloatCond::Ne,
+            FloatCond::Lt,
+            FloatCond::Le,
+            FloatCond::Gt,
+            FloatCond::Ge,
+        ];
+        let functions: Vec<Function> = CONDS
+            .iter()
+            .enumerate()
+            .map(|(index, cond)| {
+                let mut func =
+                    Function::new(format!("f{index}"), &[Type::F64, Type::F64], Some(Type::I8));
+                let entry = func.entry();
+                let (a, b) = (func.param(0), func.param(1));
+                let result = func.fcmp(entry, *cond, a, b);
+                func.ret(entry, Some(result));
+                func
+            })
+            .collect();
+        let mut emu = emulate(&functions);
+        for (index, cond) in CONDS.iter().enumerate() {
+            for a in INPUTS {
+                for b in INPUTS {
+                    let expected = match cond {
+                        FloatCond::Eq => a == b,
+                        FloatCond::Ne => a != b,
+                        FloatCond::Lt => a < b,
+                        FloatCond::Le => a <= b,
+                        FloatCond::Gt => a > b,
+                        FloatCond::Ge => a >= b,
+                    };
+                    let result = emu.call(&format!("f{index}"), &[a.to_bits(), b.to_bits()]);
+                    assert_eq!(result, Ok(expected as u64), "{a} {cond:?} {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        let int_types = [Type::I8, Type::I16, Type::I32, Type::I64];
+        let float_types = [Type::F32, Type::F64];
+        let mut functions = Vec::new();
+        for int_ty in int_types {
+            for float_ty in float_types {
+                let mut func = Function::new(
+                    format!("{int_ty:?}_to_{float_ty:?}"),
+                    &[int_ty],
+                    Some(float_ty),
+                );
+                let entry = func.entry();
+                let result = func.int_to_float(entry, float_ty, func.param(0));
+                func.ret(entry, Some(result));
+                functions.push(func);
+
+                let mut func = Function::new(
+                    format!("{float_ty:?}_to_{int_ty:?}"),
+                    &[float_ty],
+                    Some(int_ty),
+                );
+                let entry = func.entry();
+                let result = func.float_to_int(entry, int_ty, func.param(0));
+                func.ret(entry, Some(result));
+                functions.push(func);
+            }
+        }
+        let mut emu = emulate(&functions);
+        // Constant folding evaluates conversions the same way
+        let ints: [i64; 7] = [0, 1, -1, 127, -128, i32::MIN as i64, i64::MAX];
+        let floats = [0.0, -0.9, 2.5, -200.7, 40000.0, 3e9, -1e19, 1e30, f64::NAN];
+        for int_ty in int_types {
+            for float_ty in float_types {
+                let max = (int_ty.mask() >> 1) as i64;
+                let shift = 64 - int_ty.bytes() * 8;
+                for value in ints {
+                    let signed = (value << shift) >> shift;
+                    let expected = match float_ty {
+                        Type::F32 => (signed as f32).to_bits() as u64,
+                        _ => (signed as f64).to_bits(),
+                    };
+                    let value = value as u64 & int_ty.mask();
+                    let label = format!("{int_ty:?}_to_{float_ty:?}");
+                    assert_eq!(emu.call(&label, &[value]), Ok(expected), "{label}({value})");
+                }
+                for value in floats {
+                    let (bits, wide) = match float_ty {
+                        Type::F32 => ((value as f32).to_bits() as u64, value as f32 as i64),
+                        _ => (value.to_bits(), value as i64),
+                    };
+                    // `fcvtzs` saturates to the target width and turns NaN into 0
+                    let expected = wide.clamp(!max, max) as u64 & int_ty.mask();
+                    let label = format!("{float_ty:?}_to_{int_ty:?}");
+                    assert_eq!(emu.call(&label, &[bits]), Ok(expected), "{label}({value})");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn float_ops_are_not_calls() {
+        // Values live across float instructions stay in caller-saved registers
+        let mut func = Function::new("f".to_string(), &[Type::F64, Type::I64], Some(Type::I64));
+        let entry = func.entry();
+        let (a, n) = (func.param(0), func.param(1));
+        let square = func.float(entry, FloatOp::Mul, a, a);
+        let truncated = func.float_to_int(entry, Type::I64, square);
+        let result = func.int(entry, IntOp::Add, truncated, n);
+        func.ret(entry, Some(result));
+        assert!(allocate(&func).callee_saved.is_empty());
+
+        let mut asm = Asm::default();
+        lower(&mut asm, &[func]);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        assert!(
+            code.chunks(4).all(
+                |insn| u32::from_le_bytes(insn.try_into().unwrap()) & 0xFFFFFC1F != 0xD63F0000
+            ),
+            "Float instructions were lowered to calls"
+        );
+        let mut emu = Emulator::new(code, vtable);
+        assert_eq!(emu.call("f", &[2.5f64.to_bits(), 10]), Ok(16));
+    }
+}
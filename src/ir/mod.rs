@@ -0,0 +1,501 @@
+pub mod cfg;
+pub mod lower;
+pub mod opt;
+pub mod regalloc;
+pub mod validate;
+
+pub use crate::assembler::Condition;
+
+/// Type of an SSA value
+///
+/// Comparisons produce `I8` values that are either 0 or 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl Type {
+    pub fn is_float(self) -> bool {
+        matches!(self, Self::F32 | Self::F64)
+    }
+
+    pub fn bytes(self) -> usize {
+        match self {
+            Self::I8 => 1,
+            Self::I16 => 2,
+            Self::I32 | Self::F32 => 4,
+            Self::I64 | Self::F64 => 8,
+        }
+    }
+
+    /// Returns the mask of the bits an integer value of this type occupies
+    pub fn mask(self) -> u64 {
+        match self.bytes() {
+            8 => u64::MAX,
+            bytes => (1 << (bytes * 8)) - 1,
+        }
+    }
+}
+
+/// SSA value defined either by an instruction or as a block parameter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub(crate) u32);
+
+impl Value {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Basic block of a function, the first one being the entry block
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Block(pub(crate) u32);
+
+impl Block {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Integer operation on two values of the same type, wrapping on overflow
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IntOp {
+    Add,
+    Sub,
+    Mul,
+    And,
+    Or,
+    Xor,
+}
+
+/// IEEE 754 operation on two values of the same type
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Ordered comparison of two floats, false if either is NaN except for `Ne`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FloatCond {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inst {
+    /// Integer constant truncated to `ty`
+    Iconst {
+        ty: Type,
+        imm: i64,
+    },
+    /// Float constant rounded to `ty`
+    Fconst {
+        ty: Type,
+        imm: f64,
+    },
+    Int {
+        op: IntOp,
+        lhs: Value,
+        rhs: Value,
+    },
+    Float {
+        op: FloatOp,
+        lhs: Value,
+        rhs: Value,
+    },
+    Icmp {
+        cond: Condition,
+        lhs: Value,
+        rhs: Value,
+    },
+    Fcmp {
+        cond: FloatCond,
+        lhs: Value,
+        rhs: Value,
+    },
+    /// Converts a signed integer to the float type `ty`
+    IntToFloat {
+        ty: Type,
+        value: Value,
+    },
+    /// Converts a float to the signed integer type `ty`, rounding towards zero and saturating
+    FloatToInt {
+        ty: Type,
+        value: Value,
+    },
+    /// Loads a value of `ty` from the address `addr + offset`
+    Load {
+        ty: Type,
+        addr: Value,
+        offset: i32,
+    },
+    /// Stores `value` at the address `addr + offset`
+    Store {
+        value: Value,
+        addr: Value,
+        offset: i32,
+    },
+    /// Calls label that must be present in the V-Table
+    Call {
+        label: String,
+        args: Vec<Value>,
+        ret: Option<Type>,
+    },
+}
+
+impl Inst {
+    /// Returns the values used by the instruction
+    pub fn args(&self) -> Vec<Value> {
+        match self {
+            Self::Iconst { .. } | Self::Fconst { .. } => Vec::new(),
+            Self::Int { lhs, rhs, .. }
+            | Self::Float { lhs, rhs, .. }
+            | Self::Icmp { lhs, rhs, .. }
+            | Self::Fcmp { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Self::IntToFloat { value, .. } | Self::FloatToInt { value, .. } => vec![*value],
+            Self::Load { addr, .. } => vec![*addr],
+            Self::Store { value, addr, .. } => vec![*value, *addr],
+            Self::Call { args, .. } => args.clone(),
+        }
+    }
+
+    /// Returns mutable references to the values used by the instruction
+    pub fn args_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Self::Iconst { .. } | Self::Fconst { .. } => Vec::new(),
+            Self::Int { lhs, rhs, .. }
+            | Self::Float { lhs, rhs, .. }
+            | Self::Icmp { lhs, rhs, .. }
+            | Self::Fcmp { lhs, rhs, .. } => vec![lhs, rhs],
+            Self::IntToFloat { value, .. } | Self::FloatToInt { value, .. } => vec![value],
+            Self::Load { addr, .. } => vec![addr],
+            Self::Store { value, addr, .. } => vec![value, addr],
+            Self::Call { args, .. } => args.iter_mut().collect(),
+        }
+    }
+
+    /// Whether the instruction has effects besides defining its result
+    pub fn has_side_effects(&self) -> bool {
+        matches!(self, Self::Store { .. } | Self::Call { .. })
+    }
+}
+
+/// Target of a branch passing values to the parameters of the block
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockCall {
+    pub block: Block,
+    pub args: Vec<Value>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Jump(BlockCall),
+    /// Branches to `then` if `cond` is not zero and to `otherwise` if it is
+    Branch {
+        cond: Value,
+        then: BlockCall,
+        otherwise: BlockCall,
+    },
+    Return(Option<Value>),
+}
+
+impl Terminator {
+    /// Returns the blocks that can be branched to
+    pub fn successors(&self) -> Vec<&BlockCall> {
+        match self {
+            Self::Jump(call) => vec![call],
+            Self::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Self::Return(_) => Vec::new(),
+        }
+    }
+
+    /// Returns mutable references to the blocks that can be branched to
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockCall> {
+        match self {
+            Self::Jump(call) => vec![call],
+            Self::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Self::Return(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the values used by the terminator
+    pub fn args(&self) -> Vec<Value> {
+        match self {
+            Self::Jump(call) => call.args.clone(),
+            Self::Branch {
+                cond,
+                then,
+                otherwise,
+            } => std::iter::once(*cond)
+                .chain(then.args.iter().copied())
+                .chain(otherwise.args.iter().copied())
+                .collect(),
+            Self::Return(value) => value.iter().copied().collect(),
+        }
+    }
+
+    /// Returns mutable references to the values used by the terminator
+    pub fn args_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Self::Jump(call) => call.args.iter_mut().collect(),
+            Self::Branch {
+                cond,
+                then,
+                otherwise,
+            } => std::iter::once(cond)
+                .chain(then.args.iter_mut())
+                .chain(otherwise.args.iter_mut())
+                .collect(),
+            Self::Return(value) => value.iter_mut().collect(),
+        }
+    }
+}
+
+/// Instruction and the value it defines
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstData {
+    pub inst: Inst,
+    pub result: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockData {
+    pub params: Vec<Value>,
+    pub insts: Vec<InstData>,
+    pub terminator: Option<Terminator>,
+}
+
+/// Function in SSA form whose parameters are the parameters of the entry block
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub ret: Option<Type>,
+    pub blocks: Vec<BlockData>,
+    /// Type of every value indexed by `Value::index`
+    pub types: Vec<Type>,
+}
+
+impl Function {
+    /// Creates a function with an entry block taking the parameters
+    pub fn new(name: String, params: &[Type], ret: Option<Type>) -> Self {
+        let mut func = Self {
+            name,
+            ret,
+            blocks: Vec::with_capacity(1),
+            types: Vec::with_capacity(params.len()),
+        };
+        let entry = func.create_block();
+        for ty in params {
+            func.add_block_param(entry, *ty);
+        }
+        func
+    }
+
+    pub fn entry(&self) -> Block {
+        Block(0)
+    }
+
+    /// Returns the parameter of the function with the given index
+    pub fn param(&self, index: usize) -> Value {
+        self.blocks[0].params[index]
+    }
+
+    pub fn type_of(&self, value: Value) -> Type {
+        self.types[value.index()]
+    }
+
+    pub fn create_block(&mut self) -> Block {
+        self.blocks.push(BlockData::default());
+        Block(self.blocks.len() as u32 - 1)
+    }
+
+    pub fn add_block_param(&mut self, block: Block, ty: Type) -> Value {
+        let value = self.new_value(ty);
+        self.blocks[block.index()].params.push(value);
+        value
+    }
+
+    /// Creates a value that is not defined yet
+    pub fn new_value(&mut self, ty: Type) -> Value {
+        self.types.push(ty);
+        Value(self.types.len() as u32 - 1)
+    }
+
+    pub fn iconst(&mut self, block: Block, ty: Type, imm: i64) -> Value {
+        assert!(!ty.is_float(), "Integer constant must have an integer type");
+        self.push(block, Inst::Iconst { ty, imm }, Some(ty))
+            .unwrap()
+    }
+
+    pub fn fconst(&mut self, block: Block, ty: Type, imm: f64) -> Value {
+        assert!(ty.is_float(), "Float constant must have a float type");
+        self.push(block, Inst::Fconst { ty, imm }, Some(ty))
+            .unwrap()
+    }
+
+    pub fn int(&mut self, block: Block, op: IntOp, lhs: Value, rhs: Value) -> Value {
+        let ty = self.binary_type(lhs, rhs);
+        assert!(!ty.is_float(), "Operands must be integers");
+        self.push(block, Inst::Int { op, lhs, rhs }, Some(ty))
+            .unwrap()
+    }
+
+    pub fn float(&mut self, block: Block, op: FloatOp, lhs: Value, rhs: Value) -> Value {
+        let ty = self.binary_type(lhs, rhs);
+        assert!(ty.is_float(), "Operands must be floats");
+        self.push(block, Inst::Float { op, lhs, rhs }, Some(ty))
+            .unwrap()
+    }
+
+    pub fn icmp(&mut self, block: Block, cond: Condition, lhs: Value, rhs: Value) -> Value {
+        let ty = self.binary_type(lhs, rhs);
+        assert!(!ty.is_float(), "Operands must be integers");
+        self.push(block, Inst::Icmp { cond, lhs, rhs }, Some(Type::I8))
+            .unwrap()
+    }
+
+    pub fn fcmp(&mut self, block: Block, cond: FloatCond, lhs: Value, rhs: Value) -> Value {
+        let ty = self.binary_type(lhs, rhs);
+        assert!(ty.is_float(), "Operands must be floats");
+        self.push(block, Inst::Fcmp { cond, lhs, rhs }, Some(Type::I8))
+            .unwrap()
+    }
+
+    pub fn int_to_float(&mut self, block: Block, ty: Type, value: Value) -> Value {
+        assert!(
+            ty.is_float() && !self.type_of(value).is_float(),
+            "Can only convert integers to floats"
+        );
+        self.push(block, Inst::IntToFloat { ty, value }, Some(ty))
+            .unwrap()
+    }
+
+    pub fn float_to_int(&mut self, block: Block, ty: Type, value: Value) -> Value {
+        assert!(
+            !ty.is_float() && self.type_of(value).is_float(),
+            "Can only convert floats to integers"
+        );
+        self.push(block, Inst::FloatToInt { ty, value }, Some(ty))
+            .unwrap()
+    }
+
+    pub fn load(&mut self, block: Block, ty: Type, addr: Value, offset: i32) -> Value {
+        assert!(self.type_of(addr) == Type::I64, "Address must be I64");
+        self.push(block, Inst::Load { ty, addr, offset }, Some(ty))
+            .unwrap()
+    }
+
+    pub fn store(&mut self, block: Block, value: Value, addr: Value, offset: i32) {
+        assert!(self.type_of(addr) == Type::I64, "Address must be I64");
+        self.push(
+            block,
+            Inst::Store {
+                value,
+                addr,
+                offset,
+            },
+            None,
+        );
+    }
+
+    pub fn call(
+        &mut self,
+        block: Block,
+        label: String,
+        args: &[Value],
+        ret: Option<Type>,
+    ) -> Option<Value> {
+        let args = args.to_vec();
+        self.push(block, Inst::Call { label, args, ret }, ret)
+    }
+
+    pub fn jump(&mut self, block: Block, target: Block, args: &[Value]) {
+        let target = self.block_call(target, args);
+        self.terminate(block, Terminator::Jump(target));
+    }
+
+    pub fn branch(
+        &mut self,
+        block: Block,
+        cond: Value,
+        then: (Block, &[Value]),
+        otherwise: (Block, &[Value]),
+    ) {
+        assert!(
+            !self.type_of(cond).is_float(),
+            "Condition must be an integer"
+        );
+        let then = self.block_call(then.0, then.1);
+        let otherwise = self.block_call(otherwise.0, otherwise.1);
+        self.terminate(
+            block,
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            },
+        );
+    }
+
+    pub fn ret(&mut self, block: Block, value: Option<Value>) {
+        assert!(
+            value.map(|value| self.type_of(value)) == self.ret,
+            "Returned value must match the return type"
+        );
+        self.terminate(block, Terminator::Return(value));
+    }
+
+    fn binary_type(&self, lhs: Value, rhs: Value) -> Type {
+        let ty = self.type_of(lhs);
+        assert!(ty == self.type_of(rhs), "Operands must be of the same type");
+        ty
+    }
+
+    fn block_call(&self, block: Block, args: &[Value]) -> BlockCall {
+        let params = &self.blocks[block.index()].params;
+        assert!(
+            params.len() == args.len()
+                && params
+                    .iter()
+                    .zip(args)
+                    .all(|(param, arg)| self.type_of(*param) == self.type_of(*arg)),
+            "Arguments must match the block parameters"
+        );
+        BlockCall {
+            block,
+            args: args.to_vec(),
+        }
+    }
+
+    fn push(&mut self, block: Block, inst: Inst, ty: Option<Type>) -> Option<Value> {
+        let data = &self.blocks[block.index()];
+        assert!(data.terminator.is_none(), "Block is already terminated");
+        let result = ty.map(|ty| self.new_value(ty));
+        self.blocks[block.index()]
+            .insts
+            .push(InstData { inst, result });
+        result
+    }
+
+    fn terminate(&mut self, block: Block, terminator: Terminator) {
+        let data = &mut self.blocks[block.index()];
+        assert!(data.terminator.is_none(), "Block is already terminated");
+        data.terminator = Some(terminator);
+    }
+}
@@ -0,0 +1,49 @@
+use super::{replace_uses, retain_params};
+use crate::ir::{cfg::Cfg, Function, Value};
+use std::collections::HashMap;
+
+pub(super) fn run(func: &mut Function) -> bool {
+    let cfg = Cfg::new(func);
+    let mut map: HashMap<Value, Value> = HashMap::new();
+    let resolve = |map: &HashMap<Value, Value>, mut value: Value| {
+        while let Some(next) = map.get(&value) {
+            value = *next;
+        }
+        value
+    };
+    let mut removed = Vec::new();
+    for block in cfg.reverse_postorder().iter().skip(1) {
+        let params = &func.blocks[block.index()].params;
+        let mut keep = vec![true; params.len()];
+        for (index, param) in params.iter().enumerate() {
+            let mut source = None;
+            let mut unique = true;
+            for pred in cfg.predecessors(*block) {
+                let terminator = func.blocks[pred.index()].terminator.as_ref().unwrap();
+                for call in terminator.successors() {
+                    if call.block != *block {
+                        continue;
+                    }
+                    let arg = resolve(&map, call.args[index]);
+                    if arg == *param {
+                        continue;
+                    }
+                    unique &= source.is_none_or(|source| source == arg);
+                    source = Some(arg);
+                }
+            }
+            if let (Some(source), true) = (source, unique) {
+                map.insert(*param, source);
+                keep[index] = false;
+            }
+        }
+        if keep.contains(&false) {
+            removed.push((block.index(), keep));
+        }
+    }
+    for (block, keep) in &removed {
+        retain_params(func, *block, keep);
+    }
+    replace_uses(func, &map);
+    !removed.is_empty()
+}
@@ -0,0 +1,66 @@
+use super::replace_uses;
+use crate::ir::{cfg::Cfg, Block, Function, Inst, Value};
+use std::{collections::HashMap, mem::Discriminant};
+
+type Key = (Discriminant<Inst>, Vec<Value>);
+
+pub(super) fn run(func: &mut Function) -> bool {
+    let cfg = Cfg::new(func);
+    let mut map: HashMap<Value, Value> = HashMap::new();
+    let mut available: HashMap<Key, Vec<(Inst, Value)>> = HashMap::new();
+    let mut changed = false;
+    // Walks the dominator tree keeping the instructions of the dominating blocks available, every
+    // block is visited twice, the second time removing what it made available
+    let mut stack = vec![(Block(0), true)];
+    let mut scopes: Vec<Vec<Key>> = Vec::new();
+    while let Some((block, enter)) = stack.pop() {
+        if !enter {
+            for key in scopes.pop().unwrap() {
+                available.get_mut(&key).unwrap().pop();
+            }
+            continue;
+        }
+        let mut scope = Vec::new();
+        func.blocks[block.index()].insts.retain_mut(|inst| {
+            for arg in inst.inst.args_mut() {
+                if let Some(value) = map.get(arg) {
+                    *arg = *value;
+                }
+            }
+            let Some(result) = inst.result else {
+                return true;
+            };
+            if inst.inst.has_side_effects() || matches!(inst.inst, Inst::Load { .. }) {
+                return true;
+            }
+            let key = (std::mem::discriminant(&inst.inst), inst.inst.args());
+            let candidates = available.entry(key.clone()).or_default();
+            if let Some((_, value)) = candidates.iter().find(|(other, _)| same(other, &inst.inst)) {
+                map.insert(result, *value);
+                changed = true;
+                return false;
+            }
+            candidates.push((inst.inst.clone(), result));
+            scope.push(key);
+            true
+        });
+        scopes.push(scope);
+        stack.push((block, false));
+        for child in cfg.children(block).iter().rev() {
+            stack.push((*child, true));
+        }
+    }
+    replace_uses(func, &map);
+    changed
+}
+
+/// Whether the instructions compute the same value, telling apart float constants that compare
+/// equal but have different bit patterns
+fn same(a: &Inst, b: &Inst) -> bool {
+    match (a, b) {
+        (Inst::Fconst { ty: a_ty, imm: a }, Inst::Fconst { ty: b_ty, imm: b }) => {
+            a_ty == b_ty && a.to_bits() == b.to_bits()
+        }
+        _ => a == b,
+    }
+}
@@ -0,0 +1,40 @@
+use super::retain_params;
+use crate::ir::{Function, Value};
+
+pub(super) fn run(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let mut uses = vec![0usize; func.types.len()];
+        for data in &func.blocks {
+            let insts = data.insts.iter().flat_map(|inst| inst.inst.args());
+            let terminator = data.terminator.iter().flat_map(|t| t.args());
+            for arg in insts.chain(terminator) {
+                uses[arg.index()] += 1;
+            }
+        }
+        let used = |value: Option<Value>| value.is_some_and(|value| uses[value.index()] > 0);
+
+        let mut removed = false;
+        for data in &mut func.blocks {
+            let len = data.insts.len();
+            data.insts
+                .retain(|inst| inst.inst.has_side_effects() || used(inst.result));
+            removed |= data.insts.len() != len;
+        }
+        for block in 1..func.blocks.len() {
+            let keep: Vec<bool> = func.blocks[block]
+                .params
+                .iter()
+                .map(|param| used(Some(*param)))
+                .collect();
+            if keep.contains(&false) {
+                retain_params(func, block, &keep);
+                removed = true;
+            }
+        }
+        if !removed {
+            return changed;
+        }
+        changed = true;
+    }
+}
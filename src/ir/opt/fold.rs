@@ -0,0 +1,175 @@
+use crate::ir::{
+    cfg::Cfg, Condition, FloatCond, FloatOp, Function, Inst, IntOp, Terminator, Type, Value,
+};
+use std::collections::HashMap;
+use std::ops::{Add, Div, Mul, Sub};
+
+pub(super) fn run(func: &mut Function) -> bool {
+    let cfg = Cfg::new(func);
+    let Function { blocks, types, .. } = func;
+    // Bit patterns of the constants as the lowering keeps them in registers
+    let mut consts: HashMap<Value, u64> = HashMap::new();
+    let mut changed = false;
+    for block in cfg.reverse_postorder() {
+        let data = &mut blocks[block.index()];
+        for inst in &mut data.insts {
+            let Some(result) = inst.result else {
+                continue;
+            };
+            let ty = types[result.index()];
+            let bits = match &inst.inst {
+                Inst::Iconst { imm, .. } => *imm as u64 & ty.mask(),
+                Inst::Fconst { imm, .. } => float_bits(ty, *imm),
+                other => match fold(other, &consts, types) {
+                    Some(bits) => {
+                        inst.inst = constant(ty, bits);
+                        changed = true;
+                        bits
+                    }
+                    None => continue,
+                },
+            };
+            consts.insert(result, bits);
+        }
+        if let Some(Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        }) = &data.terminator
+        {
+            if let Some(cond) = consts.get(cond) {
+                let target = if *cond != 0 { then } else { otherwise }.clone();
+                data.terminator = Some(Terminator::Jump(target));
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Evaluates the instruction the same way the lowered code would
+fn fold(inst: &Inst, consts: &HashMap<Value, u64>, types: &[Type]) -> Option<u64> {
+    let get = |value: &Value| consts.get(value).copied();
+    let ty = |value: &Value| types[value.index()];
+    let bits = match inst {
+        Inst::Int { op, lhs, rhs } => {
+            let (l, r) = (get(lhs)?, get(rhs)?);
+            let bits = match op {
+                IntOp::Add => l.wrapping_add(r),
+                IntOp::Sub => l.wrapping_sub(r),
+                IntOp::Mul => l.wrapping_mul(r),
+                IntOp::And => l & r,
+                IntOp::Or => l | r,
+                IntOp::Xor => l ^ r,
+            };
+            bits & ty(lhs).mask()
+        }
+        Inst::Icmp { cond, lhs, rhs } => {
+            let (l, r) = (get(lhs)?, get(rhs)?);
+            let shift = 64 - ty(lhs).bytes() * 8;
+            let (sl, sr) = (
+                ((l << shift) as i64) >> shift,
+                ((r << shift) as i64) >> shift,
+            );
+            let result = match cond {
+                Condition::Eq => l == r,
+                Condition::Ne => l != r,
+                Condition::Lt => sl < sr,
+                Condition::Le => sl <= sr,
+                Condition::Gt => sl > sr,
+                Condition::Ge => sl >= sr,
+                Condition::Below => l < r,
+                Condition::BelowEq => l <= r,
+                Condition::Above => l > r,
+                Condition::AboveEq => l >= r,
+            };
+            result as u64
+        }
+        Inst::Float { op, lhs, rhs } => {
+            let (l, r) = (get(lhs)?, get(rhs)?);
+            match ty(lhs) {
+                Type::F32 => {
+                    let result = binary(*op, f32::from_bits(l as u32), f32::from_bits(r as u32));
+                    result.to_bits() as u64
+                }
+                _ => binary(*op, f64::from_bits(l), f64::from_bits(r)).to_bits(),
+            }
+        }
+        Inst::Fcmp { cond, lhs, rhs } => {
+            let (l, r) = (get(lhs)?, get(rhs)?);
+            let result = match ty(lhs) {
+                Type::F32 => compare(*cond, f32::from_bits(l as u32), f32::from_bits(r as u32)),
+                _ => compare(*cond, f64::from_bits(l), f64::from_bits(r)),
+            };
+            result as u64
+        }
+        Inst::IntToFloat { ty: to, value } => {
+            let shift = 64 - ty(value).bytes() * 8;
+            let signed = ((get(value)? << shift) as i64) >> shift;
+            match to {
+                Type::F32 => (signed as f32).to_bits() as u64,
+                _ => (signed as f64).to_bits(),
+            }
+        }
+        Inst::FloatToInt { ty: to, value } => {
+            let bits = get(value)?;
+            // Like `fcvtzs` the conversion saturates and NaN becomes 0
+            let wide = match ty(value) {
+                Type::F32 => f32::from_bits(bits as u32) as i64,
+                _ => f64::from_bits(bits) as i64,
+            };
+            let max = (to.mask() >> 1) as i64;
+            wide.clamp(!max, max) as u64 & to.mask()
+        }
+        _ => return None,
+    };
+    Some(bits)
+}
+
+fn binary<T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Div<Output = T>>(
+    op: FloatOp,
+    lhs: T,
+    rhs: T,
+) -> T {
+    match op {
+        FloatOp::Add => lhs + rhs,
+        FloatOp::Sub => lhs - rhs,
+        FloatOp::Mul => lhs * rhs,
+        FloatOp::Div => lhs / rhs,
+    }
+}
+
+fn compare<T: PartialOrd>(cond: FloatCond, lhs: T, rhs: T) -> bool {
+    match cond {
+        FloatCond::Eq => lhs == rhs,
+        FloatCond::Ne => lhs != rhs,
+        FloatCond::Lt => lhs < rhs,
+        FloatCond::Le => lhs <= rhs,
+        FloatCond::Gt => lhs > rhs,
+        FloatCond::Ge => lhs >= rhs,
+    }
+}
+
+fn float_bits(ty: Type, imm: f64) -> u64 {
+    match ty {
+        Type::F32 => (imm as f32).to_bits() as u64,
+        _ => imm.to_bits(),
+    }
+}
+
+fn constant(ty: Type, bits: u64) -> Inst {
+    match ty {
+        Type::F32 => Inst::Fconst {
+            ty,
+            imm: f32::from_bits(bits as u32) as f64,
+        },
+        Type::F64 => Inst::Fconst {
+            ty,
+            imm: f64::from_bits(bits),
+        },
+        _ => Inst::Iconst {
+            ty,
+            imm: bits as i64,
+        },
+    }
+}
@@ -0,0 +1,75 @@
+use crate::ir::{cfg::Cfg, Block, Function, Inst, Terminator};
+use std::collections::HashSet;
+
+pub(super) fn run(func: &mut Function) -> bool {
+    let cfg = Cfg::new(func);
+    let mut changed = false;
+    for header in cfg.reverse_postorder() {
+        let preds = cfg.predecessors(*header);
+        let latches: Vec<Block> = preds
+            .iter()
+            .copied()
+            .filter(|pred| cfg.dominates(*header, *pred))
+            .collect();
+        if latches.is_empty() {
+            continue;
+        }
+
+        // Natural loop of the back edges: the header and every block reaching a latch without
+        // passing the header
+        let mut body = HashSet::from([*header]);
+        let mut work = latches;
+        while let Some(block) = work.pop() {
+            if body.insert(block) {
+                work.extend(cfg.predecessors(block));
+            }
+        }
+        let entries: Vec<Block> = preds
+            .iter()
+            .copied()
+            .filter(|pred| !body.contains(pred))
+            .collect();
+        let [preheader] = entries[..] else {
+            continue;
+        };
+        if !matches!(
+            func.blocks[preheader.index()].terminator,
+            Some(Terminator::Jump(_))
+        ) {
+            continue;
+        }
+
+        let mut defined = HashSet::new();
+        for block in &body {
+            let data = &func.blocks[block.index()];
+            defined.extend(data.params.iter().copied());
+            defined.extend(data.insts.iter().filter_map(|inst| inst.result));
+        }
+        let order: Vec<Block> = cfg
+            .reverse_postorder()
+            .iter()
+            .copied()
+            .filter(|block| body.contains(block))
+            .collect();
+        let mut hoisted = Vec::new();
+        for block in order {
+            func.blocks[block.index()].insts.retain(|inst| {
+                let invariant = !inst.inst.has_side_effects()
+                    && !matches!(inst.inst, Inst::Load { .. })
+                    && inst.inst.args().iter().all(|arg| !defined.contains(arg));
+                if invariant {
+                    if let Some(result) = inst.result {
+                        defined.remove(&result);
+                    }
+                    hoisted.push(inst.clone());
+                }
+                !invariant
+            });
+        }
+        if !hoisted.is_empty() {
+            func.blocks[preheader.index()].insts.extend(hoisted);
+            changed = true;
+        }
+    }
+    changed
+}
@@ -0,0 +1,540 @@
+mod copy_prop;
+mod cse;
+mod dce;
+mod fold;
+mod licm;
+mod simplify_cfg;
+
+use super::{validate::validate, Function, Value};
+use std::collections::HashMap;
+
+/// Upper bound of the rounds `PassManager::run` runs all passes for until none of them changes
+/// the function
+const MAX_ROUNDS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Pass {
+    /// Evaluates instructions and branches whose operands are constants
+    ConstantFolding,
+    /// Replaces block parameters that always receive the same value by that value
+    CopyPropagation,
+    /// Removes unused instructions without side effects and unused block parameters
+    DeadCodeElimination,
+    /// Merges blocks into their only predecessor, skips empty blocks and removes unreachable
+    /// blocks
+    CfgSimplification,
+    /// Replaces instructions by an identical instruction in a dominating position
+    CommonSubexpressionElimination,
+    /// Moves instructions that do not depend on a loop out of it, given the loop has a single
+    /// block jumping into it
+    LoopInvariantCodeMotion,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 6] = [
+        Self::ConstantFolding,
+        Self::CopyPropagation,
+        Self::CfgSimplification,
+        Self::CommonSubexpressionElimination,
+        Self::LoopInvariantCodeMotion,
+        Self::DeadCodeElimination,
+    ];
+
+    /// Runs the pass once returning whether it changed the function
+    pub fn run(self, func: &mut Function) -> bool {
+        match self {
+            Self::ConstantFolding => fold::run(func),
+            Self::CopyPropagation => copy_prop::run(func),
+            Self::DeadCodeElimination => dce::run(func),
+            Self::CfgSimplification => simplify_cfg::run(func),
+            Self::CommonSubexpressionElimination => cse::run(func),
+            Self::LoopInvariantCodeMotion => licm::run(func),
+        }
+    }
+}
+
+/// Runs the enabled passes in the order of `Pass::ALL` until the function does not change anymore
+///
+/// The function is validated before the first pass and after every pass, panicking with the name
+/// of the pass that produced invalid IR.
+pub struct PassManager {
+    enabled: Vec<Pass>,
+}
+
+impl PassManager {
+    pub fn new(passes: &[Pass]) -> Self {
+        let mut manager = Self {
+            enabled: Vec::with_capacity(passes.len()),
+        };
+        for pass in passes {
+            manager.enable(*pass);
+        }
+        manager
+    }
+
+    pub fn enable(&mut self, pass: Pass) {
+        if !self.enabled.contains(&pass) {
+            self.enabled.push(pass);
+        }
+    }
+
+    pub fn disable(&mut self, pass: Pass) {
+        self.enabled.retain(|enabled| *enabled != pass);
+    }
+
+    pub fn is_enabled(&self, pass: Pass) -> bool {
+        self.enabled.contains(&pass)
+    }
+
+    pub fn run(&self, func: &mut Function) {
+        if let Err(err) = validate(func) {
+            panic!("Invalid IR in {}: {err}", func.name);
+        }
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for pass in Pass::ALL {
+                if !self.is_enabled(pass) || !pass.run(func) {
+                    continue;
+                }
+                changed = true;
+                if let Err(err) = validate(func) {
+                    panic!("{pass:?} produced invalid IR in {}: {err}", func.name);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for PassManager {
+    /// Enables all passes
+    fn default() -> Self {
+        Self::new(&Pass::ALL)
+    }
+}
+
+/// Replaces every use of a key of `map` by its value, following chains of replacements
+fn replace_uses(func: &mut Function, map: &HashMap<Value, Value>) {
+    if map.is_empty() {
+        return;
+    }
+    let resolve = |mut value: Value| {
+        while let Some(next) = map.get(&value) {
+            value = *next;
+        }
+        value
+    };
+    for data in &mut func.blocks {
+        for inst in &mut data.insts {
+            for arg in inst.inst.args_mut() {
+                *arg = resolve(*arg);
+            }
+        }
+        for arg in data.terminator.iter_mut().flat_map(|t| t.args_mut()) {
+            *arg = resolve(*arg);
+        }
+    }
+}
+
+/// Removes the parameters of `block` for which `keep` returns false together with the arguments
+/// passed to them
+fn retain_params(func: &mut Function, block: usize, keep: &[bool]) {
+    let mut index = 0;
+    func.blocks[block].params.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+    for data in &mut func.blocks {
+        let Some(terminator) = &mut data.terminator else {
+            continue;
+        };
+        for call in terminator.successors_mut() {
+            if call.block.index() == block {
+                let mut index = 0;
+                call.args.retain(|_| {
+                    index += 1;
+                    keep[index - 1]
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::a64::{asm::Asm, emu::Emulator},
+        assembler::Assembler,
+        ir::{
+            lower::lower, BlockCall, BlockData, Condition, FloatOp, Inst, IntOp, Terminator, Type,
+        },
+    };
+
+    const ARGS: [[u64; 2]; 5] = [[0, 0], [1, 2], [5, 3], [7, 7], [u64::MAX, 4]];
+
+    fn emulate(functions: &[Function], args: &[u64]) -> u64 {
+        let mut asm = Asm::default();
+        lower(&mut asm, functions);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let result = Emulator::new(code, vtable).call(&functions[0].name, args);
+        result.unwrap() & functions[0].ret.map_or(0, |ty| ty.mask())
+    }
+
+    /// Runs the pass on the first function, checking that it changed valid IR without changing
+    /// the results of the function
+    fn optimize(pass: Pass, functions: &[Function]) -> Function {
+        let mut optimized = functions.to_vec();
+        assert!(
+            pass.run(&mut optimized[0]),
+            "{pass:?} did not change the function"
+        );
+        validate(&optimized[0]).unwrap();
+        for args in ARGS {
+            assert_eq!(
+                emulate(functions, &args),
+                emulate(&optimized, &args),
+                "{pass:?} changed the result for {args:?}"
+            );
+        }
+        optimized.swap_remove(0)
+    }
+
+    #[test]
+    fn constant_folding() {
+        let mut func = Function::new("f".to_string(), &[Type::I64], Some(Type::I64));
+        let (entry, then, otherwise) = (func.entry(), func.create_block(), func.create_block());
+        let x = func.param(0);
+        let two = func.iconst(entry, Type::I64, 2);
+        let three = func.iconst(entry, Type::I64, 3);
+        let five = func.int(entry, IntOp::Add, two, three);
+        let cond = func.icmp(entry, Condition::Gt, five, two);
+        func.branch(entry, cond, (then, &[]), (otherwise, &[]));
+        let product = func.int(then, IntOp::Mul, five, x);
+        func.ret(then, Some(product));
+        func.ret(otherwise, Some(x));
+
+        let optimized = optimize(Pass::ConstantFolding, &[func.clone()]);
+        let mut expected = func;
+        let entry = &mut expected.blocks[0];
+        entry.insts[2].inst = Inst::Iconst {
+            ty: Type::I64,
+            imm: 5,
+        };
+        entry.insts[3].inst = Inst::Iconst {
+            ty: Type::I8,
+            imm: 1,
+        };
+        entry.terminator = Some(Terminator::Jump(BlockCall {
+            block: then,
+            args: Vec::new(),
+        }));
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn constant_folding_floats() {
+        let mut func = Function::new("f".to_string(), &[Type::I32], Some(Type::I32));
+        let entry = func.entry();
+        let x = func.param(0);
+        let a = func.fconst(entry, Type::F64, 1.5);
+        let b = func.fconst(entry, Type::F64, -2.0);
+        let product = func.float(entry, FloatOp::Mul, a, b);
+        let truncated = func.float_to_int(entry, Type::I32, product);
+        let seven = func.iconst(entry, Type::I8, -7);
+        let converted = func.int_to_float(entry, Type::F32, seven);
+        let back = func.float_to_int(entry, Type::I32, converted);
+        let sum = func.int(entry, IntOp::Add, truncated, back);
+        let result = func.int(entry, IntOp::Add, sum, x);
+        func.ret(entry, Some(result));
+
+        let optimized = optimize(Pass::ConstantFolding, &[func.clone()]);
+        let mut expected = func;
+        let insts = &mut expected.blocks[0].insts;
+        insts[2].inst = Inst::Fconst {
+            ty: Type::F64,
+            imm: -3.0,
+        };
+        insts[3].inst = Inst::Iconst {
+            ty: Type::I32,
+            imm: -3i32 as u32 as i64,
+        };
+        insts[5].inst = Inst::Fconst {
+            ty: Type::F32,
+            imm: -7.0,
+        };
+        insts[6].inst = Inst::Iconst {
+            ty: Type::I32,
+            imm: -7i32 as u32 as i64,
+        };
+        insts[7].inst = Inst::Iconst {
+            ty: Type::I32,
+            imm: -10i32 as u32 as i64,
+        };
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn common_subexpression_elimination() {
+        let mut func = Function::new("f".to_string(), &[Type::I64, Type::I64], Some(Type::I64));
+        let (entry, then, otherwise) = (func.entry(), func.create_block(), func.create_block());
+        let (a, b) = (func.param(0), func.param(1));
+        let sum = func.int(entry, IntOp::Add, a, b);
+        let cond = func.icmp(entry, Condition::Lt, a, b);
+        func.fconst(entry, Type::F64, 0.0);
+        func.fconst(entry, Type::F64, -0.0);
+        func.fconst(entry, Type::F64, 0.0);
+        func.branch(entry, cond, (then, &[]), (otherwise, &[]));
+        // Dominated by the entry block
+        let same_sum = func.int(then, IntOp::Add, a, b);
+        let difference = func.int(then, IntOp::Sub, a, b);
+        let product = func.int(then, IntOp::Mul, same_sum, difference);
+        func.ret(then, Some(product));
+        // Not dominated by the other branch
+        let same_difference = func.int(otherwise, IntOp::Sub, a, b);
+        let swapped = func.int(otherwise, IntOp::Add, b, a);
+        let result = func.int(otherwise, IntOp::Xor, same_difference, swapped);
+        func.ret(otherwise, Some(result));
+
+        let optimized = optimize(Pass::CommonSubexpressionElimination, &[func.clone()]);
+        let mut expected = func;
+        expected.blocks[0].insts.remove(4);
+        let then = &mut expected.blocks[1].insts;
+        then.remove(0);
+        then[1].inst = Inst::Int {
+            op: IntOp::Mul,
+            lhs: sum,
+            rhs: difference,
+        };
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn loop_invariant_code_motion() {
+        let mut func = Function::new("f".to_string(), &[Type::I64], Some(Type::I64));
+        let (entry, header, exit) = (func.entry(), func.create_block(), func.create_block());
+        let n = func.param(0);
+        let i = func.add_block_param(header, Type::I64);
+        let acc = func.add_block_param(header, Type::I64);
+        let r = func.add_block_param(exit, Type::I64);
+        let zero = func.iconst(entry, Type::I64, 0);
+        let one = func.iconst(entry, Type::I64, 1);
+        let three = func.iconst(entry, Type::I64, 3);
+        func.jump(entry, header, &[zero, zero]);
+        func.iconst(header, Type::I64, 3);
+        let step = func.int(header, IntOp::Mul, n, three);
+        let next_acc = func.int(header, IntOp::Add, acc, step);
+        let next_i = func.int(header, IntOp::Add, i, one);
+        let cond = func.icmp(header, Condition::Lt, next_i, n);
+        func.branch(
+            header,
+            cond,
+            (header, &[next_i, next_acc]),
+            (exit, &[next_acc]),
+        );
+        func.ret(exit, Some(r));
+
+        let optimized = optimize(Pass::LoopInvariantCodeMotion, &[func.clone()]);
+        let mut expected = func;
+        let hoisted: Vec<_> = expected.blocks[1].insts.drain(..2).collect();
+        expected.blocks[0].insts.extend(hoisted);
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn cfg_simplification() {
+        let mut func = Function::new("f".to_string(), &[Type::I64], Some(Type::I64));
+        let entry = func.entry();
+        let [empty, merged, unreachable] = [(); 3].map(|_| func.create_block());
+        let x = func.param(0);
+        let p = func.add_block_param(merged, Type::I64);
+        let zero = func.iconst(entry, Type::I64, 0);
+        let cond = func.icmp(entry, Condition::Ne, x, zero);
+        func.branch(entry, cond, (empty, &[]), (empty, &[]));
+        func.jump(empty, merged, &[x]);
+        let one = func.iconst(merged, Type::I64, 1);
+        let result = func.int(merged, IntOp::Add, p, one);
+        func.ret(merged, Some(result));
+        let other = func.iconst(unreachable, Type::I64, 2);
+        func.ret(unreachable, Some(other));
+
+        let optimized = optimize(Pass::CfgSimplification, &[func.clone()]);
+        let mut insts = func.blocks[0].insts.clone();
+        insts.extend(func.blocks[merged.index()].insts.clone());
+        insts[3].inst = Inst::Int {
+            op: IntOp::Add,
+            lhs: x,
+            rhs: one,
+        };
+        let expected = Function {
+            blocks: vec![BlockData {
+                params: vec![x],
+                insts,
+                terminator: Some(Terminator::Return(Some(result))),
+            }],
+            ..func
+        };
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn copy_propagation() {
+        let mut func = Function::new("f".to_string(), &[Type::I64, Type::I64], Some(Type::I64));
+        let entry = func.entry();
+        let [then, otherwise, join] = [(); 3].map(|_| func.create_block());
+        let (x, y) = (func.param(0), func.param(1));
+        let p = func.add_block_param(join, Type::I64);
+        let q = func.add_block_param(join, Type::I64);
+        let cond = func.icmp(entry, Condition::Lt, x, y);
+        func.branch(entry, cond, (then, &[]), (otherwise, &[]));
+        func.jump(then, join, &[x, y]);
+        func.jump(otherwise, join, &[x, x]);
+        let result = func.int(join, IntOp::Sub, p, q);
+        func.ret(join, Some(result));
+
+        let optimized = optimize(Pass::CopyPropagation, &[func.clone()]);
+        let mut expected = func;
+        expected.blocks[join.index()].params = vec![q];
+        expected.blocks[join.index()].insts[0].inst = Inst::Int {
+            op: IntOp::Sub,
+            lhs: x,
+            rhs: q,
+        };
+        expected.blocks[then.index()].terminator = Some(Terminator::Jump(BlockCall {
+            block: join,
+            args: vec![y],
+        }));
+        expected.blocks[otherwise.index()].terminator = Some(Terminator::Jump(BlockCall {
+            block: join,
+            args: vec![x],
+        }));
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn copy_propagation_through_loops() {
+        let mut func = Function::new("f".to_string(), &[Type::I64, Type::I64], Some(Type::I64));
+        let (entry, header, exit) = (func.entry(), func.create_block(), func.create_block());
+        let (x, n) = (func.param(0), func.param(1));
+        // Only ever receives x or itself
+        let p = func.add_block_param(header, Type::I64);
+        let i = func.add_block_param(header, Type::I64);
+        let zero = func.iconst(entry, Type::I64, 0);
+        let one = func.iconst(entry, Type::I64, 1);
+        func.jump(entry, header, &[x, zero]);
+        let next_i = func.int(header, IntOp::Add, i, one);
+        let sum = func.int(header, IntOp::Add, p, next_i);
+        let cond = func.icmp(header, Condition::Lt, next_i, n);
+        func.branch(header, cond, (header, &[p, next_i]), (exit, &[]));
+        func.ret(exit, Some(sum));
+
+        let optimized = optimize(Pass::CopyPropagation, &[func.clone()]);
+        let mut expected = func;
+        expected.blocks[0].terminator = Some(Terminator::Jump(BlockCall {
+            block: header,
+            args: vec![zero],
+        }));
+        let header = &mut expected.blocks[header.index()];
+        header.params = vec![i];
+        header.insts[1].inst = Inst::Int {
+            op: IntOp::Add,
+            lhs: x,
+            rhs: next_i,
+        };
+        let Some(Terminator::Branch { then, .. }) = &mut header.terminator else {
+            unreachable!()
+        };
+        then.args = vec![next_i];
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn dead_code_elimination() {
+        let mut callee = Function::new("g".to_string(), &[Type::I64], None);
+        let entry = callee.entry();
+        callee.ret(entry, None);
+
+        let mut func = Function::new("f".to_string(), &[Type::I64], Some(Type::I64));
+        let (entry, next) = (func.entry(), func.create_block());
+        let x = func.param(0);
+        let p = func.add_block_param(next, Type::I64);
+        func.add_block_param(next, Type::I64);
+        func.int(entry, IntOp::Mul, x, x);
+        // Only used by the unused parameter
+        let one = func.iconst(entry, Type::I64, 1);
+        func.call(entry, "g".to_string(), &[x], None);
+        func.jump(entry, next, &[x, one]);
+        let result = func.int(next, IntOp::Add, p, p);
+        func.ret(next, Some(result));
+
+        let optimized = optimize(Pass::DeadCodeElimination, &[func.clone(), callee]);
+        let mut expected = func;
+        expected.blocks[0].insts.drain(..2);
+        expected.blocks[0].terminator = Some(Terminator::Jump(BlockCall {
+            block: next,
+            args: vec![x],
+        }));
+        expected.blocks[1].params = vec![p];
+        assert_eq!(optimized, expected);
+    }
+
+    #[test]
+    fn unchanged_functions() {
+        let mut func = Function::new("f".to_string(), &[Type::I64], Some(Type::I64));
+        let entry = func.entry();
+        let x = func.param(0);
+        let square = func.int(entry, IntOp::Mul, x, x);
+        func.ret(entry, Some(square));
+        for pass in Pass::ALL {
+            let mut optimized = func.clone();
+            assert!(!pass.run(&mut optimized), "{pass:?} changed the function");
+            assert_eq!(optimized, func);
+        }
+    }
+
+    #[test]
+    fn pass_manager() {
+        // Sums 1 to 10 in a loop whose result the passes cannot compute
+        let mut func = Function::new("f".to_string(), &[Type::I64], Some(Type::I64));
+        let (entry, header, exit) = (func.entry(), func.create_block(), func.create_block());
+        let x = func.param(0);
+        let i = func.add_block_param(header, Type::I64);
+        let acc = func.add_block_param(header, Type::I64);
+        let zero = func.iconst(entry, Type::I64, 0);
+        func.jump(entry, header, &[zero, x]);
+        let one = func.iconst(header, Type::I64, 1);
+        let ten = func.iconst(header, Type::I64, 10);
+        let unused = func.int(header, IntOp::Mul, x, ten);
+        func.int(header, IntOp::Add, unused, one);
+        let next_i = func.int(header, IntOp::Add, i, one);
+        let next_acc = func.int(header, IntOp::Add, acc, next_i);
+        let cond = func.icmp(header, Condition::Lt, next_i, ten);
+        func.branch(header, cond, (header, &[next_i, next_acc]), (exit, &[]));
+        func.ret(exit, Some(next_acc));
+
+        let mut optimized = func.clone();
+        PassManager::default().run(&mut optimized);
+        assert_eq!(optimized.blocks.len(), 3);
+        let [preheader, header, _] = &optimized.blocks[..] else {
+            unreachable!()
+        };
+        assert_eq!(preheader.insts.len(), 3, "Constants were not hoisted");
+        assert_eq!(header.insts.len(), 3, "Dead instructions were not removed");
+        for args in ARGS {
+            assert_eq!(
+                emulate(&[func.clone()], &args),
+                emulate(&[optimized.clone()], &args)
+            );
+        }
+        assert_eq!(emulate(&[optimized], &[4, 0]), 59);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid IR in f: Block(0) is not terminated")]
+    fn pass_manager_rejects_invalid_ir() {
+        let mut func = Function::new("f".to_string(), &[], None);
+        PassManager::default().run(&mut func);
+    }
+}
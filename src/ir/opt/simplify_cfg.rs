@@ -0,0 +1,107 @@
+use super::replace_uses;
+use crate::ir::{cfg::Cfg, Block, Function, Terminator};
+use std::collections::HashMap;
+
+pub(super) fn run(func: &mut Function) -> bool {
+    let mut changed = false;
+    while fold_branches(func) | thread_jumps(func) | merge_blocks(func) | remove_unreachable(func) {
+        changed = true;
+    }
+    changed
+}
+
+/// Turns branches with identical targets into jumps
+fn fold_branches(func: &mut Function) -> bool {
+    let mut changed = false;
+    for data in &mut func.blocks {
+        if let Some(Terminator::Branch {
+            then, otherwise, ..
+        }) = &data.terminator
+        {
+            if then == otherwise {
+                data.terminator = Some(Terminator::Jump(then.clone()));
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Lets predecessors of blocks that only jump somewhere else branch there directly
+fn thread_jumps(func: &mut Function) -> bool {
+    let mut changed = false;
+    for block in 1..func.blocks.len() {
+        let data = &func.blocks[block];
+        let Some(Terminator::Jump(target)) = &data.terminator else {
+            continue;
+        };
+        if !data.params.is_empty() || !data.insts.is_empty() || target.block.index() == block {
+            continue;
+        }
+        let target = target.clone();
+        for data in &mut func.blocks {
+            for call in data.terminator.iter_mut().flat_map(|t| t.successors_mut()) {
+                if call.block.index() == block {
+                    *call = target.clone();
+                    changed = true;
+                }
+            }
+        }
+    }
+    changed
+}
+
+/// Appends a block to its only predecessor if that jumps to it unconditionally
+fn merge_blocks(func: &mut Function) -> bool {
+    let mut edges = vec![0usize; func.blocks.len()];
+    for data in &func.blocks {
+        for call in data.terminator.iter().flat_map(|t| t.successors()) {
+            edges[call.block.index()] += 1;
+        }
+    }
+    for block in 0..func.blocks.len() {
+        let Some(Terminator::Jump(call)) = &func.blocks[block].terminator else {
+            continue;
+        };
+        let succ = call.block.index();
+        if succ == block || succ == 0 || edges[succ] != 1 {
+            continue;
+        }
+        let args = call.args.clone();
+        let map: HashMap<_, _> = func.blocks[succ].params.drain(..).zip(args).collect();
+        let insts = std::mem::take(&mut func.blocks[succ].insts);
+        let terminator = func.blocks[succ].terminator.take();
+        let data = &mut func.blocks[block];
+        data.insts.extend(insts);
+        data.terminator = terminator;
+        replace_uses(func, &map);
+        return true;
+    }
+    false
+}
+
+/// Removes blocks that cannot be reached from the entry block
+fn remove_unreachable(func: &mut Function) -> bool {
+    let cfg = Cfg::new(func);
+    let mut indices = Vec::with_capacity(func.blocks.len());
+    let mut next = 0;
+    for index in 0..func.blocks.len() {
+        let reachable = cfg.is_reachable(Block(index as u32));
+        indices.push(reachable.then_some(Block(next)));
+        next += reachable as u32;
+    }
+    if next as usize == func.blocks.len() {
+        return false;
+    }
+    let mut index = 0;
+    func.blocks.retain(|_| {
+        index += 1;
+        indices[index - 1].is_some()
+    });
+    for data in &mut func.blocks {
+        for call in data.terminator.iter_mut().flat_map(|t| t.successors_mut()) {
+            call.block = indices[call.block.index()].unwrap();
+        }
+    }
+    true
+}
@@ -0,0 +1,368 @@
+use super::{Function, Inst, Value};
+use crate::arch::a64::reg::{is_callee_saved, Reg, CALLEE_SAVED};
+use std::collections::HashSet;
+
+/// Caller-saved registers handed out by the allocator
+///
+/// `X0` to `X7` are left for arguments and `X9` to `X11` for the lowering to load spilled values
+/// into, while `X16` to `X18` are reserved by the platform.
+const CALLER_SAVED: [Reg; 5] = [Reg::X8, Reg::X12, Reg::X13, Reg::X14, Reg::X15];
+
+/// Where a value lives for its whole lifetime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Location {
+    Reg(Reg),
+    /// Index of an 8-byte spill slot
+    Stack(usize),
+}
+
+/// Result of the register allocation of a function
+#[derive(Clone, Debug)]
+pub struct Allocation {
+    /// Location of every value indexed by `Value::index`, `None` for values that are never defined
+    pub locations: Vec<Option<Location>>,
+    pub spill_slots: usize,
+    /// Callee-saved registers in use, which the prologue has to save
+    pub callee_saved: Vec<Reg>,
+}
+
+impl Allocation {
+    pub fn location(&self, value: Value) -> Location {
+        self.locations[value.index()].expect("Value has no location")
+    }
+}
+
+/// Whether the lowering turns the instruction into a call clobbering the caller-saved registers
+pub fn is_call(inst: &Inst) -> bool {
+    matches!(inst, Inst::Call { .. })
+}
+
+struct Interval {
+    value: Value,
+    start: usize,
+    end: usize,
+    /// Whether a call happens while the value is live
+    crosses_call: bool,
+}
+
+/// Assigns every value a register or a spill slot with linear scan over the blocks in layout
+/// order
+///
+/// Values that are live across a call only get callee-saved registers. When no register is left,
+/// the value whose interval ends last is spilled for its whole lifetime.
+pub fn allocate(func: &Function) -> Allocation {
+    let mut intervals = intervals(func);
+    intervals.sort_by_key(|interval| interval.start);
+
+    let mut locations = vec![None; func.types.len()];
+    let mut spill_slots = 0;
+    let mut callee_saved = Vec::new();
+    let mut free_caller: Vec<Reg> = CALLER_SAVED.iter().rev().copied().collect();
+    let mut free_callee: Vec<Reg> = CALLEE_SAVED.iter().rev().copied().collect();
+    // Intervals holding a register as `(end, value, reg)`
+    let mut active: Vec<(usize, Value, Reg)> = Vec::new();
+    for interval in &intervals {
+        active.retain(|(end, _, reg)| {
+            if *end >= interval.start {
+                return true;
+            }
+            if is_callee_saved(*reg) {
+                free_callee.push(*reg);
+            } else {
+                free_caller.push(*reg);
+            }
+            false
+        });
+
+        let reg = if interval.crosses_call {
+            free_callee.pop()
+        } else {
+            free_caller.pop().or_else(|| free_callee.pop())
+        };
+        let location = match reg {
+            Some(reg) => {
+                if is_callee_saved(reg) && !callee_saved.contains(&reg) {
+                    callee_saved.push(reg);
+                }
+                active.push((interval.end, interval.value, reg));
+                Location::Reg(reg)
+            }
+            None => {
+                let victim = active
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, _, reg))| !interval.crosses_call || is_callee_saved(*reg))
+                    .max_by_key(|(_, (end, _, _))| *end)
+                    .map(|(index, _)| index);
+                let slot = Location::Stack(spill_slots);
+                spill_slots += 1;
+                match victim {
+                    Some(index) if active[index].0 > interval.end => {
+                        let (_, value, reg) = active[index];
+                        locations[value.index()] = Some(slot);
+                        active[index] = (interval.end, interval.value, reg);
+                        Location::Reg(reg)
+                    }
+                    _ => slot,
+                }
+            }
+        };
+        locations[interval.value.index()] = Some(location);
+    }
+    callee_saved.sort_by_key(|reg| *reg as i8);
+    Allocation {
+        locations,
+        spill_slots,
+        callee_saved,
+    }
+}
+
+/// Computes one interval per value covering all points it is live at
+///
+/// Every block gets a point for its parameters, every instruction a point for its operands
+/// followed by one for its result and the terminator a point for its operands.
+fn intervals(func: &Function) -> Vec<Interval> {
+    let (live_in, live_out) = liveness(func);
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; func.types.len()];
+    let mut extend = |value: Value, point: usize| {
+        let range = &mut ranges[value.index()];
+        *range = Some(match *range {
+            Some((start, end)) => (start.min(point), end.max(point)),
+            None => (point, point),
+        });
+    };
+    let mut calls = Vec::new();
+    let mut point = 0;
+    for (index, data) in func.blocks.iter().enumerate() {
+        let start = point;
+        for param in &data.params {
+            extend(*param, start);
+        }
+        for value in &live_in[index] {
+            extend(*value, start);
+        }
+        point += 2;
+        for inst in &data.insts {
+            for arg in inst.inst.args() {
+                extend(arg, point);
+            }
+            if is_call(&inst.inst) {
+                calls.push(point + 1);
+            }
+            if let Some(result) = inst.result {
+                extend(result, point + 1);
+            }
+            point += 2;
+        }
+        for arg in data.terminator.iter().flat_map(|t| t.args()) {
+            extend(arg, point);
+        }
+        for value in &live_out[index] {
+            extend(*value, point);
+        }
+        point += 2;
+    }
+
+    ranges
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, range)| {
+            let (start, end) = range?;
+            let first_call = calls.partition_point(|call| *call <= start);
+            Some(Interval {
+                value: Value(index as u32),
+                start,
+                end,
+                crosses_call: calls.get(first_call).is_some_and(|call| *call < end),
+            })
+        })
+        .collect()
+}
+
+/// Returns the values live at the start and at the end of every block
+fn liveness(func: &Function) -> (Vec<HashSet<Value>>, Vec<HashSet<Value>>) {
+    let len = func.blocks.len();
+    let mut uses = vec![HashSet::new(); len];
+    let mut defs = vec![HashSet::new(); len];
+    for (index, data) in func.blocks.iter().enumerate() {
+        defs[index].extend(data.params.iter().copied());
+        for inst in &data.insts {
+            for arg in inst.inst.args() {
+                if !defs[index].contains(&arg) {
+                    uses[index].insert(arg);
+                }
+            }
+            defs[index].extend(inst.result);
+        }
+        for arg in data.terminator.iter().flat_map(|t| t.args()) {
+            if !defs[index].contains(&arg) {
+                uses[index].insert(arg);
+            }
+        }
+    }
+
+    let mut live_in: Vec<HashSet<Value>> = uses.clone();
+    let mut live_out: Vec<HashSet<Value>> = vec![HashSet::new(); len];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in (0..len).rev() {
+            let data = &func.blocks[index];
+            let mut out = HashSet::new();
+            for call in data.terminator.iter().flat_map(|t| t.successors()) {
+                out.extend(live_in[call.block.index()].iter().copied());
+            }
+            let mut new_in = uses[index].clone();
+            new_in.extend(out.difference(&defs[index]).copied());
+            if new_in.len() != live_in[index].len() || out.len() != live_out[index].len() {
+                changed = true;
+            }
+            live_in[index] = new_in;
+            live_out[index] = out;
+        }
+    }
+    (live_in, live_out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::a64::{asm::Asm, emu::Emulator},
+        assembler::Assembler,
+        ir::{lower::lower, IntOp, Type},
+    };
+
+    /// Runs `f` with `g` replaced by a routine returning `arg * 2` and clobbering every other
+    /// caller-saved register
+    fn emulate(func: &Function, arg: u64) -> u64 {
+        let mut callee = Function::new("g".to_string(), &[Type::I64], Some(Type::I64));
+        let entry = callee.entry();
+        let param = callee.param(0);
+        callee.ret(entry, Some(param));
+
+        let mut asm = Asm::default();
+        lower(&mut asm, &[func.clone(), callee]);
+        let (code, vtable) = asm.virtual_jit().unwrap();
+        let mut emu = Emulator::new(code, vtable);
+        emu.hook_symbol("g", |emu| {
+            emu.set_reg(0, emu.reg(0) * 2);
+            for index in 1..18 {
+                emu.set_reg(index, 0xDEAD_0000 + index as u64);
+            }
+        });
+        emu.call("f", &[arg]).unwrap()
+    }
+
+    /// Checks that values live at the same time never share a register or a spill slot
+    fn check_interference(func: &Function, alloc: &Allocation) {
+        let intervals = intervals(func);
+        for (index, a) in intervals.iter().enumerate() {
+            for b in &intervals[index + 1..] {
+                if a.start <= b.end && b.start <= a.end {
+                    assert_ne!(
+                        alloc.location(a.value),
+                        alloc.location(b.value),
+                        "{:?} and {:?} interfere",
+                        a.value,
+                        b.value
+                    );
+                }
+            }
+        }
+    }
+
+    /// Defines `count` values that are all live at once, optionally calling `g` before summing
+    /// them
+    fn pressure(count: i64, call: bool) -> Function {
+        let mut func = Function::new("f".to_string(), &[Type::I64], Some(Type::I64));
+        let entry = func.entry();
+        let x = func.param(0);
+        let mut values = vec![x];
+        for index in 1..count {
+            let imm = func.iconst(entry, Type::I64, index);
+            values.push(func.int(entry, IntOp::Mul, x, imm));
+        }
+        let mut sum = if call {
+            func.call(entry, "g".to_string(), &[x], Some(Type::I64))
+                .unwrap()
+        } else {
+            func.iconst(entry, Type::I64, 0)
+        };
+        for value in values {
+            sum = func.int(entry, IntOp::Add, sum, value);
+        }
+        func.ret(entry, Some(sum));
+        func
+    }
+
+    #[test]
+    fn caller_saved_registers_first() {
+        let func = pressure(3, false);
+        let alloc = allocate(&func);
+        check_interference(&func, &alloc);
+        assert!(alloc.callee_saved.is_empty());
+        assert_eq!(alloc.spill_slots, 0);
+        assert!(alloc.locations.iter().flatten().all(|location| matches!(
+            location,
+            Location::Reg(reg) if CALLER_SAVED.contains(reg)
+        )));
+        assert_eq!(emulate(&func, 5), 5 * (1 + 1 + 2));
+    }
+
+    #[test]
+    fn callee_saved_registers_across_calls() {
+        let func = pressure(4, true);
+        let alloc = allocate(&func);
+        check_interference(&func, &alloc);
+        let x = func.param(0);
+        let values: Vec<Value> = func.blocks[0]
+            .insts
+            .iter()
+            .filter(|inst| matches!(inst.inst, Inst::Int { op: IntOp::Mul, .. }))
+            .filter_map(|inst| inst.result)
+            .chain([x])
+            .collect();
+        for value in values {
+            let Location::Reg(reg) = alloc.location(value) else {
+                panic!("{value:?} was spilled");
+            };
+            assert!(
+                is_callee_saved(reg),
+                "{value:?} is in {reg:?} across the call"
+            );
+            assert!(alloc.callee_saved.contains(&reg));
+        }
+        assert_eq!(alloc.callee_saved.len(), 4);
+        assert_eq!(alloc.spill_slots, 0);
+        assert_eq!(emulate(&func, 5), 5 * 2 + 5 * (1 + 1 + 2 + 3));
+    }
+
+    #[test]
+    fn spills_under_pressure() {
+        let registers = CALLER_SAVED.len() + CALLEE_SAVED.len();
+        let func = pressure(registers as i64 + 5, false);
+        let alloc = allocate(&func);
+        check_interference(&func, &alloc);
+        assert!(alloc.spill_slots >= 5, "{} spill slots", alloc.spill_slots);
+        assert_eq!(alloc.callee_saved, CALLEE_SAVED);
+        let n = registers as u64 + 5;
+        assert_eq!(emulate(&func, 3), 3 * n * (n - 1) / 2 + 3);
+    }
+
+    #[test]
+    fn spills_across_calls() {
+        // More values live across the call than there are callee-saved registers
+        let func = pressure(CALLEE_SAVED.len() as i64 + 4, true);
+        let alloc = allocate(&func);
+        check_interference(&func, &alloc);
+        for interval in intervals(&func).iter().filter(|it| it.crosses_call) {
+            if let Location::Reg(reg) = alloc.location(interval.value) {
+                assert!(is_callee_saved(reg), "{:?} is in {reg:?}", interval.value);
+            }
+        }
+        assert!(alloc.spill_slots >= 4, "{} spill slots", alloc.spill_slots);
+        let n = CALLEE_SAVED.len() as u64 + 4;
+        assert_eq!(emulate(&func, 7), 7 * 2 + 7 * n * (n - 1) / 2 + 7);
+    }
+}
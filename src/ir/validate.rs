@@ -0,0 +1,411 @@
+use super::{cfg::Cfg, Block, BlockCall, Function, Inst, Terminator, Type, Value};
+
+/// Where a value is defined, instructions being numbered from 0 after the block parameters
+#[derive(Clone, Copy)]
+struct Def {
+    block: Block,
+    position: usize,
+}
+
+/// Checks that the function is well-formed SSA
+///
+/// Every block must be terminated, every value defined exactly once with the type recorded in
+/// `types`, every operand must have the type its instruction expects and every use in a reachable
+/// block must be dominated by the definition of the value.
+pub fn validate(func: &Function) -> Result<(), String> {
+    if func.blocks.is_empty() {
+        return Err("Function has no blocks".to_string());
+    }
+    let mut defs: Vec<Option<Def>> = vec![None; func.types.len()];
+    let mut define = |value: Value, block: Block, position: usize| {
+        let Some(def) = defs.get_mut(value.index()) else {
+            return Err(format!("{value:?} has no type"));
+        };
+        if def.is_some() {
+            return Err(format!("{value:?} is defined more than once"));
+        }
+        *def = Some(Def { block, position });
+        Ok(())
+    };
+    for (index, data) in func.blocks.iter().enumerate() {
+        let block = Block(index as u32);
+        for param in &data.params {
+            define(*param, block, 0)?;
+        }
+        for (position, inst) in data.insts.iter().enumerate() {
+            if let Some(result) = inst.result {
+                define(result, block, position + 1)?;
+            }
+        }
+        // Checked before building the CFG, which indexes the blocks by the targets
+        for call in data.terminator.iter().flat_map(|t| t.successors()) {
+            if call.block.index() >= func.blocks.len() {
+                return Err(format!("{block:?}: {:?} does not exist", call.block));
+            }
+        }
+    }
+
+    let cfg = Cfg::new(func);
+    let check_use = |value: Value, block: Block, position: usize| {
+        let Some(def) = defs.get(value.index()).copied().flatten() else {
+            return Err(format!("{value:?} used in {block:?} is never defined"));
+        };
+        let dominated = if def.block == block {
+            def.position < position
+        } else {
+            cfg.dominates(def.block, block)
+        };
+        if cfg.is_reachable(block) && !dominated {
+            return Err(format!(
+                "{value:?} used in {block:?} is not dominated by its definition"
+            ));
+        }
+        Ok(())
+    };
+
+    for (index, data) in func.blocks.iter().enumerate() {
+        let block = Block(index as u32);
+        for (position, inst) in data.insts.iter().enumerate() {
+            for arg in inst.inst.args() {
+                check_use(arg, block, position + 1)?;
+            }
+            let ty = inst_type(func, &inst.inst)
+                .map_err(|err| format!("{:?} in {block:?}: {err}", inst.inst))?;
+            if ty != inst.result.map(|result| func.type_of(result)) {
+                return Err(format!(
+                    "Result of {:?} in {block:?} does not have type {ty:?}",
+                    inst.inst
+                ));
+            }
+        }
+        let Some(terminator) = &data.terminator else {
+            return Err(format!("{block:?} is not terminated"));
+        };
+        for arg in terminator.args() {
+            check_use(arg, block, data.insts.len() + 1)?;
+        }
+        for call in terminator.successors() {
+            check_block_call(func, call).map_err(|err| format!("{block:?}: {err}"))?;
+        }
+        match terminator {
+            Terminator::Branch { cond, .. } if func.type_of(*cond).is_float() => {
+                return Err(format!("Condition of {block:?} must be an integer"));
+            }
+            Terminator::Return(value) if value.map(|value| func.type_of(value)) != func.ret => {
+                return Err(format!("{block:?} does not return {:?}", func.ret));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_block_call(func: &Function, call: &BlockCall) -> Result<(), String> {
+    if call.block.index() == 0 {
+        return Err("Entry block cannot be branched to".to_string());
+    }
+    let target = &func.blocks[call.block.index()];
+    let matches = target.params.len() == call.args.len()
+        && target
+            .params
+            .iter()
+            .zip(&call.args)
+            .all(|(param, arg)| func.type_of(*param) == func.type_of(*arg));
+    if !matches {
+        return Err(format!(
+            "Arguments do not match the parameters of {:?}",
+            call.block
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the type of the value the instruction defines after checking its operands
+fn inst_type(func: &Function, inst: &Inst) -> Result<Option<Type>, String> {
+    let ty = |value: Value| func.type_of(value);
+    let binary = |lhs: Value, rhs: Value, float: bool| {
+        if ty(lhs) != ty(rhs) {
+            Err("Operands must be of the same type".to_string())
+        } else if ty(lhs).is_float() != float {
+            Err(format!("Operands must not be of type {:?}", ty(lhs)))
+        } else {
+            Ok(ty(lhs))
+        }
+    };
+    let result = match inst {
+        Inst::Iconst { ty, .. } if ty.is_float() => return Err("Type must be an integer".into()),
+        Inst::Fconst { ty, .. } if !ty.is_float() => return Err("Type must be a float".into()),
+        Inst::Iconst { ty, .. } | Inst::Fconst { ty, .. } => Some(*ty),
+        Inst::Int { lhs, rhs, .. } => Some(binary(*lhs, *rhs, false)?),
+        Inst::Float { lhs, rhs, .. } => Some(binary(*lhs, *rhs, true)?),
+        Inst::Icmp { lhs, rhs, .. } => {
+            binary(*lhs, *rhs, false)?;
+            Some(Type::I8)
+        }
+        Inst::Fcmp { lhs, rhs, .. } => {
+            binary(*lhs, *rhs, true)?;
+            Some(Type::I8)
+        }
+        Inst::IntToFloat { ty: to, value } if to.is_float() && !ty(*value).is_float() => Some(*to),
+        Inst::FloatToInt { ty: to, value } if !to.is_float() && ty(*value).is_float() => Some(*to),
+        Inst::IntToFloat { .. } | Inst::FloatToInt { .. } => {
+            return Err("Conversion between invalid types".into())
+        }
+        Inst::Load { addr, .. } | Inst::Store { addr, .. } if ty(*addr) != Type::I64 => {
+            return Err("Address must be I64".into())
+        }
+        Inst::Load { ty, .. } => Some(*ty),
+        Inst::Store { .. } => None,
+        Inst::Call { ret, .. } => *ret,
+    };
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Condition, FloatCond, FloatOp, InstData, IntOp};
+
+    /// Branches on whether the parameters are equal, adding them in the first successor
+    fn diamond() -> Function {
+        let mut func = Function::new("f".to_string(), &[Type::I64, Type::I64], Some(Type::I64));
+        let (entry, then, otherwise) = (func.entry(), func.create_block(), func.create_block());
+        let (a, b) = (func.param(0), func.param(1));
+        let cond = func.icmp(entry, Condition::Eq, a, b);
+        func.branch(entry, cond, (then, &[]), (otherwise, &[]));
+        let sum = func.int(then, IntOp::Add, a, b);
+        func.ret(then, Some(sum));
+        func.ret(otherwise, Some(a));
+        func
+    }
+
+    #[test]
+    fn accepts_valid_functions() {
+        assert_eq!(validate(&diamond()), Ok(()));
+
+        let mut func = Function::new("f".to_string(), &[Type::F32], None);
+        let (entry, header, unreachable) = (func.entry(), func.create_block(), func.create_block());
+        let x = func.param(0);
+        let param = func.add_block_param(header, Type::F32);
+        func.jump(entry, header, &[x]);
+        let sum = func.float(header, FloatOp::Add, param, x);
+        let cond = func.fcmp(header, FloatCond::Lt, sum, x);
+        func.branch(header, cond, (header, &[sum]), (header, &[x]));
+        // Uses in unreachable blocks need not be dominated
+        func.int_to_float(unreachable, Type::F32, cond);
+        func.ret(unreachable, None);
+        assert_eq!(validate(&func), Ok(()));
+    }
+
+    #[test]
+    fn rejects_bad_definitions() {
+        let mut func = diamond();
+        func.blocks.clear();
+        assert_eq!(validate(&func), Err("Function has no blocks".to_string()));
+
+        let mut func = diamond();
+        func.blocks[2].terminator = None;
+        assert_eq!(
+            validate(&func),
+            Err("Block(2) is not terminated".to_string())
+        );
+
+        let mut func = diamond();
+        let param = func.param(0);
+        func.blocks[1].params.push(param);
+        assert_eq!(
+            validate(&func),
+            Err("Value(0) is defined more than once".to_string())
+        );
+
+        let mut func = diamond();
+        func.blocks[1].insts[0].result = Some(Value(9));
+        assert_eq!(validate(&func), Err("Value(9) has no type".to_string()));
+
+        let mut func = diamond();
+        let undefined = func.new_value(Type::I64);
+        func.blocks[2].terminator = Some(Terminator::Return(Some(undefined)));
+        assert_eq!(
+            validate(&func),
+            Err("Value(4) used in Block(2) is never defined".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_uses_not_dominated() {
+        // Defined in the other branch
+        let mut func = diamond();
+        let sum = func.blocks[1].insts[0].result;
+        func.blocks[2].terminator = Some(Terminator::Return(sum));
+        assert_eq!(
+            validate(&func),
+            Err("Value(3) used in Block(2) is not dominated by its definition".to_string())
+        );
+
+        // Defined later in the same block
+        let mut func = diamond();
+        let cond = func.blocks[0].insts[0].result.unwrap();
+        let result = func.new_value(Type::I8);
+        let inst = Inst::Icmp {
+            cond: Condition::Ne,
+            lhs: cond,
+            rhs: cond,
+        };
+        func.blocks[0].insts.insert(
+            0,
+            InstData {
+                inst,
+                result: Some(result),
+            },
+        );
+        assert_eq!(
+            validate(&func),
+            Err("Value(2) used in Block(0) is not dominated by its definition".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_type_errors() {
+        let mut func = diamond();
+        let a = func.param(0);
+        let narrow = func.new_value(Type::I32);
+        func.blocks[1].insts[0].inst = Inst::Int {
+            op: IntOp::Add,
+            lhs: a,
+            rhs: narrow,
+        };
+        let inst = Inst::Iconst {
+            ty: Type::I32,
+            imm: 1,
+        };
+        func.blocks[1].insts.insert(
+            0,
+            InstData {
+                inst,
+                result: Some(narrow),
+            },
+        );
+        assert_eq!(
+            validate(&func),
+            Err(
+                "Int { op: Add, lhs: Value(0), rhs: Value(4) } in Block(1): Operands must be of \
+                 the same type"
+                    .to_string()
+            )
+        );
+
+        let mut func = diamond();
+        let a = func.param(0);
+        func.blocks[1].insts[0].inst = Inst::Float {
+            op: FloatOp::Add,
+            lhs: a,
+            rhs: a,
+        };
+        assert_eq!(
+            validate(&func),
+            Err(
+                "Float { op: Add, lhs: Value(0), rhs: Value(0) } in Block(1): Operands must not \
+                 be of type I64"
+                    .to_string()
+            )
+        );
+
+        let mut func = diamond();
+        func.types[3] = Type::I32;
+        assert_eq!(
+            validate(&func),
+            Err(
+                "Result of Int { op: Add, lhs: Value(0), rhs: Value(1) } in Block(1) does not \
+                 have type Some(I64)"
+                    .to_string()
+            )
+        );
+
+        let mut func = diamond();
+        let a = func.param(0);
+        func.blocks[1].insts[0].inst = Inst::IntToFloat {
+            ty: Type::I64,
+            value: a,
+        };
+        assert_eq!(
+            validate(&func),
+            Err(
+                "IntToFloat { ty: I64, value: Value(0) } in Block(1): Conversion between invalid \
+                 types"
+                    .to_string()
+            )
+        );
+
+        let mut func = diamond();
+        let a = func.param(0);
+        func.blocks[1].insts[0].inst = Inst::Load {
+            ty: Type::I64,
+            addr: a,
+            offset: 0,
+        };
+        func.types[0] = Type::I32;
+        func.types[1] = Type::I32;
+        assert_eq!(
+            validate(&func),
+            Err(
+                "Load { ty: I64, addr: Value(0), offset: 0 } in Block(1): Address must be I64"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_bad_terminators() {
+        let mut func = diamond();
+        func.blocks[2].terminator = Some(Terminator::Return(None));
+        assert_eq!(
+            validate(&func),
+            Err("Block(2) does not return Some(I64)".to_string())
+        );
+
+        let mut func = diamond();
+        func.blocks[2].terminator = Some(Terminator::Jump(BlockCall {
+            block: Block(0),
+            args: vec![Value(0), Value(1)],
+        }));
+        assert_eq!(
+            validate(&func),
+            Err("Block(2): Entry block cannot be branched to".to_string())
+        );
+
+        let mut func = diamond();
+        func.blocks[2].terminator = Some(Terminator::Jump(BlockCall {
+            block: Block(3),
+            args: Vec::new(),
+        }));
+        assert_eq!(
+            validate(&func),
+            Err("Block(2): Block(3) does not exist".to_string())
+        );
+
+        let mut func = diamond();
+        func.blocks[2].terminator = Some(Terminator::Jump(BlockCall {
+            block: Block(1),
+            args: vec![Value(0)],
+        }));
+        assert_eq!(
+            validate(&func),
+            Err("Block(2): Arguments do not match the parameters of Block(1)".to_string())
+        );
+
+        let mut func = diamond();
+        func.types[2] = Type::F64;
+        func.blocks[0].insts.clear();
+        func.blocks[0].insts.push(InstData {
+            inst: Inst::Fconst {
+                ty: Type::F64,
+                imm: 1.0,
+            },
+            result: Some(Value(2)),
+        });
+        assert_eq!(
+            validate(&func),
+            Err("Condition of Block(0) must be an integer".to_string())
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use std::{alloc, slice};
 
 pub const fn align(size: usize, align: usize) -> usize {
-    if size % align == 0 {
+    if size.is_multiple_of(align) {
         size
     } else {
         size - (size % align) + align
@@ -98,6 +98,58 @@ pub fn make_readwrite_aligned(ptr: *mut u8, size: usize) -> bool {
     }
 }
 
+/// Makes instruction fetches of all harts observe the code written to the given memory
+///
+/// This is required on RISC-V where `fence.i` only affects the executing hart and on ARM where
+/// the instruction cache is not coherent with the data cache
+pub fn flush_instruction_cache(ptr: *mut u8, size: usize) {
+    #[cfg(all(target_arch = "riscv64", target_os = "linux"))]
+    {
+        const SYS_RISCV_FLUSH_ICACHE: libc::c_long = 259;
+        unsafe {
+            libc::syscall(SYS_RISCV_FLUSH_ICACHE, ptr, ptr.add(size), 0);
+        }
+    }
+    #[cfg(all(target_arch = "arm", target_os = "linux"))]
+    {
+        const ARM_NR_CACHEFLUSH: libc::c_long = 0x0F_0002;
+        unsafe {
+            libc::syscall(ARM_NR_CACHEFLUSH, ptr, ptr.add(size), 0);
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        use std::arch::asm;
+        // Cleans the data cache and invalidates the instruction cache line by line, taking the
+        // smallest line sizes from CTR_EL0
+        let ctr: usize;
+        unsafe {
+            asm!("mrs {}, ctr_el0", out(reg) ctr);
+        }
+        let start = ptr as usize;
+        let end = start + size;
+        let d_line = 4 << ((ctr >> 16) & 0xF);
+        let i_line = 4 << (ctr & 0xF);
+        unsafe {
+            for line in (start & !(d_line - 1)..end).step_by(d_line) {
+                asm!("dc cvau, {}", in(reg) line);
+            }
+            asm!("dsb ish");
+            for line in (start & !(i_line - 1)..end).step_by(i_line) {
+                asm!("ic ivau, {}", in(reg) line);
+            }
+            asm!("dsb ish", "isb");
+        }
+    }
+    #[cfg(not(any(
+        all(any(target_arch = "riscv64", target_arch = "arm"), target_os = "linux"),
+        target_arch = "aarch64"
+    )))]
+    {
+        let _ = (ptr, size);
+    }
+}
+
 pub trait MemoryView<'a> {
     fn address(&self) -> usize;
 